@@ -856,6 +856,15 @@ if __name__ == "__main__":
             "(i.e. more debug messages will be sent over the console port "
             "such as hexdumps of packets)."),
   )
+  main_parser.add_argument(
+      "--debug-hid",
+      action="append_const",
+      const="debug_hid",
+      dest="features",
+      help=("The debug messages are also buffered and can be read with a "
+            "vendor CTAPHID command, for boards without a console port. "
+            "This also automatically activates --debug."),
+  )
   main_parser.add_argument(
       "--debug-allocations",
       action="append_const",
@@ -872,6 +881,25 @@ if __name__ == "__main__":
       help=("The console will be used to output verbose information about the "
             "OpenSK application. This also automatically activates --debug."),
   )
+  main_parser.add_argument(
+      "--deterministic-clock",
+      action="append_const",
+      const="deterministic_clock",
+      dest="features",
+      help=("Compiles the OpenSK application with a software clock that "
+            "advances on each received packet instead of the hardware timer. "
+            "This makes timeouts reproducible when replaying captured "
+            "transactions."),
+  )
+  main_parser.add_argument(
+      "--rng-health-test",
+      action="append_const",
+      const="rng_health_test",
+      dest="features",
+      help=("Compiles the OpenSK application with continuous health tests on "
+            "the hardware RNG output. The application panics if the RNG gets "
+            "stuck or heavily biased."),
+  )
   main_parser.add_argument(
       "--no-u2f",
       action=RemoveConstAction,
@@ -36,7 +36,7 @@ fn is_page_erased(storage: &dyn Storage, page: usize) -> bool {
 fn main() {
     led::get(1).flex_unwrap().on().flex_unwrap(); // red on dongle
     const NUM_PAGES: usize = 20; // should be at least ctap::storage::NUM_PAGES
-    let mut storage = new_storage(NUM_PAGES);
+    let mut storage = new_storage(NUM_PAGES).unwrap();
     writeln!(Console::new(), "Erase {} pages of storage:", NUM_PAGES).unwrap();
     for page in 0..NUM_PAGES {
         write!(Console::new(), "- Page {} ", page).unwrap();
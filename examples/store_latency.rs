@@ -37,7 +37,7 @@ fn measure<T>(timer: &Timer, operation: impl FnOnce() -> T) -> (T, Duration<f64>
 
 // Only use one store at a time.
 unsafe fn boot_store(num_pages: usize, erase: bool) -> Store<Storage> {
-    let mut storage = new_storage(num_pages);
+    let mut storage = new_storage(num_pages).unwrap();
     if erase {
         for page in 0..num_pages {
             use persistent_store::Storage;
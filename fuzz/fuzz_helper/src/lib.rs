@@ -147,7 +147,8 @@ fn process_message<CheckUserPresence>(
 pub fn process_ctap_any_type(data: &[u8]) {
     // Initialize ctap state and hid and get the allocated cid.
     let mut rng = ThreadRng256 {};
-    let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+    let mut ctap_state =
+        CtapState::try_new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE).unwrap();
     let mut ctap_hid = CtapHid::new();
     let cid = initialize(&mut ctap_state, &mut ctap_hid);
     // Wrap input as message with the allocated cid.
@@ -156,6 +157,27 @@ pub fn process_ctap_any_type(data: &[u8]) {
     process_message(&command, &mut ctap_state, &mut ctap_hid);
 }
 
+// Interprets the raw data as any ctap command (including the command byte) and passes it as a
+// complete message to the CTAP HID layer, skipping packet assembly. Unlike assembled messages,
+// the payload length is not limited by the packet format.
+pub fn process_hid_message(data: &[u8]) {
+    // Initialize ctap state and hid and get the allocated cid.
+    let mut rng = ThreadRng256 {};
+    let mut ctap_state =
+        CtapState::try_new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE).unwrap();
+    let mut ctap_hid = CtapHid::new();
+    let cid = initialize(&mut ctap_state, &mut ctap_hid);
+    // Wrap input as message with the allocated cid.
+    let mut command = cid.to_vec();
+    command.extend(data);
+    let message = raw_to_message(&command);
+    let mut assembler_reply = MessageAssembler::new();
+    for pkt_reply in ctap_hid.process_message(message, DUMMY_CLOCK_VALUE, &mut ctap_state) {
+        // Only checks for assembling crashes, not for semantics.
+        let _ = assembler_reply.parse_packet(&pkt_reply, DUMMY_TIMESTAMP);
+    }
+}
+
 // Interprets the raw data as of the given input type and
 // invokes message splitting, packet processing at CTAP HID level and response assembling
 // using an initialized and allocated channel.
@@ -165,7 +187,8 @@ pub fn process_ctap_specific_type(data: &[u8], input_type: InputType) {
     }
     // Initialize ctap state and hid and get the allocated cid.
     let mut rng = ThreadRng256 {};
-    let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+    let mut ctap_state =
+        CtapState::try_new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE).unwrap();
     let mut ctap_hid = CtapHid::new();
     let cid = initialize(&mut ctap_state, &mut ctap_hid);
     // Wrap input as message with allocated cid and command type.
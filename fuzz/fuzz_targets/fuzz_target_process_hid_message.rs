@@ -0,0 +1,9 @@
+#![no_main]
+
+use fuzz_helper::process_hid_message;
+use libfuzzer_sys::fuzz_target;
+
+// Fuzz inputs as complete CTAP HID messages, bypassing packet assembly.
+fuzz_target!(|data: &[u8]| {
+    process_hid_message(data);
+});
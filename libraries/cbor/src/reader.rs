@@ -645,6 +645,21 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_read_indefinite_length_error() {
+        let cases = vec![
+            // Byte string with a single chunk.
+            vec![0x5F, 0x41, 0x01, 0xFF],
+            // Array with a single element.
+            vec![0x9F, 0x01, 0xFF],
+            // Map with a single pair.
+            vec![0xBF, 0x01, 0x02, 0xFF],
+        ];
+        for cbor in cases {
+            assert_eq!(read(&cbor), Err(DecoderError::UnknownAdditionalInfo));
+        }
+    }
+
     #[test]
     fn test_read_too_much_nesting_error() {
         let cases = vec![
@@ -0,0 +1,67 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::hmac::hmac_256;
+use super::{Hash256, HashBlockSize64Bytes};
+
+const HASH_SIZE: usize = 32;
+
+// HKDF as specified in RFC 5869, with an empty salt and a single block of output.
+// This is the key derivation function of the FIDO2 PIN protocol 2, that always uses a salt of 32
+// zero bytes. An empty salt is equivalent, since HMAC pads the key with zeros.
+pub fn hkdf_empty_salt_256<H>(ikm: &[u8], info: &[u8]) -> [u8; HASH_SIZE]
+where
+    H: Hash256 + HashBlockSize64Bytes,
+{
+    let prk = hmac_256::<H>(&[0; HASH_SIZE], ikm);
+    let mut expand_input = info.to_vec();
+    expand_input.push(0x01);
+    hmac_256::<H>(&prk, &expand_input)
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::sha256::Sha256;
+    use super::*;
+
+    #[test]
+    fn test_hkdf_empty_salt_256_rfc5869() {
+        // Test case 3 of RFC 5869, truncated to the first block of output.
+        let ikm = [0x0B; 22];
+        let expected_okm = [
+            0x8D, 0xA4, 0xE7, 0x75, 0xA5, 0x63, 0xC1, 0x8F, 0x71, 0x5F, 0x80, 0x2A, 0x06, 0x3C,
+            0x5A, 0x31, 0xB8, 0xA1, 0x1F, 0x5C, 0x5E, 0xE1, 0x87, 0x9E, 0xC3, 0x45, 0x4E, 0x5F,
+            0x3C, 0x73, 0x8D, 0x2D,
+        ];
+        assert_eq!(hkdf_empty_salt_256::<Sha256>(&ikm, &[]), expected_okm);
+    }
+
+    #[test]
+    fn test_hkdf_empty_salt_256_with_info() {
+        let ikm = [0x88; 32];
+        let expected_okm = [
+            0x6B, 0x91, 0x96, 0xCE, 0xC0, 0xBC, 0x3B, 0x23, 0xA6, 0xDD, 0x20, 0x64, 0x55, 0xC1,
+            0x0A, 0x97, 0xCA, 0x19, 0x29, 0x0C, 0x50, 0x3A, 0x63, 0x2D, 0x1A, 0x27, 0xED, 0xB8,
+            0x95, 0x55, 0x30, 0x42,
+        ];
+        assert_eq!(
+            hkdf_empty_salt_256::<Sha256>(&ikm, b"CTAP2 HMAC key"),
+            expected_okm
+        );
+        assert_ne!(
+            hkdf_empty_salt_256::<Sha256>(&ikm, b"CTAP2 AES key"),
+            expected_okm
+        );
+    }
+}
@@ -12,6 +12,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+#[cfg(feature = "std")]
+use super::sha256::Sha256;
+#[cfg(feature = "std")]
+use super::Hash256;
 use arrayref::array_ref;
 use libtock_drivers::rng;
 
@@ -48,6 +52,94 @@ impl Rng256 for TockRng256 {
     }
 }
 
+// Continuous health tests from NIST SP 800-90B, section 4.4, applied to each output byte.
+// The cutoffs assume a min-entropy of 4 bits per byte, which is conservative for hardware RNGs
+// and gives a false positive probability of about 2^-20 per test for such a source.
+const REPETITION_COUNT_CUTOFF: usize = 6;
+const ADAPTIVE_PROPORTION_WINDOW_SIZE: usize = 512;
+const ADAPTIVE_PROPORTION_CUTOFF: usize = 62;
+
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "derive_debug", derive(Debug))]
+pub enum HealthTestFailure {
+    RepetitionCount,
+    AdaptiveProportion,
+}
+
+// RNG wrapper that checks the output of another RNG for stuck or heavily biased patterns.
+// Both tests only keep a few counters, so the overhead per generated byte is a handful of
+// comparisons.
+pub struct HealthTestedRng256<R: Rng256> {
+    rng: R,
+    // Repetition count test state.
+    last_sample: Option<u8>,
+    repetition_count: usize,
+    // Adaptive proportion test state.
+    window_sample: u8,
+    window_index: usize,
+    window_count: usize,
+}
+
+impl<R: Rng256> HealthTestedRng256<R> {
+    pub fn new(rng: R) -> Self {
+        HealthTestedRng256 {
+            rng,
+            last_sample: None,
+            repetition_count: 0,
+            window_sample: 0,
+            window_index: 0,
+            window_count: 0,
+        }
+    }
+
+    // Returns random bytes, or the test that failed on them.
+    pub fn try_gen_uniform_u8x32(&mut self) -> Result<[u8; 32], HealthTestFailure> {
+        let bytes = self.rng.gen_uniform_u8x32();
+        for &sample in bytes.iter() {
+            self.repetition_count_test(sample)?;
+            self.adaptive_proportion_test(sample)?;
+        }
+        Ok(bytes)
+    }
+
+    fn repetition_count_test(&mut self, sample: u8) -> Result<(), HealthTestFailure> {
+        if self.last_sample == Some(sample) {
+            self.repetition_count += 1;
+            if self.repetition_count >= REPETITION_COUNT_CUTOFF {
+                return Err(HealthTestFailure::RepetitionCount);
+            }
+        } else {
+            self.last_sample = Some(sample);
+            self.repetition_count = 1;
+        }
+        Ok(())
+    }
+
+    fn adaptive_proportion_test(&mut self, sample: u8) -> Result<(), HealthTestFailure> {
+        if self.window_index == 0 {
+            self.window_sample = sample;
+            self.window_count = 1;
+        } else if sample == self.window_sample {
+            self.window_count += 1;
+            if self.window_count >= ADAPTIVE_PROPORTION_CUTOFF {
+                return Err(HealthTestFailure::AdaptiveProportion);
+            }
+        }
+        self.window_index = (self.window_index + 1) % ADAPTIVE_PROPORTION_WINDOW_SIZE;
+        Ok(())
+    }
+}
+
+impl<R: Rng256> Rng256 for HealthTestedRng256<R> {
+    // A failing entropy source can't be recovered from, so we stop instead of using its output.
+    fn gen_uniform_u8x32(&mut self) -> [u8; 32] {
+        match self.try_gen_uniform_u8x32() {
+            Ok(bytes) => bytes,
+            Err(_) => panic!("RNG health test failed"),
+        }
+    }
+}
+
 // For tests on the desktop, we use the cryptographically secure thread rng as entropy source.
 #[cfg(feature = "std")]
 pub struct ThreadRng256 {}
@@ -64,6 +156,49 @@ impl Rng256 for ThreadRng256 {
     }
 }
 
+// For reproducible tests and fuzzing, a deterministic RNG that records its output.
+// Block i of the stream is SHA256(seed || i), so the same seed always yields the same bytes.
+#[cfg(feature = "std")]
+pub struct SeededRng256 {
+    seed: [u8; 32],
+    counter: u64,
+    log: Vec<u8>,
+}
+
+#[cfg(feature = "std")]
+impl SeededRng256 {
+    pub fn new(seed: [u8; 32]) -> Self {
+        SeededRng256 {
+            seed,
+            counter: 0,
+            log: Vec::new(),
+        }
+    }
+
+    // Restarts the stream from the given seed and clears the log.
+    pub fn set_seed(&mut self, seed: [u8; 32]) {
+        *self = SeededRng256::new(seed);
+    }
+
+    // Returns all bytes generated since the seed was set.
+    pub fn log(&self) -> &[u8] {
+        &self.log
+    }
+}
+
+#[cfg(feature = "std")]
+impl Rng256 for SeededRng256 {
+    fn gen_uniform_u8x32(&mut self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(&self.seed);
+        hasher.update(&self.counter.to_be_bytes());
+        let result = hasher.finalize();
+        self.counter += 1;
+        self.log.extend_from_slice(&result);
+        result
+    }
+}
+
 #[cfg(test)]
 pub mod test {
     use super::*;
@@ -89,4 +224,114 @@ pub mod test {
 
         assert_eq!(bytes_to_u32(*bytes), expected);
     }
+
+    // Returns the bytes of a fixed sequence, repeated as needed.
+    struct SequenceRng256 {
+        sequence: Vec<u8>,
+        index: usize,
+    }
+
+    impl Rng256 for SequenceRng256 {
+        fn gen_uniform_u8x32(&mut self) -> [u8; 32] {
+            let mut result = [0; 32];
+            for byte in result.iter_mut() {
+                *byte = self.sequence[self.index];
+                self.index = (self.index + 1) % self.sequence.len();
+            }
+            result
+        }
+    }
+
+    #[test]
+    fn test_health_test_passes() {
+        let mut rng = HealthTestedRng256::new(ThreadRng256 {});
+        for _ in 0..1000 {
+            assert!(rng.try_gen_uniform_u8x32().is_ok());
+        }
+    }
+
+    #[test]
+    fn test_health_test_stuck_output() {
+        let stuck_rng = SequenceRng256 {
+            sequence: vec![0x00],
+            index: 0,
+        };
+        let mut rng = HealthTestedRng256::new(stuck_rng);
+        assert_eq!(
+            rng.try_gen_uniform_u8x32(),
+            Err(HealthTestFailure::RepetitionCount)
+        );
+    }
+
+    #[test]
+    fn test_health_test_repetition_across_calls() {
+        // Each call ends and starts with a run of 3 identical bytes.
+        let mut sequence: Vec<u8> = (0..32).collect();
+        sequence[0] = 0xFF;
+        sequence[1] = 0xFF;
+        sequence[2] = 0xFF;
+        sequence[29] = 0xFF;
+        sequence[30] = 0xFF;
+        sequence[31] = 0xFF;
+        let sequence_rng = SequenceRng256 { sequence, index: 0 };
+        let mut rng = HealthTestedRng256::new(sequence_rng);
+        assert!(rng.try_gen_uniform_u8x32().is_ok());
+        assert_eq!(
+            rng.try_gen_uniform_u8x32(),
+            Err(HealthTestFailure::RepetitionCount)
+        );
+    }
+
+    #[test]
+    fn test_health_test_biased_output() {
+        // Never repeats a byte, but half of the bytes are equal.
+        let biased_rng = SequenceRng256 {
+            sequence: vec![0x00, 0x01, 0x00, 0x02, 0x00, 0x03],
+            index: 0,
+        };
+        let mut rng = HealthTestedRng256::new(biased_rng);
+        let mut result = Ok([0; 32]);
+        for _ in 0..ADAPTIVE_PROPORTION_WINDOW_SIZE / 32 {
+            result = rng.try_gen_uniform_u8x32();
+            if result.is_err() {
+                break;
+            }
+        }
+        assert_eq!(result, Err(HealthTestFailure::AdaptiveProportion));
+    }
+
+    #[test]
+    fn test_seeded_rng_is_reproducible() {
+        let mut first = SeededRng256::new([0x55; 32]);
+        let mut second = SeededRng256::new([0x55; 32]);
+        for _ in 0..10 {
+            assert_eq!(first.gen_uniform_u8x32(), second.gen_uniform_u8x32());
+        }
+        assert_eq!(first.log().len(), 10 * 32);
+        assert_eq!(first.log(), second.log());
+
+        let mut other = SeededRng256::new([0xAA; 32]);
+        assert_ne!(other.gen_uniform_u8x32(), first.log()[..32]);
+    }
+
+    #[test]
+    fn test_seeded_rng_set_seed() {
+        let mut rng = SeededRng256::new([0x55; 32]);
+        let first_output = rng.gen_uniform_u8x32();
+        rng.gen_uniform_u8x32();
+        rng.set_seed([0x55; 32]);
+        assert!(rng.log().is_empty());
+        assert_eq!(rng.gen_uniform_u8x32(), first_output);
+        assert_eq!(rng.log(), &first_output[..]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_health_test_panics() {
+        let stuck_rng = SequenceRng256 {
+            sequence: vec![0x42],
+            index: 0,
+        };
+        HealthTestedRng256::new(stuck_rng).gen_uniform_u8x32();
+    }
 }
@@ -12,6 +12,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic;
 #[cfg(test)]
 use subtle::CtOption;
 
@@ -24,6 +26,39 @@ pub fn xor_block_16(block: &mut Block16, mask: &Block16) {
     }
 }
 
+// Overwrites the buffer with zeros. Contrary to a plain assignment, the compiler doesn't remove
+// the writes when the buffer is not read afterwards.
+pub fn zeroize(buffer: &mut [u8]) {
+    for byte in buffer.iter_mut() {
+        // Safety: the pointer comes from a mutable reference, so it is valid and aligned.
+        unsafe { core::ptr::write_volatile(byte, 0) };
+    }
+    atomic::compiler_fence(atomic::Ordering::SeqCst);
+}
+
+// Wrapper for secret buffers, that zeroizes them when dropped.
+pub struct Zeroizing<T: AsMut<[u8]>>(pub T);
+
+impl<T: AsMut<[u8]>> Deref for Zeroizing<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: AsMut<[u8]>> DerefMut for Zeroizing<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T: AsMut<[u8]>> Drop for Zeroizing<T> {
+    fn drop(&mut self) {
+        zeroize(self.0.as_mut());
+    }
+}
+
 #[cfg(test)]
 pub trait ToOption<T> {
     fn to_option(self) -> Option<T>;
@@ -39,3 +74,32 @@ impl<T> ToOption<T> for CtOption<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn test_zeroize() {
+        let mut buffer = [0x55; 20];
+        zeroize(&mut buffer[..10]);
+        assert_eq!(buffer[..10], [0x00; 10]);
+        assert_eq!(buffer[10..], [0x55; 10]);
+    }
+
+    #[test]
+    fn test_zeroizing_drop() {
+        let mut buffer = [0x55; 32];
+        {
+            let mut secret = Zeroizing(&mut buffer);
+            secret[0] = 0xAA;
+            assert_eq!(secret[..2], [0xAA, 0x55]);
+        }
+        assert_eq!(buffer, [0x00; 32]);
+
+        let mut buffer = vec![0x55; 64];
+        drop(Zeroizing(&mut buffer));
+        assert_eq!(buffer, vec![0x00; 64]);
+    }
+}
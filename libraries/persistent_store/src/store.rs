@@ -336,6 +336,29 @@ impl<S: Storage> Store<S> {
         Ok(())
     }
 
+    /// Returns whether the next write of a value of maximum length may compact the store.
+    ///
+    /// This is the case when the immediate capacity is smaller than such an entry, and the
+    /// remaining capacity is not. Calling [`consolidate`] moves those compactions ahead of time.
+    ///
+    /// [`consolidate`]: struct.Store.html#method.consolidate
+    pub fn needs_compaction(&self) -> StoreResult<bool> {
+        Ok(self.immediate_capacity()? < self.consolidate_length()?)
+    }
+
+    /// Compacts the store until the next write of a value of maximum length doesn't need to.
+    ///
+    /// Nothing is modified if the store doesn't [need compaction].
+    ///
+    /// [need compaction]: struct.Store.html#method.needs_compaction
+    pub fn consolidate(&mut self) -> StoreResult<()> {
+        let length = self.consolidate_length()?;
+        while self.immediate_capacity()? < length {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
     /// Recovers a possible interrupted operation.
     ///
     /// If the storage is completely erased, it is initialized.
@@ -410,6 +433,11 @@ impl<S: Storage> Store<S> {
         self.format.max_value_len() as usize
     }
 
+    /// Returns the maximum number of updates per transaction.
+    pub fn max_updates(&self) -> usize {
+        self.format.max_updates() as usize
+    }
+
     /// Returns the value of an entry given its handle.
     fn get_value(&self, handle: &StoreHandle) -> StoreResult<Vec<u8>> {
         self.check_handle(handle)?;
@@ -852,6 +880,16 @@ impl<S: Storage> Store<S> {
         Ok(end.get().saturating_sub(tail.get()))
     }
 
+    /// Returns the immediate capacity in words targeted by consolidation.
+    ///
+    /// This is the size of an entry with a value of maximum length, bounded by the remaining
+    /// capacity.
+    fn consolidate_length(&self) -> StoreResult<Nat> {
+        let length = 1 + self.format.bytes_to_words(self.format.max_value_len());
+        let remaining = usize_to_nat(self.capacity()?.remaining());
+        Ok(min(length, remaining))
+    }
+
     /// Returns the position of the first word in the store.
     pub(crate) fn head(&self) -> StoreResult<Position> {
         self.get_extremum_page_head(Ordering::Less)
@@ -1429,6 +1467,33 @@ mod tests {
         assert_eq!(driver.store().capacity().unwrap().remaining(), 18);
     }
 
+    #[test]
+    fn consolidate_ok() {
+        let mut driver = MINIMAL.new_driver().power_on().unwrap();
+
+        // Don't compact if enough immediate capacity.
+        assert!(!driver.store().needs_compaction().unwrap());
+        driver.store_mut().consolidate().unwrap();
+        assert_eq!(driver.store().head().unwrap().get(), 0);
+
+        // Fill the store and remove some entries.
+        for key in 0..4 {
+            driver.insert(key, &[0x38; 28]).unwrap();
+        }
+        driver.remove(0).unwrap();
+        driver.remove(2).unwrap();
+        driver.check().unwrap();
+        assert_eq!(driver.store().immediate_capacity().unwrap(), 7);
+        assert!(driver.store().needs_compaction().unwrap());
+
+        // Consolidate until a value of maximum length can be written without compaction.
+        driver.store_mut().consolidate().unwrap();
+        driver.check().unwrap();
+        assert!(!driver.store().needs_compaction().unwrap());
+        assert_eq!(driver.store().head().unwrap().get(), 16);
+        assert_eq!(driver.store().capacity().unwrap().remaining(), 18);
+    }
+
     #[test]
     fn reboot_ok() {
         let mut driver = MINIMAL.new_driver().power_on().unwrap();
@@ -49,6 +49,10 @@ cargo check --release --target=thumbv7em-none-eabi --features debug_ctap
 cargo check --release --target=thumbv7em-none-eabi --features panic_console
 cargo check --release --target=thumbv7em-none-eabi --features debug_allocations
 cargo check --release --target=thumbv7em-none-eabi --features verbose
+cargo check --release --target=thumbv7em-none-eabi --features debug_ctap,hid_stats
+cargo check --release --target=thumbv7em-none-eabi --features debug_hid
+cargo check --release --target=thumbv7em-none-eabi --features deterministic_clock
+cargo check --release --target=thumbv7em-none-eabi --features rng_health_test
 cargo check --release --target=thumbv7em-none-eabi --features debug_ctap,with_ctap1
 cargo check --release --target=thumbv7em-none-eabi --features debug_ctap,with_ctap1,panic_console,debug_allocations,verbose
 
@@ -97,6 +101,10 @@ then
   cd libraries/persistent_store
   cargo test --release --features std
   cd ../..
+  cd third_party/libtock-drivers
+  cargo test --release
+  cargo test --release --features hid_stats
+  cd ../..
   cargo test --release --features std
 
   echo "Running unit tests on the desktop (debug mode)..."
@@ -109,6 +117,10 @@ then
   cd libraries/persistent_store
   cargo test --features std
   cd ../..
+  cd third_party/libtock-drivers
+  cargo test
+  cargo test --features hid_stats
+  cd ../..
   cargo test --features std
 
   echo "Running unit tests on the desktop (release mode + CTAP1)..."
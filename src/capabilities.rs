@@ -0,0 +1,78 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use libtock_drivers::{buttons, crp, led};
+
+/// Hardware and firmware features available at runtime.
+///
+/// Unlike cargo features, this reflects the board the firmware actually runs on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Capabilities {
+    pub num_leds: usize,
+    pub num_buttons: usize,
+    /// Whether the chip supports the lockdown of the vendor configure command.
+    pub firmware_protection: bool,
+    /// Whether the firmware was built with NFC support.
+    pub nfc: bool,
+}
+
+impl Capabilities {
+    /// Queries the drivers. A missing driver means the board doesn't have the feature.
+    pub fn detect() -> Capabilities {
+        Capabilities::from_drivers(
+            led::count().ok(),
+            buttons::count().ok(),
+            crp::is_available().is_ok(),
+        )
+    }
+
+    fn from_drivers(
+        num_leds: Option<usize>,
+        num_buttons: Option<usize>,
+        firmware_protection: bool,
+    ) -> Capabilities {
+        Capabilities {
+            num_leds: num_leds.unwrap_or(0),
+            num_buttons: num_buttons.unwrap_or(0),
+            firmware_protection,
+            nfc: cfg!(feature = "with_nfc"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_capabilities_from_drivers() {
+        assert_eq!(
+            Capabilities::from_drivers(Some(4), Some(1), true),
+            Capabilities {
+                num_leds: 4,
+                num_buttons: 1,
+                firmware_protection: true,
+                nfc: cfg!(feature = "with_nfc"),
+            }
+        );
+    }
+
+    #[test]
+    fn test_capabilities_missing_drivers() {
+        let capabilities = Capabilities::from_drivers(None, None, false);
+        assert_eq!(capabilities.num_leds, 0);
+        assert_eq!(capabilities.num_buttons, 0);
+        assert!(!capabilities.firmware_protection);
+    }
+}
@@ -0,0 +1,116 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use libtock_drivers::timer::{ClockValue, Duration};
+
+/// Software clock that only advances when it is explicitly ticked.
+///
+/// The main loop ticks it once per received packet instead of reading the alarm driver. Replaying
+/// a captured packet log then reproduces the exact same clock values, including all timeouts.
+pub struct DeterministicClock {
+    elapsed_ms: isize,
+    tick_duration: Duration<isize>,
+}
+
+impl DeterministicClock {
+    pub const CLOCK_FREQUENCY_HZ: usize = 1000;
+
+    pub fn new(tick_duration: Duration<isize>) -> DeterministicClock {
+        DeterministicClock {
+            elapsed_ms: 0,
+            tick_duration,
+        }
+    }
+
+    pub fn tick(&mut self) {
+        self.elapsed_ms = self.elapsed_ms.wrapping_add(self.tick_duration.ms());
+    }
+
+    /// Advances the clock by an arbitrary time, independently of the tick duration.
+    ///
+    /// Tests use it to jump straight past timeouts.
+    #[cfg(test)]
+    pub fn advance_ms(&mut self, ms: u32) {
+        self.elapsed_ms = self.elapsed_ms.wrapping_add(ms as isize);
+    }
+
+    pub fn get_current_clock(&self) -> ClockValue {
+        // At 1 kHz, clock ticks are milliseconds.
+        ClockValue::new(self.elapsed_ms, DeterministicClock::CLOCK_FREQUENCY_HZ)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ctap::timed_permission::TimedPermission;
+
+    const TICK_DURATION: Duration<isize> = Duration::from_ms(100);
+
+    #[test]
+    fn test_clock_only_advances_on_tick() {
+        let mut clock = DeterministicClock::new(TICK_DURATION);
+        let start = clock.get_current_clock();
+        assert_eq!(clock.get_current_clock().num_ticks(), start.num_ticks());
+        clock.tick();
+        assert_eq!(
+            clock.get_current_clock().wrapping_sub(start),
+            Some(TICK_DURATION)
+        );
+    }
+
+    #[test]
+    fn test_timeout_elapses_after_ticks() {
+        let mut clock = DeterministicClock::new(TICK_DURATION);
+        // 10 ticks of 100 ms each.
+        let permission =
+            TimedPermission::granted(clock.get_current_clock(), Duration::from_ms(1000));
+        for _ in 0..9 {
+            clock.tick();
+            assert!(permission.is_granted(clock.get_current_clock()));
+        }
+        clock.tick();
+        assert!(!permission.is_granted(clock.get_current_clock()));
+    }
+
+    #[test]
+    fn test_advance_ms() {
+        let mut clock = DeterministicClock::new(TICK_DURATION);
+        let start = clock.get_current_clock();
+        clock.advance_ms(1234);
+        assert_eq!(
+            clock.get_current_clock().wrapping_sub(start),
+            Some(Duration::from_ms(1234))
+        );
+        clock.tick();
+        assert_eq!(
+            clock.get_current_clock().wrapping_sub(start),
+            Some(Duration::from_ms(1334))
+        );
+    }
+
+    #[test]
+    fn test_replay_is_reproducible() {
+        let mut first = DeterministicClock::new(TICK_DURATION);
+        let mut second = DeterministicClock::new(TICK_DURATION);
+        for _ in 0..42 {
+            first.tick();
+            second.tick();
+        }
+        assert_eq!(
+            first.get_current_clock().num_ticks(),
+            second.get_current_clock().num_ticks()
+        );
+    }
+}
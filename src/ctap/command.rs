@@ -19,13 +19,20 @@ use super::data_formats::{
     PublicKeyCredentialDescriptor, PublicKeyCredentialParameter, PublicKeyCredentialRpEntity,
     PublicKeyCredentialUserEntity,
 };
+#[cfg(feature = "with_ctap2_1")]
+use super::data_formats::{
+    ConfigSubCommand, CredentialBackupSubCommand, CredentialManagementSubCommand,
+};
 use super::key_material;
 use super::status_code::Ctap2StatusCode;
+#[cfg(feature = "with_ctap2_1")]
+use alloc::collections::BTreeMap;
 use alloc::string::String;
 use alloc::vec::Vec;
 use arrayref::array_ref;
 use cbor::destructure_cbor_map;
 use core::convert::TryFrom;
+use libtock_drivers::crp::ProtectionLevel;
 
 // Depending on your memory, you can use Some(n) to limit request sizes in
 // MakeCredential and GetAssertion. This affects allowList and excludeList.
@@ -42,10 +49,23 @@ pub enum Command {
     AuthenticatorReset,
     AuthenticatorGetNextAssertion,
     #[cfg(feature = "with_ctap2_1")]
+    AuthenticatorCredentialManagement(AuthenticatorCredentialManagementParameters),
+    #[cfg(feature = "with_ctap2_1")]
     AuthenticatorSelection,
+    #[cfg(feature = "with_ctap2_1")]
+    AuthenticatorLargeBlobs(AuthenticatorLargeBlobsParameters),
+    #[cfg(feature = "with_ctap2_1")]
+    AuthenticatorConfig(AuthenticatorConfigParameters),
     // TODO(kaczmarczyck) implement FIDO 2.1 commands (see below consts)
     // Vendor specific commands
     AuthenticatorVendorConfigure(AuthenticatorVendorConfigureParameters),
+    AuthenticatorVendorFactoryReset(AuthenticatorVendorFactoryResetParameters),
+    AuthenticatorVendorRpIdPolicy(AuthenticatorVendorRpIdPolicyParameters),
+    AuthenticatorVendorStoreHealth,
+    #[cfg(feature = "with_ctap2_1")]
+    AuthenticatorVendorCredentialBackup(AuthenticatorVendorCredentialBackupParameters),
+    #[cfg(feature = "with_ctap2_1")]
+    AuthenticatorVendorResetBootCount(AuthenticatorVendorResetBootCountParameters),
 }
 
 impl From<cbor::reader::DecoderError> for Ctap2StatusCode {
@@ -71,6 +91,13 @@ impl Command {
     const AUTHENTICATOR_CONFIG: u8 = 0x0D;
     const _AUTHENTICATOR_VENDOR_FIRST: u8 = 0x40;
     const AUTHENTICATOR_VENDOR_CONFIGURE: u8 = 0x40;
+    const AUTHENTICATOR_VENDOR_FACTORY_RESET: u8 = 0x41;
+    const AUTHENTICATOR_VENDOR_RP_ID_POLICY: u8 = 0x42;
+    const AUTHENTICATOR_VENDOR_STORE_HEALTH: u8 = 0x43;
+    #[cfg(feature = "with_ctap2_1")]
+    const AUTHENTICATOR_VENDOR_CREDENTIAL_BACKUP: u8 = 0x44;
+    #[cfg(feature = "with_ctap2_1")]
+    const AUTHENTICATOR_VENDOR_RESET_BOOT_COUNT: u8 = 0x45;
     const _AUTHENTICATOR_VENDOR_LAST: u8 = 0xBF;
 
     pub fn deserialize(bytes: &[u8]) -> Result<Command, Ctap2StatusCode> {
@@ -112,16 +139,73 @@ impl Command {
                 Ok(Command::AuthenticatorGetNextAssertion)
             }
             #[cfg(feature = "with_ctap2_1")]
+            Command::AUTHENTICATOR_CREDENTIAL_MANAGEMENT => {
+                let decoded_cbor = cbor::read(&bytes[1..])?;
+                Ok(Command::AuthenticatorCredentialManagement(
+                    AuthenticatorCredentialManagementParameters::try_from(decoded_cbor)?,
+                ))
+            }
+            #[cfg(feature = "with_ctap2_1")]
             Command::AUTHENTICATOR_SELECTION => {
                 // Parameters are ignored.
                 Ok(Command::AuthenticatorSelection)
             }
+            #[cfg(feature = "with_ctap2_1")]
+            Command::AUTHENTICATOR_LARGE_BLOBS => {
+                let decoded_cbor = cbor::read(&bytes[1..])?;
+                Ok(Command::AuthenticatorLargeBlobs(
+                    AuthenticatorLargeBlobsParameters::try_from(decoded_cbor)?,
+                ))
+            }
+            #[cfg(feature = "with_ctap2_1")]
+            Command::AUTHENTICATOR_CONFIG => {
+                let decoded_cbor = cbor::read(&bytes[1..])?;
+                Ok(Command::AuthenticatorConfig(
+                    AuthenticatorConfigParameters::try_from(decoded_cbor)?,
+                ))
+            }
             Command::AUTHENTICATOR_VENDOR_CONFIGURE => {
                 let decoded_cbor = cbor::read(&bytes[1..])?;
                 Ok(Command::AuthenticatorVendorConfigure(
                     AuthenticatorVendorConfigureParameters::try_from(decoded_cbor)?,
                 ))
             }
+            Command::AUTHENTICATOR_VENDOR_FACTORY_RESET => {
+                // Parameters are only needed if a PIN is set.
+                if bytes.len() == 1 {
+                    return Ok(Command::AuthenticatorVendorFactoryReset(
+                        AuthenticatorVendorFactoryResetParameters::default(),
+                    ));
+                }
+                let decoded_cbor = cbor::read(&bytes[1..])?;
+                Ok(Command::AuthenticatorVendorFactoryReset(
+                    AuthenticatorVendorFactoryResetParameters::try_from(decoded_cbor)?,
+                ))
+            }
+            Command::AUTHENTICATOR_VENDOR_RP_ID_POLICY => {
+                let decoded_cbor = cbor::read(&bytes[1..])?;
+                Ok(Command::AuthenticatorVendorRpIdPolicy(
+                    AuthenticatorVendorRpIdPolicyParameters::try_from(decoded_cbor)?,
+                ))
+            }
+            Command::AUTHENTICATOR_VENDOR_STORE_HEALTH => {
+                // Parameters are ignored.
+                Ok(Command::AuthenticatorVendorStoreHealth)
+            }
+            #[cfg(feature = "with_ctap2_1")]
+            Command::AUTHENTICATOR_VENDOR_CREDENTIAL_BACKUP => {
+                let decoded_cbor = cbor::read(&bytes[1..])?;
+                Ok(Command::AuthenticatorVendorCredentialBackup(
+                    AuthenticatorVendorCredentialBackupParameters::try_from(decoded_cbor)?,
+                ))
+            }
+            #[cfg(feature = "with_ctap2_1")]
+            Command::AUTHENTICATOR_VENDOR_RESET_BOOT_COUNT => {
+                let decoded_cbor = cbor::read(&bytes[1..])?;
+                Ok(Command::AuthenticatorVendorResetBootCount(
+                    AuthenticatorVendorResetBootCountParameters::try_from(decoded_cbor)?,
+                ))
+            }
             _ => Err(Ctap2StatusCode::CTAP1_ERR_INVALID_COMMAND),
         }
     }
@@ -139,6 +223,7 @@ pub struct AuthenticatorMakeCredentialParameters {
     pub options: MakeCredentialOptions,
     pub pin_uv_auth_param: Option<Vec<u8>>,
     pub pin_uv_auth_protocol: Option<u64>,
+    pub enterprise_attestation: Option<u64>,
 }
 
 impl TryFrom<cbor::Value> for AuthenticatorMakeCredentialParameters {
@@ -156,6 +241,7 @@ impl TryFrom<cbor::Value> for AuthenticatorMakeCredentialParameters {
                 7 => options,
                 8 => pin_uv_auth_param,
                 9 => pin_uv_auth_protocol,
+                10 => enterprise_attestation,
             } = extract_map(cbor_value)?;
         }
 
@@ -197,6 +283,7 @@ impl TryFrom<cbor::Value> for AuthenticatorMakeCredentialParameters {
 
         let pin_uv_auth_param = pin_uv_auth_param.map(extract_byte_string).transpose()?;
         let pin_uv_auth_protocol = pin_uv_auth_protocol.map(extract_unsigned).transpose()?;
+        let enterprise_attestation = enterprise_attestation.map(extract_unsigned).transpose()?;
 
         Ok(AuthenticatorMakeCredentialParameters {
             client_data_hash,
@@ -208,6 +295,7 @@ impl TryFrom<cbor::Value> for AuthenticatorMakeCredentialParameters {
             options,
             pin_uv_auth_param,
             pin_uv_auth_protocol,
+            enterprise_attestation,
         })
     }
 }
@@ -385,10 +473,147 @@ impl TryFrom<cbor::Value> for AuthenticatorClientPinParameters {
     }
 }
 
+#[cfg(feature = "with_ctap2_1")]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug, PartialEq))]
+pub struct AuthenticatorCredentialManagementParameters {
+    pub sub_command: CredentialManagementSubCommand,
+    pub sub_command_params: Option<BTreeMap<cbor::KeyType, cbor::Value>>,
+    pub pin_uv_auth_protocol: Option<u64>,
+    pub pin_uv_auth_param: Option<Vec<u8>>,
+}
+
+#[cfg(feature = "with_ctap2_1")]
+impl TryFrom<cbor::Value> for AuthenticatorCredentialManagementParameters {
+    type Error = Ctap2StatusCode;
+
+    fn try_from(cbor_value: cbor::Value) -> Result<Self, Ctap2StatusCode> {
+        destructure_cbor_map! {
+            let {
+                1 => sub_command,
+                2 => sub_command_params,
+                3 => pin_uv_auth_protocol,
+                4 => pin_uv_auth_param,
+            } = extract_map(cbor_value)?;
+        }
+
+        let sub_command = CredentialManagementSubCommand::try_from(ok_or_missing(sub_command)?)?;
+        // The parameters are kept as a map, since the PIN auth is computed over their encoding.
+        let sub_command_params = sub_command_params.map(extract_map).transpose()?;
+        let pin_uv_auth_protocol = pin_uv_auth_protocol.map(extract_unsigned).transpose()?;
+        let pin_uv_auth_param = pin_uv_auth_param.map(extract_byte_string).transpose()?;
+
+        Ok(AuthenticatorCredentialManagementParameters {
+            sub_command,
+            sub_command_params,
+            pin_uv_auth_protocol,
+            pin_uv_auth_param,
+        })
+    }
+}
+
+#[cfg(feature = "with_ctap2_1")]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug, PartialEq))]
+pub struct AuthenticatorLargeBlobsParameters {
+    pub get: Option<usize>,
+    pub set: Option<Vec<u8>>,
+    pub offset: usize,
+    pub length: Option<usize>,
+    pub pin_uv_auth_param: Option<Vec<u8>>,
+    pub pin_uv_auth_protocol: Option<u64>,
+}
+
+#[cfg(feature = "with_ctap2_1")]
+impl TryFrom<cbor::Value> for AuthenticatorLargeBlobsParameters {
+    type Error = Ctap2StatusCode;
+
+    fn try_from(cbor_value: cbor::Value) -> Result<Self, Ctap2StatusCode> {
+        destructure_cbor_map! {
+            let {
+                1 => get,
+                2 => set,
+                3 => offset,
+                4 => length,
+                5 => pin_uv_auth_param,
+                6 => pin_uv_auth_protocol,
+            } = extract_map(cbor_value)?;
+        }
+
+        let get = get.map(extract_unsigned).transpose()?.map(|u| u as usize);
+        let set = set.map(extract_byte_string).transpose()?;
+        // CTAP 2.1 reports a missing offset as an invalid parameter.
+        let offset =
+            extract_unsigned(offset.ok_or(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)?)? as usize;
+        let length = length
+            .map(extract_unsigned)
+            .transpose()?
+            .map(|u| u as usize);
+        let pin_uv_auth_param = pin_uv_auth_param.map(extract_byte_string).transpose()?;
+        let pin_uv_auth_protocol = pin_uv_auth_protocol.map(extract_unsigned).transpose()?;
+
+        // Exactly one of get and set must be present.
+        if get.is_some() == set.is_some() {
+            return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+        }
+        if get.is_some() && (length.is_some() || pin_uv_auth_param.is_some()) {
+            return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+        }
+
+        Ok(AuthenticatorLargeBlobsParameters {
+            get,
+            set,
+            offset,
+            length,
+            pin_uv_auth_param,
+            pin_uv_auth_protocol,
+        })
+    }
+}
+
+#[cfg(feature = "with_ctap2_1")]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug, PartialEq))]
+pub struct AuthenticatorConfigParameters {
+    pub sub_command: ConfigSubCommand,
+    pub sub_command_params: Option<BTreeMap<cbor::KeyType, cbor::Value>>,
+    pub pin_uv_auth_param: Option<Vec<u8>>,
+    pub pin_uv_auth_protocol: Option<u64>,
+}
+
+#[cfg(feature = "with_ctap2_1")]
+impl TryFrom<cbor::Value> for AuthenticatorConfigParameters {
+    type Error = Ctap2StatusCode;
+
+    fn try_from(cbor_value: cbor::Value) -> Result<Self, Ctap2StatusCode> {
+        destructure_cbor_map! {
+            let {
+                1 => sub_command,
+                2 => sub_command_params,
+                3 => pin_uv_auth_protocol,
+                4 => pin_uv_auth_param,
+            } = extract_map(cbor_value)?;
+        }
+
+        let sub_command = ConfigSubCommand::try_from(ok_or_missing(sub_command)?)?;
+        let sub_command_params = sub_command_params.map(extract_map).transpose()?;
+        let pin_uv_auth_param = pin_uv_auth_param.map(extract_byte_string).transpose()?;
+        let pin_uv_auth_protocol = pin_uv_auth_protocol.map(extract_unsigned).transpose()?;
+
+        Ok(AuthenticatorConfigParameters {
+            sub_command,
+            sub_command_params,
+            pin_uv_auth_param,
+            pin_uv_auth_protocol,
+        })
+    }
+}
+
 #[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug, PartialEq))]
 pub struct AuthenticatorAttestationMaterial {
     pub certificate: Vec<u8>,
     pub private_key: [u8; key_material::ATTESTATION_PRIVATE_KEY_LENGTH],
+    // If present, the material is only used for this relying party.
+    pub rp_id: Option<String>,
+    // If present, replaces the AAGUID of the build. Only valid with batch material.
+    pub aaguid: Option<[u8; key_material::AAGUID_LENGTH]>,
 }
 
 impl TryFrom<cbor::Value> for AuthenticatorAttestationMaterial {
@@ -399,6 +624,8 @@ impl TryFrom<cbor::Value> for AuthenticatorAttestationMaterial {
             let {
                 1 => certificate,
                 2 => private_key,
+                3 => rp_id,
+                4 => aaguid,
             } = extract_map(cbor_value)?;
         }
         let certificate = extract_byte_string(ok_or_missing(certificate)?)?;
@@ -407,16 +634,28 @@ impl TryFrom<cbor::Value> for AuthenticatorAttestationMaterial {
             return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
         }
         let private_key = array_ref!(private_key, 0, key_material::ATTESTATION_PRIVATE_KEY_LENGTH);
+        let rp_id = rp_id.map(extract_text_string).transpose()?;
+        let aaguid = aaguid.map(extract_byte_string).transpose()?;
+        let aaguid = match aaguid {
+            None => None,
+            Some(aaguid) if aaguid.len() == key_material::AAGUID_LENGTH && rp_id.is_none() => {
+                Some(*array_ref!(aaguid, 0, key_material::AAGUID_LENGTH))
+            }
+            Some(_) => return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER),
+        };
         Ok(AuthenticatorAttestationMaterial {
             certificate,
             private_key: *private_key,
+            rp_id,
+            aaguid,
         })
     }
 }
 
 #[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug, PartialEq))]
 pub struct AuthenticatorVendorConfigureParameters {
-    pub lockdown: bool,
+    // If present, the firmware protection is raised to this level.
+    pub lockdown: Option<ProtectionLevel>,
     pub attestation_material: Option<AuthenticatorAttestationMaterial>,
 }
 
@@ -428,9 +667,24 @@ impl TryFrom<cbor::Value> for AuthenticatorVendorConfigureParameters {
             let {
                 1 => lockdown,
                 2 => attestation_material,
+                3 => protection_level,
             } = extract_map(cbor_value)?;
         }
         let lockdown = lockdown.map_or(Ok(false), extract_bool)?;
+        let protection_level = protection_level
+            .map(extract_unsigned)
+            .transpose()?
+            .map(|level| {
+                usize::try_from(level).map_or(ProtectionLevel::Unknown, ProtectionLevel::from)
+            });
+        let lockdown = match (lockdown, protection_level) {
+            (false, None) => None,
+            (true, None) => Some(ProtectionLevel::FullyLocked),
+            (true, Some(ProtectionLevel::Unknown)) | (false, Some(_)) => {
+                return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+            }
+            (true, Some(level)) => Some(level),
+        };
         let attestation_material = attestation_material
             .map(AuthenticatorAttestationMaterial::try_from)
             .transpose()?;
@@ -441,6 +695,138 @@ impl TryFrom<cbor::Value> for AuthenticatorVendorConfigureParameters {
     }
 }
 
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug, PartialEq))]
+pub struct AuthenticatorVendorRpIdPolicyParameters {
+    // Whether the RP IDs are an allow list, instead of a deny list.
+    pub allow: bool,
+    pub rp_ids: Vec<String>,
+    pub pin_uv_auth_protocol: Option<u64>,
+    pub pin_uv_auth_param: Option<Vec<u8>>,
+}
+
+impl TryFrom<cbor::Value> for AuthenticatorVendorRpIdPolicyParameters {
+    type Error = Ctap2StatusCode;
+
+    fn try_from(cbor_value: cbor::Value) -> Result<Self, Ctap2StatusCode> {
+        destructure_cbor_map! {
+            let {
+                1 => allow,
+                2 => rp_ids,
+                3 => pin_uv_auth_protocol,
+                4 => pin_uv_auth_param,
+            } = extract_map(cbor_value)?;
+        }
+        let allow = extract_bool(ok_or_missing(allow)?)?;
+        let rp_ids = extract_array(ok_or_missing(rp_ids)?)?
+            .into_iter()
+            .map(extract_text_string)
+            .collect::<Result<Vec<String>, Ctap2StatusCode>>()?;
+        let pin_uv_auth_protocol = pin_uv_auth_protocol.map(extract_unsigned).transpose()?;
+        let pin_uv_auth_param = pin_uv_auth_param.map(extract_byte_string).transpose()?;
+        Ok(AuthenticatorVendorRpIdPolicyParameters {
+            allow,
+            rp_ids,
+            pin_uv_auth_protocol,
+            pin_uv_auth_param,
+        })
+    }
+}
+
+#[derive(Default)]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug, PartialEq))]
+pub struct AuthenticatorVendorFactoryResetParameters {
+    pub pin_uv_auth_protocol: Option<u64>,
+    pub pin_uv_auth_param: Option<Vec<u8>>,
+}
+
+impl TryFrom<cbor::Value> for AuthenticatorVendorFactoryResetParameters {
+    type Error = Ctap2StatusCode;
+
+    fn try_from(cbor_value: cbor::Value) -> Result<Self, Ctap2StatusCode> {
+        destructure_cbor_map! {
+            let {
+                1 => pin_uv_auth_protocol,
+                2 => pin_uv_auth_param,
+            } = extract_map(cbor_value)?;
+        }
+
+        let pin_uv_auth_protocol = pin_uv_auth_protocol.map(extract_unsigned).transpose()?;
+        let pin_uv_auth_param = pin_uv_auth_param.map(extract_byte_string).transpose()?;
+
+        Ok(AuthenticatorVendorFactoryResetParameters {
+            pin_uv_auth_protocol,
+            pin_uv_auth_param,
+        })
+    }
+}
+
+#[cfg(feature = "with_ctap2_1")]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug, PartialEq))]
+pub struct AuthenticatorVendorCredentialBackupParameters {
+    pub sub_command: CredentialBackupSubCommand,
+    // The encrypted bundle, only used for imports.
+    pub bundle: Option<Vec<u8>>,
+    pub pin_uv_auth_protocol: Option<u64>,
+    pub pin_uv_auth_param: Option<Vec<u8>>,
+}
+
+#[cfg(feature = "with_ctap2_1")]
+impl TryFrom<cbor::Value> for AuthenticatorVendorCredentialBackupParameters {
+    type Error = Ctap2StatusCode;
+
+    fn try_from(cbor_value: cbor::Value) -> Result<Self, Ctap2StatusCode> {
+        destructure_cbor_map! {
+            let {
+                1 => sub_command,
+                2 => bundle,
+                3 => pin_uv_auth_protocol,
+                4 => pin_uv_auth_param,
+            } = extract_map(cbor_value)?;
+        }
+
+        let sub_command = CredentialBackupSubCommand::try_from(ok_or_missing(sub_command)?)?;
+        let bundle = bundle.map(extract_byte_string).transpose()?;
+        let pin_uv_auth_protocol = pin_uv_auth_protocol.map(extract_unsigned).transpose()?;
+        let pin_uv_auth_param = pin_uv_auth_param.map(extract_byte_string).transpose()?;
+
+        Ok(AuthenticatorVendorCredentialBackupParameters {
+            sub_command,
+            bundle,
+            pin_uv_auth_protocol,
+            pin_uv_auth_param,
+        })
+    }
+}
+
+#[cfg(feature = "with_ctap2_1")]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug, PartialEq))]
+pub struct AuthenticatorVendorResetBootCountParameters {
+    pub pin_uv_auth_protocol: Option<u64>,
+    pub pin_uv_auth_param: Option<Vec<u8>>,
+}
+
+#[cfg(feature = "with_ctap2_1")]
+impl TryFrom<cbor::Value> for AuthenticatorVendorResetBootCountParameters {
+    type Error = Ctap2StatusCode;
+
+    fn try_from(cbor_value: cbor::Value) -> Result<Self, Ctap2StatusCode> {
+        destructure_cbor_map! {
+            let {
+                1 => pin_uv_auth_protocol,
+                2 => pin_uv_auth_param,
+            } = extract_map(cbor_value)?;
+        }
+
+        let pin_uv_auth_protocol = pin_uv_auth_protocol.map(extract_unsigned).transpose()?;
+        let pin_uv_auth_param = pin_uv_auth_param.map(extract_byte_string).transpose()?;
+
+        Ok(AuthenticatorVendorResetBootCountParameters {
+            pin_uv_auth_protocol,
+            pin_uv_auth_param,
+        })
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::super::data_formats::{
@@ -471,6 +857,7 @@ mod test {
             5 => cbor_array![],
             8 => vec![0x12, 0x34],
             9 => 1,
+            10 => 2,
         };
         let returned_make_credential_parameters =
             AuthenticatorMakeCredentialParameters::try_from(cbor_value).unwrap();
@@ -504,6 +891,7 @@ mod test {
             options,
             pin_uv_auth_param: Some(vec![0x12, 0x34]),
             pin_uv_auth_protocol: Some(1),
+            enterprise_attestation: Some(2),
         };
 
         assert_eq!(
@@ -610,6 +998,34 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_deserialize_non_canonical_cbor() {
+        let cases = vec![
+            // Duplicate key 0x01.
+            vec![0xA2, 0x01, 0x41, 0xCD, 0x01, 0x41, 0xCD],
+            // Indefinite length map.
+            vec![0xBF, 0x01, 0x41, 0xCD, 0xFF],
+            // Key 0x02 before key 0x01.
+            vec![0xA2, 0x02, 0xA0, 0x01, 0x41, 0xCD],
+            // Key 0x01 with a non-minimal encoding.
+            vec![0xA1, 0x18, 0x01, 0x41, 0xCD],
+        ];
+        for cbor_bytes in cases {
+            for &command in &[
+                Command::AUTHENTICATOR_MAKE_CREDENTIAL,
+                Command::AUTHENTICATOR_GET_ASSERTION,
+                Command::AUTHENTICATOR_CLIENT_PIN,
+            ] {
+                let mut bytes = vec![command];
+                bytes.extend(&cbor_bytes);
+                assert_eq!(
+                    Command::deserialize(&bytes),
+                    Err(Ctap2StatusCode::CTAP2_ERR_INVALID_CBOR)
+                );
+            }
+        }
+    }
+
     #[test]
     fn test_deserialize_get_info() {
         let cbor_bytes = [Command::AUTHENTICATOR_GET_INFO];
@@ -640,6 +1056,283 @@ mod test {
         assert_eq!(command, Ok(Command::AuthenticatorSelection));
     }
 
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_from_cbor_large_blobs_parameters() {
+        // successful get
+        let cbor_value = cbor_map! {
+            1 => 2,
+            3 => 4,
+        };
+        let returned_large_blobs_parameters =
+            AuthenticatorLargeBlobsParameters::try_from(cbor_value).unwrap();
+        let expected_large_blobs_parameters = AuthenticatorLargeBlobsParameters {
+            get: Some(2),
+            set: None,
+            offset: 4,
+            length: None,
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: None,
+        };
+        assert_eq!(
+            returned_large_blobs_parameters,
+            expected_large_blobs_parameters
+        );
+
+        // successful first set
+        let cbor_value = cbor_map! {
+            2 => vec![0x5E],
+            3 => 0,
+            4 => 1,
+            5 => vec![0xA9],
+            6 => 1,
+        };
+        let returned_large_blobs_parameters =
+            AuthenticatorLargeBlobsParameters::try_from(cbor_value).unwrap();
+        let expected_large_blobs_parameters = AuthenticatorLargeBlobsParameters {
+            get: None,
+            set: Some(vec![0x5E]),
+            offset: 0,
+            length: Some(1),
+            pin_uv_auth_param: Some(vec![0xA9]),
+            pin_uv_auth_protocol: Some(1),
+        };
+        assert_eq!(
+            returned_large_blobs_parameters,
+            expected_large_blobs_parameters
+        );
+
+        // missing offset
+        let cbor_value = cbor_map! {
+            1 => 2,
+        };
+        assert_eq!(
+            AuthenticatorLargeBlobsParameters::try_from(cbor_value),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+
+        // both get and set
+        let cbor_value = cbor_map! {
+            1 => 2,
+            2 => vec![0x5E],
+            3 => 0,
+        };
+        assert_eq!(
+            AuthenticatorLargeBlobsParameters::try_from(cbor_value),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+
+        // get with length
+        let cbor_value = cbor_map! {
+            1 => 2,
+            3 => 0,
+            4 => 1,
+        };
+        assert_eq!(
+            AuthenticatorLargeBlobsParameters::try_from(cbor_value),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_from_cbor_config_parameters() {
+        let cbor_value = cbor_map! {
+            1 => ConfigSubCommand::ToggleAlwaysUv,
+            3 => 1,
+            4 => vec![0x9A; 16],
+        };
+        let returned_config_parameters =
+            AuthenticatorConfigParameters::try_from(cbor_value).unwrap();
+        let expected_config_parameters = AuthenticatorConfigParameters {
+            sub_command: ConfigSubCommand::ToggleAlwaysUv,
+            sub_command_params: None,
+            pin_uv_auth_param: Some(vec![0x9A; 16]),
+            pin_uv_auth_protocol: Some(1),
+        };
+        assert_eq!(returned_config_parameters, expected_config_parameters);
+
+        // Unknown subcommands are rejected.
+        let cbor_value = cbor_map! {
+            1 => 0x7F,
+        };
+        assert_eq!(
+            AuthenticatorConfigParameters::try_from(cbor_value),
+            Err(Ctap2StatusCode::CTAP2_ERR_INVALID_SUBCOMMAND)
+        );
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_from_cbor_credential_management_parameters() {
+        let cbor_value = cbor_map! {
+            1 => CredentialManagementSubCommand::DeleteCredential,
+            2 => cbor_map! {
+                0x02 => cbor_map! {
+                    "id" => vec![0x2D; 32],
+                    "type" => "public-key",
+                },
+            },
+            3 => 1,
+            4 => vec![0x9A; 16],
+        };
+        let returned_parameters =
+            AuthenticatorCredentialManagementParameters::try_from(cbor_value).unwrap();
+        let mut sub_command_params = BTreeMap::new();
+        sub_command_params.insert(
+            cbor::KeyType::Unsigned(0x02),
+            cbor_map! {
+                "id" => vec![0x2D; 32],
+                "type" => "public-key",
+            },
+        );
+        let expected_parameters = AuthenticatorCredentialManagementParameters {
+            sub_command: CredentialManagementSubCommand::DeleteCredential,
+            sub_command_params: Some(sub_command_params),
+            pin_uv_auth_protocol: Some(1),
+            pin_uv_auth_param: Some(vec![0x9A; 16]),
+        };
+        assert_eq!(returned_parameters, expected_parameters);
+
+        let cbor_value = cbor_map! {
+            3 => 1,
+        };
+        assert_eq!(
+            AuthenticatorCredentialManagementParameters::try_from(cbor_value),
+            Err(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)
+        );
+    }
+
+    #[test]
+    fn test_deserialize_vendor_factory_reset() {
+        let cbor_bytes = [Command::AUTHENTICATOR_VENDOR_FACTORY_RESET];
+        let command = Command::deserialize(&cbor_bytes);
+        assert_eq!(
+            command,
+            Ok(Command::AuthenticatorVendorFactoryReset(
+                AuthenticatorVendorFactoryResetParameters::default()
+            ))
+        );
+
+        let cbor_value = cbor_map! {
+            1 => 1,
+            2 => vec![0x9A; 16],
+        };
+        let mut cbor_bytes = vec![Command::AUTHENTICATOR_VENDOR_FACTORY_RESET];
+        assert!(cbor::write(cbor_value, &mut cbor_bytes));
+        let command = Command::deserialize(&cbor_bytes);
+        assert_eq!(
+            command,
+            Ok(Command::AuthenticatorVendorFactoryReset(
+                AuthenticatorVendorFactoryResetParameters {
+                    pin_uv_auth_protocol: Some(1),
+                    pin_uv_auth_param: Some(vec![0x9A; 16]),
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_deserialize_vendor_store_health() {
+        let cbor_bytes = [Command::AUTHENTICATOR_VENDOR_STORE_HEALTH];
+        let command = Command::deserialize(&cbor_bytes);
+        assert_eq!(command, Ok(Command::AuthenticatorVendorStoreHealth));
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_deserialize_vendor_credential_backup() {
+        let cbor_value = cbor_map! {
+            1 => CredentialBackupSubCommand::Import as u64,
+            2 => vec![0xB0, 0x01],
+            3 => 1,
+            4 => vec![0x9A; 16],
+        };
+        let mut cbor_bytes = vec![Command::AUTHENTICATOR_VENDOR_CREDENTIAL_BACKUP];
+        assert!(cbor::write(cbor_value, &mut cbor_bytes));
+        let command = Command::deserialize(&cbor_bytes);
+        assert_eq!(
+            command,
+            Ok(Command::AuthenticatorVendorCredentialBackup(
+                AuthenticatorVendorCredentialBackupParameters {
+                    sub_command: CredentialBackupSubCommand::Import,
+                    bundle: Some(vec![0xB0, 0x01]),
+                    pin_uv_auth_protocol: Some(1),
+                    pin_uv_auth_param: Some(vec![0x9A; 16]),
+                }
+            ))
+        );
+
+        let cbor_value = cbor_map! {
+            2 => vec![0xB0, 0x01],
+        };
+        assert_eq!(
+            AuthenticatorVendorCredentialBackupParameters::try_from(cbor_value),
+            Err(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)
+        );
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_deserialize_vendor_reset_boot_count() {
+        let cbor_value = cbor_map! {
+            1 => 1,
+            2 => vec![0x9A; 16],
+        };
+        let mut cbor_bytes = vec![Command::AUTHENTICATOR_VENDOR_RESET_BOOT_COUNT];
+        assert!(cbor::write(cbor_value, &mut cbor_bytes));
+        let command = Command::deserialize(&cbor_bytes);
+        assert_eq!(
+            command,
+            Ok(Command::AuthenticatorVendorResetBootCount(
+                AuthenticatorVendorResetBootCountParameters {
+                    pin_uv_auth_protocol: Some(1),
+                    pin_uv_auth_param: Some(vec![0x9A; 16]),
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_deserialize_vendor_rp_id_policy() {
+        let cbor_value = cbor_map! {
+            1 => true,
+            2 => cbor_array!["example.com"],
+            3 => 1,
+            4 => vec![0x9A; 16],
+        };
+        let mut cbor_bytes = vec![Command::AUTHENTICATOR_VENDOR_RP_ID_POLICY];
+        assert!(cbor::write(cbor_value, &mut cbor_bytes));
+        let command = Command::deserialize(&cbor_bytes);
+        assert_eq!(
+            command,
+            Ok(Command::AuthenticatorVendorRpIdPolicy(
+                AuthenticatorVendorRpIdPolicyParameters {
+                    allow: true,
+                    rp_ids: vec![String::from("example.com")],
+                    pin_uv_auth_protocol: Some(1),
+                    pin_uv_auth_param: Some(vec![0x9A; 16]),
+                }
+            ))
+        );
+
+        let cbor_value = cbor_map! {
+            2 => cbor_array!["example.com"],
+        };
+        assert_eq!(
+            AuthenticatorVendorRpIdPolicyParameters::try_from(cbor_value),
+            Err(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)
+        );
+        let cbor_value = cbor_map! {
+            1 => false,
+            2 => cbor_array![0x55],
+        };
+        assert_eq!(
+            AuthenticatorVendorRpIdPolicyParameters::try_from(cbor_value),
+            Err(Ctap2StatusCode::CTAP2_ERR_CBOR_UNEXPECTED_TYPE)
+        );
+    }
+
     #[test]
     fn test_vendor_configure() {
         // Incomplete command
@@ -653,12 +1346,51 @@ mod test {
             command,
             Ok(Command::AuthenticatorVendorConfigure(
                 AuthenticatorVendorConfigureParameters {
-                    lockdown: true,
+                    lockdown: Some(ProtectionLevel::FullyLocked),
                     attestation_material: None
                 }
             ))
         );
 
+        // Each supported protection level is accepted.
+        for level in &[
+            ProtectionLevel::NoProtection,
+            ProtectionLevel::JtagDisabled,
+            ProtectionLevel::FullyLocked,
+        ] {
+            let cbor_value = cbor_map! {
+                1 => true,
+                3 => *level as u64,
+            };
+            assert_eq!(
+                AuthenticatorVendorConfigureParameters::try_from(cbor_value),
+                Ok(AuthenticatorVendorConfigureParameters {
+                    lockdown: Some(*level),
+                    attestation_material: None,
+                })
+            );
+        }
+
+        // Unknown protection level
+        let cbor_value = cbor_map! {
+            1 => true,
+            3 => 0x03,
+        };
+        assert_eq!(
+            AuthenticatorVendorConfigureParameters::try_from(cbor_value),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+
+        // Protection level without lockdown
+        let cbor_value = cbor_map! {
+            1 => false,
+            3 => ProtectionLevel::JtagDisabled as u64,
+        };
+        assert_eq!(
+            AuthenticatorVendorConfigureParameters::try_from(cbor_value),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+
         let dummy_cert = [0xddu8; 20];
         let dummy_pkey = [0x41u8; key_material::ATTESTATION_PRIVATE_KEY_LENGTH];
 
@@ -710,12 +1442,83 @@ mod test {
         assert_eq!(
             AuthenticatorVendorConfigureParameters::try_from(cbor_value),
             Ok(AuthenticatorVendorConfigureParameters {
-                lockdown: false,
+                lockdown: None,
+                attestation_material: Some(AuthenticatorAttestationMaterial {
+                    certificate: dummy_cert.to_vec(),
+                    private_key: dummy_pkey,
+                    rp_id: None,
+                    aaguid: None,
+                })
+            })
+        );
+
+        // Valid, for a single relying party
+        let cbor_value = cbor_map! {
+            2 => cbor_map! {
+                1 => dummy_cert,
+                2 => dummy_pkey,
+                3 => "example.com",
+            }
+        };
+        assert_eq!(
+            AuthenticatorVendorConfigureParameters::try_from(cbor_value),
+            Ok(AuthenticatorVendorConfigureParameters {
+                lockdown: None,
+                attestation_material: Some(AuthenticatorAttestationMaterial {
+                    certificate: dummy_cert.to_vec(),
+                    private_key: dummy_pkey,
+                    rp_id: Some(String::from("example.com")),
+                    aaguid: None,
+                })
+            })
+        );
+
+        // Valid, with an AAGUID for the batch
+        let cbor_value = cbor_map! {
+            2 => cbor_map! {
+                1 => dummy_cert,
+                2 => dummy_pkey,
+                4 => [0xAA; 16],
+            }
+        };
+        assert_eq!(
+            AuthenticatorVendorConfigureParameters::try_from(cbor_value),
+            Ok(AuthenticatorVendorConfigureParameters {
+                lockdown: None,
                 attestation_material: Some(AuthenticatorAttestationMaterial {
                     certificate: dummy_cert.to_vec(),
-                    private_key: dummy_pkey
+                    private_key: dummy_pkey,
+                    rp_id: None,
+                    aaguid: Some([0xAA; 16]),
                 })
             })
         );
+
+        // Invalid AAGUID length
+        let cbor_value = cbor_map! {
+            2 => cbor_map! {
+                1 => dummy_cert,
+                2 => dummy_pkey,
+                4 => [0xAA; 15],
+            }
+        };
+        assert_eq!(
+            AuthenticatorVendorConfigureParameters::try_from(cbor_value),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+
+        // Invalid, the AAGUID can't depend on the relying party
+        let cbor_value = cbor_map! {
+            2 => cbor_map! {
+                1 => dummy_cert,
+                2 => dummy_pkey,
+                3 => "example.com",
+                4 => [0xAA; 16],
+            }
+        };
+        assert_eq!(
+            AuthenticatorVendorConfigureParameters::try_from(cbor_value),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
     }
 }
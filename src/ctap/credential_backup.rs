@@ -0,0 +1,514 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::command::AuthenticatorVendorCredentialBackupParameters;
+use super::data_formats::{extract_array, CredentialBackupSubCommand, PublicKeyCredentialSource};
+use super::pin_protocol_v1::{
+    aes256_cbc_decrypt, aes256_cbc_encrypt, check_pin_uv_auth_protocol, PinPermission,
+    PinProtocolV1,
+};
+use super::response::{AuthenticatorVendorCredentialBackupResponse, ResponseData};
+use super::status_code::Ctap2StatusCode;
+use super::storage::PersistentStore;
+use alloc::vec;
+use alloc::vec::Vec;
+use arrayref::array_ref;
+use core::convert::TryFrom;
+use crypto::hkdf::hkdf_empty_salt_256;
+use crypto::hmac::{hmac_256, verify_hmac_256};
+use crypto::rng256::Rng256;
+use crypto::sha256::Sha256;
+use crypto::util::Zeroizing;
+
+/// Version of the bundle format, stored in its first byte.
+const BUNDLE_VERSION: u8 = 0x01;
+const SALT_LENGTH: usize = 32;
+const IV_LENGTH: usize = 16;
+const HEADER_LENGTH: usize = 1 + SALT_LENGTH + IV_LENGTH;
+const TAG_LENGTH: usize = 32;
+/// The space a bundle leaves in a message for the status byte and its CBOR overhead.
+const RESPONSE_OVERHEAD: usize = 8;
+/// Number of PBKDF2 iterations, each of which an offline PIN guess has to repeat.
+const KDF_ITERATIONS: usize = 10_000;
+
+/// Processes the credential backup vendor command.
+///
+/// Both subcommands require a PIN/UV auth token with the credential management permission, and
+/// cover all relying parties. The bundle looks like this:
+/// - the version byte,
+/// - a random salt and IV,
+/// - the AES-256-CBC encrypted array of resident credentials,
+/// - an HMAC-SHA256 tag over all of the above.
+///
+/// Both keys are derived from the PIN hash and the salt. So a bundle can only be imported by an
+/// authenticator with the same PIN. The derivation is stretched with PBKDF2, but the bundle is
+/// still only as strong as the PIN against offline guessing.
+///
+/// The exported bundle must fit in a message of `max_msg_size`, the size negotiated with the
+/// transport.
+pub fn process_credential_backup(
+    rng: &mut impl Rng256,
+    persistent_store: &mut PersistentStore,
+    pin_protocol_v1: &mut PinProtocolV1,
+    backup_params: AuthenticatorVendorCredentialBackupParameters,
+    max_msg_size: usize,
+) -> Result<ResponseData, Ctap2StatusCode> {
+    let AuthenticatorVendorCredentialBackupParameters {
+        sub_command,
+        bundle,
+        pin_uv_auth_protocol,
+        pin_uv_auth_param,
+    } = backup_params;
+
+    let pin_uv_auth_param = pin_uv_auth_param.ok_or(Ctap2StatusCode::CTAP2_ERR_PIN_REQUIRED)?;
+    let pin_uv_auth_protocol = check_pin_uv_auth_protocol(pin_uv_auth_protocol)?;
+    // Like for credential management: verify(pinUvAuthToken, uint8(subCommand) || bundle,
+    // pinUvAuthParam)
+    let mut message = vec![sub_command as u8];
+    if let Some(bundle) = &bundle {
+        message.extend(bundle);
+    }
+    if !pin_protocol_v1.verify_pin_auth_token(&message, &pin_uv_auth_param, pin_uv_auth_protocol) {
+        return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID);
+    }
+    pin_protocol_v1.has_permission(PinPermission::CredentialManagement)?;
+    pin_protocol_v1.has_no_rp_id_permission()?;
+    let pin_hash = persistent_store
+        .pin_hash()?
+        .ok_or(Ctap2StatusCode::CTAP2_ERR_PIN_NOT_SET)?;
+
+    match sub_command {
+        CredentialBackupSubCommand::Export => {
+            process_export(rng, persistent_store, &pin_hash, max_msg_size)
+        }
+        CredentialBackupSubCommand::Import => process_import(
+            persistent_store,
+            &pin_hash,
+            bundle.ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?,
+        ),
+    }
+}
+
+/// Derives the AES and HMAC keys of a bundle.
+///
+/// The PIN hash is stretched with PBKDF2-HMAC-SHA256 first. A single block of output is needed:
+/// U_1 = HMAC(PIN hash, salt || INT(1)), U_i = HMAC(PIN hash, U_{i-1}), and the secret is the XOR
+/// of all U_i.
+fn derive_keys(pin_hash: &[u8], salt: &[u8]) -> (Zeroizing<[u8; 32]>, Zeroizing<[u8; 32]>) {
+    let mut first_message = salt.to_vec();
+    first_message.extend(&[0x00, 0x00, 0x00, 0x01]);
+    let mut block = Zeroizing(hmac_256::<Sha256>(pin_hash, &first_message));
+    let mut secret = Zeroizing(*block);
+    for _ in 1..KDF_ITERATIONS {
+        *block = hmac_256::<Sha256>(pin_hash, &*block);
+        for (secret_byte, block_byte) in secret.iter_mut().zip(block.iter()) {
+            *secret_byte ^= block_byte;
+        }
+    }
+    let aes_key = hkdf_empty_salt_256::<Sha256>(&*secret, b"OpenSK backup AES key");
+    let hmac_key = hkdf_empty_salt_256::<Sha256>(&*secret, b"OpenSK backup HMAC key");
+    (Zeroizing(aes_key), Zeroizing(hmac_key))
+}
+
+fn process_export(
+    rng: &mut impl Rng256,
+    persistent_store: &PersistentStore,
+    pin_hash: &[u8],
+    max_msg_size: usize,
+) -> Result<ResponseData, Ctap2StatusCode> {
+    let credentials = persistent_store
+        .credentials()?
+        .into_iter()
+        .map(cbor::Value::from)
+        .collect();
+    // Exports that fit don't grow the buffer, so that no copy of the private keys is left behind.
+    let mut plaintext = Zeroizing(Vec::with_capacity(max_msg_size));
+    if !cbor::write(cbor::Value::Array(credentials), &mut plaintext) {
+        return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_RESPONSE_CANNOT_WRITE_CBOR);
+    }
+    // ISO/IEC 7816-4 padding: a 0x80 byte followed by zeros up to the block size.
+    let padded_length = (plaintext.len() / 16 + 1) * 16;
+    if HEADER_LENGTH + padded_length + TAG_LENGTH + RESPONSE_OVERHEAD > max_msg_size {
+        return Err(Ctap2StatusCode::CTAP2_ERR_REQUEST_TOO_LARGE);
+    }
+    plaintext.push(0x80);
+    plaintext.resize(padded_length, 0x00);
+
+    let salt = rng.gen_uniform_u8x32();
+    let random_bytes = rng.gen_uniform_u8x32();
+    let iv = *array_ref![random_bytes, 0, IV_LENGTH];
+    let (aes_key, hmac_key) = derive_keys(pin_hash, &salt);
+    let mut bundle = vec![BUNDLE_VERSION];
+    bundle.extend(&salt);
+    bundle.extend(&iv);
+    bundle.extend(aes256_cbc_encrypt(&aes_key, iv, &plaintext));
+    let tag = hmac_256::<Sha256>(&*hmac_key, &bundle);
+    bundle.extend(&tag);
+    Ok(ResponseData::AuthenticatorVendorCredentialBackup(
+        AuthenticatorVendorCredentialBackupResponse {
+            bundle: Some(bundle),
+            num_imported: None,
+        },
+    ))
+}
+
+fn process_import(
+    persistent_store: &mut PersistentStore,
+    pin_hash: &[u8],
+    bundle: Vec<u8>,
+) -> Result<ResponseData, Ctap2StatusCode> {
+    if bundle.len() < HEADER_LENGTH + TAG_LENGTH || bundle[0] != BUNDLE_VERSION {
+        return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+    }
+    let (authenticated, tag) = bundle.split_at(bundle.len() - TAG_LENGTH);
+    let salt = &authenticated[1..1 + SALT_LENGTH];
+    let iv = *array_ref![authenticated, 1 + SALT_LENGTH, IV_LENGTH];
+    let (aes_key, hmac_key) = derive_keys(pin_hash, salt);
+    // Bundles exported with another PIN fail here, like tampered ones.
+    if !verify_hmac_256::<Sha256>(&*hmac_key, authenticated, array_ref![tag, 0, TAG_LENGTH]) {
+        return Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE);
+    }
+    let mut plaintext = Zeroizing(
+        aes256_cbc_decrypt(&aes_key, iv, &authenticated[HEADER_LENGTH..])
+            .ok_or(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)?,
+    );
+    let padding_start = plaintext
+        .iter()
+        .rposition(|&byte| byte != 0x00)
+        .filter(|&index| plaintext[index] == 0x80)
+        .ok_or(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)?;
+    plaintext.truncate(padding_start);
+
+    // All credentials are parsed before storing any, so that a malformed bundle imports nothing.
+    let credentials = extract_array(cbor::read(&plaintext)?)?
+        .into_iter()
+        .map(PublicKeyCredentialSource::try_from)
+        .collect::<Result<Vec<_>, Ctap2StatusCode>>()?;
+    let mut new_credentials = Vec::new();
+    for credential in credentials {
+        match persistent_store.get_credential(&credential.credential_id) {
+            // The credential is already there, for example from a previous import.
+            Ok(_) => continue,
+            Err(Ctap2StatusCode::CTAP2_ERR_NO_CREDENTIALS) => (),
+            Err(error) => return Err(error),
+        }
+        new_credentials.push(credential);
+    }
+    let num_imported = new_credentials.len() as u64;
+    // Each batch is written atomically. If the import is interrupted, retrying it skips the
+    // credentials of the completed batches.
+    let max_updates = persistent_store.max_updates_per_transaction();
+    while !new_credentials.is_empty() {
+        let remaining =
+            new_credentials.split_off(core::cmp::min(max_updates, new_credentials.len()));
+        persistent_store.store_credentials(new_credentials)?;
+        new_credentials = remaining;
+    }
+    Ok(ResponseData::AuthenticatorVendorCredentialBackup(
+        AuthenticatorVendorCredentialBackupResponse {
+            bundle: None,
+            num_imported: Some(num_imported),
+        },
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::data_formats::PublicKeyCredentialType;
+    use super::super::pin_protocol_v1::{PIN_AUTH_LENGTH, PIN_PROTOCOL_V1};
+    use super::*;
+    use crate::ctap::MAX_MSG_SIZE;
+    use alloc::string::String;
+    use crypto::rng256::ThreadRng256;
+
+    const PIN_UV_AUTH_TOKEN: [u8; 32] = [0x88; 32];
+    const PIN_HASH: [u8; PIN_AUTH_LENGTH] = [0x55; PIN_AUTH_LENGTH];
+
+    fn create_credential_source(
+        rng: &mut ThreadRng256,
+        rp_id: &str,
+        user_handle: Vec<u8>,
+    ) -> PublicKeyCredentialSource {
+        PublicKeyCredentialSource {
+            key_type: PublicKeyCredentialType::PublicKey,
+            credential_id: rng.gen_uniform_u8x32().to_vec(),
+            private_key: crypto::ecdsa::SecKey::gensk(rng),
+            rp_id: String::from(rp_id),
+            user_handle,
+            user_display_name: None,
+            cred_protect_policy: None,
+            creation_order: 0,
+            user_name: None,
+            user_icon: None,
+            cred_blob: None,
+            signature_counter: None,
+            large_blob_key: None,
+        }
+    }
+
+    // Simulates an authenticator with a PIN and a PIN/UV auth token.
+    fn create_authenticator(
+        rng: &mut ThreadRng256,
+        pin_hash: &[u8; PIN_AUTH_LENGTH],
+    ) -> (PersistentStore, PinProtocolV1) {
+        let mut persistent_store = PersistentStore::new(rng);
+        persistent_store.set_pin_hash(pin_hash).unwrap();
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(rng);
+        let pin_protocol_v1 = PinProtocolV1::new_test(key_agreement_key, PIN_UV_AUTH_TOKEN);
+        (persistent_store, pin_protocol_v1)
+    }
+
+    // Builds the parameters, authenticated with PIN_UV_AUTH_TOKEN.
+    fn create_params(
+        sub_command: CredentialBackupSubCommand,
+        bundle: Option<Vec<u8>>,
+    ) -> AuthenticatorVendorCredentialBackupParameters {
+        let mut message = vec![sub_command as u8];
+        if let Some(bundle) = &bundle {
+            message.extend(bundle);
+        }
+        let pin_uv_auth_param = hmac_256::<Sha256>(&PIN_UV_AUTH_TOKEN, &message)[..16].to_vec();
+        AuthenticatorVendorCredentialBackupParameters {
+            sub_command,
+            bundle,
+            pin_uv_auth_protocol: Some(PIN_PROTOCOL_V1),
+            pin_uv_auth_param: Some(pin_uv_auth_param),
+        }
+    }
+
+    fn export(
+        rng: &mut ThreadRng256,
+        persistent_store: &mut PersistentStore,
+        pin_protocol_v1: &mut PinProtocolV1,
+    ) -> Vec<u8> {
+        let params = create_params(CredentialBackupSubCommand::Export, None);
+        match process_credential_backup(
+            rng,
+            persistent_store,
+            pin_protocol_v1,
+            params,
+            MAX_MSG_SIZE,
+        ) {
+            Ok(ResponseData::AuthenticatorVendorCredentialBackup(response)) => {
+                response.bundle.unwrap()
+            }
+            _ => panic!("Invalid response type"),
+        }
+    }
+
+    fn import(
+        rng: &mut ThreadRng256,
+        persistent_store: &mut PersistentStore,
+        pin_protocol_v1: &mut PinProtocolV1,
+        bundle: Vec<u8>,
+    ) -> Result<ResponseData, Ctap2StatusCode> {
+        let params = create_params(CredentialBackupSubCommand::Import, Some(bundle));
+        process_credential_backup(rng, persistent_store, pin_protocol_v1, params, MAX_MSG_SIZE)
+    }
+
+    fn imported_response(num_imported: u64) -> Result<ResponseData, Ctap2StatusCode> {
+        Ok(ResponseData::AuthenticatorVendorCredentialBackup(
+            AuthenticatorVendorCredentialBackupResponse {
+                bundle: None,
+                num_imported: Some(num_imported),
+            },
+        ))
+    }
+
+    #[test]
+    fn test_export_import_round_trip() {
+        let mut rng = ThreadRng256 {};
+        let (mut source_store, mut source_pin_protocol) = create_authenticator(&mut rng, &PIN_HASH);
+        let mut exported_credentials = vec![];
+        for user_handle in 0..2 {
+            let credential = create_credential_source(&mut rng, "example.com", vec![user_handle]);
+            exported_credentials.push(credential.clone());
+            assert!(source_store.store_credential(credential).is_ok());
+        }
+        let bundle = export(&mut rng, &mut source_store, &mut source_pin_protocol);
+        // The private keys don't appear in clear.
+        for credential in &exported_credentials {
+            let mut private_key = [0u8; 32];
+            credential.private_key.to_bytes(&mut private_key);
+            assert!(!bundle.windows(32).any(|window| window == private_key));
+        }
+
+        let (mut target_store, mut target_pin_protocol) = create_authenticator(&mut rng, &PIN_HASH);
+        let own_credential = create_credential_source(&mut rng, "other.com", vec![0x1D]);
+        assert!(target_store.store_credential(own_credential).is_ok());
+        assert_eq!(
+            import(
+                &mut rng,
+                &mut target_store,
+                &mut target_pin_protocol,
+                bundle.clone()
+            ),
+            imported_response(2)
+        );
+        assert_eq!(target_store.count_credentials().unwrap(), 3);
+        for credential in exported_credentials {
+            assert_eq!(
+                target_store.get_credential(&credential.credential_id),
+                Ok(credential)
+            );
+        }
+
+        // Importing the same bundle again skips the duplicates.
+        assert_eq!(
+            import(
+                &mut rng,
+                &mut target_store,
+                &mut target_pin_protocol,
+                bundle
+            ),
+            imported_response(0)
+        );
+        assert_eq!(target_store.count_credentials().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_import_with_other_pin() {
+        let mut rng = ThreadRng256 {};
+        let (mut source_store, mut source_pin_protocol) = create_authenticator(&mut rng, &PIN_HASH);
+        let credential = create_credential_source(&mut rng, "example.com", vec![0x1D]);
+        assert!(source_store.store_credential(credential).is_ok());
+        let bundle = export(&mut rng, &mut source_store, &mut source_pin_protocol);
+
+        let other_pin_hash = [0x66; PIN_AUTH_LENGTH];
+        let (mut target_store, mut target_pin_protocol) =
+            create_authenticator(&mut rng, &other_pin_hash);
+        assert_eq!(
+            import(
+                &mut rng,
+                &mut target_store,
+                &mut target_pin_protocol,
+                bundle
+            ),
+            Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE)
+        );
+        assert_eq!(target_store.count_credentials().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_import_tampered_bundle() {
+        let mut rng = ThreadRng256 {};
+        let (mut source_store, mut source_pin_protocol) = create_authenticator(&mut rng, &PIN_HASH);
+        let credential = create_credential_source(&mut rng, "example.com", vec![0x1D]);
+        assert!(source_store.store_credential(credential).is_ok());
+        let bundle = export(&mut rng, &mut source_store, &mut source_pin_protocol);
+
+        let (mut target_store, mut target_pin_protocol) = create_authenticator(&mut rng, &PIN_HASH);
+        let mut tampered_bundle = bundle.clone();
+        tampered_bundle[HEADER_LENGTH] ^= 0x01;
+        assert_eq!(
+            import(
+                &mut rng,
+                &mut target_store,
+                &mut target_pin_protocol,
+                tampered_bundle
+            ),
+            Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE)
+        );
+        let truncated_bundle = bundle[..HEADER_LENGTH + TAG_LENGTH - 1].to_vec();
+        assert_eq!(
+            import(
+                &mut rng,
+                &mut target_store,
+                &mut target_pin_protocol,
+                truncated_bundle
+            ),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+        assert_eq!(target_store.count_credentials().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_export_too_large() {
+        let mut rng = ThreadRng256 {};
+        let (mut persistent_store, mut pin_protocol_v1) = create_authenticator(&mut rng, &PIN_HASH);
+        let credential = create_credential_source(&mut rng, "example.com", vec![0x1D]);
+        assert!(persistent_store.store_credential(credential).is_ok());
+        let bundle = export(&mut rng, &mut persistent_store, &mut pin_protocol_v1);
+
+        // The response must fit in a message.
+        let max_msg_size = bundle.len() + RESPONSE_OVERHEAD;
+        let params = create_params(CredentialBackupSubCommand::Export, None);
+        assert!(process_credential_backup(
+            &mut rng,
+            &mut persistent_store,
+            &mut pin_protocol_v1,
+            params,
+            max_msg_size
+        )
+        .is_ok());
+        let params = create_params(CredentialBackupSubCommand::Export, None);
+        assert_eq!(
+            process_credential_backup(
+                &mut rng,
+                &mut persistent_store,
+                &mut pin_protocol_v1,
+                params,
+                max_msg_size - 1
+            ),
+            Err(Ctap2StatusCode::CTAP2_ERR_REQUEST_TOO_LARGE)
+        );
+    }
+
+    #[test]
+    fn test_backup_pin_auth() {
+        let mut rng = ThreadRng256 {};
+        let (mut persistent_store, mut pin_protocol_v1) = create_authenticator(&mut rng, &PIN_HASH);
+
+        let mut params = create_params(CredentialBackupSubCommand::Export, None);
+        params.pin_uv_auth_param = None;
+        assert_eq!(
+            process_credential_backup(
+                &mut rng,
+                &mut persistent_store,
+                &mut pin_protocol_v1,
+                params,
+                MAX_MSG_SIZE
+            ),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_REQUIRED)
+        );
+
+        // The PIN auth covers the bundle.
+        let mut params = create_params(CredentialBackupSubCommand::Import, Some(vec![0x01]));
+        params.bundle = Some(vec![0x02]);
+        assert_eq!(
+            process_credential_backup(
+                &mut rng,
+                &mut persistent_store,
+                &mut pin_protocol_v1,
+                params,
+                MAX_MSG_SIZE
+            ),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+        );
+
+        // The token lacks the credential management permission.
+        pin_protocol_v1.set_permissions(PinPermission::GetAssertion as u8);
+        let params = create_params(CredentialBackupSubCommand::Export, None);
+        assert_eq!(
+            process_credential_backup(
+                &mut rng,
+                &mut persistent_store,
+                &mut pin_protocol_v1,
+                params,
+                MAX_MSG_SIZE
+            ),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+        );
+    }
+}
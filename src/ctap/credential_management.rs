@@ -0,0 +1,462 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::command::AuthenticatorCredentialManagementParameters;
+use super::data_formats::{
+    CredentialManagementSubCommand, CredentialManagementSubCommandParameters,
+    PublicKeyCredentialDescriptor, PublicKeyCredentialUserEntity,
+};
+use super::pin_protocol_v1::{check_pin_uv_auth_protocol, PinPermission, PinProtocolV1};
+use super::response::{AuthenticatorCredentialManagementResponse, ResponseData};
+use super::status_code::Ctap2StatusCode;
+use super::storage::PersistentStore;
+use super::{truncate_to_char_boundary, MAX_USER_INFO_LENGTH};
+use alloc::string::{String, ToString};
+use alloc::vec;
+use core::convert::TryFrom;
+
+/// Processes the credential management command.
+///
+/// Every subcommand requires a PIN/UV auth token with the credential management permission.
+pub fn process_credential_management(
+    persistent_store: &mut PersistentStore,
+    pin_protocol_v1: &mut PinProtocolV1,
+    cred_management_params: AuthenticatorCredentialManagementParameters,
+) -> Result<ResponseData, Ctap2StatusCode> {
+    let AuthenticatorCredentialManagementParameters {
+        sub_command,
+        sub_command_params,
+        pin_uv_auth_protocol,
+        pin_uv_auth_param,
+    } = cred_management_params;
+
+    let pin_uv_auth_param = pin_uv_auth_param.ok_or(Ctap2StatusCode::CTAP2_ERR_PIN_REQUIRED)?;
+    let pin_uv_auth_protocol = check_pin_uv_auth_protocol(pin_uv_auth_protocol)?;
+    // From CTAP2.1: "verify(pinUvAuthToken, uint8(subCommand) || subCommandParams,
+    // pinUvAuthParam)"
+    let mut message = vec![sub_command as u8];
+    if let Some(sub_command_params) = sub_command_params.clone() {
+        if !cbor::write(cbor::Value::Map(sub_command_params), &mut message) {
+            return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_RESPONSE_CANNOT_WRITE_CBOR);
+        }
+    }
+    if !pin_protocol_v1.verify_pin_auth_token(&message, &pin_uv_auth_param, pin_uv_auth_protocol) {
+        return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID);
+    }
+    pin_protocol_v1.has_permission(PinPermission::CredentialManagement)?;
+
+    let sub_command_params = CredentialManagementSubCommandParameters::try_from(cbor::Value::Map(
+        sub_command_params.unwrap_or_default(),
+    ))?;
+    match sub_command {
+        CredentialManagementSubCommand::GetCredsMetadata => {
+            // The metadata covers all relying parties.
+            pin_protocol_v1.has_no_rp_id_permission()?;
+            process_get_creds_metadata(persistent_store)
+        }
+        CredentialManagementSubCommand::DeleteCredential => process_delete_credential(
+            persistent_store,
+            pin_protocol_v1,
+            sub_command_params.credential_id,
+        ),
+        CredentialManagementSubCommand::UpdateUserInformation => process_update_user_information(
+            persistent_store,
+            pin_protocol_v1,
+            sub_command_params.credential_id,
+            sub_command_params.user,
+        ),
+        _ => Err(Ctap2StatusCode::CTAP2_ERR_INVALID_SUBCOMMAND),
+    }
+}
+
+fn process_get_creds_metadata(
+    persistent_store: &PersistentStore,
+) -> Result<ResponseData, Ctap2StatusCode> {
+    Ok(ResponseData::AuthenticatorCredentialManagement(Some(
+        AuthenticatorCredentialManagementResponse {
+            existing_resident_credentials_count: Some(persistent_store.count_credentials()? as u64),
+            max_possible_remaining_resident_credentials_count: Some(
+                persistent_store.remaining_credentials()? as u64,
+            ),
+        },
+    )))
+}
+
+fn process_delete_credential(
+    persistent_store: &mut PersistentStore,
+    pin_protocol_v1: &PinProtocolV1,
+    credential_id: Option<PublicKeyCredentialDescriptor>,
+) -> Result<ResponseData, Ctap2StatusCode> {
+    let credential_id = credential_id
+        .ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?
+        .key_id;
+    let credential = persistent_store.get_credential(&credential_id)?;
+    pin_protocol_v1.has_no_or_rp_id_permission(&credential.rp_id)?;
+    persistent_store.delete_credential(&credential_id)?;
+    Ok(ResponseData::AuthenticatorCredentialManagement(None))
+}
+
+fn process_update_user_information(
+    persistent_store: &mut PersistentStore,
+    pin_protocol_v1: &PinProtocolV1,
+    credential_id: Option<PublicKeyCredentialDescriptor>,
+    user: Option<PublicKeyCredentialUserEntity>,
+) -> Result<ResponseData, Ctap2StatusCode> {
+    let credential_id = credential_id
+        .ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?
+        .key_id;
+    let user = user.ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?;
+    let mut credential = persistent_store.get_credential(&credential_id)?;
+    pin_protocol_v1.has_no_or_rp_id_permission(&credential.rp_id)?;
+    if credential.user_handle != user.user_id {
+        return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+    }
+    // Missing or empty fields are removed. The key, counter and icon of the credential are kept.
+    credential.user_name = crop_user_info(user.user_name);
+    credential.user_display_name = crop_user_info(user.user_display_name);
+    persistent_store.store_credential(credential)?;
+    Ok(ResponseData::AuthenticatorCredentialManagement(None))
+}
+
+// Crops user provided information like makeCredential does, and drops empty strings.
+fn crop_user_info(info: Option<String>) -> Option<String> {
+    info.filter(|s| !s.is_empty())
+        .map(|s| truncate_to_char_boundary(&s, MAX_USER_INFO_LENGTH).to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::data_formats::{PublicKeyCredentialSource, PublicKeyCredentialType};
+    use super::super::pin_protocol_v1::PIN_PROTOCOL_V1;
+    use super::*;
+    use alloc::collections::BTreeMap;
+    use alloc::vec::Vec;
+    use crypto::hmac::hmac_256;
+    use crypto::rng256::{Rng256, ThreadRng256};
+    use crypto::sha256::Sha256;
+
+    const PIN_UV_AUTH_TOKEN: [u8; 32] = [0x88; 32];
+
+    fn create_credential_source(
+        rng: &mut ThreadRng256,
+        rp_id: &str,
+        user_handle: Vec<u8>,
+    ) -> PublicKeyCredentialSource {
+        PublicKeyCredentialSource {
+            key_type: PublicKeyCredentialType::PublicKey,
+            credential_id: rng.gen_uniform_u8x32().to_vec(),
+            private_key: crypto::ecdsa::SecKey::gensk(rng),
+            rp_id: String::from(rp_id),
+            user_handle,
+            user_display_name: None,
+            cred_protect_policy: None,
+            creation_order: 0,
+            user_name: None,
+            user_icon: None,
+            cred_blob: None,
+            signature_counter: None,
+            large_blob_key: None,
+        }
+    }
+
+    fn create_pin_protocol(rng: &mut ThreadRng256) -> PinProtocolV1 {
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(rng);
+        PinProtocolV1::new_test(key_agreement_key, PIN_UV_AUTH_TOKEN)
+    }
+
+    // Builds the parameters, authenticated with PIN_UV_AUTH_TOKEN.
+    fn create_params(
+        sub_command: CredentialManagementSubCommand,
+        sub_command_params: Option<BTreeMap<cbor::KeyType, cbor::Value>>,
+    ) -> AuthenticatorCredentialManagementParameters {
+        let mut message = vec![sub_command as u8];
+        if let Some(sub_command_params) = sub_command_params.clone() {
+            assert!(cbor::write(
+                cbor::Value::Map(sub_command_params),
+                &mut message
+            ));
+        }
+        let pin_uv_auth_param = hmac_256::<Sha256>(&PIN_UV_AUTH_TOKEN, &message)[..16].to_vec();
+        AuthenticatorCredentialManagementParameters {
+            sub_command,
+            sub_command_params,
+            pin_uv_auth_protocol: Some(PIN_PROTOCOL_V1),
+            pin_uv_auth_param: Some(pin_uv_auth_param),
+        }
+    }
+
+    fn create_delete_params(credential_id: Vec<u8>) -> AuthenticatorCredentialManagementParameters {
+        let credential_descriptor = PublicKeyCredentialDescriptor {
+            key_type: PublicKeyCredentialType::PublicKey,
+            key_id: credential_id,
+            transports: None,
+        };
+        let mut sub_command_params = BTreeMap::new();
+        sub_command_params.insert(
+            cbor::KeyType::Unsigned(0x02),
+            cbor::Value::from(credential_descriptor),
+        );
+        create_params(
+            CredentialManagementSubCommand::DeleteCredential,
+            Some(sub_command_params),
+        )
+    }
+
+    fn create_update_params(
+        credential_id: Vec<u8>,
+        user: PublicKeyCredentialUserEntity,
+    ) -> AuthenticatorCredentialManagementParameters {
+        let credential_descriptor = PublicKeyCredentialDescriptor {
+            key_type: PublicKeyCredentialType::PublicKey,
+            key_id: credential_id,
+            transports: None,
+        };
+        let mut sub_command_params = BTreeMap::new();
+        sub_command_params.insert(
+            cbor::KeyType::Unsigned(0x02),
+            cbor::Value::from(credential_descriptor),
+        );
+        sub_command_params.insert(cbor::KeyType::Unsigned(0x03), cbor::Value::from(user));
+        create_params(
+            CredentialManagementSubCommand::UpdateUserInformation,
+            Some(sub_command_params),
+        )
+    }
+
+    fn existing_credentials_count(
+        persistent_store: &mut PersistentStore,
+        pin_protocol_v1: &mut PinProtocolV1,
+    ) -> u64 {
+        let params = create_params(CredentialManagementSubCommand::GetCredsMetadata, None);
+        match process_credential_management(persistent_store, pin_protocol_v1, params) {
+            Ok(ResponseData::AuthenticatorCredentialManagement(Some(response))) => {
+                response.existing_resident_credentials_count.unwrap()
+            }
+            _ => panic!("Invalid response type"),
+        }
+    }
+
+    #[test]
+    fn test_process_get_creds_metadata() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut pin_protocol_v1 = create_pin_protocol(&mut rng);
+        let remaining = persistent_store.remaining_credentials().unwrap() as u64;
+
+        let params = create_params(CredentialManagementSubCommand::GetCredsMetadata, None);
+        let response =
+            process_credential_management(&mut persistent_store, &mut pin_protocol_v1, params);
+        let expected_response = AuthenticatorCredentialManagementResponse {
+            existing_resident_credentials_count: Some(0),
+            max_possible_remaining_resident_credentials_count: Some(remaining),
+        };
+        assert_eq!(
+            response,
+            Ok(ResponseData::AuthenticatorCredentialManagement(Some(
+                expected_response
+            )))
+        );
+
+        let credential_source = create_credential_source(&mut rng, "example.com", vec![0x1D]);
+        assert!(persistent_store.store_credential(credential_source).is_ok());
+        let params = create_params(CredentialManagementSubCommand::GetCredsMetadata, None);
+        let response =
+            process_credential_management(&mut persistent_store, &mut pin_protocol_v1, params);
+        let expected_response = AuthenticatorCredentialManagementResponse {
+            existing_resident_credentials_count: Some(1),
+            max_possible_remaining_resident_credentials_count: Some(remaining - 1),
+        };
+        assert_eq!(
+            response,
+            Ok(ResponseData::AuthenticatorCredentialManagement(Some(
+                expected_response
+            )))
+        );
+    }
+
+    #[test]
+    fn test_process_get_creds_metadata_scope() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut pin_protocol_v1 = create_pin_protocol(&mut rng);
+
+        // The token lacks the credential management permission.
+        pin_protocol_v1.set_permissions(PinPermission::GetAssertion as u8);
+        let params = create_params(CredentialManagementSubCommand::GetCredsMetadata, None);
+        let response =
+            process_credential_management(&mut persistent_store, &mut pin_protocol_v1, params);
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID));
+
+        // The token is bound to a relying party.
+        pin_protocol_v1.set_permissions(PinPermission::CredentialManagement as u8);
+        assert!(pin_protocol_v1
+            .has_permission_for_rp_id("example.com")
+            .is_ok());
+        let params = create_params(CredentialManagementSubCommand::GetCredsMetadata, None);
+        let response =
+            process_credential_management(&mut persistent_store, &mut pin_protocol_v1, params);
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID));
+    }
+
+    #[test]
+    fn test_process_delete_credential() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut pin_protocol_v1 = create_pin_protocol(&mut rng);
+        let mut credential_ids = vec![];
+        for user_handle in 0..3 {
+            let credential_source =
+                create_credential_source(&mut rng, "example.com", vec![user_handle]);
+            credential_ids.push(credential_source.credential_id.clone());
+            assert!(persistent_store.store_credential(credential_source).is_ok());
+        }
+        assert_eq!(
+            existing_credentials_count(&mut persistent_store, &mut pin_protocol_v1),
+            3
+        );
+
+        let params = create_delete_params(credential_ids[1].clone());
+        let response =
+            process_credential_management(&mut persistent_store, &mut pin_protocol_v1, params);
+        assert_eq!(
+            response,
+            Ok(ResponseData::AuthenticatorCredentialManagement(None))
+        );
+        assert_eq!(
+            existing_credentials_count(&mut persistent_store, &mut pin_protocol_v1),
+            2
+        );
+        assert_eq!(
+            persistent_store.get_credential(&credential_ids[1]),
+            Err(Ctap2StatusCode::CTAP2_ERR_NO_CREDENTIALS)
+        );
+
+        let params = create_delete_params(credential_ids[1].clone());
+        let response =
+            process_credential_management(&mut persistent_store, &mut pin_protocol_v1, params);
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP2_ERR_NO_CREDENTIALS));
+    }
+
+    #[test]
+    fn test_process_delete_credential_rp_id_permission() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut pin_protocol_v1 = create_pin_protocol(&mut rng);
+        let credential_source = create_credential_source(&mut rng, "example.com", vec![0x1D]);
+        let credential_id = credential_source.credential_id.clone();
+        assert!(persistent_store.store_credential(credential_source).is_ok());
+
+        // The token was bound to another relying party, for example by a getAssertion.
+        assert!(pin_protocol_v1
+            .has_permission_for_rp_id("another.example.com")
+            .is_ok());
+        let params = create_delete_params(credential_id.clone());
+        let response =
+            process_credential_management(&mut persistent_store, &mut pin_protocol_v1, params);
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID));
+        assert!(persistent_store.get_credential(&credential_id).is_ok());
+    }
+
+    #[test]
+    fn test_process_credential_management_pin_auth() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut pin_protocol_v1 = create_pin_protocol(&mut rng);
+        let credential_source = create_credential_source(&mut rng, "example.com", vec![0x1D]);
+        let credential_id = credential_source.credential_id.clone();
+        assert!(persistent_store.store_credential(credential_source).is_ok());
+
+        let mut params = create_delete_params(credential_id.clone());
+        params.pin_uv_auth_param = None;
+        let response =
+            process_credential_management(&mut persistent_store, &mut pin_protocol_v1, params);
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP2_ERR_PIN_REQUIRED));
+
+        let mut params = create_delete_params(credential_id.clone());
+        params.pin_uv_auth_param = Some(vec![0x00; 16]);
+        let response =
+            process_credential_management(&mut persistent_store, &mut pin_protocol_v1, params);
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID));
+
+        let mut params = create_delete_params(credential_id.clone());
+        params.pin_uv_auth_protocol = Some(2);
+        let response =
+            process_credential_management(&mut persistent_store, &mut pin_protocol_v1, params);
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID));
+        assert!(persistent_store.get_credential(&credential_id).is_ok());
+    }
+
+    #[test]
+    fn test_process_update_user_information() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut pin_protocol_v1 = create_pin_protocol(&mut rng);
+        let mut credential_source = create_credential_source(&mut rng, "example.com", vec![0x1D]);
+        credential_source.user_name = Some(String::from("foo"));
+        credential_source.user_display_name = Some(String::from("Foo"));
+        credential_source.user_icon = Some(String::from("icon"));
+        credential_source.signature_counter = Some(5);
+        let credential_id = credential_source.credential_id.clone();
+        let private_key = credential_source.private_key.clone();
+        assert!(persistent_store.store_credential(credential_source).is_ok());
+
+        let user = PublicKeyCredentialUserEntity {
+            user_id: vec![0x1D],
+            user_name: Some(String::from("")),
+            user_display_name: Some("B".repeat(MAX_USER_INFO_LENGTH + 1)),
+            user_icon: None,
+        };
+        let params = create_update_params(credential_id.clone(), user);
+        let response =
+            process_credential_management(&mut persistent_store, &mut pin_protocol_v1, params);
+        assert_eq!(
+            response,
+            Ok(ResponseData::AuthenticatorCredentialManagement(None))
+        );
+
+        let credential = persistent_store.get_credential(&credential_id).unwrap();
+        assert_eq!(credential.user_name, None);
+        assert_eq!(
+            credential.user_display_name,
+            Some("B".repeat(MAX_USER_INFO_LENGTH))
+        );
+        assert_eq!(credential.user_icon, Some(String::from("icon")));
+        assert_eq!(credential.signature_counter, Some(5));
+        assert_eq!(credential.private_key, private_key);
+        assert_eq!(persistent_store.count_credentials().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_process_update_user_information_wrong_user() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut pin_protocol_v1 = create_pin_protocol(&mut rng);
+        let credential_source = create_credential_source(&mut rng, "example.com", vec![0x1D]);
+        let credential_id = credential_source.credential_id.clone();
+        assert!(persistent_store.store_credential(credential_source).is_ok());
+
+        let user = PublicKeyCredentialUserEntity {
+            user_id: vec![0x2D],
+            user_name: Some(String::from("bar")),
+            user_display_name: None,
+            user_icon: None,
+        };
+        let params = create_update_params(credential_id.clone(), user);
+        let response =
+            process_credential_management(&mut persistent_store, &mut pin_protocol_v1, params);
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER));
+        let credential = persistent_store.get_credential(&credential_id).unwrap();
+        assert_eq!(credential.user_name, None);
+    }
+}
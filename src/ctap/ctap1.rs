@@ -261,16 +261,14 @@ impl Ctap1Command {
             return Err(Ctap1StatusCode::SW_INTERNAL_EXCEPTION);
         }
 
-        let certificate = ctap_state
+        // Material specific to the relying party is reserved for enterprise attestation, which
+        // U2F doesn't have.
+        let attestation_material = ctap_state
             .persistent_store
-            .attestation_certificate()
+            .attestation_material()
             .map_err(|_| Ctap1StatusCode::SW_MEMERR)?
             .ok_or(Ctap1StatusCode::SW_INTERNAL_EXCEPTION)?;
-        let private_key = ctap_state
-            .persistent_store
-            .attestation_private_key()
-            .map_err(|_| Ctap1StatusCode::SW_INTERNAL_EXCEPTION)?
-            .ok_or(Ctap1StatusCode::SW_INTERNAL_EXCEPTION)?;
+        let certificate = attestation_material.certificate;
 
         let mut response = Vec::with_capacity(105 + key_handle.len() + certificate.len());
         response.push(Ctap1Command::LEGACY_BYTE);
@@ -288,7 +286,8 @@ impl Ctap1Command {
         signature_data.extend(key_handle);
         signature_data.extend_from_slice(&user_pk);
 
-        let attestation_key = crypto::ecdsa::SecKey::from_bytes(&private_key).unwrap();
+        let attestation_key =
+            crypto::ecdsa::SecKey::from_bytes(&attestation_material.private_key).unwrap();
         let signature = attestation_key.sign_rfc6979::<crypto::sha256::Sha256>(&signature_data);
 
         response.extend(signature.to_asn1_der());
@@ -349,8 +348,17 @@ impl Ctap1Command {
 
 #[cfg(test)]
 mod test {
-    use super::super::{key_material, CREDENTIAL_ID_SIZE, USE_SIGNATURE_COUNTER};
+    use super::super::command::AuthenticatorGetAssertionParameters;
+    use super::super::data_formats::{
+        GetAssertionOptions, PublicKeyCredentialDescriptor, PublicKeyCredentialType,
+    };
+    use super::super::response::ResponseData;
+    use super::super::{
+        key_material, CREDENTIAL_ID_SIZE, USE_PER_CREDENTIAL_SIGNATURE_COUNTER,
+        USE_SIGNATURE_COUNTER, USE_U2F_COMPATIBLE_SIGNATURE_COUNTER,
+    };
     use super::*;
+    use alloc::string::String;
     use crypto::rng256::ThreadRng256;
     use crypto::Hash256;
 
@@ -662,6 +670,65 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_process_authenticate_then_get_assertion_counter() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let sk = crypto::ecdsa::SecKey::gensk(&mut rng);
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, START_CLOCK_VALUE);
+
+        let rp_id = "example.com";
+        let application = crypto::sha256::Sha256::hash(rp_id.as_bytes());
+        let key_handle = ctap_state.encrypt_key_handle(sk, &application).unwrap();
+        let message = create_authenticate_message(
+            &application,
+            Ctap1Flags::DontEnforceUpAndSign,
+            &key_handle,
+        );
+        let response =
+            Ctap1Command::process_command(&message, &mut ctap_state, START_CLOCK_VALUE).unwrap();
+        let u2f_counter = u32::from_be_bytes(*array_ref!(response, 1, 4));
+
+        let get_assertion_params = AuthenticatorGetAssertionParameters {
+            rp_id: String::from(rp_id),
+            client_data_hash: vec![0xCD],
+            allow_list: Some(vec![PublicKeyCredentialDescriptor {
+                key_type: PublicKeyCredentialType::PublicKey,
+                key_id: key_handle,
+                transports: None,
+            }]),
+            extensions: None,
+            options: GetAssertionOptions {
+                up: false,
+                uv: false,
+            },
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: None,
+        };
+        let get_assertion_response = ctap_state.process_get_assertion(
+            get_assertion_params,
+            [0x12, 0x34, 0x56, 0x78],
+            START_CLOCK_VALUE,
+        );
+        let auth_data = match get_assertion_response.unwrap() {
+            ResponseData::AuthenticatorGetAssertion(response) => response.auth_data,
+            _ => panic!("Invalid response type"),
+        };
+        let fido2_counter = u32::from_be_bytes(*array_ref!(auth_data, 33, 4));
+
+        if !USE_PER_CREDENTIAL_SIGNATURE_COUNTER || USE_U2F_COMPATIBLE_SIGNATURE_COUNTER {
+            // The count continues across protocols.
+            if USE_SIGNATURE_COUNTER {
+                assert!(fido2_counter > u2f_counter);
+            } else {
+                assert_eq!(fido2_counter, u2f_counter);
+            }
+        } else {
+            // The credential has no counter of its own.
+            assert_eq!(fido2_counter, 0);
+        }
+    }
+
     #[test]
     fn test_process_authenticate_bad_key_handle() {
         let application = [0x0A; 32];
@@ -262,6 +262,10 @@ impl From<PublicKeyCredentialDescriptor> for cbor::Value {
 pub struct MakeCredentialExtensions {
     pub hmac_secret: bool,
     pub cred_protect: Option<CredentialProtectionPolicy>,
+    pub cred_blob: Option<Vec<u8>>,
+    pub large_blob_key: bool,
+    #[cfg(feature = "with_ctap2_1")]
+    pub min_pin_length: bool,
 }
 
 impl TryFrom<cbor::Value> for MakeCredentialExtensions {
@@ -270,8 +274,11 @@ impl TryFrom<cbor::Value> for MakeCredentialExtensions {
     fn try_from(cbor_value: cbor::Value) -> Result<Self, Ctap2StatusCode> {
         destructure_cbor_map! {
             let {
+                "credBlob" => cred_blob,
                 "credProtect" => cred_protect,
                 "hmac-secret" => hmac_secret,
+                "largeBlobKey" => large_blob_key,
+                "minPinLength" => min_pin_length,
             } = extract_map(cbor_value)?;
         }
 
@@ -279,9 +286,19 @@ impl TryFrom<cbor::Value> for MakeCredentialExtensions {
         let cred_protect = cred_protect
             .map(CredentialProtectionPolicy::try_from)
             .transpose()?;
+        let cred_blob = cred_blob.map(extract_byte_string).transpose()?;
+        let large_blob_key = extract_large_blob_key(large_blob_key)?;
+        #[cfg(feature = "with_ctap2_1")]
+        let min_pin_length = min_pin_length.map_or(Ok(false), extract_bool)?;
+        #[cfg(not(feature = "with_ctap2_1"))]
+        let _ = min_pin_length;
         Ok(Self {
             hmac_secret,
             cred_protect,
+            cred_blob,
+            large_blob_key,
+            #[cfg(feature = "with_ctap2_1")]
+            min_pin_length,
         })
     }
 }
@@ -289,6 +306,8 @@ impl TryFrom<cbor::Value> for MakeCredentialExtensions {
 #[cfg_attr(any(test, feature = "debug_ctap"), derive(Clone, Debug, PartialEq))]
 pub struct GetAssertionExtensions {
     pub hmac_secret: Option<GetAssertionHmacSecretInput>,
+    pub cred_blob: bool,
+    pub large_blob_key: bool,
 }
 
 impl TryFrom<cbor::Value> for GetAssertionExtensions {
@@ -297,14 +316,30 @@ impl TryFrom<cbor::Value> for GetAssertionExtensions {
     fn try_from(cbor_value: cbor::Value) -> Result<Self, Ctap2StatusCode> {
         destructure_cbor_map! {
             let {
+                "credBlob" => cred_blob,
                 "hmac-secret" => hmac_secret,
+                "largeBlobKey" => large_blob_key,
             } = extract_map(cbor_value)?;
         }
 
         let hmac_secret = hmac_secret
             .map(GetAssertionHmacSecretInput::try_from)
             .transpose()?;
-        Ok(Self { hmac_secret })
+        let cred_blob = cred_blob.map_or(Ok(false), extract_bool)?;
+        let large_blob_key = extract_large_blob_key(large_blob_key)?;
+        Ok(Self {
+            hmac_secret,
+            cred_blob,
+            large_blob_key,
+        })
+    }
+}
+
+// The largeBlobKey extension input is only allowed to be true.
+fn extract_large_blob_key(cbor_value: Option<cbor::Value>) -> Result<bool, Ctap2StatusCode> {
+    match cbor_value.map(extract_bool).transpose()? {
+        Some(false) => Err(Ctap2StatusCode::CTAP2_ERR_INVALID_OPTION),
+        large_blob_key => Ok(large_blob_key.is_some()),
     }
 }
 
@@ -314,6 +349,7 @@ pub struct GetAssertionHmacSecretInput {
     pub key_agreement: CoseKey,
     pub salt_enc: Vec<u8>,
     pub salt_auth: Vec<u8>,
+    pub pin_uv_auth_protocol: u64,
 }
 
 impl TryFrom<cbor::Value> for GetAssertionHmacSecretInput {
@@ -325,16 +361,23 @@ impl TryFrom<cbor::Value> for GetAssertionHmacSecretInput {
                 1 => cose_key,
                 2 => salt_enc,
                 3 => salt_auth,
+                4 => pin_uv_auth_protocol,
             } = extract_map(cbor_value)?;
         }
 
         let cose_key = extract_map(ok_or_missing(cose_key)?)?;
         let salt_enc = extract_byte_string(ok_or_missing(salt_enc)?)?;
         let salt_auth = extract_byte_string(ok_or_missing(salt_auth)?)?;
+        // Platforms that don't send a PIN protocol use version 1.
+        let pin_uv_auth_protocol = pin_uv_auth_protocol
+            .map(extract_unsigned)
+            .transpose()?
+            .unwrap_or(1);
         Ok(Self {
             key_agreement: CoseKey(cose_key),
             salt_enc,
             salt_auth,
+            pin_uv_auth_protocol,
         })
     }
 }
@@ -502,6 +545,9 @@ pub struct PublicKeyCredentialSource {
     pub creation_order: u64,
     pub user_name: Option<String>,
     pub user_icon: Option<String>,
+    pub cred_blob: Option<Vec<u8>>,
+    pub signature_counter: Option<u32>,
+    pub large_blob_key: Option<Vec<u8>>,
 }
 
 // We serialize credentials for the persistent storage using CBOR maps. Each field of a credential
@@ -516,6 +562,9 @@ enum PublicKeyCredentialSourceField {
     CreationOrder = 7,
     UserName = 8,
     UserIcon = 9,
+    CredBlob = 10,
+    SignatureCounter = 11,
+    LargeBlobKey = 12,
     // When a field is removed, its tag should be reserved and not used for new fields. We document
     // those reserved tags below.
     // Reserved tags:
@@ -532,6 +581,7 @@ impl From<PublicKeyCredentialSource> for cbor::Value {
     fn from(credential: PublicKeyCredentialSource) -> cbor::Value {
         let mut private_key = [0u8; 32];
         credential.private_key.to_bytes(&mut private_key);
+        let signature_counter = credential.signature_counter.map(|c| c as u64);
         cbor_map_options! {
             PublicKeyCredentialSourceField::CredentialId => Some(credential.credential_id),
             PublicKeyCredentialSourceField::PrivateKey => Some(private_key.to_vec()),
@@ -542,6 +592,9 @@ impl From<PublicKeyCredentialSource> for cbor::Value {
             PublicKeyCredentialSourceField::CreationOrder => credential.creation_order,
             PublicKeyCredentialSourceField::UserName => credential.user_name,
             PublicKeyCredentialSourceField::UserIcon => credential.user_icon,
+            PublicKeyCredentialSourceField::CredBlob => credential.cred_blob,
+            PublicKeyCredentialSourceField::SignatureCounter => signature_counter,
+            PublicKeyCredentialSourceField::LargeBlobKey => credential.large_blob_key,
         }
     }
 }
@@ -561,6 +614,9 @@ impl TryFrom<cbor::Value> for PublicKeyCredentialSource {
                 PublicKeyCredentialSourceField::CreationOrder => creation_order,
                 PublicKeyCredentialSourceField::UserName => user_name,
                 PublicKeyCredentialSourceField::UserIcon => user_icon,
+                PublicKeyCredentialSourceField::CredBlob => cred_blob,
+                PublicKeyCredentialSourceField::SignatureCounter => signature_counter,
+                PublicKeyCredentialSourceField::LargeBlobKey => large_blob_key,
             } = extract_map(cbor_value)?;
         }
 
@@ -580,6 +636,13 @@ impl TryFrom<cbor::Value> for PublicKeyCredentialSource {
         let creation_order = creation_order.map(extract_unsigned).unwrap_or(Ok(0))?;
         let user_name = user_name.map(extract_text_string).transpose()?;
         let user_icon = user_icon.map(extract_text_string).transpose()?;
+        let cred_blob = cred_blob.map(extract_byte_string).transpose()?;
+        let signature_counter = signature_counter
+            .map(extract_unsigned)
+            .transpose()?
+            .map(|c| u32::try_from(c).map_err(|_| Ctap2StatusCode::CTAP2_ERR_INVALID_CBOR))
+            .transpose()?;
+        let large_blob_key = large_blob_key.map(extract_byte_string).transpose()?;
         // We don't return whether there were unknown fields in the CBOR value. This means that
         // deserialization is not injective. In particular deserialization is only an inverse of
         // serialization at a given version of OpenSK. This is not a problem because:
@@ -601,6 +664,9 @@ impl TryFrom<cbor::Value> for PublicKeyCredentialSource {
             creation_order,
             user_name,
             user_icon,
+            cred_blob,
+            signature_counter,
+            large_blob_key,
         })
     }
 }
@@ -747,6 +813,195 @@ impl TryFrom<cbor::Value> for ClientPinSubCommand {
     }
 }
 
+#[cfg(feature = "with_ctap2_1")]
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
+#[cfg_attr(test, derive(IntoEnumIterator))]
+pub enum ConfigSubCommand {
+    EnableEnterpriseAttestation = 0x01,
+    ToggleAlwaysUv = 0x02,
+    SetMinPinLength = 0x03,
+    VendorPrototype = 0xFF,
+}
+
+#[cfg(feature = "with_ctap2_1")]
+impl From<ConfigSubCommand> for cbor::Value {
+    fn from(subcommand: ConfigSubCommand) -> Self {
+        (subcommand as u64).into()
+    }
+}
+
+#[cfg(feature = "with_ctap2_1")]
+impl TryFrom<cbor::Value> for ConfigSubCommand {
+    type Error = Ctap2StatusCode;
+
+    fn try_from(cbor_value: cbor::Value) -> Result<Self, Ctap2StatusCode> {
+        let subcommand_int = extract_unsigned(cbor_value)?;
+        match subcommand_int {
+            0x01 => Ok(ConfigSubCommand::EnableEnterpriseAttestation),
+            0x02 => Ok(ConfigSubCommand::ToggleAlwaysUv),
+            0x03 => Ok(ConfigSubCommand::SetMinPinLength),
+            0xFF => Ok(ConfigSubCommand::VendorPrototype),
+            _ => Err(Ctap2StatusCode::CTAP2_ERR_INVALID_SUBCOMMAND),
+        }
+    }
+}
+
+#[cfg(feature = "with_ctap2_1")]
+#[derive(Clone, Default)]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug, PartialEq))]
+pub struct SetMinPinLengthParams {
+    pub new_min_pin_length: Option<u8>,
+    pub min_pin_length_rp_ids: Option<Vec<String>>,
+    pub force_change_pin: Option<bool>,
+}
+
+#[cfg(feature = "with_ctap2_1")]
+impl TryFrom<cbor::Value> for SetMinPinLengthParams {
+    type Error = Ctap2StatusCode;
+
+    fn try_from(cbor_value: cbor::Value) -> Result<Self, Ctap2StatusCode> {
+        destructure_cbor_map! {
+            let {
+                0x01 => new_min_pin_length,
+                0x02 => min_pin_length_rp_ids,
+                0x03 => force_change_pin,
+            } = extract_map(cbor_value)?;
+        }
+
+        let new_min_pin_length = new_min_pin_length
+            .map(extract_unsigned)
+            .transpose()?
+            .map(u8::try_from)
+            .transpose()
+            .map_err(|_| Ctap2StatusCode::CTAP2_ERR_PIN_POLICY_VIOLATION)?;
+        let min_pin_length_rp_ids = match min_pin_length_rp_ids {
+            Some(entry) => Some(
+                extract_array(entry)?
+                    .into_iter()
+                    .map(extract_text_string)
+                    .collect::<Result<Vec<String>, Ctap2StatusCode>>()?,
+            ),
+            None => None,
+        };
+        let force_change_pin = force_change_pin.map(extract_bool).transpose()?;
+
+        Ok(Self {
+            new_min_pin_length,
+            min_pin_length_rp_ids,
+            force_change_pin,
+        })
+    }
+}
+
+#[cfg(feature = "with_ctap2_1")]
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
+#[cfg_attr(test, derive(IntoEnumIterator))]
+pub enum CredentialManagementSubCommand {
+    GetCredsMetadata = 0x01,
+    EnumerateRpsBegin = 0x02,
+    EnumerateRpsGetNextRp = 0x03,
+    EnumerateCredentialsBegin = 0x04,
+    EnumerateCredentialsGetNextCredential = 0x05,
+    DeleteCredential = 0x06,
+    UpdateUserInformation = 0x07,
+}
+
+#[cfg(feature = "with_ctap2_1")]
+impl From<CredentialManagementSubCommand> for cbor::Value {
+    fn from(subcommand: CredentialManagementSubCommand) -> Self {
+        (subcommand as u64).into()
+    }
+}
+
+#[cfg(feature = "with_ctap2_1")]
+impl TryFrom<cbor::Value> for CredentialManagementSubCommand {
+    type Error = Ctap2StatusCode;
+
+    fn try_from(cbor_value: cbor::Value) -> Result<Self, Ctap2StatusCode> {
+        let subcommand_int = extract_unsigned(cbor_value)?;
+        match subcommand_int {
+            0x01 => Ok(CredentialManagementSubCommand::GetCredsMetadata),
+            0x02 => Ok(CredentialManagementSubCommand::EnumerateRpsBegin),
+            0x03 => Ok(CredentialManagementSubCommand::EnumerateRpsGetNextRp),
+            0x04 => Ok(CredentialManagementSubCommand::EnumerateCredentialsBegin),
+            0x05 => Ok(CredentialManagementSubCommand::EnumerateCredentialsGetNextCredential),
+            0x06 => Ok(CredentialManagementSubCommand::DeleteCredential),
+            0x07 => Ok(CredentialManagementSubCommand::UpdateUserInformation),
+            _ => Err(Ctap2StatusCode::CTAP2_ERR_INVALID_SUBCOMMAND),
+        }
+    }
+}
+
+#[cfg(feature = "with_ctap2_1")]
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
+#[cfg_attr(test, derive(IntoEnumIterator))]
+pub enum CredentialBackupSubCommand {
+    Export = 0x01,
+    Import = 0x02,
+}
+
+#[cfg(feature = "with_ctap2_1")]
+impl From<CredentialBackupSubCommand> for cbor::Value {
+    fn from(subcommand: CredentialBackupSubCommand) -> Self {
+        (subcommand as u64).into()
+    }
+}
+
+#[cfg(feature = "with_ctap2_1")]
+impl TryFrom<cbor::Value> for CredentialBackupSubCommand {
+    type Error = Ctap2StatusCode;
+
+    fn try_from(cbor_value: cbor::Value) -> Result<Self, Ctap2StatusCode> {
+        let subcommand_int = extract_unsigned(cbor_value)?;
+        match subcommand_int {
+            0x01 => Ok(CredentialBackupSubCommand::Export),
+            0x02 => Ok(CredentialBackupSubCommand::Import),
+            _ => Err(Ctap2StatusCode::CTAP2_ERR_INVALID_SUBCOMMAND),
+        }
+    }
+}
+
+#[cfg(feature = "with_ctap2_1")]
+#[derive(Default)]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Clone, Debug, PartialEq))]
+pub struct CredentialManagementSubCommandParameters {
+    pub rp_id_hash: Option<Vec<u8>>,
+    pub credential_id: Option<PublicKeyCredentialDescriptor>,
+    pub user: Option<PublicKeyCredentialUserEntity>,
+}
+
+#[cfg(feature = "with_ctap2_1")]
+impl TryFrom<cbor::Value> for CredentialManagementSubCommandParameters {
+    type Error = Ctap2StatusCode;
+
+    fn try_from(cbor_value: cbor::Value) -> Result<Self, Ctap2StatusCode> {
+        destructure_cbor_map! {
+            let {
+                0x01 => rp_id_hash,
+                0x02 => credential_id,
+                0x03 => user,
+            } = extract_map(cbor_value)?;
+        }
+
+        let rp_id_hash = rp_id_hash.map(extract_byte_string).transpose()?;
+        let credential_id = credential_id
+            .map(PublicKeyCredentialDescriptor::try_from)
+            .transpose()?;
+        let user = user
+            .map(PublicKeyCredentialUserEntity::try_from)
+            .transpose()?;
+
+        Ok(Self {
+            rp_id_hash,
+            credential_id,
+            user,
+        })
+    }
+}
+
 pub(super) fn extract_unsigned(cbor_value: cbor::Value) -> Result<u64, Ctap2StatusCode> {
     match cbor_value {
         cbor::Value::KeyValue(cbor::KeyType::Unsigned(unsigned)) => Ok(unsigned),
@@ -1251,11 +1506,58 @@ mod test {
         let cbor_extensions = cbor_map! {
             "hmac-secret" => true,
             "credProtect" => CredentialProtectionPolicy::UserVerificationRequired,
+            "credBlob" => vec![0xCB],
         };
         let extensions = MakeCredentialExtensions::try_from(cbor_extensions);
         let expected_extensions = MakeCredentialExtensions {
             hmac_secret: true,
             cred_protect: Some(CredentialProtectionPolicy::UserVerificationRequired),
+            cred_blob: Some(vec![0xCB]),
+            large_blob_key: false,
+            #[cfg(feature = "with_ctap2_1")]
+            min_pin_length: false,
+        };
+        assert_eq!(extensions, Ok(expected_extensions));
+    }
+
+    #[test]
+    fn test_from_make_credential_extensions_large_blob_key() {
+        let cbor_extensions = cbor_map! {
+            "largeBlobKey" => true,
+        };
+        let extensions = MakeCredentialExtensions::try_from(cbor_extensions);
+        let expected_extensions = MakeCredentialExtensions {
+            hmac_secret: false,
+            cred_protect: None,
+            cred_blob: None,
+            large_blob_key: true,
+            #[cfg(feature = "with_ctap2_1")]
+            min_pin_length: false,
+        };
+        assert_eq!(extensions, Ok(expected_extensions));
+
+        let cbor_extensions = cbor_map! {
+            "largeBlobKey" => false,
+        };
+        assert_eq!(
+            MakeCredentialExtensions::try_from(cbor_extensions),
+            Err(Ctap2StatusCode::CTAP2_ERR_INVALID_OPTION)
+        );
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_from_make_credential_extensions_min_pin_length() {
+        let cbor_extensions = cbor_map! {
+            "minPinLength" => true,
+        };
+        let extensions = MakeCredentialExtensions::try_from(cbor_extensions);
+        let expected_extensions = MakeCredentialExtensions {
+            hmac_secret: false,
+            cred_protect: None,
+            cred_blob: None,
+            large_blob_key: false,
+            min_pin_length: true,
         };
         assert_eq!(extensions, Ok(expected_extensions));
     }
@@ -1272,19 +1574,47 @@ mod test {
                 2 => vec![0x02; 32],
                 3 => vec![0x03; 16],
             },
+            "credBlob" => true,
+            "largeBlobKey" => true,
         };
         let extensions = GetAssertionExtensions::try_from(cbor_extensions);
         let expected_input = GetAssertionHmacSecretInput {
             key_agreement: cose_key,
             salt_enc: vec![0x02; 32],
             salt_auth: vec![0x03; 16],
+            pin_uv_auth_protocol: 1,
         };
         let expected_extensions = GetAssertionExtensions {
             hmac_secret: Some(expected_input),
+            cred_blob: true,
+            large_blob_key: true,
         };
         assert_eq!(extensions, Ok(expected_extensions));
     }
 
+    #[test]
+    fn test_from_get_assertion_hmac_secret_input_pin_protocol() {
+        let mut rng = ThreadRng256 {};
+        let sk = crypto::ecdh::SecKey::gensk(&mut rng);
+        let cose_key = CoseKey::from(sk.genpk());
+        let cbor_input = cbor_map! {
+            1 => cbor::Value::Map(cose_key.0.clone()),
+            2 => vec![0x02; 48],
+            3 => vec![0x03; 32],
+            4 => 2,
+        };
+        let expected_input = GetAssertionHmacSecretInput {
+            key_agreement: cose_key,
+            salt_enc: vec![0x02; 48],
+            salt_auth: vec![0x03; 32],
+            pin_uv_auth_protocol: 2,
+        };
+        assert_eq!(
+            GetAssertionHmacSecretInput::try_from(cbor_input),
+            Ok(expected_input)
+        );
+    }
+
     #[test]
     fn test_from_make_credential_options() {
         let cbor_make_options = cbor_map! {
@@ -1358,6 +1688,127 @@ mod test {
         }
     }
 
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_from_into_credential_backup_sub_command() {
+        let cbor_sub_command: cbor::Value = cbor_int!(0x02);
+        let sub_command = CredentialBackupSubCommand::try_from(cbor_sub_command.clone());
+        let expected_sub_command = CredentialBackupSubCommand::Import;
+        assert_eq!(sub_command, Ok(expected_sub_command));
+        let created_cbor: cbor::Value = sub_command.unwrap().into();
+        assert_eq!(created_cbor, cbor_sub_command);
+
+        for command in CredentialBackupSubCommand::into_enum_iter() {
+            let created_cbor: cbor::Value = command.into();
+            let reconstructed = CredentialBackupSubCommand::try_from(created_cbor).unwrap();
+            assert_eq!(command, reconstructed);
+        }
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_from_into_credential_management_sub_command() {
+        let cbor_sub_command: cbor::Value = cbor_int!(0x06);
+        let sub_command = CredentialManagementSubCommand::try_from(cbor_sub_command.clone());
+        let expected_sub_command = CredentialManagementSubCommand::DeleteCredential;
+        assert_eq!(sub_command, Ok(expected_sub_command));
+        let created_cbor: cbor::Value = sub_command.unwrap().into();
+        assert_eq!(created_cbor, cbor_sub_command);
+
+        for command in CredentialManagementSubCommand::into_enum_iter() {
+            let created_cbor: cbor::Value = command.into();
+            let reconstructed = CredentialManagementSubCommand::try_from(created_cbor).unwrap();
+            assert_eq!(command, reconstructed);
+        }
+        let cbor_unknown_sub_command: cbor::Value = cbor_int!(0x08);
+        assert_eq!(
+            CredentialManagementSubCommand::try_from(cbor_unknown_sub_command),
+            Err(Ctap2StatusCode::CTAP2_ERR_INVALID_SUBCOMMAND)
+        );
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_from_into_config_sub_command() {
+        let cbor_sub_command: cbor::Value = cbor_int!(0x02);
+        let sub_command = ConfigSubCommand::try_from(cbor_sub_command.clone());
+        let expected_sub_command = ConfigSubCommand::ToggleAlwaysUv;
+        assert_eq!(sub_command, Ok(expected_sub_command));
+        let created_cbor: cbor::Value = sub_command.unwrap().into();
+        assert_eq!(created_cbor, cbor_sub_command);
+
+        for command in ConfigSubCommand::into_enum_iter() {
+            let created_cbor: cbor::Value = command.into();
+            let reconstructed = ConfigSubCommand::try_from(created_cbor).unwrap();
+            assert_eq!(command, reconstructed);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "with_ctap2_1")]
+    fn test_from_set_min_pin_length_params() {
+        let cbor_params = cbor_map! {
+            0x01 => 6,
+            0x02 => cbor_array!["example.com"],
+            0x03 => true,
+        };
+        let params = SetMinPinLengthParams::try_from(cbor_params);
+        let expected_params = SetMinPinLengthParams {
+            new_min_pin_length: Some(6),
+            min_pin_length_rp_ids: Some(vec!["example.com".to_string()]),
+            force_change_pin: Some(true),
+        };
+        assert_eq!(params, Ok(expected_params));
+
+        let params = SetMinPinLengthParams::try_from(cbor_map! {});
+        assert_eq!(params, Ok(SetMinPinLengthParams::default()));
+
+        let cbor_params = cbor_map! {
+            0x01 => 256,
+        };
+        assert_eq!(
+            SetMinPinLengthParams::try_from(cbor_params),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_POLICY_VIOLATION)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "with_ctap2_1")]
+    fn test_from_credential_management_sub_command_params() {
+        let cbor_params = cbor_map! {
+            0x02 => cbor_map! {
+                "id" => vec![0x2D; 32],
+                "type" => "public-key",
+            },
+            0x03 => cbor_map! {
+                "id" => vec![0x1D],
+                "name" => "foo",
+            },
+        };
+        let params = CredentialManagementSubCommandParameters::try_from(cbor_params);
+        let expected_params = CredentialManagementSubCommandParameters {
+            rp_id_hash: None,
+            credential_id: Some(PublicKeyCredentialDescriptor {
+                key_type: PublicKeyCredentialType::PublicKey,
+                key_id: vec![0x2D; 32],
+                transports: None,
+            }),
+            user: Some(PublicKeyCredentialUserEntity {
+                user_id: vec![0x1D],
+                user_name: Some("foo".to_string()),
+                user_display_name: None,
+                user_icon: None,
+            }),
+        };
+        assert_eq!(params, Ok(expected_params));
+
+        let params = CredentialManagementSubCommandParameters::try_from(cbor_map! {});
+        assert_eq!(
+            params,
+            Ok(CredentialManagementSubCommandParameters::default())
+        );
+    }
+
     #[test]
     fn test_credential_source_cbor_round_trip() {
         let mut rng = ThreadRng256 {};
@@ -1372,6 +1823,9 @@ mod test {
             creation_order: 0,
             user_name: None,
             user_icon: None,
+            cred_blob: None,
+            signature_counter: None,
+            large_blob_key: None,
         };
 
         assert_eq!(
@@ -1414,6 +1868,36 @@ mod test {
             ..credential
         };
 
+        assert_eq!(
+            PublicKeyCredentialSource::try_from(cbor::Value::from(credential.clone())),
+            Ok(credential.clone())
+        );
+
+        let credential = PublicKeyCredentialSource {
+            cred_blob: Some(vec![0xCB; 32]),
+            ..credential
+        };
+
+        assert_eq!(
+            PublicKeyCredentialSource::try_from(cbor::Value::from(credential.clone())),
+            Ok(credential.clone())
+        );
+
+        let credential = PublicKeyCredentialSource {
+            signature_counter: Some(5),
+            ..credential
+        };
+
+        assert_eq!(
+            PublicKeyCredentialSource::try_from(cbor::Value::from(credential.clone())),
+            Ok(credential.clone())
+        );
+
+        let credential = PublicKeyCredentialSource {
+            large_blob_key: Some(vec![0x1B; 32]),
+            ..credential
+        };
+
         assert_eq!(
             PublicKeyCredentialSource::try_from(cbor::Value::from(credential.clone())),
             Ok(credential)
@@ -0,0 +1,239 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::HidPacket;
+use alloc::collections::VecDeque;
+use libtock_drivers::timer::Duration;
+use libtock_drivers::usb_ctap_hid::SendOrRecvStatus;
+
+/// HID connection for tests, replacing the `usb_ctap_hid` driver.
+///
+/// Tests inject the packets sent by the host, and pop the packets sent by the authenticator.
+/// Nothing ever waits: operations that would wait for the host time out immediately.
+#[derive(Default)]
+pub struct LoopbackHid {
+    // Packets sent by the host, not yet received by the authenticator.
+    host_packets: VecDeque<HidPacket>,
+    // Packets sent by the authenticator, not yet read by the host.
+    authenticator_packets: VecDeque<HidPacket>,
+}
+
+impl LoopbackHid {
+    pub fn new() -> LoopbackHid {
+        LoopbackHid::default()
+    }
+
+    /// Queues a packet sent by the host.
+    pub fn inject(&mut self, packet: HidPacket) {
+        self.host_packets.push_back(packet);
+    }
+
+    /// Returns the oldest packet sent by the authenticator that was not popped yet.
+    pub fn pop_sent(&mut self) -> Option<HidPacket> {
+        self.authenticator_packets.pop_front()
+    }
+
+    /// Receives a packet like `usb_ctap_hid::recv_with_timeout`.
+    ///
+    /// Returns `None` for a timeout if no injected packet is queued.
+    pub fn recv_with_timeout(
+        &mut self,
+        buf: &mut HidPacket,
+        _timeout: Duration<isize>,
+    ) -> Option<SendOrRecvStatus> {
+        *buf = self.host_packets.pop_front()?;
+        Some(SendOrRecvStatus::Received)
+    }
+
+    /// Sends a packet like `usb_ctap_hid::send_or_recv_with_timeout`.
+    ///
+    /// If an injected packet is queued, it is received instead, as if the host sent it first.
+    pub fn send_or_recv_with_timeout(
+        &mut self,
+        buf: &mut HidPacket,
+        _timeout: Duration<isize>,
+    ) -> Option<SendOrRecvStatus> {
+        match self.host_packets.pop_front() {
+            Some(packet) => {
+                *buf = packet;
+                Some(SendOrRecvStatus::Received)
+            }
+            None => {
+                self.authenticator_packets.push_back(*buf);
+                Some(SendOrRecvStatus::Sent)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::receive::MessageAssembler;
+    use super::super::send::{send_all, HidPacketIterator};
+    use super::super::{ChannelID, CtapHid, KeepaliveStatus, Message};
+    use super::*;
+    use crate::ctap::status_code::Ctap2StatusCode;
+    use crate::ctap::CtapState;
+    use arrayref::array_ref;
+    use crypto::rng256::ThreadRng256;
+    use libtock_drivers::timer::{ClockValue, Timestamp};
+
+    const CLOCK_FREQUENCY_HZ: usize = 32768;
+    const DUMMY_CLOCK_VALUE: ClockValue = ClockValue::new(0, CLOCK_FREQUENCY_HZ);
+    const DUMMY_TIMESTAMP: Timestamp<isize> = Timestamp::from_ms(0);
+    const DUMMY_TIMEOUT: Duration<isize> = Duration::from_ms(100);
+
+    // Sends a message through the loopback, processes the received packets like the main loop
+    // and returns the reply read from the loopback.
+    fn round_trip<CheckUserPresence>(
+        loopback: &mut LoopbackHid,
+        ctap_hid: &mut CtapHid,
+        ctap_state: &mut CtapState<ThreadRng256, CheckUserPresence>,
+        message: Message,
+    ) -> Message
+    where
+        CheckUserPresence: Fn(ChannelID) -> Result<(), Ctap2StatusCode>,
+    {
+        for packet in HidPacketIterator::new(message).unwrap() {
+            loopback.inject(packet);
+        }
+
+        let mut pkt_request = [0; 64];
+        while loopback
+            .recv_with_timeout(&mut pkt_request, DUMMY_TIMEOUT)
+            .is_some()
+        {
+            let mut reply =
+                ctap_hid.process_hid_packet(&pkt_request, DUMMY_CLOCK_VALUE, ctap_state);
+            let mut pkt_reply = [0; 64];
+            let status = send_all(&mut reply, &mut pkt_reply, |pkt| {
+                loopback.send_or_recv_with_timeout(pkt, DUMMY_TIMEOUT)
+            });
+            assert!(status == Some(SendOrRecvStatus::Sent));
+        }
+
+        let mut assembler = MessageAssembler::new();
+        while let Some(packet) = loopback.pop_sent() {
+            if let Some(message) = assembler.parse_packet(&packet, DUMMY_TIMESTAMP).unwrap() {
+                return message;
+            }
+        }
+        panic!("The authenticator did not send a complete reply.");
+    }
+
+    #[test]
+    fn test_recv_timeout() {
+        let mut loopback = LoopbackHid::new();
+        let mut buf = [0; 64];
+        assert!(loopback
+            .recv_with_timeout(&mut buf, DUMMY_TIMEOUT)
+            .is_none());
+
+        loopback.inject([0x55; 64]);
+        assert!(
+            loopback.recv_with_timeout(&mut buf, DUMMY_TIMEOUT) == Some(SendOrRecvStatus::Received)
+        );
+        assert_eq!(&buf[..], &[0x55; 64][..]);
+        assert!(loopback
+            .recv_with_timeout(&mut buf, DUMMY_TIMEOUT)
+            .is_none());
+    }
+
+    #[test]
+    fn test_send_keepalive() {
+        let cid = [0x12, 0x34, 0x56, 0x78];
+        let mut loopback = LoopbackHid::new();
+        assert_eq!(
+            CtapHid::send_keepalive(cid, KeepaliveStatus::UpNeeded, |pkt| {
+                loopback.send_or_recv_with_timeout(pkt, DUMMY_TIMEOUT)
+            }),
+            Ok(())
+        );
+        let keepalive = loopback.pop_sent().unwrap();
+        assert_eq!(&keepalive[..4], &cid);
+        assert_eq!(&keepalive[4..8], &[0xBB, 0x00, 0x01, 0x02]);
+        assert!(loopback.pop_sent().is_none());
+    }
+
+    #[test]
+    fn test_send_keepalive_cancel() {
+        let cid = [0x12, 0x34, 0x56, 0x78];
+        let mut loopback = LoopbackHid::new();
+        let mut cancel = [0x00; 64];
+        cancel[..4].copy_from_slice(&cid);
+        cancel[4] = 0x80 | CtapHid::COMMAND_CANCEL;
+        loopback.inject(cancel);
+        assert_eq!(
+            CtapHid::send_keepalive(cid, KeepaliveStatus::UpNeeded, |pkt| {
+                loopback.send_or_recv_with_timeout(pkt, DUMMY_TIMEOUT)
+            }),
+            Err(Ctap2StatusCode::CTAP2_ERR_KEEPALIVE_CANCEL)
+        );
+        assert!(loopback.pop_sent().is_none());
+    }
+
+    #[test]
+    fn test_send_keepalive_other_channel_busy() {
+        let cid = [0x12, 0x34, 0x56, 0x78];
+        let other_cid = [0x87, 0x65, 0x43, 0x21];
+        let mut loopback = LoopbackHid::new();
+        let mut ping = [0x00; 64];
+        ping[..4].copy_from_slice(&other_cid);
+        ping[4] = 0x80 | CtapHid::COMMAND_PING;
+        loopback.inject(ping);
+        assert_eq!(
+            CtapHid::send_keepalive(cid, KeepaliveStatus::UpNeeded, |pkt| {
+                loopback.send_or_recv_with_timeout(pkt, DUMMY_TIMEOUT)
+            }),
+            Ok(())
+        );
+        let busy_reply = loopback.pop_sent().unwrap();
+        assert_eq!(&busy_reply[..4], &other_cid);
+        assert_eq!(&busy_reply[4..8], &[0xBF, 0x00, 0x01, 0x06]);
+        assert!(loopback.pop_sent().is_none());
+    }
+
+    #[test]
+    fn test_ping_round_trip() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        let mut ctap_hid = CtapHid::new();
+        let mut loopback = LoopbackHid::new();
+
+        let nonce = vec![0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC, 0xDE, 0xF0];
+        let init_reply = round_trip(
+            &mut loopback,
+            &mut ctap_hid,
+            &mut ctap_state,
+            Message {
+                cid: CtapHid::CHANNEL_BROADCAST,
+                cmd: CtapHid::COMMAND_INIT,
+                payload: nonce.clone(),
+            },
+        );
+        assert_eq!(&init_reply.payload[..8], &nonce[..]);
+        let cid = *array_ref!(init_reply.payload, 8, 4);
+
+        // The ping payload spans several packets.
+        let ping = Message {
+            cid,
+            cmd: CtapHid::COMMAND_PING,
+            payload: vec![0x99; 100],
+        };
+        let ping_reply = round_trip(&mut loopback, &mut ctap_hid, &mut ctap_state, ping.clone());
+        assert_eq!(ping_reply, ping);
+        assert!(loopback.pop_sent().is_none());
+    }
+}
@@ -12,25 +12,26 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+#[cfg(test)]
+pub mod loopback;
 pub mod receive;
 pub mod send;
 
 use self::receive::MessageAssembler;
-use self::send::HidPacketIterator;
+use self::send::{send_all, HidPacketIterator};
 #[cfg(feature = "with_ctap1")]
 use super::ctap1;
 use super::status_code::Ctap2StatusCode;
 use super::timed_permission::TimedPermission;
 use super::CtapState;
+#[cfg(feature = "debug_ctap")]
+use crate::debug_log::{log, LogLevel};
 use alloc::vec;
 use alloc::vec::Vec;
 use arrayref::{array_ref, array_refs};
-#[cfg(feature = "debug_ctap")]
-use core::fmt::Write;
 use crypto::rng256::Rng256;
-#[cfg(feature = "debug_ctap")]
-use libtock_drivers::console::Console;
 use libtock_drivers::timer::{ClockValue, Duration, Timestamp};
+use libtock_drivers::usb_ctap_hid::SendOrRecvStatus;
 
 // CTAP specification (version 20190130) section 8.1
 // TODO: Channel allocation, section 8.1.3?
@@ -72,6 +73,11 @@ pub struct CtapHid {
     // u32::to/from_be_bytes methods).
     allocated_cids: usize,
     pub wink_permission: TimedPermission,
+    // How long the device winks after a WINK command.
+    wink_duration: Duration<isize>,
+    // The channel with exclusive access after a LOCK command, while the permission is granted.
+    lock_cid: ChannelID,
+    lock_permission: TimedPermission,
 }
 
 #[allow(dead_code)]
@@ -80,12 +86,264 @@ pub enum KeepaliveStatus {
     UpNeeded,
 }
 
+// Returns whether `now` is within `duration` after `start`, or None if the clock frequency changed.
+// Ticks are compared, because the elapsed time in milliseconds is rounded down and would keep the
+// interval open for up to one more millisecond.
+fn is_within(start: ClockValue, duration: Duration<isize>, now: ClockValue) -> Option<bool> {
+    now.wrapping_sub(start)?;
+    let elapsed_ticks = now.num_ticks().wrapping_sub(start.num_ticks());
+    let duration_ticks = start
+        .wrapping_add(duration)
+        .num_ticks()
+        .wrapping_sub(start.num_ticks());
+    Some(elapsed_ticks >= 0 && elapsed_ticks < duration_ticks)
+}
+
+// Decides when to send processing keep-alives while a command is processed. The host expects them
+// regularly, otherwise it may time out waiting for the response.
+#[derive(Clone, Copy)]
+pub struct KeepalivePacer {
+    delay: Duration<isize>,
+    last_keepalive: ClockValue,
+}
+
+impl KeepalivePacer {
+    // The delay starts when the command is received.
+    pub fn new(now: ClockValue, delay: Duration<isize>) -> KeepalivePacer {
+        KeepalivePacer {
+            delay,
+            last_keepalive: now,
+        }
+    }
+
+    // Returns whether a keep-alive is due, and restarts the delay if so.
+    pub fn is_due(&mut self, now: ClockValue) -> bool {
+        match is_within(self.last_keepalive, self.delay, now) {
+            Some(true) => false,
+            // The clock wrapped around, we assume that the whole delay elapsed.
+            _ => {
+                self.last_keepalive = now;
+                true
+            }
+        }
+    }
+}
+
+// Imposes a cooldown before a user presence check that closely follows a timed out one, to
+// discourage rapidly re-triggering the check. The cooldown doubles with each consecutive timeout.
+#[derive(Clone, Copy)]
+pub struct PresenceBackoff {
+    // When the last check timed out, if it did.
+    last_timeout: Option<ClockValue>,
+    consecutive_timeouts: u32,
+}
+
+impl PresenceBackoff {
+    // Checks starting later than this after a timeout have no cooldown.
+    const WINDOW: Duration<isize> = Duration::from_ms(5000);
+    const MIN_COOLDOWN_MS: isize = 1000;
+    const MAX_COOLDOWN_MS: isize = 8000;
+
+    pub fn new() -> PresenceBackoff {
+        PresenceBackoff {
+            last_timeout: None,
+            consecutive_timeouts: 0,
+        }
+    }
+
+    // Returns how long the LEDs stay off and touches are ignored, for a check starting now.
+    pub fn cooldown(&self, now: ClockValue) -> Duration<isize> {
+        if !self.is_within_window(now) {
+            return Duration::from_ms(0);
+        }
+        let shift = core::cmp::min(self.consecutive_timeouts - 1, 3);
+        let cooldown_ms = PresenceBackoff::MIN_COOLDOWN_MS << shift;
+        Duration::from_ms(core::cmp::min(
+            cooldown_ms,
+            PresenceBackoff::MAX_COOLDOWN_MS,
+        ))
+    }
+
+    // Records the outcome of a check that ended now.
+    pub fn record(&mut self, now: ClockValue, timed_out: bool) {
+        if !timed_out {
+            *self = PresenceBackoff::new();
+            return;
+        }
+        if self.is_within_window(now) {
+            self.consecutive_timeouts += 1;
+        } else {
+            self.consecutive_timeouts = 1;
+        }
+        self.last_timeout = Some(now);
+    }
+
+    fn is_within_window(&self, now: ClockValue) -> bool {
+        match self.last_timeout {
+            None => false,
+            Some(last_timeout) => match now.wrapping_sub(last_timeout) {
+                Some(elapsed) => elapsed.ms() >= 0 && elapsed < PresenceBackoff::WINDOW,
+                None => false,
+            },
+        }
+    }
+}
+
+// Tracks how long a button has been held down, for user presence checks that require a continuous
+// hold instead of a single press.
+pub struct ButtonHold {
+    duration: Duration<isize>,
+    // When the current hold started, if a button is down.
+    pressed_since: Option<ClockValue>,
+}
+
+impl ButtonHold {
+    pub fn new(duration: Duration<isize>) -> ButtonHold {
+        ButtonHold {
+            duration,
+            pressed_since: None,
+        }
+    }
+
+    // Starts a hold, unless one is already in progress.
+    pub fn press(&mut self, now: ClockValue) {
+        if self.pressed_since.is_none() {
+            self.pressed_since = Some(now);
+        }
+    }
+
+    pub fn release(&mut self) {
+        self.pressed_since = None;
+    }
+
+    // Returns whether the current hold lasted long enough to confirm user presence.
+    pub fn is_confirmed(&mut self, now: ClockValue) -> bool {
+        let pressed_since = match self.pressed_since {
+            None => return false,
+            Some(pressed_since) => pressed_since,
+        };
+        match now.wrapping_sub(pressed_since) {
+            Some(elapsed) if elapsed.ms() >= 0 => elapsed >= self.duration,
+            // The clock wrapped around, so the hold restarts.
+            _ => {
+                self.pressed_since = Some(now);
+                self.duration.ms() <= 0
+            }
+        }
+    }
+}
+
+// Tracks which buttons are pressed, for user presence checks that require several buttons. Only
+// the first 32 buttons are supported.
+#[derive(Clone, Copy)]
+pub struct ButtonChord {
+    // Bitmask of the required buttons, 0 if any single button is enough.
+    required: u32,
+    // Whether the required buttons have to be held down at the same time.
+    concurrent: bool,
+    // Bitmask of the buttons currently held down.
+    held: u32,
+    // Bitmask of the buttons pressed since the check started.
+    pressed: u32,
+}
+
+impl ButtonChord {
+    pub fn new(required_buttons: &[usize], concurrent: bool) -> ButtonChord {
+        ButtonChord {
+            required: required_buttons
+                .iter()
+                .fold(0, |mask, &button| mask | ButtonChord::mask(button)),
+            concurrent,
+            held: 0,
+            pressed: 0,
+        }
+    }
+
+    // Records a press, and returns whether it counts as a touch, because the chord is held now.
+    pub fn press(&mut self, button: usize) -> bool {
+        let mask = ButtonChord::mask(button);
+        self.held |= mask;
+        self.pressed |= mask;
+        mask & self.relevant() != 0 && self.is_held()
+    }
+
+    // Records a release, and returns whether it ended the chord.
+    pub fn release(&mut self, button: usize) -> bool {
+        let was_held = self.is_held();
+        self.held &= !ButtonChord::mask(button);
+        was_held && !self.is_held()
+    }
+
+    // Returns whether the chord is complete and still held down.
+    pub fn is_held(&self) -> bool {
+        if self.concurrent {
+            self.covers(self.held)
+        } else {
+            self.covers(self.pressed) && self.held & self.relevant() != 0
+        }
+    }
+
+    fn mask(button: usize) -> u32 {
+        1u32.checked_shl(button as u32).unwrap_or(0)
+    }
+
+    // Returns the bitmask of buttons that take part in the chord.
+    fn relevant(&self) -> u32 {
+        if self.required == 0 {
+            u32::MAX
+        } else {
+            self.required
+        }
+    }
+
+    fn covers(&self, buttons: u32) -> bool {
+        if self.required == 0 {
+            buttons != 0
+        } else {
+            buttons & self.required == self.required
+        }
+    }
+}
+
+// Detects two button presses in quick succession, used as a gesture to cancel user presence checks.
+pub struct DoubleTap {
+    window: Duration<isize>,
+    // When the last press happened, if it can still start a double tap.
+    last_tap: Option<ClockValue>,
+}
+
+impl DoubleTap {
+    pub fn new(window: Duration<isize>) -> DoubleTap {
+        DoubleTap {
+            window,
+            last_tap: None,
+        }
+    }
+
+    // Records a press, and returns whether it completes a double tap.
+    pub fn tap(&mut self, now: ClockValue) -> bool {
+        let is_double_tap = self.is_pending(now);
+        self.last_tap = if is_double_tap { None } else { Some(now) };
+        is_double_tap
+    }
+
+    // Returns whether a press now would complete a double tap.
+    pub fn is_pending(&self, now: ClockValue) -> bool {
+        match self.last_tap {
+            None => false,
+            Some(last_tap) => is_within(last_tap, self.window, now).unwrap_or(false),
+        }
+    }
+}
+
 #[allow(dead_code)]
 // TODO(kaczmarczyck) disable the warning in the end
 impl CtapHid {
     // CTAP specification (version 20190130) section 8.1.3
     const CHANNEL_RESERVED: ChannelID = [0, 0, 0, 0];
     const CHANNEL_BROADCAST: ChannelID = [0xFF, 0xFF, 0xFF, 0xFF];
+    // All IDs between the reserved and the broadcast channel can be allocated.
+    const MAX_ALLOCATED_CIDS: usize = 0xFFFF_FFFE;
     const TYPE_INIT_BIT: u8 = 0x80;
     const PACKET_TYPE_MASK: u8 = 0x80;
 
@@ -94,13 +352,14 @@ impl CtapHid {
     const COMMAND_MSG: u8 = 0x03;
     const COMMAND_INIT: u8 = 0x06;
     const COMMAND_CBOR: u8 = 0x10;
-    pub const COMMAND_CANCEL: u8 = 0x11;
+    const COMMAND_CANCEL: u8 = 0x11;
     const COMMAND_KEEPALIVE: u8 = 0x3B;
     const COMMAND_ERROR: u8 = 0x3F;
-    // TODO: optional lock command
     const COMMAND_LOCK: u8 = 0x04;
     const COMMAND_WINK: u8 = 0x08;
     const COMMAND_VENDOR_FIRST: u8 = 0x40;
+    // Vendor command returning the buffered debug log output.
+    const COMMAND_VENDOR_DEBUG_LOG: u8 = 0x41;
     const COMMAND_VENDOR_LAST: u8 = 0x7F;
 
     // CTAP specification (version 20190130) section 8.1.9.1.6
@@ -114,6 +373,10 @@ impl CtapHid {
     const ERR_INVALID_CHANNEL: u8 = 0x0B;
     const ERR_OTHER: u8 = 0x7F;
 
+    // Maximum payload length of a message, split into one initialization and 128 continuation
+    // packets.
+    pub const MAX_PAYLOAD_LENGTH: usize = 7609;
+
     // CTAP specification (version 20190130) section 8.1.9.1.3
     const PROTOCOL_VERSION: u8 = 2;
 
@@ -132,18 +395,39 @@ impl CtapHid {
     const CAPABILITIES: u8 =
         CtapHid::CAPABILITY_WINK | CtapHid::CAPABILITY_CBOR | CtapHid::CAPABILITY_NMSG;
 
-    // TODO: Is this timeout duration specified?
-    const TIMEOUT_DURATION: Duration<isize> = Duration::from_ms(100);
-    const WINK_TIMEOUT_DURATION: Duration<isize> = Duration::from_ms(5000);
+    // Maximum delay between two packets of the same message. After it elapses, the partial message
+    // is discarded and the client receives ERR_MSG_TIMEOUT.
+    const TIMEOUT_DURATION: Duration<isize> = Duration::from_ms(500);
+    const DEFAULT_WINK_DURATION: Duration<isize> = Duration::from_ms(5000);
+    // CTAP specification (version 20190130) section 8.1.9.2.2
+    const MAX_LOCK_SECONDS: u8 = 10;
 
     pub fn new() -> CtapHid {
         CtapHid {
             assembler: MessageAssembler::new(),
             allocated_cids: 0,
             wink_permission: TimedPermission::waiting(),
+            wink_duration: CtapHid::DEFAULT_WINK_DURATION,
+            lock_cid: CtapHid::CHANNEL_RESERVED,
+            lock_permission: TimedPermission::waiting(),
         }
     }
 
+    // Changes how long the device winks after subsequent WINK commands.
+    pub fn set_wink_duration(&mut self, wink_duration: Duration<isize>) {
+        self.wink_duration = wink_duration;
+    }
+
+    // Returns whether the LEDs should show the wink pattern.
+    pub fn should_wink(&self, now: ClockValue) -> bool {
+        self.wink_permission.is_granted(now)
+    }
+
+    // Returns how long the LEDs keep showing the wink pattern, zero if they don't.
+    pub fn wink_remaining(&self, now: ClockValue) -> Duration<isize> {
+        self.wink_permission.remaining(now)
+    }
+
     // Process an incoming USB HID packet, and optionally returns a list of outgoing packets to
     // send as a reply.
     pub fn process_hid_packet<R, CheckUserPresence>(
@@ -161,138 +445,7 @@ impl CtapHid {
             .assembler
             .parse_packet(packet, Timestamp::<isize>::from_clock_value(clock_value))
         {
-            Ok(Some(message)) => {
-                #[cfg(feature = "debug_ctap")]
-                writeln!(&mut Console::new(), "Received message: {:02x?}", message).unwrap();
-
-                let cid = message.cid;
-                if !self.has_valid_channel(&message) {
-                    #[cfg(feature = "debug_ctap")]
-                    writeln!(&mut Console::new(), "Invalid channel: {:02x?}", cid).unwrap();
-                    return CtapHid::error_message(cid, CtapHid::ERR_INVALID_CHANNEL);
-                }
-                // If another command arrives, stop winking to prevent accidential button touches.
-                self.wink_permission = TimedPermission::waiting();
-
-                match message.cmd {
-                    // CTAP specification (version 20190130) section 8.1.9.1.1
-                    CtapHid::COMMAND_MSG => {
-                        // If we don't have CTAP1 backward compatibilty, this command in invalid.
-                        #[cfg(not(feature = "with_ctap1"))]
-                        return CtapHid::error_message(cid, CtapHid::ERR_INVALID_CMD);
-
-                        #[cfg(feature = "with_ctap1")]
-                        match ctap1::Ctap1Command::process_command(
-                            &message.payload,
-                            ctap_state,
-                            clock_value,
-                        ) {
-                            Ok(payload) => CtapHid::ctap1_success_message(cid, &payload),
-                            Err(ctap1_status_code) => {
-                                CtapHid::ctap1_error_message(cid, ctap1_status_code)
-                            }
-                        }
-                    }
-                    // CTAP specification (version 20190130) section 8.1.9.1.2
-                    CtapHid::COMMAND_CBOR => {
-                        // CTAP specification (version 20190130) section 8.1.5.1
-                        // Each transaction is atomic, so we process the command directly here and
-                        // don't handle any other packet in the meantime.
-                        // TODO: Send keep-alive packets in the meantime.
-                        let response =
-                            ctap_state.process_command(&message.payload, cid, clock_value);
-                        if let Some(iterator) = CtapHid::split_message(Message {
-                            cid,
-                            cmd: CtapHid::COMMAND_CBOR,
-                            payload: response,
-                        }) {
-                            iterator
-                        } else {
-                            // Handle the case of a payload > 7609 bytes.
-                            // Although this shouldn't happen if the FIDO2 commands are implemented
-                            // correctly, we reply with a vendor specific code instead of silently
-                            // ignoring the error.
-                            //
-                            // The error payload that we send instead is 1 <= 7609 bytes, so it is
-                            // safe to unwrap() the result.
-                            CtapHid::split_message(Message {
-                                cid,
-                                cmd: CtapHid::COMMAND_CBOR,
-                                payload: vec![
-                                    Ctap2StatusCode::CTAP2_ERR_VENDOR_RESPONSE_TOO_LONG as u8,
-                                ],
-                            })
-                            .unwrap()
-                        }
-                    }
-                    // CTAP specification (version 20190130) section 8.1.9.1.3
-                    CtapHid::COMMAND_INIT => {
-                        if message.payload.len() != 8 {
-                            return CtapHid::error_message(cid, CtapHid::ERR_INVALID_LEN);
-                        }
-
-                        let new_cid = if cid == CtapHid::CHANNEL_BROADCAST {
-                            // TODO: Prevent allocating 2^32 channels.
-                            self.allocated_cids += 1;
-                            (self.allocated_cids as u32).to_be_bytes()
-                        } else {
-                            // Sync the channel and discard the current transaction.
-                            cid
-                        };
-
-                        let mut payload = vec![0; 17];
-                        payload[..8].copy_from_slice(&message.payload);
-                        payload[8..12].copy_from_slice(&new_cid);
-                        payload[12] = CtapHid::PROTOCOL_VERSION;
-                        payload[13] = CtapHid::DEVICE_VERSION_MAJOR;
-                        payload[14] = CtapHid::DEVICE_VERSION_MINOR;
-                        payload[15] = CtapHid::DEVICE_VERSION_BUILD;
-                        payload[16] = CtapHid::CAPABILITIES;
-
-                        // This unwrap is safe because the payload length is 17 <= 7609 bytes.
-                        CtapHid::split_message(Message {
-                            cid,
-                            cmd: CtapHid::COMMAND_INIT,
-                            payload,
-                        })
-                        .unwrap()
-                    }
-                    // CTAP specification (version 20190130) section 8.1.9.1.4
-                    CtapHid::COMMAND_PING => {
-                        // Pong the same message.
-                        // This unwrap is safe because if we could parse the incoming message, it's
-                        // payload length must be <= 7609 bytes.
-                        CtapHid::split_message(message).unwrap()
-                    }
-                    // CTAP specification (version 20190130) section 8.1.9.1.5
-                    CtapHid::COMMAND_CANCEL => {
-                        // Authenticators MUST NOT reply to this message.
-                        // CANCEL is handled during user presence checks in main.
-                        HidPacketIterator::none()
-                    }
-                    // Optional commands
-                    // CTAP specification (version 20190130) section 8.1.9.2.1
-                    CtapHid::COMMAND_WINK => {
-                        if !message.payload.is_empty() {
-                            return CtapHid::error_message(cid, CtapHid::ERR_INVALID_LEN);
-                        }
-                        self.wink_permission =
-                            TimedPermission::granted(clock_value, CtapHid::WINK_TIMEOUT_DURATION);
-                        CtapHid::split_message(Message {
-                            cid,
-                            cmd: CtapHid::COMMAND_WINK,
-                            payload: vec![],
-                        })
-                        .unwrap()
-                    }
-                    // CTAP specification (version 20190130) section 8.1.9.2.2
-                    // TODO: implement LOCK
-                    _ => {
-                        // Unknown or unsupported command.
-                        CtapHid::error_message(cid, CtapHid::ERR_INVALID_CMD)
-                    }
-                }
-            }
+            Ok(Some(message)) => self.process_message(message, clock_value, ctap_state),
             Ok(None) => {
                 // Waiting for more packets to assemble the message, nothing to send for now.
                 HidPacketIterator::none()
@@ -331,6 +484,221 @@ impl CtapHid {
         }
     }
 
+    /// Processes a complete message and returns the packets of the reply.
+    ///
+    /// Messages are usually assembled from packets by `process_hid_packet`. Calling this directly
+    /// skips the assembly, which is useful for fuzzing the command handling.
+    pub fn process_message<R, CheckUserPresence>(
+        &mut self,
+        message: Message,
+        clock_value: ClockValue,
+        ctap_state: &mut CtapState<R, CheckUserPresence>,
+    ) -> HidPacketIterator
+    where
+        R: Rng256,
+        CheckUserPresence: Fn(ChannelID) -> Result<(), Ctap2StatusCode>,
+    {
+        #[cfg(feature = "debug_ctap")]
+        log(
+            LogLevel::Trace,
+            format_args!("Received message: {:02x?}", message),
+        );
+
+        let cid = message.cid;
+        if !self.has_valid_channel(&message) {
+            #[cfg(feature = "debug_ctap")]
+            log(
+                LogLevel::Warn,
+                format_args!("Invalid channel: {:02x?}", cid),
+            );
+            return CtapHid::error_message(cid, CtapHid::ERR_INVALID_CHANNEL);
+        }
+        // Assembled messages always fit, but messages passed directly might not.
+        if message.payload.len() > CtapHid::MAX_PAYLOAD_LENGTH {
+            return CtapHid::error_message(cid, CtapHid::ERR_INVALID_LEN);
+        }
+        // An expired lock is released.
+        self.lock_permission = self.lock_permission.check_expiration(clock_value);
+        if self.is_locked_for(cid, clock_value) {
+            return CtapHid::error_message(cid, CtapHid::ERR_CHANNEL_BUSY);
+        }
+        // If another command arrives, stop winking to prevent accidential button touches.
+        self.wink_permission = TimedPermission::waiting();
+
+        match message.cmd {
+            // CTAP specification (version 20190130) section 8.1.9.1.1
+            CtapHid::COMMAND_MSG => {
+                // If we don't have CTAP1 backward compatibilty, this command in invalid.
+                #[cfg(not(feature = "with_ctap1"))]
+                return CtapHid::error_message(cid, CtapHid::ERR_INVALID_CMD);
+
+                #[cfg(feature = "with_ctap1")]
+                match ctap1::Ctap1Command::process_command(
+                    &message.payload,
+                    ctap_state,
+                    clock_value,
+                ) {
+                    Ok(payload) => CtapHid::ctap1_success_message(cid, &payload),
+                    Err(ctap1_status_code) => CtapHid::ctap1_error_message(cid, ctap1_status_code),
+                }
+            }
+            // CTAP specification (version 20190130) section 8.1.9.1.2
+            CtapHid::COMMAND_CBOR => {
+                // CTAP specification (version 20190130) section 8.1.5.1
+                // Each transaction is atomic, so we process the command directly here and
+                // don't handle any other packet in the meantime.
+                // Long operations send processing keep-alives in the meantime, see
+                // CtapState::set_processing_keepalive.
+                let response = ctap_state.process_command(&message.payload, cid, clock_value);
+                if let Some(iterator) = CtapHid::split_message(Message {
+                    cid,
+                    cmd: CtapHid::COMMAND_CBOR,
+                    payload: response,
+                }) {
+                    iterator
+                } else {
+                    // Handle the case of a payload > 7609 bytes.
+                    // Although this shouldn't happen if the FIDO2 commands are implemented
+                    // correctly, we reply with a vendor specific code instead of silently
+                    // ignoring the error.
+                    //
+                    // The error payload that we send instead is 1 <= 7609 bytes, so it is
+                    // safe to unwrap() the result.
+                    CtapHid::split_message(Message {
+                        cid,
+                        cmd: CtapHid::COMMAND_CBOR,
+                        payload: vec![Ctap2StatusCode::CTAP2_ERR_VENDOR_RESPONSE_TOO_LONG as u8],
+                    })
+                    .unwrap()
+                }
+            }
+            // CTAP specification (version 20190130) section 8.1.9.1.3
+            CtapHid::COMMAND_INIT => {
+                if message.payload.len() != 8 {
+                    return CtapHid::error_message(cid, CtapHid::ERR_INVALID_LEN);
+                }
+
+                let new_cid = if cid == CtapHid::CHANNEL_BROADCAST {
+                    // Allocating incrementally never returns an active channel. Once all IDs
+                    // are used, wrapping around would hijack one, so we refuse instead.
+                    if self.allocated_cids >= CtapHid::MAX_ALLOCATED_CIDS {
+                        return CtapHid::error_message(cid, CtapHid::ERR_CHANNEL_BUSY);
+                    }
+                    self.allocated_cids += 1;
+                    (self.allocated_cids as u32).to_be_bytes()
+                } else {
+                    // Sync the channel and discard the current transaction.
+                    cid
+                };
+
+                let mut payload = vec![0; 17];
+                payload[..8].copy_from_slice(&message.payload);
+                payload[8..12].copy_from_slice(&new_cid);
+                payload[12] = CtapHid::PROTOCOL_VERSION;
+                payload[13] = CtapHid::DEVICE_VERSION_MAJOR;
+                payload[14] = CtapHid::DEVICE_VERSION_MINOR;
+                payload[15] = CtapHid::DEVICE_VERSION_BUILD;
+                payload[16] = CtapHid::CAPABILITIES;
+
+                // This unwrap is safe because the payload length is 17 <= 7609 bytes.
+                CtapHid::split_message(Message {
+                    cid,
+                    cmd: CtapHid::COMMAND_INIT,
+                    payload,
+                })
+                .unwrap()
+            }
+            // CTAP specification (version 20190130) section 8.1.9.1.4
+            CtapHid::COMMAND_PING => {
+                // Pong the same message.
+                // This unwrap is safe because the payload length was checked to be <= 7609 bytes.
+                CtapHid::split_message(message).unwrap()
+            }
+            // CTAP specification (version 20190130) section 8.1.9.1.5
+            CtapHid::COMMAND_CANCEL => {
+                // Authenticators MUST NOT reply to this message.
+                // CANCEL is handled during user presence checks in main.
+                HidPacketIterator::none()
+            }
+            // Optional commands
+            // CTAP specification (version 20190130) section 8.1.9.2.1
+            CtapHid::COMMAND_WINK => {
+                if !message.payload.is_empty() {
+                    return CtapHid::error_message(cid, CtapHid::ERR_INVALID_LEN);
+                }
+                self.wink_permission = TimedPermission::granted(clock_value, self.wink_duration);
+                CtapHid::split_message(Message {
+                    cid,
+                    cmd: CtapHid::COMMAND_WINK,
+                    payload: vec![],
+                })
+                .unwrap()
+            }
+            // CTAP specification (version 20190130) section 8.1.9.2.2
+            CtapHid::COMMAND_LOCK => {
+                if message.payload.len() != 1 {
+                    return CtapHid::error_message(cid, CtapHid::ERR_INVALID_LEN);
+                }
+                let lock_seconds = message.payload[0];
+                if lock_seconds > CtapHid::MAX_LOCK_SECONDS {
+                    return CtapHid::error_message(cid, CtapHid::ERR_INVALID_PAR);
+                }
+                // A lock time of 0 releases the lock.
+                self.lock_permission = if lock_seconds == 0 {
+                    TimedPermission::waiting()
+                } else {
+                    TimedPermission::granted(
+                        clock_value,
+                        Duration::from_ms(1000 * lock_seconds as isize),
+                    )
+                };
+                self.lock_cid = cid;
+                CtapHid::split_message(Message {
+                    cid,
+                    cmd: CtapHid::COMMAND_LOCK,
+                    payload: vec![],
+                })
+                .unwrap()
+            }
+            #[cfg(feature = "debug_hid")]
+            CtapHid::COMMAND_VENDOR_DEBUG_LOG => {
+                if !message.payload.is_empty() {
+                    return CtapHid::error_message(cid, CtapHid::ERR_INVALID_LEN);
+                }
+                // Output that doesn't fit is returned on the next command.
+                let payload = crate::debug_log::log_buffer().drain(CtapHid::MAX_PAYLOAD_LENGTH);
+                // This unwrap is safe because the payload length is <= 7609 bytes.
+                CtapHid::split_message(Message {
+                    cid,
+                    cmd: CtapHid::COMMAND_VENDOR_DEBUG_LOG,
+                    payload,
+                })
+                .unwrap()
+            }
+            _ => {
+                // Unknown or unsupported command.
+                CtapHid::error_message(cid, CtapHid::ERR_INVALID_CMD)
+            }
+        }
+    }
+
+    // Returns whether a message is partially received, so more packets are expected soon.
+    pub fn has_pending_message(&self) -> bool {
+        !self.assembler.is_idle()
+    }
+
+    // Discards a partially received message if its channel timed out, and optionally returns the
+    // timeout error to send on that channel. This should be called when no packet was received.
+    pub fn check_timeout(&mut self, clock_value: ClockValue) -> HidPacketIterator {
+        match self
+            .assembler
+            .check_timeout(Timestamp::<isize>::from_clock_value(clock_value))
+        {
+            Some(cid) => CtapHid::error_message(cid, CtapHid::ERR_MSG_TIMEOUT),
+            None => HidPacketIterator::none(),
+        }
+    }
+
     fn has_valid_channel(&self, message: &Message) -> bool {
         match message.cid {
             // Only INIT commands use the broadcast channel.
@@ -340,6 +708,11 @@ impl CtapHid {
         }
     }
 
+    // Returns whether another channel than cid holds the lock.
+    fn is_locked_for(&self, cid: ChannelID, now: ClockValue) -> bool {
+        self.lock_permission.is_granted(now) && self.lock_cid != cid
+    }
+
     fn is_allocated_channel(&self, cid: ChannelID) -> bool {
         cid != CtapHid::CHANNEL_RESERVED && u32::from_be_bytes(cid) as usize <= self.allocated_cids
     }
@@ -378,9 +751,88 @@ impl CtapHid {
         }
     }
 
+    // Returns whether a packet received while waiting for user presence on the channel `cid` is a
+    // CANCEL for this channel. Packets on other channels are ignored, as are continuation packets
+    // and other commands.
+    pub fn is_cancel_packet(packet: &HidPacket, cid: &ChannelID) -> bool {
+        let (received_cid, processed_packet) = CtapHid::process_single_packet(packet);
+        if received_cid != cid {
+            return false;
+        }
+        match processed_packet {
+            ProcessedPacket::InitPacket { cmd, .. } => cmd == CtapHid::COMMAND_CANCEL,
+            ProcessedPacket::ContinuationPacket { .. } => false,
+        }
+    }
+
+    // Returns the reply to a packet received while a transaction is in progress on `cid`.
+    // Commands from other channels are rejected with ERR_CHANNEL_BUSY. Other packets get no reply,
+    // and the caller checks for CANCEL separately.
+    pub fn busy_reply(packet: &HidPacket, cid: &ChannelID) -> HidPacketIterator {
+        let (received_cid, processed_packet) = CtapHid::process_single_packet(packet);
+        match processed_packet {
+            ProcessedPacket::InitPacket { .. } if received_cid != cid => {
+                CtapHid::error_message(*received_cid, CtapHid::ERR_CHANNEL_BUSY)
+            }
+            _ => HidPacketIterator::none(),
+        }
+    }
+
+    // Sends a keep-alive on the channel `cid`, through any HID connection exposed by
+    // `send_or_recv`. Packets received meanwhile are answered with `busy_reply`, and a CANCEL on
+    // `cid` aborts with CTAP2_ERR_KEEPALIVE_CANCEL.
+    pub fn send_keepalive(
+        cid: ChannelID,
+        status: KeepaliveStatus,
+        mut send_or_recv: impl FnMut(&mut HidPacket) -> Option<SendOrRecvStatus>,
+    ) -> Result<(), Ctap2StatusCode> {
+        for mut pkt in CtapHid::keepalive(cid, status) {
+            match send_or_recv(&mut pkt) {
+                None => {
+                    #[cfg(feature = "debug_ctap")]
+                    log(
+                        LogLevel::Warn,
+                        format_args!("Sending a KEEPALIVE packet timed out"),
+                    );
+                    // TODO: abort user presence test?
+                }
+                Some(SendOrRecvStatus::Error) => panic!("Error sending KEEPALIVE packet"),
+                Some(SendOrRecvStatus::Sent) => {
+                    #[cfg(feature = "debug_ctap")]
+                    log(LogLevel::Trace, format_args!("Sent KEEPALIVE packet"));
+                }
+                Some(SendOrRecvStatus::Received) => {
+                    // We only parse one packet, because we only care about CANCEL.
+                    if CtapHid::is_cancel_packet(&pkt, &cid) {
+                        // We ignore the payload, we can't answer with an error code anyway.
+                        #[cfg(feature = "debug_ctap")]
+                        log(
+                            LogLevel::Info,
+                            format_args!("Cancelled while sending a KEEPALIVE packet"),
+                        );
+                        return Err(Ctap2StatusCode::CTAP2_ERR_KEEPALIVE_CANCEL);
+                    }
+                    // Other channels have to wait until this transaction is over.
+                    let mut busy_reply = CtapHid::busy_reply(&pkt, &cid);
+                    let mut pkt_reply = [0; 64];
+                    send_all(&mut busy_reply, &mut pkt_reply, &mut send_or_recv);
+                    #[cfg(feature = "debug_ctap")]
+                    log(
+                        LogLevel::Warn,
+                        format_args!("Discarded packet received while sending a KEEPALIVE packet"),
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
     fn split_message(message: Message) -> Option<HidPacketIterator> {
         #[cfg(feature = "debug_ctap")]
-        writeln!(&mut Console::new(), "Sending message: {:02x?}", message).unwrap();
+        log(
+            LogLevel::Trace,
+            format_args!("Sending message: {:02x?}", message),
+        );
         HidPacketIterator::new(message)
     }
 
@@ -430,6 +882,7 @@ impl CtapHid {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::clock::DeterministicClock;
     use crypto::rng256::ThreadRng256;
 
     const CLOCK_FREQUENCY_HZ: usize = 32768;
@@ -442,6 +895,18 @@ mod test {
         ctap_state: &mut CtapState<ThreadRng256, CheckUserPresence>,
         request: Vec<Message>,
     ) -> Option<Vec<Message>>
+    where
+        CheckUserPresence: Fn(ChannelID) -> Result<(), Ctap2StatusCode>,
+    {
+        process_messages_at(ctap_hid, ctap_state, request, DUMMY_CLOCK_VALUE)
+    }
+
+    fn process_messages_at<CheckUserPresence>(
+        ctap_hid: &mut CtapHid,
+        ctap_state: &mut CtapState<ThreadRng256, CheckUserPresence>,
+        request: Vec<Message>,
+        clock_value: ClockValue,
+    ) -> Option<Vec<Message>>
     where
         CheckUserPresence: Fn(ChannelID) -> Result<(), Ctap2StatusCode>,
     {
@@ -449,8 +914,7 @@ mod test {
         let mut assembler_reply = MessageAssembler::new();
         for msg_request in request {
             for pkt_request in HidPacketIterator::new(msg_request).unwrap() {
-                for pkt_reply in
-                    ctap_hid.process_hid_packet(&pkt_request, DUMMY_CLOCK_VALUE, ctap_state)
+                for pkt_reply in ctap_hid.process_hid_packet(&pkt_request, clock_value, ctap_state)
                 {
                     match assembler_reply.parse_packet(&pkt_reply, DUMMY_TIMESTAMP) {
                         Ok(Some(message)) => result.push(message),
@@ -537,6 +1001,526 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_partial_message_timeout() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        let mut ctap_hid = CtapHid::new();
+        let cid = cid_from_init(&mut ctap_hid, &mut ctap_state);
+        assert!(!ctap_hid.has_pending_message());
+
+        // Send only the initialization packet of a PING with 0x40 bytes.
+        let mut packet = [0x00; 64];
+        packet[..4].copy_from_slice(&cid);
+        packet[4..7].copy_from_slice(&[0x81, 0x00, 0x40]);
+        assert_eq!(
+            ctap_hid
+                .process_hid_packet(&packet, DUMMY_CLOCK_VALUE, &mut ctap_state)
+                .count(),
+            0
+        );
+        assert!(ctap_hid.has_pending_message());
+
+        // Nothing happens before the timeout.
+        let almost_timeout_clock_value =
+            DUMMY_CLOCK_VALUE.wrapping_add(CtapHid::TIMEOUT_DURATION - Duration::from_ms(1));
+        assert_eq!(
+            ctap_hid.check_timeout(almost_timeout_clock_value).count(),
+            0
+        );
+
+        // The timeout discards the message and reports it on the channel.
+        let timeout_clock_value = DUMMY_CLOCK_VALUE.wrapping_add(CtapHid::TIMEOUT_DURATION);
+        let mut expected_packet = [0x00; 64];
+        expected_packet[..4].copy_from_slice(&cid);
+        expected_packet[4..8].copy_from_slice(&[0xBF, 0x00, 0x01, CtapHid::ERR_MSG_TIMEOUT]);
+        let reply: Vec<HidPacket> = ctap_hid.check_timeout(timeout_clock_value).collect();
+        assert_eq!(reply.len(), 1);
+        assert_eq!(&reply[0][..], &expected_packet[..]);
+        assert_eq!(ctap_hid.check_timeout(timeout_clock_value).count(), 0);
+        assert!(!ctap_hid.has_pending_message());
+
+        // The channel is free again.
+        let mut ping_packet = [0x00; 64];
+        ping_packet[..4].copy_from_slice(&cid);
+        ping_packet[4..8].copy_from_slice(&[0x81, 0x00, 0x01, 0x99]);
+        let reply: Vec<HidPacket> = ctap_hid
+            .process_hid_packet(&ping_packet, timeout_clock_value, &mut ctap_state)
+            .collect();
+        assert_eq!(reply.len(), 1);
+        assert_eq!(&reply[0][..8], &ping_packet[..8]);
+    }
+
+    #[test]
+    fn test_channel_busy_during_assembly() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        let mut ctap_hid = CtapHid::new();
+        let cid1 = cid_from_init(&mut ctap_hid, &mut ctap_state);
+        let cid2 = cid_from_init(&mut ctap_hid, &mut ctap_state);
+
+        // The first channel starts a message that needs a continuation packet.
+        let mut packet = [0x00; 64];
+        packet[..4].copy_from_slice(&cid1);
+        packet[4..7].copy_from_slice(&[0x81, 0x00, 0x40]);
+        assert_eq!(
+            ctap_hid
+                .process_hid_packet(&packet, DUMMY_CLOCK_VALUE, &mut ctap_state)
+                .count(),
+            0
+        );
+
+        let mut packet = [0x00; 64];
+        packet[..4].copy_from_slice(&cid2);
+        packet[4..8].copy_from_slice(&[0x81, 0x00, 0x01, 0x99]);
+        let mut expected_packet = [0x00; 64];
+        expected_packet[..4].copy_from_slice(&cid2);
+        expected_packet[4..8].copy_from_slice(&[0xBF, 0x00, 0x01, CtapHid::ERR_CHANNEL_BUSY]);
+        let reply: Vec<HidPacket> = ctap_hid
+            .process_hid_packet(&packet, DUMMY_CLOCK_VALUE, &mut ctap_state)
+            .collect();
+        assert_eq!(reply.len(), 1);
+        assert_eq!(&reply[0][..], &expected_packet[..]);
+    }
+
+    #[test]
+    fn test_busy_reply() {
+        let cid1 = [0x00, 0x00, 0x00, 0x01];
+        let cid2 = [0x00, 0x00, 0x00, 0x02];
+
+        // A command from another channel is rejected.
+        let mut packet = [0x00; 64];
+        packet[..4].copy_from_slice(&cid2);
+        packet[4..8].copy_from_slice(&[0x90, 0x00, 0x01, 0x04]);
+        let mut expected_packet = [0x00; 64];
+        expected_packet[..4].copy_from_slice(&cid2);
+        expected_packet[4..8].copy_from_slice(&[0xBF, 0x00, 0x01, CtapHid::ERR_CHANNEL_BUSY]);
+        let reply: Vec<HidPacket> = CtapHid::busy_reply(&packet, &cid1).collect();
+        assert_eq!(reply.len(), 1);
+        assert_eq!(&reply[0][..], &expected_packet[..]);
+
+        // Packets on the busy channel and continuation packets get no reply.
+        packet[..4].copy_from_slice(&cid1);
+        assert_eq!(CtapHid::busy_reply(&packet, &cid1).count(), 0);
+        let mut packet = [0x00; 64];
+        packet[..4].copy_from_slice(&cid2);
+        assert_eq!(CtapHid::busy_reply(&packet, &cid1).count(), 0);
+    }
+
+    #[test]
+    fn test_wink_duration() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        let mut ctap_hid = CtapHid::new();
+        let wink_duration = Duration::from_ms(1000);
+        ctap_hid.set_wink_duration(wink_duration);
+        let cid = cid_from_init(&mut ctap_hid, &mut ctap_state);
+        assert!(!ctap_hid.should_wink(DUMMY_CLOCK_VALUE));
+
+        let reply = process_messages(
+            &mut ctap_hid,
+            &mut ctap_state,
+            vec![Message {
+                cid,
+                cmd: CtapHid::COMMAND_WINK,
+                payload: vec![],
+            }],
+        );
+        assert_eq!(
+            reply,
+            Some(vec![Message {
+                cid,
+                cmd: CtapHid::COMMAND_WINK,
+                payload: vec![],
+            }])
+        );
+        let last_wink_clock_value =
+            DUMMY_CLOCK_VALUE.wrapping_add(wink_duration - Duration::from_ms(1));
+        assert!(ctap_hid.should_wink(last_wink_clock_value));
+        assert_eq!(
+            ctap_hid.wink_remaining(last_wink_clock_value),
+            Duration::from_ms(1)
+        );
+        assert!(!ctap_hid.should_wink(DUMMY_CLOCK_VALUE.wrapping_add(wink_duration)));
+        assert_eq!(
+            ctap_hid
+                .wink_remaining(DUMMY_CLOCK_VALUE.wrapping_add(wink_duration))
+                .ms(),
+            0
+        );
+        assert!(
+            !ctap_hid.should_wink(DUMMY_CLOCK_VALUE.wrapping_add(CtapHid::DEFAULT_WINK_DURATION))
+        );
+    }
+
+    #[test]
+    fn test_wink_timeout() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        let mut ctap_hid = CtapHid::new();
+        let cid = cid_from_init(&mut ctap_hid, &mut ctap_state);
+        let mut clock = DeterministicClock::new(Duration::from_ms(100));
+
+        let reply = process_messages_at(
+            &mut ctap_hid,
+            &mut ctap_state,
+            vec![Message {
+                cid,
+                cmd: CtapHid::COMMAND_WINK,
+                payload: vec![],
+            }],
+            clock.get_current_clock(),
+        );
+        assert_eq!(reply.map(|messages| messages.len()), Some(1));
+        assert!(ctap_hid.should_wink(clock.get_current_clock()));
+
+        clock.advance_ms(CtapHid::DEFAULT_WINK_DURATION.ms() as u32 - 1);
+        assert!(ctap_hid.should_wink(clock.get_current_clock()));
+        clock.advance_ms(1);
+        assert!(!ctap_hid.should_wink(clock.get_current_clock()));
+    }
+
+    #[test]
+    fn test_command_lock() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        let mut ctap_hid = CtapHid::new();
+        let cid1 = cid_from_init(&mut ctap_hid, &mut ctap_state);
+        let cid2 = cid_from_init(&mut ctap_hid, &mut ctap_state);
+        let ping1 = Message {
+            cid: cid1,
+            cmd: CtapHid::COMMAND_PING,
+            payload: vec![0x99],
+        };
+        let ping2 = Message {
+            cid: cid2,
+            cmd: CtapHid::COMMAND_PING,
+            payload: vec![0x99],
+        };
+
+        let reply = process_messages(
+            &mut ctap_hid,
+            &mut ctap_state,
+            vec![Message {
+                cid: cid1,
+                cmd: CtapHid::COMMAND_LOCK,
+                payload: vec![2],
+            }],
+        );
+        assert_eq!(
+            reply,
+            Some(vec![Message {
+                cid: cid1,
+                cmd: CtapHid::COMMAND_LOCK,
+                payload: vec![],
+            }])
+        );
+
+        // The lock owner keeps access, other channels are busy.
+        let reply = process_messages(
+            &mut ctap_hid,
+            &mut ctap_state,
+            vec![ping1.clone(), ping2.clone()],
+        );
+        assert_eq!(
+            reply,
+            Some(vec![
+                ping1,
+                Message {
+                    cid: cid2,
+                    cmd: CtapHid::COMMAND_ERROR,
+                    payload: vec![CtapHid::ERR_CHANNEL_BUSY],
+                }
+            ])
+        );
+
+        // After the lock expires, other channels have access again.
+        let last_locked_clock_value = DUMMY_CLOCK_VALUE.wrapping_add(Duration::from_ms(2000 - 1));
+        assert!(ctap_hid.is_locked_for(cid2, last_locked_clock_value));
+        let expired_clock_value = DUMMY_CLOCK_VALUE.wrapping_add(Duration::from_ms(2000));
+        assert!(!ctap_hid.is_locked_for(cid2, expired_clock_value));
+        let reply: Vec<HidPacket> = ctap_hid
+            .process_message(ping2.clone(), expired_clock_value, &mut ctap_state)
+            .collect();
+        let mut expected_packet = [0x00; 64];
+        expected_packet[..4].copy_from_slice(&cid2);
+        expected_packet[4..8].copy_from_slice(&[0x81, 0x00, 0x01, 0x99]);
+        assert_eq!(reply.len(), 1);
+        assert_eq!(&reply[0][..], &expected_packet[..]);
+    }
+
+    #[test]
+    fn test_command_lock_release() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        let mut ctap_hid = CtapHid::new();
+        let cid1 = cid_from_init(&mut ctap_hid, &mut ctap_state);
+        let cid2 = cid_from_init(&mut ctap_hid, &mut ctap_state);
+
+        let reply = process_messages(
+            &mut ctap_hid,
+            &mut ctap_state,
+            vec![
+                Message {
+                    cid: cid1,
+                    cmd: CtapHid::COMMAND_LOCK,
+                    payload: vec![10],
+                },
+                Message {
+                    cid: cid1,
+                    cmd: CtapHid::COMMAND_LOCK,
+                    payload: vec![0],
+                },
+            ],
+        );
+        assert_eq!(reply.map(|messages| messages.len()), Some(2));
+        assert!(!ctap_hid.is_locked_for(cid2, DUMMY_CLOCK_VALUE));
+    }
+
+    #[test]
+    fn test_command_lock_invalid() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        let mut ctap_hid = CtapHid::new();
+        let cid = cid_from_init(&mut ctap_hid, &mut ctap_state);
+
+        let reply = process_messages(
+            &mut ctap_hid,
+            &mut ctap_state,
+            vec![
+                Message {
+                    cid,
+                    cmd: CtapHid::COMMAND_LOCK,
+                    payload: vec![],
+                },
+                Message {
+                    cid,
+                    cmd: CtapHid::COMMAND_LOCK,
+                    payload: vec![CtapHid::MAX_LOCK_SECONDS + 1],
+                },
+            ],
+        );
+        assert_eq!(
+            reply,
+            Some(vec![
+                Message {
+                    cid,
+                    cmd: CtapHid::COMMAND_ERROR,
+                    payload: vec![CtapHid::ERR_INVALID_LEN],
+                },
+                Message {
+                    cid,
+                    cmd: CtapHid::COMMAND_ERROR,
+                    payload: vec![CtapHid::ERR_INVALID_PAR],
+                }
+            ])
+        );
+        assert!(!ctap_hid.is_locked_for([0x12, 0x34, 0x56, 0x78], DUMMY_CLOCK_VALUE));
+    }
+
+    #[cfg(feature = "debug_hid")]
+    #[test]
+    fn test_command_vendor_debug_log() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        let mut ctap_hid = CtapHid::new();
+        let cid = cid_from_init(&mut ctap_hid, &mut ctap_state);
+        let debug_log = Message {
+            cid,
+            cmd: CtapHid::COMMAND_VENDOR_DEBUG_LOG,
+            payload: vec![],
+        };
+
+        // Discard the messages logged so far.
+        process_messages(&mut ctap_hid, &mut ctap_state, vec![debug_log.clone()]);
+        log(LogLevel::Error, format_args!("first"));
+        log(LogLevel::Error, format_args!("second"));
+        // The second reply is logged after the first message was processed.
+        let reply = process_messages(&mut ctap_hid, &mut ctap_state, vec![debug_log.clone()]);
+        let reply = reply.unwrap();
+        assert_eq!(reply.len(), 1);
+        assert!(reply[0]
+            .payload
+            .ends_with(b"[ERROR] first\n[ERROR] second\n"));
+    }
+
+    #[test]
+    fn test_keepalive_pacer() {
+        let delay = Duration::from_ms(100);
+        let mut pacer = KeepalivePacer::new(DUMMY_CLOCK_VALUE, delay);
+        assert!(!pacer.is_due(DUMMY_CLOCK_VALUE));
+        assert!(!pacer.is_due(DUMMY_CLOCK_VALUE.wrapping_add(Duration::from_ms(99))));
+        let first_keepalive = DUMMY_CLOCK_VALUE.wrapping_add(delay);
+        assert!(pacer.is_due(first_keepalive));
+        // The delay restarts with each keep-alive.
+        assert!(!pacer.is_due(first_keepalive.wrapping_add(Duration::from_ms(50))));
+        assert!(pacer.is_due(first_keepalive.wrapping_add(Duration::from_ms(150))));
+    }
+
+    #[test]
+    fn test_keepalive_pacer_boundary() {
+        let delay = Duration::from_ms(100);
+        let mut pacer = KeepalivePacer::new(DUMMY_CLOCK_VALUE, delay);
+        // 100ms are not a whole number of ticks, the delay ends on the rounded down tick.
+        let deadline = DUMMY_CLOCK_VALUE.wrapping_add(delay);
+        let last_tick = ClockValue::new(deadline.num_ticks() - 1, CLOCK_FREQUENCY_HZ);
+        assert!(!pacer.is_due(last_tick));
+        assert!(pacer.is_due(deadline));
+    }
+
+    #[test]
+    fn test_presence_backoff() {
+        let mut backoff = PresenceBackoff::new();
+        assert_eq!(backoff.cooldown(DUMMY_CLOCK_VALUE).ms(), 0);
+
+        // Back-to-back timeouts extend the cooldown, up to the maximum.
+        let mut now = DUMMY_CLOCK_VALUE;
+        for &expected_ms in &[1000, 2000, 4000, 8000, 8000] {
+            backoff.record(now, true);
+            now = now.wrapping_add(Duration::from_ms(100));
+            assert_eq!(backoff.cooldown(now).ms(), expected_ms);
+        }
+
+        // A successful check resets the cooldown.
+        backoff.record(now, false);
+        assert_eq!(backoff.cooldown(now).ms(), 0);
+    }
+
+    #[test]
+    fn test_presence_backoff_window() {
+        let mut backoff = PresenceBackoff::new();
+        backoff.record(DUMMY_CLOCK_VALUE, true);
+        let last_cooldown = DUMMY_CLOCK_VALUE.wrapping_add(Duration::from_ms(4999));
+        assert_eq!(backoff.cooldown(last_cooldown).ms(), 1000);
+        let after_window = DUMMY_CLOCK_VALUE.wrapping_add(PresenceBackoff::WINDOW);
+        assert_eq!(backoff.cooldown(after_window).ms(), 0);
+
+        // A timeout after the window starts counting again.
+        backoff.record(after_window, true);
+        assert_eq!(backoff.cooldown(after_window).ms(), 1000);
+    }
+
+    #[test]
+    fn test_button_hold() {
+        let mut hold = ButtonHold::new(Duration::from_ms(1000));
+        assert!(!hold.is_confirmed(DUMMY_CLOCK_VALUE));
+        hold.press(DUMMY_CLOCK_VALUE);
+        let almost_held = DUMMY_CLOCK_VALUE.wrapping_add(Duration::from_ms(999));
+        assert!(!hold.is_confirmed(almost_held));
+        // Repeated presses don't restart a hold in progress.
+        hold.press(almost_held);
+        let held = DUMMY_CLOCK_VALUE.wrapping_add(Duration::from_ms(1000));
+        assert!(hold.is_confirmed(held));
+    }
+
+    #[test]
+    fn test_button_hold_early_release() {
+        let mut hold = ButtonHold::new(Duration::from_ms(1000));
+        hold.press(DUMMY_CLOCK_VALUE);
+        let release_time = DUMMY_CLOCK_VALUE.wrapping_add(Duration::from_ms(500));
+        assert!(!hold.is_confirmed(release_time));
+        hold.release();
+        let after_release = DUMMY_CLOCK_VALUE.wrapping_add(Duration::from_ms(1000));
+        assert!(!hold.is_confirmed(after_release));
+
+        // The next press restarts the timer.
+        hold.press(after_release);
+        assert!(!hold.is_confirmed(after_release.wrapping_add(Duration::from_ms(999))));
+        assert!(hold.is_confirmed(after_release.wrapping_add(Duration::from_ms(1000))));
+    }
+
+    #[test]
+    fn test_button_hold_single_press() {
+        let mut hold = ButtonHold::new(Duration::from_ms(0));
+        hold.press(DUMMY_CLOCK_VALUE);
+        assert!(hold.is_confirmed(DUMMY_CLOCK_VALUE));
+    }
+
+    #[test]
+    fn test_button_chord_any_button() {
+        let mut chord = ButtonChord::new(&[], true);
+        assert!(!chord.is_held());
+        assert!(chord.press(1));
+        assert!(chord.press(0));
+        assert!(!chord.release(1));
+        assert!(chord.is_held());
+        assert!(chord.release(0));
+        assert!(!chord.is_held());
+    }
+
+    #[test]
+    fn test_button_chord_concurrent() {
+        let mut chord = ButtonChord::new(&[0, 2], true);
+        assert!(!chord.press(0));
+        // Buttons outside the chord don't count.
+        assert!(!chord.press(1));
+        assert!(!chord.release(0));
+        assert!(!chord.press(2));
+        assert!(!chord.is_held());
+        assert!(chord.press(0));
+        assert!(chord.is_held());
+        assert!(chord.release(2));
+        assert!(!chord.is_held());
+    }
+
+    #[test]
+    fn test_button_chord_sequential() {
+        let mut chord = ButtonChord::new(&[0, 2], false);
+        assert!(!chord.press(0));
+        assert!(!chord.release(0));
+        assert!(chord.press(2));
+        assert!(chord.is_held());
+        assert!(chord.release(2));
+        // Pressing one of the buttons again confirms again.
+        assert!(chord.press(0));
+    }
+
+    #[test]
+    fn test_double_tap_single_press() {
+        let window = Duration::from_ms(400);
+        let mut double_tap = DoubleTap::new(window);
+        assert!(!double_tap.is_pending(DUMMY_CLOCK_VALUE));
+        assert!(!double_tap.tap(DUMMY_CLOCK_VALUE));
+        let last_pending = DUMMY_CLOCK_VALUE.wrapping_add(Duration::from_ms(399));
+        assert!(double_tap.is_pending(last_pending));
+        // Once the window closed, the single press confirms.
+        let window_end = DUMMY_CLOCK_VALUE.wrapping_add(window);
+        assert!(!double_tap.is_pending(window_end));
+        // A late second press starts a new window instead of cancelling.
+        assert!(!double_tap.tap(window_end));
+        assert!(double_tap.is_pending(window_end));
+    }
+
+    #[test]
+    fn test_double_tap_window_boundary() {
+        let window = Duration::from_ms(400);
+        let mut double_tap = DoubleTap::new(window);
+        assert!(!double_tap.tap(DUMMY_CLOCK_VALUE));
+        let window_end = DUMMY_CLOCK_VALUE.wrapping_add(window);
+        let last_tick = ClockValue::new(window_end.num_ticks() - 1, CLOCK_FREQUENCY_HZ);
+        assert!(double_tap.is_pending(last_tick));
+        assert!(!double_tap.is_pending(window_end));
+    }
+
+    #[test]
+    fn test_double_tap_cancel() {
+        let mut double_tap = DoubleTap::new(Duration::from_ms(400));
+        assert!(!double_tap.tap(DUMMY_CLOCK_VALUE));
+        let second_tap = DUMMY_CLOCK_VALUE.wrapping_add(Duration::from_ms(200));
+        assert!(double_tap.tap(second_tap));
+        // A third press doesn't count as another double tap.
+        assert!(!double_tap.is_pending(second_tap));
+        assert!(!double_tap.tap(second_tap.wrapping_add(Duration::from_ms(100))));
+    }
+
     #[test]
     fn test_command_init() {
         let mut rng = ThreadRng256 {};
@@ -582,6 +1566,51 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_command_init_distinct_cids() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        let mut ctap_hid = CtapHid::new();
+
+        let cid1 = cid_from_init(&mut ctap_hid, &mut ctap_state);
+        let cid2 = cid_from_init(&mut ctap_hid, &mut ctap_state);
+        assert_ne!(cid1, cid2);
+        assert!(ctap_hid.is_allocated_channel(cid1));
+        assert!(ctap_hid.is_allocated_channel(cid2));
+    }
+
+    #[test]
+    fn test_command_init_exhausted_cids() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        let mut ctap_hid = CtapHid::new();
+        ctap_hid.allocated_cids = CtapHid::MAX_ALLOCATED_CIDS - 1;
+
+        let last_cid = cid_from_init(&mut ctap_hid, &mut ctap_state);
+        assert_eq!(last_cid, [0xFF, 0xFF, 0xFF, 0xFE]);
+
+        // The broadcast channel is never allocated, and IDs don't wrap around.
+        let reply = process_messages(
+            &mut ctap_hid,
+            &mut ctap_state,
+            vec![Message {
+                cid: CtapHid::CHANNEL_BROADCAST,
+                cmd: CtapHid::COMMAND_INIT,
+                payload: vec![0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC, 0xDE, 0xF0],
+            }],
+        );
+        assert_eq!(
+            reply,
+            Some(vec![Message {
+                cid: CtapHid::CHANNEL_BROADCAST,
+                cmd: CtapHid::COMMAND_ERROR,
+                payload: vec![CtapHid::ERR_CHANNEL_BUSY],
+            }])
+        );
+    }
+
     #[test]
     fn test_command_init_for_sync() {
         let mut rng = ThreadRng256 {};
@@ -642,6 +1671,86 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_is_cancel_packet() {
+        let cid = [0x12, 0x34, 0x56, 0x78];
+        let mut packet = [0x00; 64];
+        packet[..4].copy_from_slice(&cid);
+        packet[4..7].copy_from_slice(&[0x91, 0x00, 0x00]);
+        assert!(CtapHid::is_cancel_packet(&packet, &cid));
+
+        // A CANCEL on another channel is ignored.
+        let other_cid = [0x87, 0x65, 0x43, 0x21];
+        assert!(!CtapHid::is_cancel_packet(&packet, &other_cid));
+
+        // A continuation packet is ignored, even if its sequence number looks like CANCEL.
+        packet[4] = CtapHid::COMMAND_CANCEL;
+        assert!(!CtapHid::is_cancel_packet(&packet, &cid));
+
+        // Other commands are ignored.
+        packet[4] = 0x81;
+        assert!(!CtapHid::is_cancel_packet(&packet, &cid));
+    }
+
+    #[test]
+    fn test_process_message_too_long() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        let mut ctap_hid = CtapHid::new();
+        let cid = cid_from_init(&mut ctap_hid, &mut ctap_state);
+
+        // A PING reply would not fit into packets.
+        for &cmd in &[CtapHid::COMMAND_PING, CtapHid::COMMAND_CBOR] {
+            let message = Message {
+                cid,
+                cmd,
+                payload: vec![0x99; CtapHid::MAX_PAYLOAD_LENGTH + 1],
+            };
+            let reply: Vec<HidPacket> = ctap_hid
+                .process_message(message, DUMMY_CLOCK_VALUE, &mut ctap_state)
+                .collect();
+            let mut expected_packet = [0x00; 64];
+            expected_packet[..4].copy_from_slice(&cid);
+            expected_packet[4..8].copy_from_slice(&[0xBF, 0x00, 0x01, CtapHid::ERR_INVALID_LEN]);
+            assert_eq!(reply.len(), 1);
+            assert_eq!(&reply[0][..], &expected_packet[..]);
+        }
+    }
+
+    #[test]
+    fn test_process_message_malformed() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        let mut ctap_hid = CtapHid::new();
+        let cid = cid_from_init(&mut ctap_hid, &mut ctap_state);
+
+        // Inputs from fuzzing: empty and truncated CBOR, a truncated APDU, and a sequence number
+        // as the command byte.
+        let inputs = [
+            (CtapHid::COMMAND_CBOR, vec![]),
+            (CtapHid::COMMAND_CBOR, vec![0x01, 0xA1]),
+            (CtapHid::COMMAND_MSG, vec![0x00, 0x02]),
+            (0x7F, vec![0x00]),
+        ];
+        for (cmd, payload) in inputs.iter() {
+            let message = Message {
+                cid,
+                cmd: *cmd,
+                payload: payload.clone(),
+            };
+            let mut assembler_reply = MessageAssembler::new();
+            let mut reply = None;
+            for packet in ctap_hid.process_message(message, DUMMY_CLOCK_VALUE, &mut ctap_state) {
+                reply = assembler_reply
+                    .parse_packet(&packet, DUMMY_TIMESTAMP)
+                    .unwrap();
+            }
+            assert!(reply.is_some());
+        }
+    }
+
     #[test]
     fn test_command_ping() {
         let mut rng = ThreadRng256 {};
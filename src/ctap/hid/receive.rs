@@ -74,6 +74,23 @@ impl MessageAssembler {
         self.payload.clear();
     }
 
+    // Returns whether no message is partially received.
+    pub fn is_idle(&self) -> bool {
+        self.idle
+    }
+
+    // Discards the current message if its channel timed out, and returns the channel ID of that
+    // message. This frees the assembler even if the client never sends another packet.
+    pub fn check_timeout(&mut self, timestamp: Timestamp<isize>) -> Option<ChannelID> {
+        if !self.idle && timestamp - self.last_timestamp >= CtapHid::TIMEOUT_DURATION {
+            let current_cid = self.cid;
+            self.reset();
+            Some(current_cid)
+        } else {
+            None
+        }
+    }
+
     // Returns:
     // - An Ok() result if the packet was parsed correctly. This contains either Some(Vec<u8>) if a
     // full message was assembled after this packet, or None if more packets are needed to fill the
@@ -90,15 +107,10 @@ impl MessageAssembler {
         // section 8.8.1
         let (cid, processed_packet) = CtapHid::process_single_packet(&packet);
 
-        if !self.idle && timestamp - self.last_timestamp >= CtapHid::TIMEOUT_DURATION {
-            // The current channel timed out.
-            // Save the channel ID and reset the state.
-            let current_cid = self.cid;
-            self.reset();
-
+        if let Some(timed_out_cid) = self.check_timeout(timestamp) {
             // If the packet is from the timed-out channel, send back a timeout error.
             // Otherwise, proceed with processing the packet.
-            if *cid == current_cid {
+            if *cid == timed_out_cid {
                 return Err((*cid, Error::Timeout));
             }
         }
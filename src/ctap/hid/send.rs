@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use super::{CtapHid, HidPacket, Message};
+use libtock_drivers::usb_ctap_hid::SendOrRecvStatus;
 
 pub struct HidPacketIterator(Option<MessageSplitter>);
 
@@ -42,6 +43,26 @@ impl Iterator for HidPacketIterator {
     }
 }
 
+/// Sends all packets of a message, using `send_or_recv` for each packet.
+///
+/// Stops at the first packet that isn't sent and returns its status. If a packet from the host
+/// arrives instead, `buf` holds that packet and the remaining packets stay in `packets`. Returns
+/// `Sent` once the whole message is sent.
+pub fn send_all(
+    packets: &mut HidPacketIterator,
+    buf: &mut HidPacket,
+    mut send_or_recv: impl FnMut(&mut HidPacket) -> Option<SendOrRecvStatus>,
+) -> Option<SendOrRecvStatus> {
+    for packet in packets {
+        *buf = packet;
+        match send_or_recv(buf) {
+            Some(SendOrRecvStatus::Sent) => (),
+            status => return status,
+        }
+    }
+    Some(SendOrRecvStatus::Sent)
+}
+
 pub struct MessageSplitter {
     message: Message,
     packet: HidPacket,
@@ -138,6 +159,64 @@ mod test {
         }
     }
 
+    fn create_three_packet_message() -> HidPacketIterator {
+        let message = Message {
+            cid: [0x12, 0x34, 0x56, 0x78],
+            cmd: 0x10,
+            payload: vec![0xAA; 150],
+        };
+        HidPacketIterator::new(message).unwrap()
+    }
+
+    #[test]
+    fn test_send_all() {
+        let mut packets = create_three_packet_message();
+        let mut buf = [0; 64];
+        let mut sent_packets = vec![];
+        let status = send_all(&mut packets, &mut buf, |packet| {
+            sent_packets.push(*packet);
+            Some(SendOrRecvStatus::Sent)
+        });
+        assert!(status == Some(SendOrRecvStatus::Sent));
+        assert_eq!(sent_packets.len(), 3);
+        assert_eq!(sent_packets[2][4], 0x01);
+    }
+
+    #[test]
+    fn test_send_all_received_mid_stream() {
+        let mut packets = create_three_packet_message();
+        let mut buf = [0; 64];
+        let received_packet = [0x55; 64];
+        let mut num_calls = 0;
+        let status = send_all(&mut packets, &mut buf, |packet| {
+            num_calls += 1;
+            if num_calls == 2 {
+                *packet = received_packet;
+                Some(SendOrRecvStatus::Received)
+            } else {
+                Some(SendOrRecvStatus::Sent)
+            }
+        });
+        assert!(status == Some(SendOrRecvStatus::Received));
+        assert_eq!(num_calls, 2);
+        assert_eq!(&buf[..], &received_packet[..]);
+        // The rest of the message is not sent.
+        assert!(packets.next().is_some());
+    }
+
+    #[test]
+    fn test_send_all_stops_on_timeout() {
+        let mut packets = create_three_packet_message();
+        let mut buf = [0; 64];
+        let mut num_calls = 0;
+        let status = send_all(&mut packets, &mut buf, |_| {
+            num_calls += 1;
+            None
+        });
+        assert!(status.is_none());
+        assert_eq!(num_calls, 1);
+    }
+
     #[test]
     fn test_hid_packet_iterator_single_packet() {
         let message = Message {
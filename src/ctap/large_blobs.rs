@@ -0,0 +1,428 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::command::AuthenticatorLargeBlobsParameters;
+use super::pin_protocol_v1::{check_pin_uv_auth_protocol, PinPermission, PinProtocolV1};
+use super::response::{AuthenticatorLargeBlobsResponse, ResponseData};
+use super::status_code::Ctap2StatusCode;
+use super::storage::{PersistentStore, MAX_LARGE_BLOB_ARRAY_SIZE};
+use alloc::vec;
+use alloc::vec::Vec;
+use byteorder::{ByteOrder, LittleEndian};
+use crypto::sha256::Sha256;
+use crypto::Hash256;
+
+/// The length of the truncated SHA256 hash at the end of the large blob array.
+const TRUNCATED_HASH_LENGTH: usize = 16;
+/// The space a fragment leaves in a message for its CBOR overhead.
+const FRAGMENT_OVERHEAD: usize = 64;
+
+/// Implements the logic for the largeBlobs command and keeps its state.
+#[derive(Default)]
+pub struct LargeBlobs {
+    buffer: Vec<u8>,
+    expected_length: usize,
+    expected_next_offset: usize,
+}
+
+impl LargeBlobs {
+    /// Returns whether a write was started and is not complete yet.
+    pub fn has_pending_write(&self) -> bool {
+        self.expected_length > 0
+    }
+
+    /// Processes the large blobs command, reading from or writing to the large blob array.
+    ///
+    /// Fragments must fit in a message of `max_msg_size`, the size negotiated with the transport.
+    pub fn process_command(
+        &mut self,
+        persistent_store: &mut PersistentStore,
+        pin_protocol_v1: &mut PinProtocolV1,
+        large_blobs_params: AuthenticatorLargeBlobsParameters,
+        max_msg_size: usize,
+    ) -> Result<ResponseData, Ctap2StatusCode> {
+        let AuthenticatorLargeBlobsParameters {
+            get,
+            set,
+            offset,
+            length,
+            pin_uv_auth_param,
+            pin_uv_auth_protocol,
+        } = large_blobs_params;
+        let max_fragment_length = max_msg_size.saturating_sub(FRAGMENT_OVERHEAD);
+
+        if let Some(get) = get {
+            if get > max_fragment_length {
+                return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_LENGTH);
+            }
+            let large_blob_array = persistent_store.large_blob_array()?;
+            if offset > large_blob_array.len() {
+                return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+            }
+            let end = core::cmp::min(offset.saturating_add(get), large_blob_array.len());
+            return Ok(ResponseData::AuthenticatorLargeBlobs(Some(
+                AuthenticatorLargeBlobsResponse {
+                    config: large_blob_array[offset..end].to_vec(),
+                },
+            )));
+        }
+
+        if let Some(mut set) = set {
+            if set.len() > max_fragment_length {
+                return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_LENGTH);
+            }
+            if offset == 0 {
+                // Starts a new write, discarding any previous incomplete one.
+                let length = length.ok_or(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)?;
+                if length > MAX_LARGE_BLOB_ARRAY_SIZE {
+                    return Err(Ctap2StatusCode::CTAP2_ERR_LARGE_BLOB_STORAGE_FULL);
+                }
+                if length < TRUNCATED_HASH_LENGTH + 1 {
+                    return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+                }
+                self.expected_length = length;
+                self.expected_next_offset = 0;
+                self.buffer = Vec::with_capacity(length);
+            } else if length.is_some() {
+                return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+            }
+            if offset != self.expected_next_offset {
+                return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_SEQ);
+            }
+            if persistent_store.pin_hash()?.is_some() || persistent_store.has_always_uv()? {
+                let pin_uv_auth_param =
+                    pin_uv_auth_param.ok_or(Ctap2StatusCode::CTAP2_ERR_PIN_REQUIRED)?;
+                let pin_uv_auth_protocol = check_pin_uv_auth_protocol(pin_uv_auth_protocol)?;
+                // From CTAP2.1: "verify(pinUvAuthToken, 32×0xff || h'0c00' ||
+                // uint32LittleEndian(offset) || SHA-256(contents of set byte string),
+                // pinUvAuthParam)"
+                let mut message = vec![0xFF; 32];
+                message.extend(&[0x0C, 0x00]);
+                let mut offset_bytes = [0u8; 4];
+                LittleEndian::write_u32(&mut offset_bytes, offset as u32);
+                message.extend(&offset_bytes);
+                message.extend(&Sha256::hash(set.as_slice()));
+                if !pin_protocol_v1.verify_pin_auth_token(
+                    &message,
+                    &pin_uv_auth_param,
+                    pin_uv_auth_protocol,
+                ) {
+                    return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID);
+                }
+                pin_protocol_v1.has_permission(PinPermission::LargeBlobWrite)?;
+            }
+            if offset + set.len() > self.expected_length {
+                return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+            }
+            self.expected_next_offset += set.len();
+            self.buffer.append(&mut set);
+            if self.buffer.len() == self.expected_length {
+                self.expected_length = 0;
+                self.expected_next_offset = 0;
+                let buffer = core::mem::take(&mut self.buffer);
+                let (array, hash) = buffer.split_at(buffer.len() - TRUNCATED_HASH_LENGTH);
+                if Sha256::hash(array)[..TRUNCATED_HASH_LENGTH] != *hash {
+                    return Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE);
+                }
+                persistent_store.commit_large_blob_array(&buffer)?;
+            }
+            return Ok(ResponseData::AuthenticatorLargeBlobs(None));
+        }
+
+        // This should be unreachable, since the command has either get or set.
+        Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ctap::MAX_MSG_SIZE;
+    use crypto::rng256::ThreadRng256;
+
+    const MAX_FRAGMENT_LENGTH: usize = MAX_MSG_SIZE - FRAGMENT_OVERHEAD;
+
+    fn new_large_blob_array(length: usize) -> Vec<u8> {
+        let mut large_blob_array = vec![0xC0; length - TRUNCATED_HASH_LENGTH];
+        let hash = Sha256::hash(&large_blob_array);
+        large_blob_array.extend_from_slice(&hash[..TRUNCATED_HASH_LENGTH]);
+        large_blob_array
+    }
+
+    fn set_params(
+        set: Vec<u8>,
+        offset: usize,
+        length: Option<usize>,
+    ) -> AuthenticatorLargeBlobsParameters {
+        AuthenticatorLargeBlobsParameters {
+            get: None,
+            set: Some(set),
+            offset,
+            length,
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: None,
+        }
+    }
+
+    fn get_params(get: usize, offset: usize) -> AuthenticatorLargeBlobsParameters {
+        AuthenticatorLargeBlobsParameters {
+            get: Some(get),
+            set: None,
+            offset,
+            length: None,
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: None,
+        }
+    }
+
+    #[test]
+    fn test_process_command_get_empty() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut pin_protocol_v1 = PinProtocolV1::new(&mut rng);
+        let mut large_blobs = LargeBlobs::default();
+
+        let large_blob = vec![
+            0x80, 0x76, 0xBE, 0x8B, 0x52, 0x8D, 0x00, 0x75, 0xF7, 0xAA, 0xE9, 0x8D, 0x6F, 0xA5,
+            0x7A, 0x6D, 0x3C,
+        ];
+        let response = large_blobs.process_command(
+            &mut persistent_store,
+            &mut pin_protocol_v1,
+            get_params(large_blob.len(), 0),
+            MAX_MSG_SIZE,
+        );
+        assert_eq!(
+            response,
+            Ok(ResponseData::AuthenticatorLargeBlobs(Some(
+                AuthenticatorLargeBlobsResponse { config: large_blob }
+            )))
+        );
+    }
+
+    #[test]
+    fn test_process_command_commit_and_get() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut pin_protocol_v1 = PinProtocolV1::new(&mut rng);
+        let mut large_blobs = LargeBlobs::default();
+
+        const BLOB_LEN: usize = 2 * MAX_FRAGMENT_LENGTH + 20;
+        let large_blob = new_large_blob_array(BLOB_LEN);
+        let fragments = large_blob.chunks(MAX_FRAGMENT_LENGTH).collect::<Vec<_>>();
+        assert_eq!(fragments.len(), 3);
+
+        let mut offset = 0;
+        for fragment in fragments {
+            let length = if offset == 0 { Some(BLOB_LEN) } else { None };
+            let response = large_blobs.process_command(
+                &mut persistent_store,
+                &mut pin_protocol_v1,
+                set_params(fragment.to_vec(), offset, length),
+                MAX_MSG_SIZE,
+            );
+            assert_eq!(response, Ok(ResponseData::AuthenticatorLargeBlobs(None)));
+            offset += fragment.len();
+        }
+
+        let mut read_blob = Vec::new();
+        let mut offset = 0;
+        while offset < BLOB_LEN {
+            let response = large_blobs.process_command(
+                &mut persistent_store,
+                &mut pin_protocol_v1,
+                get_params(MAX_FRAGMENT_LENGTH, offset),
+                MAX_MSG_SIZE,
+            );
+            match response.unwrap() {
+                ResponseData::AuthenticatorLargeBlobs(Some(response)) => {
+                    assert!(!response.config.is_empty());
+                    offset += response.config.len();
+                    read_blob.extend(response.config);
+                }
+                _ => panic!("Invalid response type"),
+            }
+        }
+        assert_eq!(read_blob, large_blob);
+    }
+
+    #[test]
+    fn test_process_command_commit_unexpected_offset() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut pin_protocol_v1 = PinProtocolV1::new(&mut rng);
+        let mut large_blobs = LargeBlobs::default();
+
+        const BLOB_LEN: usize = 200;
+        const DATA_LEN: usize = BLOB_LEN / 2;
+        let large_blob = new_large_blob_array(BLOB_LEN);
+        let response = large_blobs.process_command(
+            &mut persistent_store,
+            &mut pin_protocol_v1,
+            set_params(large_blob[..DATA_LEN].to_vec(), 0, Some(BLOB_LEN)),
+            MAX_MSG_SIZE,
+        );
+        assert_eq!(response, Ok(ResponseData::AuthenticatorLargeBlobs(None)));
+
+        // The offset has to continue where the last fragment ended.
+        let response = large_blobs.process_command(
+            &mut persistent_store,
+            &mut pin_protocol_v1,
+            set_params(large_blob[DATA_LEN..].to_vec(), DATA_LEN + 1, None),
+            MAX_MSG_SIZE,
+        );
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP1_ERR_INVALID_SEQ));
+
+        // Only the first fragment has a length.
+        let response = large_blobs.process_command(
+            &mut persistent_store,
+            &mut pin_protocol_v1,
+            set_params(large_blob[DATA_LEN..].to_vec(), DATA_LEN, Some(BLOB_LEN)),
+            MAX_MSG_SIZE,
+        );
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER));
+
+        // Reading past the end of the stored array fails.
+        let response = large_blobs.process_command(
+            &mut persistent_store,
+            &mut pin_protocol_v1,
+            get_params(1, 18),
+            MAX_MSG_SIZE,
+        );
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER));
+    }
+
+    #[test]
+    fn test_process_command_commit_invalid_length() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut pin_protocol_v1 = PinProtocolV1::new(&mut rng);
+        let mut large_blobs = LargeBlobs::default();
+
+        let response = large_blobs.process_command(
+            &mut persistent_store,
+            &mut pin_protocol_v1,
+            set_params(vec![0xC0; 16], 0, Some(16)),
+            MAX_MSG_SIZE,
+        );
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER));
+
+        let response = large_blobs.process_command(
+            &mut persistent_store,
+            &mut pin_protocol_v1,
+            set_params(vec![0xC0; 16], 0, Some(MAX_LARGE_BLOB_ARRAY_SIZE + 1)),
+            MAX_MSG_SIZE,
+        );
+        assert_eq!(
+            response,
+            Err(Ctap2StatusCode::CTAP2_ERR_LARGE_BLOB_STORAGE_FULL)
+        );
+
+        let response = large_blobs.process_command(
+            &mut persistent_store,
+            &mut pin_protocol_v1,
+            set_params(vec![0xC0; MAX_FRAGMENT_LENGTH + 1], 0, Some(1000)),
+            MAX_MSG_SIZE,
+        );
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP1_ERR_INVALID_LENGTH));
+
+        let response = large_blobs.process_command(
+            &mut persistent_store,
+            &mut pin_protocol_v1,
+            get_params(MAX_FRAGMENT_LENGTH + 1, 0),
+            MAX_MSG_SIZE,
+        );
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP1_ERR_INVALID_LENGTH));
+
+        // Writing more than announced fails.
+        let response = large_blobs.process_command(
+            &mut persistent_store,
+            &mut pin_protocol_v1,
+            set_params(vec![0xC0; 40], 0, Some(20)),
+            MAX_MSG_SIZE,
+        );
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER));
+    }
+
+    #[test]
+    fn test_process_command_negotiated_max_msg_size() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut pin_protocol_v1 = PinProtocolV1::new(&mut rng);
+        let mut large_blobs = LargeBlobs::default();
+
+        // A fragment that fits MAX_MSG_SIZE is too long for a transport with a smaller limit.
+        let max_msg_size = MAX_MSG_SIZE / 2;
+        let response = large_blobs.process_command(
+            &mut persistent_store,
+            &mut pin_protocol_v1,
+            set_params(vec![0xC0; MAX_FRAGMENT_LENGTH], 0, Some(1000)),
+            max_msg_size,
+        );
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP1_ERR_INVALID_LENGTH));
+
+        let response = large_blobs.process_command(
+            &mut persistent_store,
+            &mut pin_protocol_v1,
+            get_params(max_msg_size - FRAGMENT_OVERHEAD + 1, 0),
+            max_msg_size,
+        );
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP1_ERR_INVALID_LENGTH));
+
+        let response = large_blobs.process_command(
+            &mut persistent_store,
+            &mut pin_protocol_v1,
+            get_params(max_msg_size - FRAGMENT_OVERHEAD, 0),
+            max_msg_size,
+        );
+        assert!(response.is_ok());
+    }
+
+    #[test]
+    fn test_process_command_commit_invalid_hash() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut pin_protocol_v1 = PinProtocolV1::new(&mut rng);
+        let mut large_blobs = LargeBlobs::default();
+
+        const BLOB_LEN: usize = 200;
+        let mut large_blob = new_large_blob_array(BLOB_LEN);
+        large_blob[BLOB_LEN - 1] ^= 0x01;
+        let response = large_blobs.process_command(
+            &mut persistent_store,
+            &mut pin_protocol_v1,
+            set_params(large_blob, 0, Some(BLOB_LEN)),
+            MAX_MSG_SIZE,
+        );
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE));
+    }
+
+    #[test]
+    fn test_process_command_commit_pin_required() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut pin_protocol_v1 = PinProtocolV1::new(&mut rng);
+        let mut large_blobs = LargeBlobs::default();
+        persistent_store.set_pin_hash(&[0x88; 16]).unwrap();
+
+        const BLOB_LEN: usize = 20;
+        let response = large_blobs.process_command(
+            &mut persistent_store,
+            &mut pin_protocol_v1,
+            set_params(new_large_blob_array(BLOB_LEN), 0, Some(BLOB_LEN)),
+            MAX_MSG_SIZE,
+        );
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP2_ERR_PIN_REQUIRED));
+    }
+}
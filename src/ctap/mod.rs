@@ -14,25 +14,40 @@
 
 pub mod apdu;
 pub mod command;
+#[cfg(feature = "with_ctap2_1")]
+mod credential_backup;
+#[cfg(feature = "with_ctap2_1")]
+mod credential_management;
 #[cfg(feature = "with_ctap1")]
 mod ctap1;
 pub mod data_formats;
 pub mod hid;
 mod key_material;
+#[cfg(feature = "with_ctap2_1")]
+mod large_blobs;
 mod pin_protocol_v1;
 pub mod response;
 pub mod status_code;
 mod storage;
-mod timed_permission;
+pub(crate) mod timed_permission;
 
+use self::command::{
+    AuthenticatorAttestationMaterial, AuthenticatorClientPinParameters,
+    AuthenticatorGetAssertionParameters, AuthenticatorMakeCredentialParameters,
+    AuthenticatorVendorConfigureParameters, AuthenticatorVendorFactoryResetParameters,
+    AuthenticatorVendorRpIdPolicyParameters, Command,
+};
 #[cfg(feature = "with_ctap2_1")]
-use self::command::MAX_CREDENTIAL_COUNT_IN_LIST;
 use self::command::{
-    AuthenticatorClientPinParameters, AuthenticatorGetAssertionParameters,
-    AuthenticatorMakeCredentialParameters, AuthenticatorVendorConfigureParameters, Command,
+    AuthenticatorConfigParameters, AuthenticatorVendorResetBootCountParameters,
+    MAX_CREDENTIAL_COUNT_IN_LIST,
 };
 #[cfg(feature = "with_ctap2_1")]
-use self::data_formats::AuthenticatorTransport;
+use self::credential_backup::process_credential_backup;
+#[cfg(feature = "with_ctap2_1")]
+use self::credential_management::process_credential_management;
+#[cfg(feature = "with_ctap2_1")]
+use self::data_formats::{AuthenticatorTransport, ConfigSubCommand, SetMinPinLengthParams};
 use self::data_formats::{
     CredentialProtectionPolicy, GetAssertionHmacSecretInput, PackedAttestationStatement,
     PublicKeyCredentialDescriptor, PublicKeyCredentialParameter, PublicKeyCredentialSource,
@@ -40,33 +55,40 @@ use self::data_formats::{
 };
 use self::hid::ChannelID;
 #[cfg(feature = "with_ctap2_1")]
+use self::large_blobs::LargeBlobs;
+#[cfg(feature = "with_ctap2_1")]
 use self::pin_protocol_v1::PinPermission;
-use self::pin_protocol_v1::PinProtocolV1;
+use self::pin_protocol_v1::{check_pin_uv_auth_protocol, PinProtocolV1, PIN_PROTOCOL_VERSIONS};
 use self::response::{
     AuthenticatorGetAssertionResponse, AuthenticatorGetInfoResponse,
-    AuthenticatorMakeCredentialResponse, AuthenticatorVendorResponse, ResponseData,
+    AuthenticatorMakeCredentialResponse, AuthenticatorVendorResponse,
+    AuthenticatorVendorStoreHealthResponse, ResponseData,
 };
 use self::status_code::Ctap2StatusCode;
-use self::storage::PersistentStore;
+#[cfg(feature = "with_ctap2_1")]
+use self::storage::MAX_LARGE_BLOB_ARRAY_SIZE;
+use self::storage::{AttestationMaterial, MasterKeys, PersistentStore, RpIdPolicy};
 use self::timed_permission::TimedPermission;
 #[cfg(feature = "with_ctap1")]
 use self::timed_permission::U2fUserPresenceState;
+#[cfg(feature = "debug_ctap")]
+use crate::debug_log::{log, LogLevel};
+use alloc::boxed::Box;
 use alloc::collections::BTreeMap;
 use alloc::string::{String, ToString};
 use alloc::vec;
 use alloc::vec::Vec;
 use arrayref::array_ref;
 use byteorder::{BigEndian, ByteOrder};
-use cbor::{cbor_map, cbor_map_options};
-#[cfg(feature = "debug_ctap")]
-use core::fmt::Write;
+use cbor::cbor_map_options;
+#[cfg(feature = "with_ctap2_1")]
+use core::convert::TryFrom;
 use crypto::cbc::{cbc_decrypt, cbc_encrypt};
 use crypto::hmac::{hmac_256, verify_hmac_256};
 use crypto::rng256::Rng256;
 use crypto::sha256::Sha256;
+use crypto::util::{zeroize, Zeroizing};
 use crypto::Hash256;
-#[cfg(feature = "debug_ctap")]
-use libtock_drivers::console::Console;
 use libtock_drivers::crp;
 use libtock_drivers::timer::{ClockValue, Duration};
 
@@ -75,6 +97,9 @@ use libtock_drivers::timer::{ClockValue, Duration};
 // as a batch key. Turn it on if you want attestation. In this case, be aware that
 // it is your responsibility to generate your own key material and keep it secret.
 const USE_BATCH_ATTESTATION: bool = false;
+// Values of the enterpriseAttestation parameter of makeCredential.
+const ENTERPRISE_ATTESTATION_VENDOR_FACILITATED: u64 = 1;
+const ENTERPRISE_ATTESTATION_PLATFORM_MANAGED: u64 = 2;
 // The signature counter is currently implemented as a global counter, if you set
 // this flag to true. The spec strongly suggests to have per-credential-counters,
 // but it means you can't have an infinite amount of credentials anymore. Also,
@@ -83,6 +108,24 @@ const USE_BATCH_ATTESTATION: bool = false;
 // solution is a compromise to be compatible with U2F and not wasting storage.
 const USE_SIGNATURE_COUNTER: bool = true;
 pub const INITIAL_SIGNATURE_COUNTER: u32 = 1;
+// This flag switches FIDO2 assertions from the global signature counter to
+// per-credential counters, so that counter values can't be correlated across
+// relying parties. Each resident credential then stores its own counter, that
+// is written to flash on every assertion. Non-resident credentials have no
+// storage and always report a counter of 0, meaning that counters are not
+// supported. U2F keeps using the global counter.
+const USE_PER_CREDENTIAL_SIGNATURE_COUNTER: bool = false;
+// This flag changes how the private keys of non-resident credentials are kept. By default, the
+// key handle contains the encrypted private key. If set, the key handle only holds a random nonce
+// and a MAC, and the private key is derived from the master keys, the relying party and the nonce.
+// Key handles of both kinds are always accepted.
+const USE_DERIVED_CREDENTIAL_KEYS: bool = false;
+// With per-credential counters, credentials without their own counter report 0.
+// This includes credentials registered through U2F, whose U2F assertions use the
+// global counter. If this flag is set, FIDO2 assertions for these credentials
+// also use the global counter, so that the count stays consistent and monotonic
+// across both protocols. Only relevant with CTAP1 support.
+const USE_U2F_COMPATIBLE_SIGNATURE_COUNTER: bool = false;
 // Our credential ID consists of
 // - 16 byte initialization vector for AES-256,
 // - 32 byte ECDSA private key for the credential,
@@ -99,9 +142,29 @@ const AT_FLAG: u8 = 0x40;
 const ED_FLAG: u8 = 0x80;
 
 pub const TOUCH_TIMEOUT_MS: isize = 30000;
+// This flag enables a cooldown before user presence checks that closely follow a
+// timed out check. During the cooldown, the LEDs are off and touches are ignored,
+// so that the check is not accidentally triggered by rapid retries. The cooldown
+// grows with each consecutive timeout, and extends the total time of the check.
+pub const USE_USER_PRESENCE_BACKOFF: bool = false;
+// Minimum time a button has to be held down to confirm user presence. Releasing it earlier
+// restarts the hold. With 0, a single press confirms, like before.
+pub const USER_PRESENCE_HOLD_MS: isize = 0;
+// If set, two button presses within this window cancel a user presence check. A single press then
+// only confirms once the window closed without a second press.
+pub const DOUBLE_TAP_CANCEL_WINDOW_MS: Option<isize> = None;
+// Buttons that all have to be pressed to confirm user presence. If empty, any single button
+// confirms, like before. With USER_PRESENCE_CONCURRENT_BUTTONS, they have to be held down at the
+// same time, otherwise pressing each of them once during the check is enough.
+pub const USER_PRESENCE_BUTTONS: &[usize] = &[];
+pub const USER_PRESENCE_CONCURRENT_BUTTONS: bool = true;
 #[cfg(feature = "with_ctap1")]
 const U2F_UP_PROMPT_TIMEOUT: Duration<isize> = Duration::from_ms(10000);
 const RESET_TIMEOUT_DURATION: Duration<isize> = Duration::from_ms(10000);
+// If set, makeCredential is denied until this interval passed since the last created credential.
+// Each creation already needs user presence, but this also stops a malicious host from wearing out
+// the flash by having the user confirm creations in a loop.
+const MAKE_CREDENTIAL_MIN_INTERVAL: Option<Duration<isize>> = None;
 const STATEFUL_COMMAND_TIMEOUT_DURATION: Duration<isize> = Duration::from_ms(30000);
 
 pub const FIDO2_VERSION_STRING: &str = "FIDO_2_0";
@@ -121,6 +184,15 @@ pub const ES256_CRED_PARAM: PublicKeyCredentialParameter = PublicKeyCredentialPa
 // - Some(CredentialProtectionPolicy::UserVerificationOptionalWithCredentialIdList)
 // - Some(CredentialProtectionPolicy::UserVerificationRequired)
 const DEFAULT_CRED_PROTECT: Option<CredentialProtectionPolicy> = None;
+// Maximum size of the credBlob extension data stored with resident credentials. CTAP 2.1
+// requires at least 32 bytes.
+pub const MAX_CRED_BLOB_LENGTH: usize = 32;
+// Maximum message size advertised in GetInfo, unless the transport supports less. Large blob
+// fragments leave 64 bytes of it for the CBOR overhead of their message.
+pub const MAX_MSG_SIZE: usize = 1024;
+// Maximum size of each user information string stored with resident credentials. Longer inputs
+// are cropped.
+const MAX_USER_INFO_LENGTH: usize = 64;
 
 // This function is adapted from https://doc.rust-lang.org/nightly/src/core/str/mod.rs.html#2110
 // (as of 2020-01-20) and truncates to "max" bytes, not breaking the encoding.
@@ -141,6 +213,8 @@ struct AssertionInput {
     client_data_hash: Vec<u8>,
     auth_data: Vec<u8>,
     hmac_secret_input: Option<GetAssertionHmacSecretInput>,
+    get_cred_blob: bool,
+    get_large_blob_key: bool,
     has_uv: bool,
 }
 
@@ -155,6 +229,19 @@ enum StatefulCommand {
     GetAssertion(AssertionState),
 }
 
+// Sends a processing keep-alive on the given channel, if one is due. Returns an error if the
+// command was cancelled in the meantime.
+pub type ProcessingKeepalive<'a> = Box<dyn FnMut(ChannelID) -> Result<(), Ctap2StatusCode> + 'a>;
+
+// Public information about a resident credential, for developing host UIs.
+#[cfg(feature = "debug_ctap")]
+#[derive(Debug, PartialEq)]
+pub struct CredentialSummary {
+    pub rp_id: String,
+    pub user_name: Option<String>,
+    pub creation_order: u64,
+}
+
 // This struct currently holds all state, not only the persistent memory. The persistent members are
 // in the persistent store field.
 pub struct CtapState<'a, R: Rng256, CheckUserPresence: Fn(ChannelID) -> Result<(), Ctap2StatusCode>>
@@ -165,11 +252,23 @@ pub struct CtapState<'a, R: Rng256, CheckUserPresence: Fn(ChannelID) -> Result<(
     check_user_presence: CheckUserPresence,
     persistent_store: PersistentStore,
     pin_protocol_v1: PinProtocolV1,
+    #[cfg(feature = "with_ctap2_1")]
+    large_blobs: LargeBlobs,
     #[cfg(feature = "with_ctap1")]
     pub u2f_up_state: U2fUserPresenceState,
     // The state initializes to Reset and its timeout, and never goes back to Reset.
     stateful_command_permission: TimedPermission,
     stateful_command_type: Option<StatefulCommand>,
+    // Called during long operations, so that the transport can send processing keep-alives.
+    processing_keepalive: Option<ProcessingKeepalive<'a>>,
+    make_credential_min_interval: Option<Duration<isize>>,
+    // Granted while makeCredential is throttled after a creation.
+    make_credential_cooldown: TimedPermission,
+    // Key generated ahead of time for the next created credential. It is kept serialized, because
+    // SecKey doesn't zeroize its memory when dropped, e.g. by a reset.
+    pregenerated_key: Option<Zeroizing<[u8; 32]>>,
+    // The smaller of MAX_MSG_SIZE and the limit of the transport.
+    max_msg_size: usize,
 }
 
 impl<'a, R, CheckUserPresence> CtapState<'a, R, CheckUserPresence>
@@ -177,20 +276,30 @@ where
     R: Rng256,
     CheckUserPresence: Fn(ChannelID) -> Result<(), Ctap2StatusCode>,
 {
-    pub const PIN_PROTOCOL_VERSION: u64 = 1;
-
+    #[cfg(test)]
     pub fn new(
         rng: &'a mut R,
         check_user_presence: CheckUserPresence,
         now: ClockValue,
     ) -> CtapState<'a, R, CheckUserPresence> {
-        let persistent_store = PersistentStore::new(rng);
+        CtapState::try_new(rng, check_user_presence, now).unwrap()
+    }
+
+    // Same as `new`, but fails instead of panicking when the persistent store can't be opened.
+    pub fn try_new(
+        rng: &'a mut R,
+        check_user_presence: CheckUserPresence,
+        now: ClockValue,
+    ) -> Result<CtapState<'a, R, CheckUserPresence>, Ctap2StatusCode> {
+        let persistent_store = PersistentStore::try_new(rng)?;
         let pin_protocol_v1 = PinProtocolV1::new(rng);
-        CtapState {
+        Ok(CtapState {
             rng,
             check_user_presence,
             persistent_store,
             pin_protocol_v1,
+            #[cfg(feature = "with_ctap2_1")]
+            large_blobs: LargeBlobs::default(),
             #[cfg(feature = "with_ctap1")]
             u2f_up_state: U2fUserPresenceState::new(
                 U2F_UP_PROMPT_TIMEOUT,
@@ -198,9 +307,62 @@ where
             ),
             stateful_command_permission: TimedPermission::granted(now, RESET_TIMEOUT_DURATION),
             stateful_command_type: Some(StatefulCommand::Reset),
+            processing_keepalive: None,
+            make_credential_min_interval: MAKE_CREDENTIAL_MIN_INTERVAL,
+            make_credential_cooldown: TimedPermission::waiting(),
+            pregenerated_key: None,
+            max_msg_size: MAX_MSG_SIZE,
+        })
+    }
+
+    // Sets the function called during long operations of CBOR commands. It is responsible for
+    // pacing the processing keep-alives it sends on the given channel. It returns
+    // CTAP2_ERR_KEEPALIVE_CANCEL if the platform cancelled the command in the meantime.
+    pub fn set_processing_keepalive(&mut self, processing_keepalive: ProcessingKeepalive<'a>) {
+        self.processing_keepalive = Some(processing_keepalive);
+    }
+
+    // Sets the minimum interval between two created credentials, or removes it with None.
+    #[cfg(test)]
+    pub fn set_make_credential_min_interval(&mut self, min_interval: Option<Duration<isize>>) {
+        self.make_credential_min_interval = min_interval;
+        self.make_credential_cooldown = TimedPermission::waiting();
+    }
+
+    // Sets the maximum message size of the transport. GetInfo advertises it if it is smaller than
+    // MAX_MSG_SIZE.
+    pub fn set_transport_max_msg_size(&mut self, transport_max_msg_size: usize) {
+        self.max_msg_size = core::cmp::min(MAX_MSG_SIZE, transport_max_msg_size);
+    }
+
+    // Generates the private key of the next credential, if not done already. Key generation
+    // dominates the latency of makeCredential, so it is better done while the device is idle.
+    pub fn pregenerate_credential_key(&mut self) {
+        if self.pregenerated_key.is_none() {
+            let mut key_bytes = Zeroizing([0; 32]);
+            crypto::ecdsa::SecKey::gensk(self.rng).to_bytes(&mut key_bytes);
+            self.pregenerated_key = Some(key_bytes);
         }
     }
 
+    // Returns an error if the command was cancelled. Callers only abort if nothing was modified
+    // yet, so that a cancelled command has no effect.
+    fn keepalive_processing(&mut self, cid: ChannelID) -> Result<(), Ctap2StatusCode> {
+        match &mut self.processing_keepalive {
+            Some(processing_keepalive) => processing_keepalive(cid),
+            None => Ok(()),
+        }
+    }
+
+    // Compacts the store, if the next write would do it. Compaction erases a flash page, which
+    // slows down the command that triggers it, so it is better done while the device is idle.
+    pub fn compact_store_if_needed(&mut self) -> Result<(), Ctap2StatusCode> {
+        if self.persistent_store.needs_compaction()? {
+            self.persistent_store.compact()?;
+        }
+        Ok(())
+    }
+
     pub fn update_command_permission(&mut self, now: ClockValue) {
         self.stateful_command_permission = self.stateful_command_permission.check_expiration(now);
     }
@@ -223,6 +385,57 @@ where
         Ok(())
     }
 
+    // Increments and returns the signature counter of a resident credential.
+    // Credentials without a counter, like non-resident credentials, always return 0.
+    fn increment_credential_signature_counter(
+        &mut self,
+        credential: &PublicKeyCredentialSource,
+    ) -> Result<u32, Ctap2StatusCode> {
+        let old_value = match credential.signature_counter {
+            None => return Ok(0),
+            Some(old_value) => old_value,
+        };
+        let new_value = old_value
+            .checked_add(1)
+            .ok_or(Ctap2StatusCode::CTAP2_ERR_LIMIT_EXCEEDED)?;
+        self.persistent_store
+            .store_credential(PublicKeyCredentialSource {
+                signature_counter: Some(new_value),
+                ..credential.clone()
+            })?;
+        Ok(new_value)
+    }
+
+    // Returns the number of PIN retries left before the PIN is blocked.
+    // This is the same value as clientPin getRetries, for diagnostics.
+    #[cfg(any(test, feature = "debug_ctap"))]
+    pub fn pin_retries(&self) -> Result<u8, Ctap2StatusCode> {
+        self.persistent_store.pin_retries()
+    }
+
+    // Returns whether PIN verification is blocked until the next power cycle.
+    #[cfg(test)]
+    pub fn is_pin_auth_blocked(&self) -> bool {
+        self.pin_protocol_v1.is_pin_auth_blocked()
+    }
+
+    // Lists all resident credentials, sorted by ascending order of creation. This bypasses user
+    // verification, and is only meant for building host UIs against a development key. It is
+    // compiled out without the debug_ctap feature.
+    #[cfg(feature = "debug_ctap")]
+    pub fn list_credentials_unsafe(&self) -> Result<Vec<CredentialSummary>, Ctap2StatusCode> {
+        let mut credentials = self.persistent_store.credentials()?;
+        credentials.sort_unstable_by_key(|c| c.creation_order);
+        Ok(credentials
+            .into_iter()
+            .map(|credential| CredentialSummary {
+                rp_id: credential.rp_id,
+                user_name: credential.user_name,
+                creation_order: credential.creation_order,
+            })
+            .collect())
+    }
+
     // Encrypts the private key and relying party ID hash into a credential ID. Other
     // information, such as a user name, are not stored, because encrypted credential IDs
     // are used for credentials stored server-side. Also, we want the key handle to be
@@ -234,7 +447,7 @@ where
     ) -> Result<Vec<u8>, Ctap2StatusCode> {
         let master_keys = self.persistent_store.master_keys()?;
         let aes_enc_key = crypto::aes256::EncryptionKey::new(&master_keys.encryption);
-        let mut sk_bytes = [0; 32];
+        let mut sk_bytes = Zeroizing([0; 32]);
         private_key.to_bytes(&mut sk_bytes);
         let mut iv = [0; 16];
         iv.copy_from_slice(&self.rng.gen_uniform_u8x32()[..16]);
@@ -256,6 +469,31 @@ where
         Ok(encrypted_id)
     }
 
+    // Generates a private key and its key handle for a relying party. The key handle holds a
+    // random nonce and a MAC over the nonce and relying party. The private key is derived from
+    // both, so it doesn't need to be stored anywhere.
+    pub fn generate_derived_key_handle(
+        &mut self,
+        application: &[u8; 32],
+    ) -> Result<(crypto::ecdsa::SecKey, Vec<u8>), Ctap2StatusCode> {
+        let master_keys = self.persistent_store.master_keys()?;
+        loop {
+            let mut key_handle = Vec::with_capacity(CREDENTIAL_ID_SIZE);
+            while key_handle.len() < CREDENTIAL_ID_SIZE - 32 {
+                key_handle.extend(&self.rng.gen_uniform_u8x32());
+            }
+            key_handle.truncate(CREDENTIAL_ID_SIZE - 32);
+            // Very few nonces result in an invalid private key. We just try another one.
+            if let Some(private_key) = derive_credential_key(&master_keys, application, &key_handle)
+            {
+                let message =
+                    derived_key_handle_message(DERIVED_KEY_HANDLE_TAG, application, &key_handle);
+                key_handle.extend(&hmac_256::<Sha256>(&master_keys.hmac, &message));
+                return Ok((private_key, key_handle));
+            }
+        }
+    }
+
     // Decrypts a credential ID and writes the private key into a PublicKeyCredentialSource.
     // None is returned if the HMAC test fails or the relying party does not match the
     // decrypted relying party ID hash.
@@ -269,12 +507,17 @@ where
         }
         let master_keys = self.persistent_store.master_keys()?;
         let payload_size = credential_id.len() - 32;
-        if !verify_hmac_256::<Sha256>(
-            &master_keys.hmac,
-            &credential_id[..payload_size],
-            array_ref![credential_id, payload_size, 32],
-        ) {
-            return Ok(None);
+        let tag = array_ref![credential_id, payload_size, 32];
+        if !verify_hmac_256::<Sha256>(&master_keys.hmac, &credential_id[..payload_size], tag) {
+            // The key handle might hold a nonce for a derived private key instead. Its MAC also
+            // covers the relying party.
+            let nonce = &credential_id[..payload_size];
+            let message = derived_key_handle_message(DERIVED_KEY_HANDLE_TAG, rp_id_hash, nonce);
+            if !verify_hmac_256::<Sha256>(&master_keys.hmac, &message, tag) {
+                return Ok(None);
+            }
+            let sk_option = derive_credential_key(&master_keys, rp_id_hash, nonce);
+            return Ok(sk_option.map(|sk| key_handle_credential_source(credential_id, sk)));
         }
         let aes_enc_key = crypto::aes256::EncryptionKey::new(&master_keys.encryption);
         let aes_dec_key = crypto::aes256::DecryptionKey::new(&aes_enc_key);
@@ -286,29 +529,21 @@ where
         }
 
         cbc_decrypt(&aes_dec_key, iv, &mut blocks);
-        let mut decrypted_sk = [0; 32];
+        let mut decrypted_sk = Zeroizing([0; 32]);
         let mut decrypted_rp_id_hash = [0; 32];
         decrypted_sk[..16].clone_from_slice(&blocks[0]);
         decrypted_sk[16..].clone_from_slice(&blocks[1]);
         decrypted_rp_id_hash[..16].clone_from_slice(&blocks[2]);
         decrypted_rp_id_hash[16..].clone_from_slice(&blocks[3]);
+        for block in blocks.iter_mut() {
+            zeroize(block);
+        }
         if rp_id_hash != decrypted_rp_id_hash {
             return Ok(None);
         }
 
         let sk_option = crypto::ecdsa::SecKey::from_bytes(&decrypted_sk);
-        Ok(sk_option.map(|sk| PublicKeyCredentialSource {
-            key_type: PublicKeyCredentialType::PublicKey,
-            credential_id,
-            private_key: sk,
-            rp_id: String::from(""),
-            user_handle: vec![],
-            user_display_name: None,
-            cred_protect_policy: None,
-            creation_order: 0,
-            user_name: None,
-            user_icon: None,
-        }))
+        Ok(sk_option.map(|sk| key_handle_credential_source(credential_id, sk)))
     }
 
     pub fn process_command(
@@ -319,7 +554,10 @@ where
     ) -> Vec<u8> {
         let cmd = Command::deserialize(command_cbor);
         #[cfg(feature = "debug_ctap")]
-        writeln!(&mut Console::new(), "Received command: {:#?}", cmd).unwrap();
+        log(
+            LogLevel::Trace,
+            format_args!("Received command: {:#?}", cmd),
+        );
         match cmd {
             Ok(command) => {
                 // Correct behavior between CTAP1 and CTAP2 isn't defined yet. Just a guess.
@@ -336,6 +574,8 @@ where
                         Some(StatefulCommand::GetAssertion(_)),
                     ) => (),
                     (Command::AuthenticatorReset, Some(StatefulCommand::Reset)) => (),
+                    // Factory resets check for stateful commands in progress instead.
+                    (Command::AuthenticatorVendorFactoryReset(_), _) => (),
                     // GetInfo does not reset stateful commands.
                     (Command::AuthenticatorGetInfo, _) => (),
                     // AuthenticatorSelection does not reset stateful commands.
@@ -347,7 +587,7 @@ where
                 }
                 let response = match command {
                     Command::AuthenticatorMakeCredential(params) => {
-                        self.process_make_credential(params, cid)
+                        self.process_throttled_make_credential(params, cid, now)
                     }
                     Command::AuthenticatorGetAssertion(params) => {
                         self.process_get_assertion(params, cid, now)
@@ -357,15 +597,56 @@ where
                     Command::AuthenticatorClientPin(params) => self.process_client_pin(params),
                     Command::AuthenticatorReset => self.process_reset(cid, now),
                     #[cfg(feature = "with_ctap2_1")]
+                    Command::AuthenticatorCredentialManagement(params) => {
+                        process_credential_management(
+                            &mut self.persistent_store,
+                            &mut self.pin_protocol_v1,
+                            params,
+                        )
+                    }
+                    #[cfg(feature = "with_ctap2_1")]
                     Command::AuthenticatorSelection => self.process_selection(cid),
+                    #[cfg(feature = "with_ctap2_1")]
+                    Command::AuthenticatorLargeBlobs(params) => self.large_blobs.process_command(
+                        &mut self.persistent_store,
+                        &mut self.pin_protocol_v1,
+                        params,
+                        self.max_msg_size,
+                    ),
+                    #[cfg(feature = "with_ctap2_1")]
+                    Command::AuthenticatorConfig(params) => self.process_config(params),
                     // TODO(kaczmarczyck) implement FIDO 2.1 commands
                     // Vendor specific commands
                     Command::AuthenticatorVendorConfigure(params) => {
                         self.process_vendor_configure(params, cid)
                     }
+                    Command::AuthenticatorVendorFactoryReset(params) => {
+                        self.process_vendor_factory_reset(params, cid, now)
+                    }
+                    Command::AuthenticatorVendorRpIdPolicy(params) => {
+                        self.process_vendor_rp_id_policy(params, cid)
+                    }
+                    Command::AuthenticatorVendorStoreHealth => self.process_vendor_store_health(),
+                    #[cfg(feature = "with_ctap2_1")]
+                    Command::AuthenticatorVendorCredentialBackup(params) => {
+                        process_credential_backup(
+                            self.rng,
+                            &mut self.persistent_store,
+                            &mut self.pin_protocol_v1,
+                            params,
+                            self.max_msg_size,
+                        )
+                    }
+                    #[cfg(feature = "with_ctap2_1")]
+                    Command::AuthenticatorVendorResetBootCount(params) => {
+                        self.process_vendor_reset_boot_count(params)
+                    }
                 };
                 #[cfg(feature = "debug_ctap")]
-                writeln!(&mut Console::new(), "Sending response: {:#?}", response).unwrap();
+                log(
+                    LogLevel::Trace,
+                    format_args!("Sending response: {:#?}", response),
+                );
                 match response {
                     Ok(response_data) => {
                         let mut response_vec = vec![0x00];
@@ -403,16 +684,30 @@ where
                 }
             }
 
-            match pin_uv_auth_protocol {
-                Some(CtapState::<R, CheckUserPresence>::PIN_PROTOCOL_VERSION) => Ok(()),
-                Some(_) => Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID),
-                None => Err(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER),
-            }
+            check_pin_uv_auth_protocol(pin_uv_auth_protocol).map(|_| ())
         } else {
             Ok(())
         }
     }
 
+    // Denies makeCredential during the cooldown that follows a created credential.
+    fn process_throttled_make_credential(
+        &mut self,
+        make_credential_params: AuthenticatorMakeCredentialParameters,
+        cid: ChannelID,
+        now: ClockValue,
+    ) -> Result<ResponseData, Ctap2StatusCode> {
+        self.make_credential_cooldown = self.make_credential_cooldown.check_expiration(now);
+        if self.make_credential_cooldown.is_granted(now) {
+            return Err(Ctap2StatusCode::CTAP2_ERR_OPERATION_DENIED);
+        }
+        let response = self.process_make_credential(make_credential_params, cid)?;
+        if let Some(min_interval) = self.make_credential_min_interval {
+            self.make_credential_cooldown = TimedPermission::granted(now, min_interval);
+        }
+        Ok(response)
+    }
+
     fn process_make_credential(
         &mut self,
         make_credential_params: AuthenticatorMakeCredentialParameters,
@@ -428,6 +723,7 @@ where
             options,
             pin_uv_auth_param,
             pin_uv_auth_protocol,
+            enterprise_attestation,
         } = make_credential_params;
 
         self.pin_uv_auth_precheck(&pin_uv_auth_param, pin_uv_auth_protocol, cid)?;
@@ -436,25 +732,76 @@ where
             return Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_ALGORITHM);
         }
 
-        let (use_hmac_extension, cred_protect_policy) = if let Some(extensions) = extensions {
-            let mut cred_protect = extensions.cred_protect;
-            if cred_protect.unwrap_or(CredentialProtectionPolicy::UserVerificationOptional)
-                < DEFAULT_CRED_PROTECT
-                    .unwrap_or(CredentialProtectionPolicy::UserVerificationOptional)
-            {
-                cred_protect = DEFAULT_CRED_PROTECT;
+        #[cfg(feature = "with_ctap2_1")]
+        let has_enterprise_attestation = self.persistent_store.has_enterprise_attestation()?;
+        #[cfg(not(feature = "with_ctap2_1"))]
+        let has_enterprise_attestation = false;
+        let use_enterprise_attestation = match enterprise_attestation {
+            None => false,
+            Some(_) if !has_enterprise_attestation => {
+                return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
             }
-            (extensions.hmac_secret, cred_protect)
+            Some(ENTERPRISE_ATTESTATION_VENDOR_FACILITATED)
+            | Some(ENTERPRISE_ATTESTATION_PLATFORM_MANAGED) => true,
+            Some(_) => return Err(Ctap2StatusCode::CTAP2_ERR_INVALID_OPTION),
+        };
+
+        #[cfg(feature = "with_ctap2_1")]
+        let has_min_pin_length_extension = extensions.as_ref().map_or(false, |e| e.min_pin_length);
+        let has_large_blob_key_extension = extensions.as_ref().map_or(false, |e| e.large_blob_key);
+        // Large blob keys are only stored with resident credentials.
+        if has_large_blob_key_extension && !options.rk {
+            return Err(Ctap2StatusCode::CTAP2_ERR_INVALID_OPTION);
+        }
+        let (use_hmac_extension, cred_protect_policy, cred_blob_input) =
+            if let Some(extensions) = extensions {
+                let mut cred_protect = extensions.cred_protect;
+                if cred_protect.unwrap_or(CredentialProtectionPolicy::UserVerificationOptional)
+                    < DEFAULT_CRED_PROTECT
+                        .unwrap_or(CredentialProtectionPolicy::UserVerificationOptional)
+                {
+                    cred_protect = DEFAULT_CRED_PROTECT;
+                }
+                (extensions.hmac_secret, cred_protect, extensions.cred_blob)
+            } else {
+                (false, DEFAULT_CRED_PROTECT, None)
+            };
+        // The blob is only stored with resident credentials, and if it is not too long.
+        // Otherwise, the extension output reports that it was not stored.
+        let cred_blob_output = cred_blob_input
+            .as_ref()
+            .map(|cred_blob| options.rk && cred_blob.len() <= MAX_CRED_BLOB_LENGTH);
+        let cred_blob = cred_blob_input.filter(|_| cred_blob_output == Some(true));
+        // The minimum PIN length is only revealed to relying parties on the allow list.
+        #[cfg(feature = "with_ctap2_1")]
+        let min_pin_length_output = if has_min_pin_length_extension
+            && self
+                .persistent_store
+                .min_pin_length_rp_ids()?
+                .contains(&rp.rp_id)
+        {
+            Some(self.persistent_store.min_pin_length()? as u64)
         } else {
-            (false, DEFAULT_CRED_PROTECT)
+            None
         };
+        #[cfg(not(feature = "with_ctap2_1"))]
+        let min_pin_length_output: Option<u64> = None;
 
-        let has_extension_output = use_hmac_extension || cred_protect_policy.is_some();
+        let has_extension_output = use_hmac_extension
+            || cred_protect_policy.is_some()
+            || cred_blob_output.is_some()
+            || min_pin_length_output.is_some();
 
         let rp_id = rp.rp_id;
         let rp_id_hash = Sha256::hash(rp_id.as_bytes());
+        if let Some(policy) = self.persistent_store.rp_id_policy()? {
+            if !policy.is_allowed(&rp_id_hash) {
+                return Err(Ctap2StatusCode::CTAP2_ERR_OPERATION_DENIED);
+            }
+        }
         if let Some(exclude_list) = exclude_list {
             for cred_desc in exclude_list {
+                self.keepalive_processing(cid)?;
                 if self
                     .persistent_store
                     .find_credential(&rp_id, &cred_desc.key_id, pin_uv_auth_param.is_none())?
@@ -480,10 +827,12 @@ where
                     // Specification is unclear, could be CTAP2_ERR_INVALID_OPTION.
                     return Err(Ctap2StatusCode::CTAP2_ERR_PIN_NOT_SET);
                 }
-                if !self
-                    .pin_protocol_v1
-                    .verify_pin_auth_token(&client_data_hash, &pin_auth)
-                {
+                let pin_uv_auth_protocol = check_pin_uv_auth_protocol(pin_uv_auth_protocol)?;
+                if !self.pin_protocol_v1.verify_pin_auth_token(
+                    &client_data_hash,
+                    &pin_auth,
+                    pin_uv_auth_protocol,
+                ) {
                     return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID);
                 }
                 #[cfg(feature = "with_ctap2_1")]
@@ -495,6 +844,12 @@ where
                 UP_FLAG | UV_FLAG | AT_FLAG | ed_flag
             }
             None => {
+                #[cfg(feature = "with_ctap2_1")]
+                {
+                    if self.persistent_store.has_always_uv()? {
+                        return Err(Ctap2StatusCode::CTAP2_ERR_PIN_REQUIRED);
+                    }
+                }
                 if self.persistent_store.pin_hash()?.is_some() {
                     return Err(Ctap2StatusCode::CTAP2_ERR_PIN_REQUIRED);
                 }
@@ -507,9 +862,24 @@ where
 
         (self.check_user_presence)(cid)?;
 
-        let sk = crypto::ecdsa::SecKey::gensk(self.rng);
+        let (sk, derived_key_handle) = if !options.rk && USE_DERIVED_CREDENTIAL_KEYS {
+            let (sk, key_handle) = self.generate_derived_key_handle(&rp_id_hash)?;
+            (sk, Some(key_handle))
+        } else {
+            let sk = match self.pregenerated_key.take() {
+                Some(key_bytes) => crypto::ecdsa::SecKey::from_bytes(&key_bytes)
+                    .ok_or(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)?,
+                None => crypto::ecdsa::SecKey::gensk(self.rng),
+            };
+            (sk, None)
+        };
         let pk = sk.genpk();
 
+        let large_blob_key = if has_large_blob_key_extension {
+            Some(self.rng.gen_uniform_u8x32().to_vec())
+        } else {
+            None
+        };
         let credential_id = if options.rk {
             let random_id = self.rng.gen_uniform_u8x32().to_vec();
             let credential_source = PublicKeyCredentialSource {
@@ -518,27 +888,39 @@ where
                 private_key: sk.clone(),
                 rp_id,
                 user_handle: user.user_id,
-                // This input is user provided, so we crop it to 64 byte for storage.
+                // This input is user provided, so we crop it to MAX_USER_INFO_LENGTH bytes.
                 // The UTF8 encoding is always preserved, so the string might end up shorter.
                 user_display_name: user
                     .user_display_name
-                    .map(|s| truncate_to_char_boundary(&s, 64).to_string()),
+                    .map(|s| truncate_to_char_boundary(&s, MAX_USER_INFO_LENGTH).to_string()),
                 cred_protect_policy,
                 creation_order: self.persistent_store.new_creation_order()?,
                 user_name: user
                     .user_name
-                    .map(|s| truncate_to_char_boundary(&s, 64).to_string()),
+                    .map(|s| truncate_to_char_boundary(&s, MAX_USER_INFO_LENGTH).to_string()),
                 user_icon: user
                     .user_icon
-                    .map(|s| truncate_to_char_boundary(&s, 64).to_string()),
+                    .map(|s| truncate_to_char_boundary(&s, MAX_USER_INFO_LENGTH).to_string()),
+                cred_blob,
+                signature_counter: if USE_PER_CREDENTIAL_SIGNATURE_COUNTER {
+                    Some(0)
+                } else {
+                    None
+                },
+                large_blob_key: large_blob_key.clone(),
             };
             self.persistent_store.store_credential(credential_source)?;
             random_id
+        } else if let Some(key_handle) = derived_key_handle {
+            key_handle
         } else {
             self.encrypt_key_handle(sk.clone(), &rp_id_hash)?
         };
 
         let mut auth_data = self.generate_auth_data(&rp_id_hash, flags)?;
+        if USE_PER_CREDENTIAL_SIGNATURE_COUNTER {
+            write_signature_counter(&mut auth_data, 0);
+        }
         auth_data.extend(&self.persistent_store.aaguid()?);
         // The length is fixed to 0x20 or 0x70 and fits one byte.
         if credential_id.len() > 0xFF {
@@ -556,6 +938,8 @@ where
             let extensions_output = cbor_map_options! {
                 "hmac-secret" => hmac_secret_output,
                 "credProtect" => cred_protect_policy,
+                "credBlob" => cred_blob_output,
+                "minPinLength" => min_pin_length_output,
             };
             if !cbor::write(extensions_output, &mut auth_data) {
                 return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_RESPONSE_CANNOT_WRITE_CBOR);
@@ -565,26 +949,39 @@ where
         let mut signature_data = auth_data.clone();
         signature_data.extend(client_data_hash);
 
-        let (signature, x5c) = if USE_BATCH_ATTESTATION {
-            let attestation_private_key = self
-                .persistent_store
-                .attestation_private_key()?
-                .ok_or(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)?;
-            let attestation_key =
-                crypto::ecdsa::SecKey::from_bytes(&attestation_private_key).unwrap();
-            let attestation_certificate = self
-                .persistent_store
-                .attestation_certificate()?
-                .ok_or(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)?;
-            (
-                attestation_key.sign_rfc6979::<crypto::sha256::Sha256>(&signature_data),
-                Some(vec![attestation_certificate]),
-            )
+        // The credential might be stored already, so cancelling is too late.
+        self.keepalive_processing(cid).ok();
+        // The attestation material provisioned for a relying party identifies the device, so it
+        // doubles as enterprise attestation. The vendor list consists of these relying parties.
+        // Without such material, enterprise attestation falls back to the usual attestation.
+        let enterprise_attestation_material = if use_enterprise_attestation {
+            self.persistent_store.find_rp_attestation(&rp_id_hash)?
         } else {
-            (
+            None
+        };
+        let ep_att = enterprise_attestation_material.as_ref().map(|_| true);
+        let attestation_material = match enterprise_attestation_material {
+            Some(material) => Some(material),
+            None if USE_BATCH_ATTESTATION => Some(
+                self.persistent_store
+                    .attestation_material()?
+                    .ok_or(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)?,
+            ),
+            None => None,
+        };
+        let (signature, x5c) = match attestation_material {
+            Some(attestation_material) => {
+                let attestation_key =
+                    crypto::ecdsa::SecKey::from_bytes(&attestation_material.private_key).unwrap();
+                (
+                    attestation_key.sign_rfc6979::<crypto::sha256::Sha256>(&signature_data),
+                    Some(vec![attestation_material.certificate]),
+                )
+            }
+            None => (
                 sk.sign_rfc6979::<crypto::sha256::Sha256>(&signature_data),
                 None,
-            )
+            ),
         };
         let attestation_statement = PackedAttestationStatement {
             alg: SignatureAlgorithm::ES256 as i64,
@@ -597,6 +994,8 @@ where
                 fmt: String::from("packed"),
                 auth_data,
                 att_stmt: attestation_statement,
+                ep_att,
+                large_blob_key,
             },
         ))
     }
@@ -608,10 +1007,10 @@ where
         private_key: &crypto::ecdsa::SecKey,
         has_uv: bool,
     ) -> Result<[u8; 32], Ctap2StatusCode> {
-        let mut private_key_bytes = [0u8; 32];
+        let mut private_key_bytes = Zeroizing([0u8; 32]);
         private_key.to_bytes(&mut private_key_bytes);
-        let key = self.persistent_store.cred_random_secret(has_uv)?;
-        Ok(hmac_256::<Sha256>(&key, &private_key_bytes))
+        let key = Zeroizing(self.persistent_store.cred_random_secret(has_uv)?);
+        Ok(hmac_256::<Sha256>(&*key, &*private_key_bytes))
     }
 
     // Processes the input of a get_assertion operation for a given credential
@@ -626,17 +1025,37 @@ where
             client_data_hash,
             mut auth_data,
             hmac_secret_input,
+            get_cred_blob,
+            get_large_blob_key,
             has_uv,
         } = assertion_input;
 
+        if !uses_global_signature_counter(&credential) {
+            let signature_counter = self.increment_credential_signature_counter(&credential)?;
+            write_signature_counter(&mut auth_data, signature_counter);
+        }
+
         // Process extensions.
-        if let Some(hmac_secret_input) = hmac_secret_input {
-            let cred_random = self.generate_cred_random(&credential.private_key, has_uv)?;
-            let encrypted_output = self
-                .pin_protocol_v1
-                .process_hmac_secret(hmac_secret_input, &cred_random)?;
-            let extensions_output = cbor_map! {
+        if hmac_secret_input.is_some() || get_cred_blob {
+            let encrypted_output = if let Some(hmac_secret_input) = hmac_secret_input {
+                let cred_random = self.generate_cred_random(&credential.private_key, has_uv)?;
+                Some(self.pin_protocol_v1.process_hmac_secret(
+                    self.rng,
+                    hmac_secret_input,
+                    &cred_random,
+                )?)
+            } else {
+                None
+            };
+            // Credentials without a blob return an empty byte string.
+            let cred_blob = if get_cred_blob {
+                Some(credential.cred_blob.unwrap_or_default())
+            } else {
+                None
+            };
+            let extensions_output = cbor_map_options! {
                 "hmac-secret" => encrypted_output,
+                "credBlob" => cred_blob,
             };
             if !cbor::write(extensions_output, &mut auth_data) {
                 return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_RESPONSE_CANNOT_WRITE_CBOR);
@@ -671,6 +1090,7 @@ where
                 signature: signature.to_asn1_der(),
                 user,
                 number_of_credentials: number_of_credentials.map(|n| n as u64),
+                large_blob_key: credential.large_blob_key.filter(|_| get_large_blob_key),
             },
         ))
     }
@@ -719,7 +1139,14 @@ where
 
         self.pin_uv_auth_precheck(&pin_uv_auth_param, pin_uv_auth_protocol, cid)?;
 
-        let hmac_secret_input = extensions.map(|e| e.hmac_secret).flatten();
+        let (hmac_secret_input, get_cred_blob, get_large_blob_key) =
+            extensions.map_or((None, false, false), |extensions| {
+                (
+                    extensions.hmac_secret,
+                    extensions.cred_blob,
+                    extensions.large_blob_key,
+                )
+            });
         if hmac_secret_input.is_some() && !options.up {
             // The extension is actually supported, but we need user presence.
             return Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_EXTENSION);
@@ -734,10 +1161,12 @@ where
                     // Specification is unclear, could be CTAP2_ERR_UNSUPPORTED_OPTION.
                     return Err(Ctap2StatusCode::CTAP2_ERR_PIN_NOT_SET);
                 }
-                if !self
-                    .pin_protocol_v1
-                    .verify_pin_auth_token(&client_data_hash, &pin_auth)
-                {
+                let pin_uv_auth_protocol = check_pin_uv_auth_protocol(pin_uv_auth_protocol)?;
+                if !self.pin_protocol_v1.verify_pin_auth_token(
+                    &client_data_hash,
+                    &pin_auth,
+                    pin_uv_auth_protocol,
+                ) {
                     return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID);
                 }
                 #[cfg(feature = "with_ctap2_1")]
@@ -749,6 +1178,13 @@ where
                 UV_FLAG
             }
             None => {
+                // Assertions without user presence are still allowed with alwaysUv.
+                #[cfg(feature = "with_ctap2_1")]
+                {
+                    if options.up && self.persistent_store.has_always_uv()? {
+                        return Err(Ctap2StatusCode::CTAP2_ERR_PIN_REQUIRED);
+                    }
+                }
                 if options.uv {
                     // The specification (inconsistently) wants CTAP2_ERR_UNSUPPORTED_OPTION.
                     return Err(Ctap2StatusCode::CTAP2_ERR_INVALID_OPTION);
@@ -759,11 +1195,12 @@ where
         if options.up {
             flags |= UP_FLAG;
         }
-        if hmac_secret_input.is_some() {
+        if hmac_secret_input.is_some() || get_cred_blob {
             flags |= ED_FLAG;
         }
 
         let rp_id_hash = Sha256::hash(rp_id.as_bytes());
+        self.keepalive_processing(cid)?;
         let mut applicable_credentials = if let Some(allow_list) = allow_list {
             if let Some(credential) =
                 self.get_any_credential_from_allow_list(allow_list, &rp_id, &rp_id_hash, has_uv)?
@@ -795,12 +1232,16 @@ where
             .pop()
             .ok_or(Ctap2StatusCode::CTAP2_ERR_NO_CREDENTIALS)?;
 
-        self.increment_global_signature_counter()?;
+        if uses_global_signature_counter(&credential) {
+            self.increment_global_signature_counter()?;
+        }
 
         let assertion_input = AssertionInput {
             client_data_hash,
             auth_data: self.generate_auth_data(&rp_id_hash, flags)?,
             hmac_secret_input,
+            get_cred_blob,
+            get_large_blob_key,
             has_uv,
         };
         let number_of_credentials = if applicable_credentials.is_empty() {
@@ -815,6 +1256,7 @@ where
             }));
             number_of_credentials
         };
+        self.keepalive_processing(cid)?;
         self.assertion_response(credential, assertion_input, number_of_credentials)
     }
 
@@ -840,9 +1282,23 @@ where
 
     fn process_get_info(&self) -> Result<ResponseData, Ctap2StatusCode> {
         let mut options_map = BTreeMap::new();
-        // TODO(kaczmarczyck) add authenticatorConfig and credProtect options
+        // TODO(kaczmarczyck) add credProtect options
         options_map.insert(String::from("rk"), true);
         options_map.insert(String::from("up"), true);
+        #[cfg(feature = "with_ctap2_1")]
+        {
+            options_map.insert(
+                String::from("alwaysUv"),
+                self.persistent_store.has_always_uv()?,
+            );
+            options_map.insert(
+                String::from("ep"),
+                self.persistent_store.has_enterprise_attestation()?,
+            );
+            options_map.insert(String::from("authnrCfg"), true);
+            options_map.insert(String::from("credMgmt"), true);
+            options_map.insert(String::from("largeBlobs"), true);
+        }
         options_map.insert(
             String::from("clientPin"),
             self.persistent_store.pin_hash()?.is_some(),
@@ -856,27 +1312,40 @@ where
                     #[cfg(feature = "with_ctap2_1")]
                     String::from(FIDO2_1_VERSION_STRING),
                 ],
-                extensions: Some(vec![String::from("hmac-secret")]),
+                extensions: Some(vec![
+                    String::from("hmac-secret"),
+                    String::from("credProtect"),
+                    String::from("credBlob"),
+                    String::from("largeBlobKey"),
+                    #[cfg(feature = "with_ctap2_1")]
+                    String::from("minPinLength"),
+                ]),
                 aaguid: self.persistent_store.aaguid()?,
                 options: Some(options_map),
-                max_msg_size: Some(1024),
-                pin_protocols: Some(vec![
-                    CtapState::<R, CheckUserPresence>::PIN_PROTOCOL_VERSION,
-                ]),
+                max_msg_size: Some(self.max_msg_size as u64),
+                pin_protocols: Some(PIN_PROTOCOL_VERSIONS.to_vec()),
                 #[cfg(feature = "with_ctap2_1")]
                 max_credential_count_in_list: MAX_CREDENTIAL_COUNT_IN_LIST.map(|c| c as u64),
                 // #TODO(106) update with version 2.1 of HMAC-secret
                 #[cfg(feature = "with_ctap2_1")]
                 max_credential_id_length: Some(CREDENTIAL_ID_SIZE as u64),
                 #[cfg(feature = "with_ctap2_1")]
-                transports: Some(vec![AuthenticatorTransport::Usb]),
+                transports: Some(supported_transports()),
                 #[cfg(feature = "with_ctap2_1")]
                 algorithms: Some(vec![ES256_CRED_PARAM]),
+                #[cfg(feature = "with_ctap2_1")]
+                max_serialized_large_blob_array: Some(MAX_LARGE_BLOB_ARRAY_SIZE as u64),
                 default_cred_protect: DEFAULT_CRED_PROTECT,
                 #[cfg(feature = "with_ctap2_1")]
                 min_pin_length: self.persistent_store.min_pin_length()?,
                 #[cfg(feature = "with_ctap2_1")]
                 firmware_version: None,
+                #[cfg(feature = "with_ctap2_1")]
+                max_cred_blob_length: Some(MAX_CRED_BLOB_LENGTH as u64),
+                #[cfg(feature = "with_ctap2_1")]
+                remaining_discoverable_credentials: Some(
+                    self.persistent_store.remaining_credentials()? as u64,
+                ),
             },
         ))
     }
@@ -908,6 +1377,11 @@ where
 
         self.persistent_store.reset(self.rng)?;
         self.pin_protocol_v1.reset(self.rng);
+        self.pregenerated_key = None;
+        #[cfg(feature = "with_ctap2_1")]
+        {
+            self.large_blobs = LargeBlobs::default();
+        }
         #[cfg(feature = "with_ctap1")]
         {
             self.u2f_up_state = U2fUserPresenceState::new(
@@ -924,6 +1398,93 @@ where
         Ok(ResponseData::AuthenticatorSelection)
     }
 
+    #[cfg(feature = "with_ctap2_1")]
+    fn process_config(
+        &mut self,
+        config_params: AuthenticatorConfigParameters,
+    ) -> Result<ResponseData, Ctap2StatusCode> {
+        let AuthenticatorConfigParameters {
+            sub_command,
+            sub_command_params,
+            pin_uv_auth_param,
+            pin_uv_auth_protocol,
+        } = config_params;
+
+        if self.persistent_store.pin_hash()?.is_some() || self.persistent_store.has_always_uv()? {
+            let pin_uv_auth_param =
+                pin_uv_auth_param.ok_or(Ctap2StatusCode::CTAP2_ERR_PIN_REQUIRED)?;
+            let pin_uv_auth_protocol = check_pin_uv_auth_protocol(pin_uv_auth_protocol)?;
+            // From CTAP2.1: "verify(pinUvAuthToken, 32×0xff || 0x0d || uint8(subCommand) ||
+            // subCommandParams, pinUvAuthParam)"
+            let mut message = vec![0xFF; 32];
+            message.extend(&[0x0D, sub_command as u8]);
+            if let Some(sub_command_params) = sub_command_params.clone() {
+                if !cbor::write(cbor::Value::Map(sub_command_params), &mut message) {
+                    return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_RESPONSE_CANNOT_WRITE_CBOR);
+                }
+            }
+            if !self.pin_protocol_v1.verify_pin_auth_token(
+                &message,
+                &pin_uv_auth_param,
+                pin_uv_auth_protocol,
+            ) {
+                return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID);
+            }
+            self.pin_protocol_v1
+                .has_permission(PinPermission::AuthenticatorConfiguration)?;
+        }
+
+        match sub_command {
+            ConfigSubCommand::EnableEnterpriseAttestation => {
+                self.persistent_store.enable_enterprise_attestation()?;
+                Ok(ResponseData::AuthenticatorConfig)
+            }
+            ConfigSubCommand::ToggleAlwaysUv => {
+                self.persistent_store.toggle_always_uv()?;
+                Ok(ResponseData::AuthenticatorConfig)
+            }
+            ConfigSubCommand::SetMinPinLength => {
+                let params = SetMinPinLengthParams::try_from(cbor::Value::Map(
+                    sub_command_params.unwrap_or_default(),
+                ))?;
+                self.process_set_min_pin_length(params)
+            }
+            _ => Err(Ctap2StatusCode::CTAP2_ERR_INVALID_SUBCOMMAND),
+        }
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    fn process_set_min_pin_length(
+        &mut self,
+        params: SetMinPinLengthParams,
+    ) -> Result<ResponseData, Ctap2StatusCode> {
+        let SetMinPinLengthParams {
+            new_min_pin_length,
+            min_pin_length_rp_ids,
+            force_change_pin,
+        } = params;
+        let store_min_pin_length = self.persistent_store.min_pin_length()?;
+        let new_min_pin_length = new_min_pin_length.unwrap_or(store_min_pin_length);
+        // The minimum PIN length can only be increased.
+        if new_min_pin_length < store_min_pin_length {
+            return Err(Ctap2StatusCode::CTAP2_ERR_PIN_POLICY_VIOLATION);
+        }
+        let force_change_pin = force_change_pin.unwrap_or(false);
+        if force_change_pin && self.persistent_store.pin_hash()?.is_none() {
+            return Err(Ctap2StatusCode::CTAP2_ERR_PIN_NOT_SET);
+        }
+        self.persistent_store
+            .set_min_pin_length(new_min_pin_length)?;
+        if let Some(min_pin_length_rp_ids) = min_pin_length_rp_ids {
+            self.persistent_store
+                .set_min_pin_length_rp_ids(min_pin_length_rp_ids)?;
+        }
+        if force_change_pin {
+            self.persistent_store.force_pin_change()?;
+        }
+        Ok(ResponseData::AuthenticatorConfig)
+    }
+
     fn process_vendor_configure(
         &mut self,
         params: AuthenticatorVendorConfigureParameters,
@@ -935,18 +1496,30 @@ where
         let current_priv_key = self.persistent_store.attestation_private_key()?;
         let current_cert = self.persistent_store.attestation_certificate()?;
 
-        let response = match params.attestation_material {
+        let (cert_programmed, pkey_programmed) = match params.attestation_material {
             // Only reading values.
-            None => AuthenticatorVendorResponse {
-                cert_programmed: current_cert.is_some(),
-                pkey_programmed: current_priv_key.is_some(),
-            },
+            None => (current_cert.is_some(), current_priv_key.is_some()),
+            // Material for a single relying party. The response is about the batch material.
+            Some(AuthenticatorAttestationMaterial {
+                certificate,
+                private_key,
+                rp_id: Some(rp_id),
+                ..
+            }) => {
+                let rp_id_hash = Sha256::hash(rp_id.as_bytes());
+                let material = AttestationMaterial {
+                    private_key,
+                    certificate,
+                };
+                self.persistent_store
+                    .set_rp_attestation(&rp_id_hash, &material)?;
+                (current_cert.is_some(), current_priv_key.is_some())
+            }
             // Device is already fully programmed. We don't leak information.
             Some(_) if current_cert.is_some() && current_priv_key.is_some() => {
-                AuthenticatorVendorResponse {
-                    cert_programmed: true,
-                    pkey_programmed: true,
-                }
+                // Devices programmed before the lock existed get locked now.
+                self.persistent_store.lock_attestation()?;
+                (true, true)
             }
             // Device is partially or not programmed. We complete the process.
             Some(data) => {
@@ -968,30 +1541,209 @@ where
                     self.persistent_store
                         .set_attestation_private_key(&data.private_key)?;
                 }
-                AuthenticatorVendorResponse {
-                    cert_programmed: true,
-                    pkey_programmed: true,
+                if let Some(aaguid) = &data.aaguid {
+                    self.persistent_store.set_aaguid(aaguid)?;
                 }
+                // The batch material is written once and never changes afterwards.
+                self.persistent_store.lock_attestation()?;
+                (true, true)
             }
         };
-        if params.lockdown {
-            // To avoid bricking the authenticator, we only allow lockdown
+        if let Some(level) = params.lockdown {
+            // To avoid bricking the authenticator, we only allow a full lockdown
             // to happen if both values are programmed or if both U2F/CTAP1 and
-            // batch attestation are disabled.
+            // batch attestation are disabled. Intermediate levels are meant for
+            // development and can be set at any time.
             #[cfg(feature = "with_ctap1")]
-            let need_certificate = true;
+            let need_certificate = level == crp::ProtectionLevel::FullyLocked;
             #[cfg(not(feature = "with_ctap1"))]
-            let need_certificate = USE_BATCH_ATTESTATION;
+            let need_certificate =
+                USE_BATCH_ATTESTATION && level == crp::ProtectionLevel::FullyLocked;
 
-            if (need_certificate && !(response.pkey_programmed && response.cert_programmed))
-                || crp::set_protection(crp::ProtectionLevel::FullyLocked).is_err()
+            if (need_certificate && !(pkey_programmed && cert_programmed))
+                || crp::lock_level(level).is_err()
             {
                 return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR);
             }
         }
+        let response = AuthenticatorVendorResponse {
+            cert_programmed,
+            pkey_programmed,
+            // Read back the protection level, so that a lockdown can be verified.
+            protection_level: crp::get_protection().ok(),
+        };
         Ok(ResponseData::AuthenticatorVendor(response))
     }
 
+    fn process_vendor_factory_reset(
+        &mut self,
+        params: AuthenticatorVendorFactoryResetParameters,
+        cid: ChannelID,
+        now: ClockValue,
+    ) -> Result<ResponseData, Ctap2StatusCode> {
+        let AuthenticatorVendorFactoryResetParameters {
+            pin_uv_auth_protocol,
+            pin_uv_auth_param,
+        } = params;
+        if self.persistent_store.pin_hash()?.is_some() {
+            // Getting a PIN token takes other commands, so the PIN replaces the time window.
+            let pin_uv_auth_param =
+                pin_uv_auth_param.ok_or(Ctap2StatusCode::CTAP2_ERR_PIN_REQUIRED)?;
+            let pin_uv_auth_protocol = check_pin_uv_auth_protocol(pin_uv_auth_protocol)?;
+            // Like for authenticatorConfig: verify(pinUvAuthToken, 32×0xff || uint8(command),
+            // pinUvAuthParam)
+            let mut message = vec![0xFF; 32];
+            message.push(0x41);
+            if !self.pin_protocol_v1.verify_pin_auth_token(
+                &message,
+                &pin_uv_auth_param,
+                pin_uv_auth_protocol,
+            ) {
+                return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID);
+            }
+            #[cfg(feature = "with_ctap2_1")]
+            self.pin_protocol_v1
+                .has_permission(PinPermission::AuthenticatorConfiguration)?;
+        } else {
+            // Like resets, factory resets are only possible in the first 10 seconds after
+            // booting.
+            self.check_command_permission(now)?;
+            match &self.stateful_command_type {
+                Some(StatefulCommand::Reset) => (),
+                _ => return Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED),
+            }
+        }
+        // No operation on credentials may be in progress.
+        if let Some(StatefulCommand::GetAssertion(_)) = &self.stateful_command_type {
+            return Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED);
+        }
+        #[cfg(feature = "with_ctap2_1")]
+        {
+            if self.large_blobs.has_pending_write() {
+                return Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED);
+            }
+        }
+        (self.check_user_presence)(cid)?;
+
+        self.persistent_store.factory_reset(self.rng)?;
+        self.pin_protocol_v1.reset(self.rng);
+        self.pregenerated_key = None;
+        #[cfg(feature = "with_ctap2_1")]
+        {
+            self.large_blobs = LargeBlobs::default();
+        }
+        #[cfg(feature = "with_ctap1")]
+        {
+            self.u2f_up_state = U2fUserPresenceState::new(
+                U2F_UP_PROMPT_TIMEOUT,
+                Duration::from_ms(TOUCH_TIMEOUT_MS),
+            );
+        }
+        Ok(ResponseData::AuthenticatorVendorFactoryReset)
+    }
+
+    // Restricts which relying parties can create credentials. An empty deny list removes the
+    // restriction. If a PIN is set, changes need a PIN/UV auth token with the authenticator
+    // configuration permission. Otherwise, they are only possible during provisioning, before the
+    // attestation is locked.
+    fn process_vendor_rp_id_policy(
+        &mut self,
+        params: AuthenticatorVendorRpIdPolicyParameters,
+        cid: ChannelID,
+    ) -> Result<ResponseData, Ctap2StatusCode> {
+        let AuthenticatorVendorRpIdPolicyParameters {
+            allow,
+            rp_ids,
+            pin_uv_auth_protocol,
+            pin_uv_auth_param,
+        } = params;
+        let rp_id_hashes = rp_ids
+            .iter()
+            .map(|rp_id| Sha256::hash(rp_id.as_bytes()))
+            .collect::<Vec<[u8; 32]>>();
+        if self.persistent_store.pin_hash()?.is_some() {
+            let pin_uv_auth_param =
+                pin_uv_auth_param.ok_or(Ctap2StatusCode::CTAP2_ERR_PIN_REQUIRED)?;
+            let pin_uv_auth_protocol = check_pin_uv_auth_protocol(pin_uv_auth_protocol)?;
+            // verify(pinUvAuthToken, 32×0xff || uint8(command) || uint8(allow) ||
+            // SHA-256(rpId) for each rpId, pinUvAuthParam)
+            let mut message = vec![0xFF; 32];
+            message.push(0x42);
+            message.push(allow as u8);
+            for rp_id_hash in &rp_id_hashes {
+                message.extend(rp_id_hash);
+            }
+            if !self.pin_protocol_v1.verify_pin_auth_token(
+                &message,
+                &pin_uv_auth_param,
+                pin_uv_auth_protocol,
+            ) {
+                return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID);
+            }
+            #[cfg(feature = "with_ctap2_1")]
+            self.pin_protocol_v1
+                .has_permission(PinPermission::AuthenticatorConfiguration)?;
+        } else if self.persistent_store.attestation_locked()? {
+            return Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED);
+        }
+        (self.check_user_presence)(cid)?;
+        let policy = match (allow, rp_id_hashes.is_empty()) {
+            (false, true) => None,
+            (false, false) => Some(RpIdPolicy::Deny(rp_id_hashes)),
+            (true, _) => Some(RpIdPolicy::Allow(rp_id_hashes)),
+        };
+        self.persistent_store.set_rp_id_policy(policy)?;
+        Ok(ResponseData::AuthenticatorVendorRpIdPolicy)
+    }
+
+    // Reports statistics about the store, to monitor flash wear in the field. Like GetInfo, no
+    // user presence is needed, because the report is anonymized.
+    fn process_vendor_store_health(&self) -> Result<ResponseData, Ctap2StatusCode> {
+        let store = &self.persistent_store;
+        let response = AuthenticatorVendorStoreHealthResponse {
+            firmware_version: String::from(env!("CARGO_PKG_VERSION")),
+            boot_count: store.boot_count()? as u64,
+            num_credentials: store.count_credentials()? as u64,
+            free_space_percent: store.free_space_percent()? as u64,
+            lifetime_used_permille: store.lifetime_used_permille()? as u64,
+        };
+        Ok(ResponseData::AuthenticatorVendorStoreHealth(response))
+    }
+
+    // Resets the boot count reported by the store health report, for example after diagnosing
+    // unexpected resets. Unlike the report, it always requires a PIN/UV auth token with the
+    // authenticator configuration permission.
+    #[cfg(feature = "with_ctap2_1")]
+    fn process_vendor_reset_boot_count(
+        &mut self,
+        params: AuthenticatorVendorResetBootCountParameters,
+    ) -> Result<ResponseData, Ctap2StatusCode> {
+        let AuthenticatorVendorResetBootCountParameters {
+            pin_uv_auth_protocol,
+            pin_uv_auth_param,
+        } = params;
+        if self.persistent_store.pin_hash()?.is_none() {
+            return Err(Ctap2StatusCode::CTAP2_ERR_PIN_NOT_SET);
+        }
+        let pin_uv_auth_param = pin_uv_auth_param.ok_or(Ctap2StatusCode::CTAP2_ERR_PIN_REQUIRED)?;
+        let pin_uv_auth_protocol = check_pin_uv_auth_protocol(pin_uv_auth_protocol)?;
+        // Like for authenticatorConfig: verify(pinUvAuthToken, 32×0xff || uint8(command),
+        // pinUvAuthParam)
+        let mut message = vec![0xFF; 32];
+        message.push(0x45);
+        if !self.pin_protocol_v1.verify_pin_auth_token(
+            &message,
+            &pin_uv_auth_param,
+            pin_uv_auth_protocol,
+        ) {
+            return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID);
+        }
+        self.pin_protocol_v1
+            .has_permission(PinPermission::AuthenticatorConfiguration)?;
+        self.persistent_store.reset_boot_count()?;
+        Ok(ResponseData::AuthenticatorVendorResetBootCount)
+    }
+
     pub fn generate_auth_data(
         &self,
         rp_id_hash: &[u8],
@@ -1012,16 +1764,91 @@ where
     }
 }
 
+// Returns the transports compiled into this firmware, as reported in GetInfo.
+#[cfg(feature = "with_ctap2_1")]
+fn supported_transports() -> Vec<AuthenticatorTransport> {
+    #[allow(unused_mut)]
+    let mut transports = vec![AuthenticatorTransport::Usb];
+    #[cfg(feature = "with_nfc")]
+    transports.push(AuthenticatorTransport::Nfc);
+    transports
+}
+
+// Wraps the private key of a non-resident credential. Only the key and ID are known.
+fn key_handle_credential_source(
+    credential_id: Vec<u8>,
+    private_key: crypto::ecdsa::SecKey,
+) -> PublicKeyCredentialSource {
+    PublicKeyCredentialSource {
+        key_type: PublicKeyCredentialType::PublicKey,
+        credential_id,
+        private_key,
+        rp_id: String::from(""),
+        user_handle: vec![],
+        user_display_name: None,
+        cred_protect_policy: None,
+        creation_order: 0,
+        user_name: None,
+        user_icon: None,
+        cred_blob: None,
+        signature_counter: None,
+        large_blob_key: None,
+    }
+}
+
+// Purposes of the HMACs computed for derived key handles, so that the MAC of a key handle is
+// never the private key of a credential.
+const DERIVED_KEY_HANDLE_TAG: u8 = 0x01;
+const DERIVED_KEY_HANDLE_PRIVATE_KEY: u8 = 0x02;
+
+// Returns the HMAC input for the relying party and nonce of a derived key handle.
+fn derived_key_handle_message(purpose: u8, rp_id_hash: &[u8], nonce: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(1 + rp_id_hash.len() + nonce.len());
+    message.push(purpose);
+    message.extend(rp_id_hash);
+    message.extend(nonce);
+    message
+}
+
+// Returns None for the rare nonces that don't result in a valid private key.
+fn derive_credential_key(
+    master_keys: &MasterKeys,
+    rp_id_hash: &[u8],
+    nonce: &[u8],
+) -> Option<crypto::ecdsa::SecKey> {
+    let message = derived_key_handle_message(DERIVED_KEY_HANDLE_PRIVATE_KEY, rp_id_hash, nonce);
+    let sk_bytes = Zeroizing(hmac_256::<Sha256>(&master_keys.hmac, &message));
+    crypto::ecdsa::SecKey::from_bytes(&sk_bytes)
+}
+
+// Returns whether assertions for this credential report the global signature counter.
+fn uses_global_signature_counter(credential: &PublicKeyCredentialSource) -> bool {
+    if !USE_PER_CREDENTIAL_SIGNATURE_COUNTER {
+        return true;
+    }
+    cfg!(feature = "with_ctap1")
+        && USE_U2F_COMPATIBLE_SIGNATURE_COUNTER
+        && credential.signature_counter.is_none()
+}
+
+// Overwrites the signature counter of authenticator data from generate_auth_data.
+fn write_signature_counter(auth_data: &mut [u8], signature_counter: u32) {
+    // The counter follows the 32 byte RP ID hash and the flag byte.
+    BigEndian::write_u32(&mut auth_data[33..37], signature_counter);
+}
+
 #[cfg(test)]
 mod test {
-    use super::command::AuthenticatorAttestationMaterial;
     use super::data_formats::{
         CoseKey, GetAssertionExtensions, GetAssertionOptions, MakeCredentialExtensions,
         MakeCredentialOptions, PublicKeyCredentialRpEntity, PublicKeyCredentialUserEntity,
     };
+    use super::hid::KeepalivePacer;
+    use super::pin_protocol_v1::{aes256_cbc_decrypt, aes256_cbc_encrypt};
     use super::*;
-    use cbor::cbor_array;
-    use crypto::rng256::ThreadRng256;
+    use cbor::{cbor_array, cbor_map};
+    use core::cell::Cell;
+    use crypto::rng256::{SeededRng256, ThreadRng256};
 
     const CLOCK_FREQUENCY_HZ: usize = 32768;
     const DUMMY_CLOCK_VALUE: ClockValue = ClockValue::new(0, CLOCK_FREQUENCY_HZ);
@@ -1039,7 +1866,7 @@ mod test {
         let info_reponse = ctap_state.process_command(&[0x04], DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
 
         #[cfg(feature = "with_ctap2_1")]
-        let mut expected_response = vec![0x00, 0xAA, 0x01];
+        let mut expected_response = vec![0x00, 0xAD, 0x01];
         #[cfg(not(feature = "with_ctap2_1"))]
         let mut expected_response = vec![0x00, 0xA6, 0x01];
         // The difference here is a longer array of supported versions.
@@ -1062,21 +1889,48 @@ mod test {
         expected_response.extend(&[
             0x6C, 0x46, 0x49, 0x44, 0x4F, 0x5F, 0x32, 0x5F, 0x31, 0x5F, 0x50, 0x52, 0x45,
         ]);
+        // The 2.1 extensions add minPinLength.
+        #[cfg(not(feature = "with_ctap2_1"))]
+        expected_response.extend(&[0x02, 0x84]);
+        #[cfg(feature = "with_ctap2_1")]
+        expected_response.extend(&[0x02, 0x85]);
+        expected_response.extend(&[
+            0x6B, 0x68, 0x6D, 0x61, 0x63, 0x2D, 0x73, 0x65, 0x63, 0x72, 0x65, 0x74, 0x6B, 0x63,
+            0x72, 0x65, 0x64, 0x50, 0x72, 0x6F, 0x74, 0x65, 0x63, 0x74, 0x68, 0x63, 0x72, 0x65,
+            0x64, 0x42, 0x6C, 0x6F, 0x62, 0x6C, 0x6C, 0x61, 0x72, 0x67, 0x65, 0x42, 0x6C, 0x6F,
+            0x62, 0x4B, 0x65, 0x79,
+        ]);
+        #[cfg(feature = "with_ctap2_1")]
         expected_response.extend(&[
-            0x02, 0x81, 0x6B, 0x68, 0x6D, 0x61, 0x63, 0x2D, 0x73, 0x65, 0x63, 0x72, 0x65, 0x74,
-            0x03, 0x50,
+            0x6C, 0x6D, 0x69, 0x6E, 0x50, 0x69, 0x6E, 0x4C, 0x65, 0x6E, 0x67, 0x74, 0x68,
         ]);
+        expected_response.extend(&[0x03, 0x50]);
         expected_response.extend(&ctap_state.persistent_store.aaguid().unwrap());
+        #[cfg(not(feature = "with_ctap2_1"))]
+        expected_response.extend(&[0x04, 0xA3, 0x62, 0x72, 0x6B, 0xF5, 0x62, 0x75, 0x70, 0xF5]);
+        // The 2.1 options add ep, alwaysUv, credMgmt, authnrCfg and largeBlobs.
+        #[cfg(feature = "with_ctap2_1")]
+        expected_response.extend(&[
+            0x04, 0xA8, 0x62, 0x65, 0x70, 0xF4, 0x62, 0x72, 0x6B, 0xF5, 0x62, 0x75, 0x70, 0xF5,
+            0x68, 0x61, 0x6C, 0x77, 0x61, 0x79, 0x73, 0x55, 0x76, 0xF4, 0x68, 0x63, 0x72, 0x65,
+            0x64, 0x4D, 0x67, 0x6D, 0x74, 0xF5, 0x69, 0x61, 0x75, 0x74, 0x68, 0x6E, 0x72, 0x43,
+            0x66, 0x67, 0xF5,
+        ]);
         expected_response.extend(&[
-            0x04, 0xA3, 0x62, 0x72, 0x6B, 0xF5, 0x62, 0x75, 0x70, 0xF5, 0x69, 0x63, 0x6C, 0x69,
-            0x65, 0x6E, 0x74, 0x50, 0x69, 0x6E, 0xF4, 0x05, 0x19, 0x04, 0x00, 0x06, 0x81, 0x01,
+            0x69, 0x63, 0x6C, 0x69, 0x65, 0x6E, 0x74, 0x50, 0x69, 0x6E, 0xF4,
         ]);
         #[cfg(feature = "with_ctap2_1")]
+        expected_response.extend(&[
+            0x6A, 0x6C, 0x61, 0x72, 0x67, 0x65, 0x42, 0x6C, 0x6F, 0x62, 0x73, 0xF5,
+        ]);
+        expected_response.extend(&[0x05, 0x19, 0x04, 0x00, 0x06, 0x82, 0x02, 0x01]);
+        #[cfg(feature = "with_ctap2_1")]
         expected_response.extend(
             [
                 0x08, 0x18, 0x70, 0x09, 0x81, 0x63, 0x75, 0x73, 0x62, 0x0A, 0x81, 0xA2, 0x63, 0x61,
                 0x6C, 0x67, 0x26, 0x64, 0x74, 0x79, 0x70, 0x65, 0x6A, 0x70, 0x75, 0x62, 0x6C, 0x69,
-                0x63, 0x2D, 0x6B, 0x65, 0x79, 0x0D, 0x04,
+                0x63, 0x2D, 0x6B, 0x65, 0x79, 0x0B, 0x19, 0x08, 0x00, 0x0D, 0x04, 0x0F, 0x18, 0x20,
+                0x14, 0x18, 0x96,
             ]
             .iter(),
         );
@@ -1112,6 +1966,7 @@ mod test {
             options,
             pin_uv_auth_param: None,
             pin_uv_auth_protocol: None,
+            enterprise_attestation: None,
         }
     }
 
@@ -1135,12 +1990,77 @@ mod test {
         let extensions = Some(MakeCredentialExtensions {
             hmac_secret: false,
             cred_protect: Some(policy),
+            cred_blob: None,
+            large_blob_key: false,
+            #[cfg(feature = "with_ctap2_1")]
+            min_pin_length: false,
         });
         let mut make_credential_params = create_minimal_make_credential_parameters();
         make_credential_params.extensions = extensions;
         make_credential_params
     }
 
+    #[test]
+    fn test_get_info_transport_max_msg_size() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        // A transport with a smaller limit reduces the advertised size.
+        ctap_state.set_transport_max_msg_size(512);
+        match ctap_state.process_get_info() {
+            Ok(ResponseData::AuthenticatorGetInfo(info_response)) => {
+                assert_eq!(info_response.max_msg_size, Some(512));
+            }
+            _ => panic!("Invalid response type"),
+        }
+
+        // A larger limit doesn't raise it above what the authenticator supports.
+        ctap_state.set_transport_max_msg_size(7609);
+        match ctap_state.process_get_info() {
+            Ok(ResponseData::AuthenticatorGetInfo(info_response)) => {
+                assert_eq!(info_response.max_msg_size, Some(MAX_MSG_SIZE as u64));
+            }
+            _ => panic!("Invalid response type"),
+        }
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_supported_transports() {
+        let transports = supported_transports();
+        assert!(transports.contains(&AuthenticatorTransport::Usb));
+        assert_eq!(
+            transports.contains(&AuthenticatorTransport::Nfc),
+            cfg!(feature = "with_nfc")
+        );
+        assert!(!transports.contains(&AuthenticatorTransport::Ble));
+        assert!(!transports.contains(&AuthenticatorTransport::Internal));
+    }
+
+    #[test]
+    fn test_seeded_rng_reproduces_make_credential() {
+        let seed = [0x5E; 32];
+        let mut responses = vec![];
+        let mut rng_logs = vec![];
+        for _ in 0..2 {
+            let mut rng = SeededRng256::new(seed);
+            let user_immediately_present = |_| Ok(());
+            let mut ctap_state =
+                CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+            let make_credential_params = create_minimal_make_credential_parameters();
+            responses
+                .push(ctap_state.process_make_credential(make_credential_params, DUMMY_CHANNEL_ID));
+            // The state borrows the RNG until it is dropped.
+            drop(ctap_state);
+            rng_logs.push(rng.log().to_vec());
+        }
+        assert!(responses[0].is_ok());
+        assert_eq!(responses[0], responses[1]);
+        assert!(!rng_logs[0].is_empty());
+        assert_eq!(rng_logs[0], rng_logs[1]);
+    }
+
     #[test]
     fn test_residential_process_make_credential() {
         let mut rng = ThreadRng256 {};
@@ -1157,9 +2077,13 @@ mod test {
                     fmt,
                     auth_data,
                     att_stmt,
+                    ep_att,
+                    large_blob_key,
                 } = make_credential_response;
                 // The expected response is split to only assert the non-random parts.
                 assert_eq!(fmt, "packed");
+                assert_eq!(ep_att, None);
+                assert_eq!(large_blob_key, None);
                 let mut expected_auth_data = vec![
                     0xA3, 0x79, 0xA6, 0xF6, 0xEE, 0xAF, 0xB9, 0xA5, 0x5E, 0x37, 0x8C, 0x11, 0x80,
                     0x34, 0xE2, 0x75, 0x1E, 0x68, 0x2F, 0xAB, 0x9F, 0x2D, 0x30, 0xAB, 0x13, 0xD2,
@@ -1195,9 +2119,13 @@ mod test {
                     fmt,
                     auth_data,
                     att_stmt,
+                    ep_att,
+                    large_blob_key,
                 } = make_credential_response;
                 // The expected response is split to only assert the non-random parts.
                 assert_eq!(fmt, "packed");
+                assert_eq!(ep_att, None);
+                assert_eq!(large_blob_key, None);
                 let mut expected_auth_data = vec![
                     0xA3, 0x79, 0xA6, 0xF6, 0xEE, 0xAF, 0xB9, 0xA5, 0x5E, 0x37, 0x8C, 0x11, 0x80,
                     0x34, 0xE2, 0x75, 0x1E, 0x68, 0x2F, 0xAB, 0x9F, 0x2D, 0x30, 0xAB, 0x13, 0xD2,
@@ -1254,6 +2182,9 @@ mod test {
             creation_order: 0,
             user_name: None,
             user_icon: None,
+            cred_blob: None,
+            signature_counter: None,
+            large_blob_key: None,
         };
         assert!(ctap_state
             .persistent_store
@@ -1331,6 +2262,10 @@ mod test {
         let extensions = Some(MakeCredentialExtensions {
             hmac_secret: true,
             cred_protect: None,
+            cred_blob: None,
+            large_blob_key: false,
+            #[cfg(feature = "with_ctap2_1")]
+            min_pin_length: false,
         });
         let mut make_credential_params = create_minimal_make_credential_parameters();
         make_credential_params.options.rk = false;
@@ -1344,9 +2279,13 @@ mod test {
                     fmt,
                     auth_data,
                     att_stmt,
+                    ep_att,
+                    large_blob_key,
                 } = make_credential_response;
                 // The expected response is split to only assert the non-random parts.
                 assert_eq!(fmt, "packed");
+                assert_eq!(ep_att, None);
+                assert_eq!(large_blob_key, None);
                 let mut expected_auth_data = vec![
                     0xA3, 0x79, 0xA6, 0xF6, 0xEE, 0xAF, 0xB9, 0xA5, 0x5E, 0x37, 0x8C, 0x11, 0x80,
                     0x34, 0xE2, 0x75, 0x1E, 0x68, 0x2F, 0xAB, 0x9F, 0x2D, 0x30, 0xAB, 0x13, 0xD2,
@@ -1382,6 +2321,10 @@ mod test {
         let extensions = Some(MakeCredentialExtensions {
             hmac_secret: true,
             cred_protect: None,
+            cred_blob: None,
+            large_blob_key: false,
+            #[cfg(feature = "with_ctap2_1")]
+            min_pin_length: false,
         });
         let mut make_credential_params = create_minimal_make_credential_parameters();
         make_credential_params.extensions = extensions;
@@ -1394,9 +2337,13 @@ mod test {
                     fmt,
                     auth_data,
                     att_stmt,
+                    ep_att,
+                    large_blob_key,
                 } = make_credential_response;
                 // The expected response is split to only assert the non-random parts.
                 assert_eq!(fmt, "packed");
+                assert_eq!(ep_att, None);
+                assert_eq!(large_blob_key, None);
                 let mut expected_auth_data = vec![
                     0xA3, 0x79, 0xA6, 0xF6, 0xEE, 0xAF, 0xB9, 0xA5, 0x5E, 0x37, 0x8C, 0x11, 0x80,
                     0x34, 0xE2, 0x75, 0x1E, 0x68, 0x2F, 0xAB, 0x9F, 0x2D, 0x30, 0xAB, 0x13, 0xD2,
@@ -1423,6 +2370,148 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_process_make_credential_cred_blob_ok() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let extensions = Some(MakeCredentialExtensions {
+            hmac_secret: false,
+            cred_protect: None,
+            cred_blob: Some(vec![0xCB; MAX_CRED_BLOB_LENGTH]),
+            large_blob_key: false,
+            #[cfg(feature = "with_ctap2_1")]
+            min_pin_length: false,
+        });
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.extensions = extensions;
+        let make_credential_response =
+            ctap_state.process_make_credential(make_credential_params, DUMMY_CHANNEL_ID);
+
+        match make_credential_response.unwrap() {
+            ResponseData::AuthenticatorMakeCredential(make_credential_response) => {
+                let auth_data = make_credential_response.auth_data;
+                let expected_extension_cbor = vec![
+                    0xA1, 0x68, 0x63, 0x72, 0x65, 0x64, 0x42, 0x6C, 0x6F, 0x62, 0xF5,
+                ];
+                assert_eq!(
+                    auth_data[auth_data.len() - expected_extension_cbor.len()..auth_data.len()],
+                    expected_extension_cbor[..]
+                );
+            }
+            _ => panic!("Invalid response type"),
+        }
+
+        let stored_credential = ctap_state
+            .persistent_store
+            .filter_credential("example.com", false)
+            .unwrap()
+            .pop()
+            .unwrap();
+        assert_eq!(
+            stored_credential.cred_blob,
+            Some(vec![0xCB; MAX_CRED_BLOB_LENGTH])
+        );
+    }
+
+    #[test]
+    fn test_process_make_credential_cred_blob_too_big() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let extensions = Some(MakeCredentialExtensions {
+            hmac_secret: false,
+            cred_protect: None,
+            cred_blob: Some(vec![0xCB; MAX_CRED_BLOB_LENGTH + 1]),
+            large_blob_key: false,
+            #[cfg(feature = "with_ctap2_1")]
+            min_pin_length: false,
+        });
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.extensions = extensions;
+        let make_credential_response =
+            ctap_state.process_make_credential(make_credential_params, DUMMY_CHANNEL_ID);
+
+        match make_credential_response.unwrap() {
+            ResponseData::AuthenticatorMakeCredential(make_credential_response) => {
+                let auth_data = make_credential_response.auth_data;
+                let expected_extension_cbor = vec![
+                    0xA1, 0x68, 0x63, 0x72, 0x65, 0x64, 0x42, 0x6C, 0x6F, 0x62, 0xF4,
+                ];
+                assert_eq!(
+                    auth_data[auth_data.len() - expected_extension_cbor.len()..auth_data.len()],
+                    expected_extension_cbor[..]
+                );
+            }
+            _ => panic!("Invalid response type"),
+        }
+
+        let stored_credential = ctap_state
+            .persistent_store
+            .filter_credential("example.com", false)
+            .unwrap()
+            .pop()
+            .unwrap();
+        assert_eq!(stored_credential.cred_blob, None);
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_process_make_credential_min_pin_length() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        // The relying party is not on the allow list, so the extension is ignored.
+        let extensions = Some(MakeCredentialExtensions {
+            hmac_secret: false,
+            cred_protect: None,
+            cred_blob: None,
+            large_blob_key: false,
+            min_pin_length: true,
+        });
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.extensions = extensions.clone();
+        let make_credential_response =
+            ctap_state.process_make_credential(make_credential_params, DUMMY_CHANNEL_ID);
+
+        match make_credential_response.unwrap() {
+            ResponseData::AuthenticatorMakeCredential(make_credential_response) => {
+                assert_eq!(make_credential_response.auth_data[32], UP_FLAG | AT_FLAG);
+            }
+            _ => panic!("Invalid response type"),
+        }
+
+        // Once the relying party is allowed, it receives the minimum PIN length.
+        ctap_state.persistent_store.set_min_pin_length(8).unwrap();
+        ctap_state
+            .persistent_store
+            .set_min_pin_length_rp_ids(vec![String::from("example.com")])
+            .unwrap();
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.extensions = extensions;
+        let make_credential_response =
+            ctap_state.process_make_credential(make_credential_params, DUMMY_CHANNEL_ID);
+
+        match make_credential_response.unwrap() {
+            ResponseData::AuthenticatorMakeCredential(make_credential_response) => {
+                let auth_data = make_credential_response.auth_data;
+                assert_eq!(auth_data[32], UP_FLAG | AT_FLAG | ED_FLAG);
+                let expected_extension_cbor = vec![
+                    0xA1, 0x6C, 0x6D, 0x69, 0x6E, 0x50, 0x69, 0x6E, 0x4C, 0x65, 0x6E, 0x67, 0x74,
+                    0x68, 0x08,
+                ];
+                assert_eq!(
+                    auth_data[auth_data.len() - expected_extension_cbor.len()..auth_data.len()],
+                    expected_extension_cbor[..]
+                );
+            }
+            _ => panic!("Invalid response type"),
+        }
+    }
+
     #[test]
     fn test_process_make_credential_cancelled() {
         let mut rng = ThreadRng256 {};
@@ -1440,6 +2529,146 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_process_make_credential_processing_keepalive() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let keepalive_count = Cell::new(0);
+        let keepalive_count_ref = &keepalive_count;
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        // Simulates a slow authenticator, spending 60 ms between keep-alive checkpoints.
+        let mut now = DUMMY_CLOCK_VALUE;
+        let mut pacer = KeepalivePacer::new(now, Duration::from_ms(100));
+        ctap_state.set_processing_keepalive(Box::new(move |cid| {
+            assert_eq!(cid, DUMMY_CHANNEL_ID);
+            now = now.wrapping_add(Duration::from_ms(60));
+            if pacer.is_due(now) {
+                keepalive_count_ref.set(keepalive_count_ref.get() + 1);
+            }
+            Ok(())
+        }));
+
+        // There is one checkpoint per excluded credential, and one before signing.
+        let excluded_credential_descriptor = PublicKeyCredentialDescriptor {
+            key_type: PublicKeyCredentialType::PublicKey,
+            key_id: vec![0x00; 32],
+            transports: None,
+        };
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.exclude_list = Some(vec![excluded_credential_descriptor; 3]);
+        let make_credential_response =
+            ctap_state.process_make_credential(make_credential_params, DUMMY_CHANNEL_ID);
+        assert!(make_credential_response.is_ok());
+        assert_eq!(keepalive_count.get(), 2);
+    }
+
+    #[test]
+    fn test_process_make_credential_cancel_while_processing() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let keepalive_count = Cell::new(0);
+        let keepalive_count_ref = &keepalive_count;
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        // The platform cancels during the second keep-alive.
+        ctap_state.set_processing_keepalive(Box::new(move |_| {
+            keepalive_count_ref.set(keepalive_count_ref.get() + 1);
+            if keepalive_count_ref.get() == 2 {
+                Err(Ctap2StatusCode::CTAP2_ERR_KEEPALIVE_CANCEL)
+            } else {
+                Ok(())
+            }
+        }));
+
+        let excluded_credential_descriptor = PublicKeyCredentialDescriptor {
+            key_type: PublicKeyCredentialType::PublicKey,
+            key_id: vec![0x00; 32],
+            transports: None,
+        };
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.exclude_list = Some(vec![excluded_credential_descriptor; 3]);
+        assert_eq!(
+            ctap_state.process_make_credential(make_credential_params, DUMMY_CHANNEL_ID),
+            Err(Ctap2StatusCode::CTAP2_ERR_KEEPALIVE_CANCEL)
+        );
+        assert_eq!(keepalive_count.get(), 2);
+        assert_eq!(ctap_state.persistent_store.count_credentials().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_process_get_assertion_cancel_while_processing() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        let make_credential_params = create_minimal_make_credential_parameters();
+        assert!(ctap_state
+            .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
+            .is_ok());
+        let signature_counter = ctap_state.persistent_store.global_signature_counter();
+
+        ctap_state.set_processing_keepalive(Box::new(|_| {
+            Err(Ctap2StatusCode::CTAP2_ERR_KEEPALIVE_CANCEL)
+        }));
+        let get_assertion_params = AuthenticatorGetAssertionParameters {
+            rp_id: String::from("example.com"),
+            client_data_hash: vec![0xCD],
+            allow_list: None,
+            extensions: None,
+            options: GetAssertionOptions {
+                up: false,
+                uv: false,
+            },
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: None,
+        };
+        assert_eq!(
+            ctap_state.process_get_assertion(
+                get_assertion_params,
+                DUMMY_CHANNEL_ID,
+                DUMMY_CLOCK_VALUE
+            ),
+            Err(Ctap2StatusCode::CTAP2_ERR_KEEPALIVE_CANCEL)
+        );
+        assert_eq!(
+            ctap_state.persistent_store.global_signature_counter(),
+            signature_counter
+        );
+    }
+
+    #[cfg(feature = "debug_ctap")]
+    #[test]
+    fn test_list_credentials_unsafe() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        assert_eq!(ctap_state.list_credentials_unsafe(), Ok(vec![]));
+
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.user.user_name = Some(String::from("alice"));
+        assert!(ctap_state
+            .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
+            .is_ok());
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.rp.rp_id = String::from("example.org");
+        make_credential_params.user.user_id = vec![0x2D];
+        assert!(ctap_state
+            .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
+            .is_ok());
+        // Non-resident credentials are not listed.
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.options.rk = false;
+        assert!(ctap_state
+            .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
+            .is_ok());
+
+        let summaries = ctap_state.list_credentials_unsafe().unwrap();
+        assert_eq!(summaries.len(), 2);
+        assert_eq!(summaries[0].rp_id, "example.com");
+        assert_eq!(summaries[0].user_name, Some(String::from("alice")));
+        assert_eq!(summaries[1].rp_id, "example.org");
+        assert_eq!(summaries[1].user_name, None);
+        assert!(summaries[0].creation_order < summaries[1].creation_order);
+    }
+
     fn check_assertion_response_with_user(
         response: Result<ResponseData, Ctap2StatusCode>,
         expected_user: PublicKeyCredentialUserEntity,
@@ -1530,20 +2759,166 @@ mod test {
     }
 
     #[test]
-    fn test_process_get_assertion_hmac_secret() {
+    fn test_residential_process_get_assertion_cred_blob() {
         let mut rng = ThreadRng256 {};
-        let sk = crypto::ecdh::SecKey::gensk(&mut rng);
         let user_immediately_present = |_| Ok(());
         let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
 
         let make_extensions = Some(MakeCredentialExtensions {
-            hmac_secret: true,
+            hmac_secret: false,
             cred_protect: None,
+            cred_blob: Some(vec![0xCB; 4]),
+            large_blob_key: false,
+            #[cfg(feature = "with_ctap2_1")]
+            min_pin_length: false,
         });
         let mut make_credential_params = create_minimal_make_credential_parameters();
-        make_credential_params.options.rk = false;
         make_credential_params.extensions = make_extensions;
-        let make_credential_response =
+        assert!(ctap_state
+            .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
+            .is_ok());
+
+        let get_extensions = Some(GetAssertionExtensions {
+            hmac_secret: None,
+            cred_blob: true,
+            large_blob_key: false,
+        });
+        let get_assertion_params = AuthenticatorGetAssertionParameters {
+            rp_id: String::from("example.com"),
+            client_data_hash: vec![0xCD],
+            allow_list: None,
+            extensions: get_extensions,
+            options: GetAssertionOptions {
+                up: false,
+                uv: false,
+            },
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: None,
+        };
+        let get_assertion_response = ctap_state.process_get_assertion(
+            get_assertion_params,
+            DUMMY_CHANNEL_ID,
+            DUMMY_CLOCK_VALUE,
+        );
+
+        match get_assertion_response.unwrap() {
+            ResponseData::AuthenticatorGetAssertion(get_assertion_response) => {
+                let auth_data = get_assertion_response.auth_data;
+                assert_eq!(auth_data[32], ED_FLAG);
+                let expected_extension_cbor = vec![
+                    0xA1, 0x68, 0x63, 0x72, 0x65, 0x64, 0x42, 0x6C, 0x6F, 0x62, 0x44, 0xCB, 0xCB,
+                    0xCB, 0xCB,
+                ];
+                assert_eq!(
+                    auth_data[auth_data.len() - expected_extension_cbor.len()..auth_data.len()],
+                    expected_extension_cbor[..]
+                );
+            }
+            _ => panic!("Invalid response type"),
+        }
+    }
+
+    #[test]
+    fn test_residential_process_get_assertion_large_blob_key() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let make_extensions = Some(MakeCredentialExtensions {
+            hmac_secret: false,
+            cred_protect: None,
+            cred_blob: None,
+            large_blob_key: true,
+            #[cfg(feature = "with_ctap2_1")]
+            min_pin_length: false,
+        });
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.extensions = make_extensions;
+        let make_credential_response =
+            ctap_state.process_make_credential(make_credential_params, DUMMY_CHANNEL_ID);
+        let large_blob_key = match make_credential_response.unwrap() {
+            ResponseData::AuthenticatorMakeCredential(make_credential_response) => {
+                make_credential_response.large_blob_key.unwrap()
+            }
+            _ => panic!("Invalid response type"),
+        };
+        assert_eq!(large_blob_key.len(), 32);
+
+        let get_extensions = Some(GetAssertionExtensions {
+            hmac_secret: None,
+            cred_blob: false,
+            large_blob_key: true,
+        });
+        let get_assertion_params = AuthenticatorGetAssertionParameters {
+            rp_id: String::from("example.com"),
+            client_data_hash: vec![0xCD],
+            allow_list: None,
+            extensions: get_extensions,
+            options: GetAssertionOptions {
+                up: false,
+                uv: false,
+            },
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: None,
+        };
+        let get_assertion_response = ctap_state.process_get_assertion(
+            get_assertion_params,
+            DUMMY_CHANNEL_ID,
+            DUMMY_CLOCK_VALUE,
+        );
+
+        match get_assertion_response.unwrap() {
+            ResponseData::AuthenticatorGetAssertion(get_assertion_response) => {
+                assert_eq!(get_assertion_response.large_blob_key, Some(large_blob_key));
+            }
+            _ => panic!("Invalid response type"),
+        }
+    }
+
+    #[test]
+    fn test_process_make_credential_large_blob_key_non_resident() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let extensions = Some(MakeCredentialExtensions {
+            hmac_secret: false,
+            cred_protect: None,
+            cred_blob: None,
+            large_blob_key: true,
+            #[cfg(feature = "with_ctap2_1")]
+            min_pin_length: false,
+        });
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.options.rk = false;
+        make_credential_params.extensions = extensions;
+        let make_credential_response =
+            ctap_state.process_make_credential(make_credential_params, DUMMY_CHANNEL_ID);
+        assert_eq!(
+            make_credential_response,
+            Err(Ctap2StatusCode::CTAP2_ERR_INVALID_OPTION)
+        );
+    }
+
+    #[test]
+    fn test_process_get_assertion_hmac_secret() {
+        let mut rng = ThreadRng256 {};
+        let sk = crypto::ecdh::SecKey::gensk(&mut rng);
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let make_extensions = Some(MakeCredentialExtensions {
+            hmac_secret: true,
+            cred_protect: None,
+            cred_blob: None,
+            large_blob_key: false,
+            #[cfg(feature = "with_ctap2_1")]
+            min_pin_length: false,
+        });
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.options.rk = false;
+        make_credential_params.extensions = make_extensions;
+        let make_credential_response =
             ctap_state.process_make_credential(make_credential_params, DUMMY_CHANNEL_ID);
         assert!(make_credential_response.is_ok());
         let credential_id = match make_credential_response.unwrap() {
@@ -1562,9 +2937,12 @@ mod test {
             key_agreement: CoseKey::from(pk),
             salt_enc: vec![0x02; 32],
             salt_auth: vec![0x03; 16],
+            pin_uv_auth_protocol: 1,
         };
         let get_extensions = Some(GetAssertionExtensions {
             hmac_secret: Some(hmac_secret_input),
+            cred_blob: false,
+            large_blob_key: false,
         });
 
         let cred_desc = PublicKeyCredentialDescriptor {
@@ -1606,6 +2984,10 @@ mod test {
         let make_extensions = Some(MakeCredentialExtensions {
             hmac_secret: true,
             cred_protect: None,
+            cred_blob: None,
+            large_blob_key: false,
+            #[cfg(feature = "with_ctap2_1")]
+            min_pin_length: false,
         });
         let mut make_credential_params = create_minimal_make_credential_parameters();
         make_credential_params.extensions = make_extensions;
@@ -1618,9 +3000,12 @@ mod test {
             key_agreement: CoseKey::from(pk),
             salt_enc: vec![0x02; 32],
             salt_auth: vec![0x03; 16],
+            pin_uv_auth_protocol: 1,
         };
         let get_extensions = Some(GetAssertionExtensions {
             hmac_secret: Some(hmac_secret_input),
+            cred_blob: false,
+            large_blob_key: false,
         });
 
         let get_assertion_params = AuthenticatorGetAssertionParameters {
@@ -1647,6 +3032,113 @@ mod test {
         );
     }
 
+    // Runs getAssertion with hmac-secret for the resident credential of example.com, and returns
+    // the decrypted output. The platform uses PIN protocol 1.
+    fn get_hmac_secret_output<R, CheckUserPresence>(
+        ctap_state: &mut CtapState<R, CheckUserPresence>,
+        authenticator_key: &crypto::ecdh::PubKey,
+        platform_key: &crypto::ecdh::SecKey,
+        salts: &[u8],
+    ) -> Vec<u8>
+    where
+        R: Rng256,
+        CheckUserPresence: Fn(ChannelID) -> Result<(), Ctap2StatusCode>,
+    {
+        let shared_secret = platform_key.exchange_x_sha256(authenticator_key);
+        let salt_enc = aes256_cbc_encrypt(&shared_secret, [0; 16], salts);
+        let salt_auth = hmac_256::<Sha256>(&shared_secret, &salt_enc)[..16].to_vec();
+        let hmac_secret_input = GetAssertionHmacSecretInput {
+            key_agreement: CoseKey::from(platform_key.genpk()),
+            salt_enc,
+            salt_auth,
+            pin_uv_auth_protocol: 1,
+        };
+        let get_assertion_params = AuthenticatorGetAssertionParameters {
+            rp_id: String::from("example.com"),
+            client_data_hash: vec![0xCD],
+            allow_list: None,
+            extensions: Some(GetAssertionExtensions {
+                hmac_secret: Some(hmac_secret_input),
+                cred_blob: false,
+                large_blob_key: false,
+            }),
+            options: GetAssertionOptions {
+                up: true,
+                uv: false,
+            },
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: None,
+        };
+        let auth_data = match ctap_state.process_get_assertion(
+            get_assertion_params,
+            DUMMY_CHANNEL_ID,
+            DUMMY_CLOCK_VALUE,
+        ) {
+            Ok(ResponseData::AuthenticatorGetAssertion(response)) => response.auth_data,
+            _ => panic!("Invalid response type"),
+        };
+        // The extensions follow the RP ID hash, flags and signature counter.
+        let output_enc = match cbor::read(&auth_data[37..]) {
+            Ok(cbor::Value::Map(extensions)) => {
+                match extensions.get(&cbor::KeyType::TextString(String::from("hmac-secret"))) {
+                    Some(cbor::Value::KeyValue(cbor::KeyType::ByteString(output_enc))) => {
+                        output_enc.clone()
+                    }
+                    _ => panic!("Missing hmac-secret output"),
+                }
+            }
+            _ => panic!("Invalid extensions"),
+        };
+        aes256_cbc_decrypt(&shared_secret, [0; 16], &output_enc).unwrap()
+    }
+
+    #[test]
+    fn test_process_get_assertion_hmac_secret_salts() {
+        let mut rng = ThreadRng256 {};
+        let platform_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let authenticator_key = key_agreement_key.genpk();
+        let pin_protocol_v1 = PinProtocolV1::new_test(key_agreement_key, [0x88; 32]);
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        ctap_state.pin_protocol_v1 = pin_protocol_v1;
+
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.extensions = Some(MakeCredentialExtensions {
+            hmac_secret: true,
+            cred_protect: None,
+            cred_blob: None,
+            large_blob_key: false,
+            #[cfg(feature = "with_ctap2_1")]
+            min_pin_length: false,
+        });
+        assert!(ctap_state
+            .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
+            .is_ok());
+
+        let salt1 = [0x01; 32];
+        let salt2 = [0x02; 32];
+        let output1 =
+            get_hmac_secret_output(&mut ctap_state, &authenticator_key, &platform_key, &salt1);
+        assert_eq!(output1.len(), 32);
+        // The same salt always evaluates to the same output.
+        assert_eq!(
+            get_hmac_secret_output(&mut ctap_state, &authenticator_key, &platform_key, &salt1),
+            output1
+        );
+        let output2 =
+            get_hmac_secret_output(&mut ctap_state, &authenticator_key, &platform_key, &salt2);
+        assert_ne!(output2, output1);
+
+        // Two salts are evaluated independently, for example to rotate keys.
+        let mut salt12 = salt1.to_vec();
+        salt12.extend(&salt2);
+        let output12 =
+            get_hmac_secret_output(&mut ctap_state, &authenticator_key, &platform_key, &salt12);
+        assert_eq!(&output12[..32], &output1[..]);
+        assert_eq!(&output12[32..], &output2[..]);
+    }
+
     #[test]
     fn test_residential_process_get_assertion_with_cred_protect() {
         let mut rng = ThreadRng256 {};
@@ -1673,6 +3165,9 @@ mod test {
             creation_order: 0,
             user_name: None,
             user_icon: None,
+            cred_blob: None,
+            signature_counter: None,
+            large_blob_key: None,
         };
         assert!(ctap_state
             .persistent_store
@@ -1735,6 +3230,9 @@ mod test {
             creation_order: 0,
             user_name: None,
             user_icon: None,
+            cred_blob: None,
+            signature_counter: None,
+            large_blob_key: None,
         };
         assert!(ctap_state
             .persistent_store
@@ -1765,7 +3263,7 @@ mod test {
     }
 
     #[test]
-    fn test_process_get_next_assertion_two_credentials_with_uv() {
+    fn test_process_get_assertion_with_pin_protocol_v2() {
         let mut rng = ThreadRng256 {};
         let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
         let pin_uv_auth_token = [0x88; 32];
@@ -1775,52 +3273,54 @@ mod test {
         let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
         ctap_state.pin_protocol_v1 = pin_protocol_v1;
 
-        let mut make_credential_params = create_minimal_make_credential_parameters();
-        let user1 = PublicKeyCredentialUserEntity {
-            user_id: vec![0x01],
-            user_name: Some("user1".to_string()),
-            user_display_name: Some("User One".to_string()),
-            user_icon: Some("icon1".to_string()),
-        };
-        make_credential_params.user = user1.clone();
-        assert!(ctap_state
-            .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
-            .is_ok());
-        let mut make_credential_params = create_minimal_make_credential_parameters();
-        let user2 = PublicKeyCredentialUserEntity {
-            user_id: vec![0x02],
-            user_name: Some("user2".to_string()),
-            user_display_name: Some("User Two".to_string()),
-            user_icon: Some("icon2".to_string()),
-        };
-        make_credential_params.user = user2.clone();
+        let make_credential_params = create_minimal_make_credential_parameters();
         assert!(ctap_state
             .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
             .is_ok());
-
         ctap_state
             .persistent_store
             .set_pin_hash(&[0u8; 16])
             .unwrap();
-        let pin_uv_auth_param = Some(vec![
-            0x6F, 0x52, 0x83, 0xBF, 0x1A, 0x91, 0xEE, 0x67, 0xE9, 0xD4, 0x4C, 0x80, 0x08, 0x79,
-            0x90, 0x8D,
-        ]);
 
-        let get_assertion_params = AuthenticatorGetAssertionParameters {
-            rp_id: String::from("example.com"),
-            client_data_hash: vec![0xCD],
-            allow_list: None,
-            extensions: None,
-            options: GetAssertionOptions {
-                up: false,
-                uv: true,
-            },
-            pin_uv_auth_param,
-            pin_uv_auth_protocol: Some(1),
+        let client_data_hash = vec![0xCD];
+        let pin_uv_auth_param = hmac_256::<Sha256>(&pin_uv_auth_token, &client_data_hash);
+        let create_get_assertion_params = |pin_uv_auth_param: Vec<u8>, pin_uv_auth_protocol| {
+            AuthenticatorGetAssertionParameters {
+                rp_id: String::from("example.com"),
+                client_data_hash: client_data_hash.clone(),
+                allow_list: None,
+                extensions: None,
+                options: GetAssertionOptions {
+                    up: false,
+                    uv: true,
+                },
+                pin_uv_auth_param: Some(pin_uv_auth_param),
+                pin_uv_auth_protocol: Some(pin_uv_auth_protocol),
+            }
         };
+
+        // Protocol 2 uses the untruncated HMAC.
         let get_assertion_response = ctap_state.process_get_assertion(
-            get_assertion_params,
+            create_get_assertion_params(pin_uv_auth_param[..16].to_vec(), 2),
+            DUMMY_CHANNEL_ID,
+            DUMMY_CLOCK_VALUE,
+        );
+        assert_eq!(
+            get_assertion_response,
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+        );
+        let get_assertion_response = ctap_state.process_get_assertion(
+            create_get_assertion_params(pin_uv_auth_param.to_vec(), 1),
+            DUMMY_CHANNEL_ID,
+            DUMMY_CLOCK_VALUE,
+        );
+        assert_eq!(
+            get_assertion_response,
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+        );
+
+        let get_assertion_response = ctap_state.process_get_assertion(
+            create_get_assertion_params(pin_uv_auth_param.to_vec(), 2),
             DUMMY_CHANNEL_ID,
             DUMMY_CLOCK_VALUE,
         );
@@ -1828,46 +3328,218 @@ mod test {
             .persistent_store
             .global_signature_counter()
             .unwrap();
+        let expected_user = PublicKeyCredentialUserEntity {
+            user_id: vec![0x1D],
+            user_name: None,
+            user_display_name: None,
+            user_icon: None,
+        };
         check_assertion_response_with_user(
             get_assertion_response,
-            user2,
-            0x04,
-            signature_counter,
-            Some(2),
-        );
-
-        let get_assertion_response = ctap_state.process_get_next_assertion(DUMMY_CLOCK_VALUE);
-        check_assertion_response_with_user(
-            get_assertion_response,
-            user1,
+            expected_user,
             0x04,
             signature_counter,
             None,
         );
-
-        let get_assertion_response = ctap_state.process_get_next_assertion(DUMMY_CLOCK_VALUE);
-        assert_eq!(
-            get_assertion_response,
-            Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)
-        );
     }
 
+    #[cfg(feature = "with_ctap2_1")]
     #[test]
-    fn test_process_get_next_assertion_three_credentials_no_uv() {
+    fn test_pin_uv_auth_token_scope() {
         let mut rng = ThreadRng256 {};
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let pin_uv_auth_token = [0x88; 32];
+        let mut pin_protocol_v1 = PinProtocolV1::new_test(key_agreement_key, pin_uv_auth_token);
+        pin_protocol_v1.set_permissions(PinPermission::MakeCredential as u8);
+
         let user_immediately_present = |_| Ok(());
         let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        ctap_state.pin_protocol_v1 = pin_protocol_v1;
+        ctap_state
+            .persistent_store
+            .set_pin_hash(&[0u8; 16])
+            .unwrap();
 
-        let mut make_credential_params = create_minimal_make_credential_parameters();
-        make_credential_params.user.user_id = vec![0x01];
-        make_credential_params.user.user_name = Some("removed".to_string());
-        make_credential_params.user.user_display_name = Some("removed".to_string());
-        make_credential_params.user.user_icon = Some("removed".to_string());
-        assert!(ctap_state
-            .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
-            .is_ok());
-        let mut make_credential_params = create_minimal_make_credential_parameters();
-        make_credential_params.user.user_id = vec![0x02];
+        let client_data_hash = vec![0xCD];
+        let pin_uv_auth_param =
+            hmac_256::<Sha256>(&pin_uv_auth_token, &client_data_hash)[..16].to_vec();
+        let create_make_credential_params = |rp_id: &str| {
+            let mut make_credential_params = create_minimal_make_credential_parameters();
+            make_credential_params.rp.rp_id = String::from(rp_id);
+            make_credential_params.client_data_hash = client_data_hash.clone();
+            make_credential_params.pin_uv_auth_param = Some(pin_uv_auth_param.clone());
+            make_credential_params.pin_uv_auth_protocol = Some(1);
+            make_credential_params
+        };
+        let create_get_assertion_params = |rp_id: &str| AuthenticatorGetAssertionParameters {
+            rp_id: String::from(rp_id),
+            client_data_hash: client_data_hash.clone(),
+            allow_list: None,
+            extensions: None,
+            options: GetAssertionOptions {
+                up: false,
+                uv: true,
+            },
+            pin_uv_auth_param: Some(pin_uv_auth_param.clone()),
+            pin_uv_auth_protocol: Some(1),
+        };
+
+        // The first use binds the token to example.com.
+        assert!(ctap_state
+            .process_make_credential(
+                create_make_credential_params("example.com"),
+                DUMMY_CHANNEL_ID
+            )
+            .is_ok());
+        assert_eq!(
+            ctap_state.process_make_credential(
+                create_make_credential_params("counter-example.com"),
+                DUMMY_CHANNEL_ID
+            ),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+        );
+        // The token lacks the getAssertion permission.
+        assert_eq!(
+            ctap_state.process_get_assertion(
+                create_get_assertion_params("example.com"),
+                DUMMY_CHANNEL_ID,
+                DUMMY_CLOCK_VALUE,
+            ),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+        );
+
+        ctap_state
+            .pin_protocol_v1
+            .set_permissions(PinPermission::GetAssertion as u8);
+        assert!(ctap_state
+            .process_get_assertion(
+                create_get_assertion_params("example.com"),
+                DUMMY_CHANNEL_ID,
+                DUMMY_CLOCK_VALUE,
+            )
+            .is_ok());
+        assert_eq!(
+            ctap_state.process_get_assertion(
+                create_get_assertion_params("counter-example.com"),
+                DUMMY_CHANNEL_ID,
+                DUMMY_CLOCK_VALUE,
+            ),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+        );
+        // The token lost the makeCredential permission.
+        assert_eq!(
+            ctap_state.process_make_credential(
+                create_make_credential_params("example.com"),
+                DUMMY_CHANNEL_ID
+            ),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+        );
+    }
+
+    #[test]
+    fn test_process_get_next_assertion_two_credentials_with_uv() {
+        let mut rng = ThreadRng256 {};
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let pin_uv_auth_token = [0x88; 32];
+        let pin_protocol_v1 = PinProtocolV1::new_test(key_agreement_key, pin_uv_auth_token);
+
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        ctap_state.pin_protocol_v1 = pin_protocol_v1;
+
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        let user1 = PublicKeyCredentialUserEntity {
+            user_id: vec![0x01],
+            user_name: Some("user1".to_string()),
+            user_display_name: Some("User One".to_string()),
+            user_icon: Some("icon1".to_string()),
+        };
+        make_credential_params.user = user1.clone();
+        assert!(ctap_state
+            .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
+            .is_ok());
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        let user2 = PublicKeyCredentialUserEntity {
+            user_id: vec![0x02],
+            user_name: Some("user2".to_string()),
+            user_display_name: Some("User Two".to_string()),
+            user_icon: Some("icon2".to_string()),
+        };
+        make_credential_params.user = user2.clone();
+        assert!(ctap_state
+            .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
+            .is_ok());
+
+        ctap_state
+            .persistent_store
+            .set_pin_hash(&[0u8; 16])
+            .unwrap();
+        let pin_uv_auth_param = Some(vec![
+            0x6F, 0x52, 0x83, 0xBF, 0x1A, 0x91, 0xEE, 0x67, 0xE9, 0xD4, 0x4C, 0x80, 0x08, 0x79,
+            0x90, 0x8D,
+        ]);
+
+        let get_assertion_params = AuthenticatorGetAssertionParameters {
+            rp_id: String::from("example.com"),
+            client_data_hash: vec![0xCD],
+            allow_list: None,
+            extensions: None,
+            options: GetAssertionOptions {
+                up: false,
+                uv: true,
+            },
+            pin_uv_auth_param,
+            pin_uv_auth_protocol: Some(1),
+        };
+        let get_assertion_response = ctap_state.process_get_assertion(
+            get_assertion_params,
+            DUMMY_CHANNEL_ID,
+            DUMMY_CLOCK_VALUE,
+        );
+        let signature_counter = ctap_state
+            .persistent_store
+            .global_signature_counter()
+            .unwrap();
+        check_assertion_response_with_user(
+            get_assertion_response,
+            user2,
+            0x04,
+            signature_counter,
+            Some(2),
+        );
+
+        let get_assertion_response = ctap_state.process_get_next_assertion(DUMMY_CLOCK_VALUE);
+        check_assertion_response_with_user(
+            get_assertion_response,
+            user1,
+            0x04,
+            signature_counter,
+            None,
+        );
+
+        let get_assertion_response = ctap_state.process_get_next_assertion(DUMMY_CLOCK_VALUE);
+        assert_eq!(
+            get_assertion_response,
+            Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)
+        );
+    }
+
+    #[test]
+    fn test_process_get_next_assertion_three_credentials_no_uv() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.user.user_id = vec![0x01];
+        make_credential_params.user.user_name = Some("removed".to_string());
+        make_credential_params.user.user_display_name = Some("removed".to_string());
+        make_credential_params.user.user_icon = Some("removed".to_string());
+        assert!(ctap_state
+            .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
+            .is_ok());
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.user.user_id = vec![0x02];
         make_credential_params.user.user_name = Some("removed".to_string());
         make_credential_params.user.user_display_name = Some("removed".to_string());
         make_credential_params.user.user_icon = Some("removed".to_string());
@@ -1988,262 +3660,1894 @@ mod test {
         );
     }
 
+    #[cfg(feature = "with_ctap2_1")]
     #[test]
-    fn test_process_reset() {
+    fn test_process_credential_management_delete_credential() {
         let mut rng = ThreadRng256 {};
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let pin_uv_auth_token = [0x88; 32];
+        let pin_protocol_v1 = PinProtocolV1::new_test(key_agreement_key, pin_uv_auth_token);
+
         let user_immediately_present = |_| Ok(());
-        let private_key = crypto::ecdsa::SecKey::gensk(&mut rng);
         let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        ctap_state.pin_protocol_v1 = pin_protocol_v1;
 
-        let credential_id = vec![0x01, 0x23, 0x45, 0x67];
-        let credential_source = PublicKeyCredentialSource {
+        for user_id in &[0x1D, 0x2D] {
+            let mut make_credential_params = create_minimal_make_credential_parameters();
+            make_credential_params.user.user_id = vec![*user_id];
+            assert!(ctap_state
+                .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
+                .is_ok());
+        }
+        let deleted_credential_id = ctap_state
+            .persistent_store
+            .filter_credential("example.com", false)
+            .unwrap()
+            .into_iter()
+            .find(|credential| credential.user_handle == vec![0x1D])
+            .unwrap()
+            .credential_id;
+        let cred_desc = PublicKeyCredentialDescriptor {
             key_type: PublicKeyCredentialType::PublicKey,
-            credential_id,
-            private_key,
-            rp_id: String::from("example.com"),
-            user_handle: vec![],
-            user_display_name: None,
-            cred_protect_policy: None,
-            creation_order: 0,
-            user_name: None,
-            user_icon: None,
+            key_id: deleted_credential_id,
+            transports: None,
         };
-        assert!(ctap_state
-            .persistent_store
-            .store_credential(credential_source)
-            .is_ok());
-        assert!(ctap_state.persistent_store.count_credentials().unwrap() > 0);
-
-        let reset_reponse =
-            ctap_state.process_command(&[0x07], DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
-        let expected_response = vec![0x00];
-        assert_eq!(reset_reponse, expected_response);
-        assert!(ctap_state.persistent_store.count_credentials().unwrap() == 0);
-    }
 
-    #[test]
-    fn test_process_reset_cancelled() {
-        let mut rng = ThreadRng256 {};
-        let user_presence_always_cancel = |_| Err(Ctap2StatusCode::CTAP2_ERR_KEEPALIVE_CANCEL);
-        let mut ctap_state =
-            CtapState::new(&mut rng, user_presence_always_cancel, DUMMY_CLOCK_VALUE);
-
-        let reset_reponse = ctap_state.process_reset(DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        let sub_command_params = cbor_map! {
+            0x02 => cred_desc.clone(),
+        };
+        let mut message = vec![0x06];
+        assert!(cbor::write(sub_command_params.clone(), &mut message));
+        let pin_uv_auth_param = hmac_256::<Sha256>(&pin_uv_auth_token, &message)[..16].to_vec();
+        let mut command_cbor = vec![0x0A];
+        assert!(cbor::write(
+            cbor_map! {
+                0x01 => 0x06,
+                0x02 => sub_command_params,
+                0x03 => 1,
+                0x04 => pin_uv_auth_param,
+            },
+            &mut command_cbor
+        ));
+        let response =
+            ctap_state.process_command(&command_cbor, DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        assert_eq!(response, vec![0x00]);
+        assert_eq!(ctap_state.persistent_store.count_credentials().unwrap(), 1);
 
+        let get_assertion_params = AuthenticatorGetAssertionParameters {
+            rp_id: String::from("example.com"),
+            client_data_hash: vec![0xCD],
+            allow_list: Some(vec![cred_desc]),
+            extensions: None,
+            options: GetAssertionOptions {
+                up: false,
+                uv: false,
+            },
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: None,
+        };
+        let get_assertion_response = ctap_state.process_get_assertion(
+            get_assertion_params,
+            DUMMY_CHANNEL_ID,
+            DUMMY_CLOCK_VALUE,
+        );
         assert_eq!(
-            reset_reponse,
-            Err(Ctap2StatusCode::CTAP2_ERR_KEEPALIVE_CANCEL)
+            get_assertion_response,
+            Err(Ctap2StatusCode::CTAP2_ERR_NO_CREDENTIALS),
+        );
+
+        let get_assertion_params = AuthenticatorGetAssertionParameters {
+            rp_id: String::from("example.com"),
+            client_data_hash: vec![0xCD],
+            allow_list: None,
+            extensions: None,
+            options: GetAssertionOptions {
+                up: false,
+                uv: false,
+            },
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: None,
+        };
+        let get_assertion_response = ctap_state.process_get_assertion(
+            get_assertion_params,
+            DUMMY_CHANNEL_ID,
+            DUMMY_CLOCK_VALUE,
         );
+        let signature_counter = ctap_state
+            .persistent_store
+            .global_signature_counter()
+            .unwrap();
+        check_assertion_response(get_assertion_response, vec![0x2D], signature_counter, None);
     }
 
+    #[cfg(feature = "with_ctap2_1")]
     #[test]
-    fn test_process_reset_not_first() {
+    fn test_process_credential_management_update_user_information() {
         let mut rng = ThreadRng256 {};
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let pin_uv_auth_token = [0x88; 32];
+        let pin_protocol_v1 = PinProtocolV1::new_test(key_agreement_key, pin_uv_auth_token);
+
         let user_immediately_present = |_| Ok(());
         let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        ctap_state.pin_protocol_v1 = pin_protocol_v1;
 
-        // This is a GetNextAssertion command.
-        ctap_state.process_command(&[0x08], DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.user.user_name = Some("user".to_string());
+        make_credential_params.user.user_display_name = Some("Old Name".to_string());
+        assert!(ctap_state
+            .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
+            .is_ok());
+        let credential_id = ctap_state
+            .persistent_store
+            .filter_credential("example.com", false)
+            .unwrap()[0]
+            .credential_id
+            .clone();
 
-        let reset_reponse = ctap_state.process_reset(DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
-        assert_eq!(reset_reponse, Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED));
-    }
+        let new_user = PublicKeyCredentialUserEntity {
+            user_id: vec![0x1D],
+            user_name: Some("user".to_string()),
+            user_display_name: Some("New Name".to_string()),
+            user_icon: None,
+        };
+        let sub_command_params = cbor_map! {
+            0x02 => PublicKeyCredentialDescriptor {
+                key_type: PublicKeyCredentialType::PublicKey,
+                key_id: credential_id,
+                transports: None,
+            },
+            0x03 => new_user.clone(),
+        };
+        let mut message = vec![0x07];
+        assert!(cbor::write(sub_command_params.clone(), &mut message));
+        let pin_uv_auth_param = hmac_256::<Sha256>(&pin_uv_auth_token, &message)[..16].to_vec();
+        let mut command_cbor = vec![0x0A];
+        assert!(cbor::write(
+            cbor_map! {
+                0x01 => 0x07,
+                0x02 => sub_command_params,
+                0x03 => 1,
+                0x04 => pin_uv_auth_param,
+            },
+            &mut command_cbor
+        ));
+        let response =
+            ctap_state.process_command(&command_cbor, DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        assert_eq!(response, vec![0x00]);
 
-    #[test]
-    fn test_process_unknown_command() {
+        ctap_state
+            .persistent_store
+            .set_pin_hash(&[0u8; 16])
+            .unwrap();
+        let pin_uv_auth_param = Some(vec![
+            0x6F, 0x52, 0x83, 0xBF, 0x1A, 0x91, 0xEE, 0x67, 0xE9, 0xD4, 0x4C, 0x80, 0x08, 0x79,
+            0x90, 0x8D,
+        ]);
+        let get_assertion_params = AuthenticatorGetAssertionParameters {
+            rp_id: String::from("example.com"),
+            client_data_hash: vec![0xCD],
+            allow_list: None,
+            extensions: None,
+            options: GetAssertionOptions {
+                up: false,
+                uv: true,
+            },
+            pin_uv_auth_param,
+            pin_uv_auth_protocol: Some(1),
+        };
+        let get_assertion_response = ctap_state.process_get_assertion(
+            get_assertion_params,
+            DUMMY_CHANNEL_ID,
+            DUMMY_CLOCK_VALUE,
+        );
+        let signature_counter = ctap_state
+            .persistent_store
+            .global_signature_counter()
+            .unwrap();
+        check_assertion_response_with_user(
+            get_assertion_response,
+            new_user,
+            0x04,
+            signature_counter,
+            None,
+        );
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    fn create_toggle_always_uv_parameters(
+        pin_uv_auth_param: Option<Vec<u8>>,
+    ) -> AuthenticatorConfigParameters {
+        AuthenticatorConfigParameters {
+            sub_command: ConfigSubCommand::ToggleAlwaysUv,
+            sub_command_params: None,
+            pin_uv_auth_param,
+            pin_uv_auth_protocol: Some(1),
+        }
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    fn has_always_uv_option<R, CheckUserPresence>(
+        ctap_state: &CtapState<R, CheckUserPresence>,
+    ) -> bool
+    where
+        R: Rng256,
+        CheckUserPresence: Fn(ChannelID) -> Result<(), Ctap2StatusCode>,
+    {
+        match ctap_state.process_get_info() {
+            Ok(ResponseData::AuthenticatorGetInfo(info)) => info.options.unwrap()["alwaysUv"],
+            _ => panic!("Invalid response type"),
+        }
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_process_config_toggle_always_uv() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        assert!(!has_always_uv_option(&ctap_state));
+
+        // Without a PIN, toggling requires no authentication.
+        let config_response = ctap_state.process_config(create_toggle_always_uv_parameters(None));
+        assert_eq!(config_response, Ok(ResponseData::AuthenticatorConfig));
+        assert!(has_always_uv_option(&ctap_state));
+
+        // Once enabled, toggling back requires authentication.
+        let config_response = ctap_state.process_config(create_toggle_always_uv_parameters(None));
+        assert_eq!(
+            config_response,
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_REQUIRED)
+        );
+        assert!(has_always_uv_option(&ctap_state));
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_process_config_toggle_always_uv_with_pin() {
+        let mut rng = ThreadRng256 {};
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let pin_uv_auth_token = [0x88; 32];
+        let pin_protocol_v1 = PinProtocolV1::new_test(key_agreement_key, pin_uv_auth_token);
+
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        ctap_state.pin_protocol_v1 = pin_protocol_v1;
+        ctap_state
+            .persistent_store
+            .set_pin_hash(&[0u8; 16])
+            .unwrap();
+
+        let config_response = ctap_state.process_config(create_toggle_always_uv_parameters(None));
+        assert_eq!(
+            config_response,
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_REQUIRED)
+        );
+
+        let config_response =
+            ctap_state.process_config(create_toggle_always_uv_parameters(Some(vec![0x00; 16])));
+        assert_eq!(
+            config_response,
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+        );
+        assert!(!ctap_state.persistent_store.has_always_uv().unwrap());
+
+        let mut message = vec![0xFF; 32];
+        message.extend(&[0x0D, ConfigSubCommand::ToggleAlwaysUv as u8]);
+        let pin_uv_auth_param = hmac_256::<Sha256>(&pin_uv_auth_token, &message)[..16].to_vec();
+        let config_response =
+            ctap_state.process_config(create_toggle_always_uv_parameters(Some(pin_uv_auth_param)));
+        assert_eq!(config_response, Ok(ResponseData::AuthenticatorConfig));
+        assert!(ctap_state.persistent_store.has_always_uv().unwrap());
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    fn create_set_min_pin_length_parameters(
+        new_min_pin_length: Option<u8>,
+        force_change_pin: Option<bool>,
+    ) -> AuthenticatorConfigParameters {
+        let mut sub_command_params = BTreeMap::new();
+        if let Some(new_min_pin_length) = new_min_pin_length {
+            sub_command_params.insert(
+                cbor::KeyType::Unsigned(0x01),
+                cbor::Value::from(new_min_pin_length as u64),
+            );
+        }
+        if let Some(force_change_pin) = force_change_pin {
+            sub_command_params.insert(
+                cbor::KeyType::Unsigned(0x03),
+                cbor::Value::from(force_change_pin),
+            );
+        }
+        AuthenticatorConfigParameters {
+            sub_command: ConfigSubCommand::SetMinPinLength,
+            sub_command_params: Some(sub_command_params),
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: Some(1),
+        }
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    fn get_info_min_pin_length<R, CheckUserPresence>(
+        ctap_state: &CtapState<R, CheckUserPresence>,
+    ) -> u8
+    where
+        R: Rng256,
+        CheckUserPresence: Fn(ChannelID) -> Result<(), Ctap2StatusCode>,
+    {
+        match ctap_state.process_get_info() {
+            Ok(ResponseData::AuthenticatorGetInfo(info)) => info.min_pin_length,
+            _ => panic!("Invalid response type"),
+        }
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_process_config_set_min_pin_length() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        assert_eq!(get_info_min_pin_length(&ctap_state), 4);
+
+        // The minimum PIN length can be increased.
+        let config_response =
+            ctap_state.process_config(create_set_min_pin_length_parameters(Some(6), None));
+        assert_eq!(config_response, Ok(ResponseData::AuthenticatorConfig));
+        assert_eq!(get_info_min_pin_length(&ctap_state), 6);
+
+        // Omitting the new length keeps the current one.
+        let config_response =
+            ctap_state.process_config(create_set_min_pin_length_parameters(None, None));
+        assert_eq!(config_response, Ok(ResponseData::AuthenticatorConfig));
+        assert_eq!(get_info_min_pin_length(&ctap_state), 6);
+
+        // The minimum PIN length can't be decreased.
+        let config_response =
+            ctap_state.process_config(create_set_min_pin_length_parameters(Some(5), None));
+        assert_eq!(
+            config_response,
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_POLICY_VIOLATION)
+        );
+        assert_eq!(get_info_min_pin_length(&ctap_state), 6);
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_process_config_set_min_pin_length_force_change() {
+        let mut rng = ThreadRng256 {};
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let pin_uv_auth_token = [0x88; 32];
+        let pin_protocol_v1 = PinProtocolV1::new_test(key_agreement_key, pin_uv_auth_token);
+
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        ctap_state.pin_protocol_v1 = pin_protocol_v1;
+
+        // Forcing a PIN change requires a PIN.
+        let config_response =
+            ctap_state.process_config(create_set_min_pin_length_parameters(None, Some(true)));
+        assert_eq!(config_response, Err(Ctap2StatusCode::CTAP2_ERR_PIN_NOT_SET));
+
+        ctap_state
+            .persistent_store
+            .set_pin_hash(&[0u8; 16])
+            .unwrap();
+        let mut config_params = create_set_min_pin_length_parameters(Some(6), Some(true));
+        let mut message = vec![0xFF; 32];
+        message.extend(&[0x0D, ConfigSubCommand::SetMinPinLength as u8]);
+        assert!(cbor::write(
+            cbor::Value::Map(config_params.sub_command_params.clone().unwrap()),
+            &mut message
+        ));
+        config_params.pin_uv_auth_param =
+            Some(hmac_256::<Sha256>(&pin_uv_auth_token, &message)[..16].to_vec());
+        let config_response = ctap_state.process_config(config_params);
+        assert_eq!(config_response, Ok(ResponseData::AuthenticatorConfig));
+        assert_eq!(ctap_state.persistent_store.min_pin_length().unwrap(), 6);
+        assert!(ctap_state.persistent_store.has_force_pin_change().unwrap());
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_process_make_credential_with_always_uv() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        ctap_state.persistent_store.toggle_always_uv().unwrap();
+
+        let make_credential_params = create_minimal_make_credential_parameters();
+        let make_credential_response =
+            ctap_state.process_make_credential(make_credential_params, DUMMY_CHANNEL_ID);
+        assert_eq!(
+            make_credential_response,
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_REQUIRED)
+        );
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    fn has_enterprise_attestation_option<R, CheckUserPresence>(
+        ctap_state: &CtapState<R, CheckUserPresence>,
+    ) -> bool
+    where
+        R: Rng256,
+        CheckUserPresence: Fn(ChannelID) -> Result<(), Ctap2StatusCode>,
+    {
+        match ctap_state.process_get_info() {
+            Ok(ResponseData::AuthenticatorGetInfo(info)) => info.options.unwrap()["ep"],
+            _ => panic!("Invalid response type"),
+        }
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_process_config_enable_enterprise_attestation() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        assert!(!has_enterprise_attestation_option(&ctap_state));
+
+        let config_params = AuthenticatorConfigParameters {
+            sub_command: ConfigSubCommand::EnableEnterpriseAttestation,
+            sub_command_params: None,
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: None,
+        };
+        let config_response = ctap_state.process_config(config_params);
+        assert_eq!(config_response, Ok(ResponseData::AuthenticatorConfig));
+        assert!(has_enterprise_attestation_option(&ctap_state));
+    }
+
+    #[test]
+    fn test_process_make_credential_enterprise_attestation_disabled() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.enterprise_attestation = Some(1);
+        assert_eq!(
+            ctap_state.process_make_credential(make_credential_params, DUMMY_CHANNEL_ID),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_process_make_credential_enterprise_attestation() {
+        let mut rng = ThreadRng256 {};
+        let mut private_key = [0u8; key_material::ATTESTATION_PRIVATE_KEY_LENGTH];
+        crypto::ecdsa::SecKey::gensk(&mut rng).to_bytes(&mut private_key);
+        let certificate = vec![0xDD; 20];
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        ctap_state
+            .persistent_store
+            .enable_enterprise_attestation()
+            .unwrap();
+        ctap_state
+            .persistent_store
+            .set_rp_attestation(
+                &Sha256::hash(b"example.com"),
+                &AttestationMaterial {
+                    private_key,
+                    certificate: certificate.clone(),
+                },
+            )
+            .unwrap();
+
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.enterprise_attestation = Some(3);
+        assert_eq!(
+            ctap_state.process_make_credential(make_credential_params, DUMMY_CHANNEL_ID),
+            Err(Ctap2StatusCode::CTAP2_ERR_INVALID_OPTION)
+        );
+
+        // The vendor provisioned attestation material for this relying party.
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.enterprise_attestation = Some(1);
+        match ctap_state.process_make_credential(make_credential_params, DUMMY_CHANNEL_ID) {
+            Ok(ResponseData::AuthenticatorMakeCredential(make_credential_response)) => {
+                assert_eq!(make_credential_response.ep_att, Some(true));
+                assert_eq!(
+                    make_credential_response.att_stmt.x5c,
+                    Some(vec![certificate])
+                );
+            }
+            _ => panic!("Invalid response type"),
+        }
+
+        // Other relying parties fall back to the usual attestation.
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.rp.rp_id = String::from("other.com");
+        make_credential_params.enterprise_attestation = Some(2);
+        match ctap_state.process_make_credential(make_credential_params, DUMMY_CHANNEL_ID) {
+            Ok(ResponseData::AuthenticatorMakeCredential(make_credential_response)) => {
+                assert_eq!(make_credential_response.ep_att, None);
+                assert_eq!(make_credential_response.att_stmt.x5c, None);
+            }
+            _ => panic!("Invalid response type"),
+        }
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_process_make_credential_listed_rp_without_enterprise_attestation() {
+        let mut rng = ThreadRng256 {};
+        let mut private_key = [0u8; key_material::ATTESTATION_PRIVATE_KEY_LENGTH];
+        crypto::ecdsa::SecKey::gensk(&mut rng).to_bytes(&mut private_key);
+        let batch_certificate = vec![0xBB; 20];
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        ctap_state
+            .persistent_store
+            .enable_enterprise_attestation()
+            .unwrap();
+        ctap_state
+            .persistent_store
+            .set_attestation_private_key(&private_key)
+            .unwrap();
+        ctap_state
+            .persistent_store
+            .set_attestation_certificate(&batch_certificate)
+            .unwrap();
+        ctap_state
+            .persistent_store
+            .set_rp_attestation(
+                &Sha256::hash(b"example.com"),
+                &AttestationMaterial {
+                    private_key,
+                    certificate: vec![0xDD; 20],
+                },
+            )
+            .unwrap();
+
+        // Without the parameter, the relying party gets the batch attestation.
+        let make_credential_params = create_minimal_make_credential_parameters();
+        match ctap_state.process_make_credential(make_credential_params, DUMMY_CHANNEL_ID) {
+            Ok(ResponseData::AuthenticatorMakeCredential(make_credential_response)) => {
+                assert_eq!(make_credential_response.ep_att, None);
+                let expected_x5c = if USE_BATCH_ATTESTATION {
+                    Some(vec![batch_certificate])
+                } else {
+                    None
+                };
+                assert_eq!(make_credential_response.att_stmt.x5c, expected_x5c);
+            }
+            _ => panic!("Invalid response type"),
+        }
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_process_get_assertion_with_always_uv() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let make_credential_params = create_minimal_make_credential_parameters();
+        assert!(ctap_state
+            .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
+            .is_ok());
+        ctap_state.persistent_store.toggle_always_uv().unwrap();
+
+        let get_assertion_params = AuthenticatorGetAssertionParameters {
+            rp_id: String::from("example.com"),
+            client_data_hash: vec![0xCD],
+            allow_list: None,
+            extensions: None,
+            options: GetAssertionOptions {
+                up: true,
+                uv: false,
+            },
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: None,
+        };
+        let get_assertion_response = ctap_state.process_get_assertion(
+            get_assertion_params,
+            DUMMY_CHANNEL_ID,
+            DUMMY_CLOCK_VALUE,
+        );
+        assert_eq!(
+            get_assertion_response,
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_REQUIRED)
+        );
+    }
+
+    #[test]
+    fn test_process_reset() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let private_key = crypto::ecdsa::SecKey::gensk(&mut rng);
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let credential_id = vec![0x01, 0x23, 0x45, 0x67];
+        let credential_source = PublicKeyCredentialSource {
+            key_type: PublicKeyCredentialType::PublicKey,
+            credential_id,
+            private_key,
+            rp_id: String::from("example.com"),
+            user_handle: vec![],
+            user_display_name: None,
+            cred_protect_policy: None,
+            creation_order: 0,
+            user_name: None,
+            user_icon: None,
+            cred_blob: None,
+            signature_counter: None,
+            large_blob_key: None,
+        };
+        assert!(ctap_state
+            .persistent_store
+            .store_credential(credential_source)
+            .is_ok());
+        assert!(ctap_state.persistent_store.count_credentials().unwrap() > 0);
+
+        let reset_reponse =
+            ctap_state.process_command(&[0x07], DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        let expected_response = vec![0x00];
+        assert_eq!(reset_reponse, expected_response);
+        assert!(ctap_state.persistent_store.count_credentials().unwrap() == 0);
+    }
+
+    #[test]
+    fn test_process_reset_cancelled() {
+        let mut rng = ThreadRng256 {};
+        let user_presence_always_cancel = |_| Err(Ctap2StatusCode::CTAP2_ERR_KEEPALIVE_CANCEL);
+        let mut ctap_state =
+            CtapState::new(&mut rng, user_presence_always_cancel, DUMMY_CLOCK_VALUE);
+
+        let reset_reponse = ctap_state.process_reset(DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+
+        assert_eq!(
+            reset_reponse,
+            Err(Ctap2StatusCode::CTAP2_ERR_KEEPALIVE_CANCEL)
+        );
+    }
+
+    #[test]
+    fn test_process_reset_not_first() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        // This is a GetNextAssertion command.
+        ctap_state.process_command(&[0x08], DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+
+        let reset_reponse = ctap_state.process_reset(DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        assert_eq!(reset_reponse, Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED));
+    }
+
+    #[test]
+    fn test_process_vendor_factory_reset() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let dummy_key = [0x41u8; key_material::ATTESTATION_PRIVATE_KEY_LENGTH];
+        let dummy_cert = [0xddu8; 20];
+        ctap_state
+            .persistent_store
+            .set_attestation_private_key(&dummy_key)
+            .unwrap();
+        ctap_state
+            .persistent_store
+            .set_attestation_certificate(&dummy_cert)
+            .unwrap();
+
+        // This is a VendorFactoryReset command.
+        let reset_reponse =
+            ctap_state.process_command(&[0x41], DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        let expected_response = vec![0x00];
+        assert_eq!(reset_reponse, expected_response);
+        assert!(ctap_state
+            .persistent_store
+            .attestation_private_key()
+            .unwrap()
+            .is_none());
+        assert!(ctap_state
+            .persistent_store
+            .attestation_certificate()
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_process_vendor_factory_reset_not_first() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        // This is a GetNextAssertion command.
+        ctap_state.process_command(&[0x08], DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+
+        let reset_reponse = ctap_state.process_vendor_factory_reset(
+            AuthenticatorVendorFactoryResetParameters::default(),
+            DUMMY_CHANNEL_ID,
+            DUMMY_CLOCK_VALUE,
+        );
+        assert_eq!(reset_reponse, Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED));
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_process_selection() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let selection_response =
+            ctap_state.process_command(&[0x0B], DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        assert_eq!(selection_response, vec![0x00]);
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_process_selection_timeout() {
+        let mut rng = ThreadRng256 {};
+        let user_never_present = |_| Err(Ctap2StatusCode::CTAP2_ERR_USER_ACTION_TIMEOUT);
+        let mut ctap_state = CtapState::new(&mut rng, user_never_present, DUMMY_CLOCK_VALUE);
+
+        let selection_response =
+            ctap_state.process_command(&[0x0B], DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        assert_eq!(
+            selection_response,
+            vec![Ctap2StatusCode::CTAP2_ERR_USER_ACTION_TIMEOUT as u8]
+        );
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_process_reset_after_selection() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        ctap_state.process_command(&[0x0B], DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+
+        // Reset is still allowed after a Selection command.
+        let reset_reponse = ctap_state.process_reset(DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        assert_eq!(reset_reponse, Ok(ResponseData::AuthenticatorReset));
+    }
+
+    #[test]
+    fn test_process_vendor_factory_reset_with_pin() {
+        let mut rng = ThreadRng256 {};
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let pin_uv_auth_token = [0x88; 32];
+        let pin_protocol_v1 = PinProtocolV1::new_test(key_agreement_key, pin_uv_auth_token);
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        ctap_state.pin_protocol_v1 = pin_protocol_v1;
+        ctap_state
+            .persistent_store
+            .set_pin_hash(&[0u8; 16])
+            .unwrap();
+
+        let mut message = vec![0xFF; 32];
+        message.push(0x41);
+        let pin_uv_auth_param = hmac_256::<Sha256>(&pin_uv_auth_token, &message)[..16].to_vec();
+        let params = |pin_uv_auth_param| AuthenticatorVendorFactoryResetParameters {
+            pin_uv_auth_protocol: Some(1),
+            pin_uv_auth_param,
+        };
+
+        assert_eq!(
+            ctap_state.process_vendor_factory_reset(
+                params(None),
+                DUMMY_CHANNEL_ID,
+                DUMMY_CLOCK_VALUE
+            ),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_REQUIRED)
+        );
+        assert_eq!(
+            ctap_state.process_vendor_factory_reset(
+                params(Some(vec![0x00; 16])),
+                DUMMY_CHANNEL_ID,
+                DUMMY_CLOCK_VALUE
+            ),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+        );
+        assert!(ctap_state.persistent_store.pin_hash().unwrap().is_some());
+
+        // With the PIN, the factory reset also works after the first 10 seconds.
+        // This is a GetNextAssertion command.
+        ctap_state.process_command(&[0x08], DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        assert_eq!(
+            ctap_state.process_vendor_factory_reset(
+                params(Some(pin_uv_auth_param)),
+                DUMMY_CHANNEL_ID,
+                DUMMY_CLOCK_VALUE
+            ),
+            Ok(ResponseData::AuthenticatorVendorFactoryReset)
+        );
+        assert!(ctap_state.persistent_store.pin_hash().unwrap().is_none());
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_process_vendor_factory_reset_pending_large_blob_write() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        // This is a LargeBlobs command, writing the first half of an array.
+        let cbor_value = cbor_map! {
+            2 => vec![0x55; 10],
+            3 => 0,
+            4 => 20,
+        };
+        let mut command_cbor = vec![0x0C];
+        assert!(cbor::write(cbor_value, &mut command_cbor));
+        let large_blobs_response =
+            ctap_state.process_command(&command_cbor, DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        assert_eq!(large_blobs_response, vec![0x00]);
+
+        // This is a VendorFactoryReset command.
+        let reset_reponse =
+            ctap_state.process_command(&[0x41], DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        assert_eq!(
+            reset_reponse,
+            vec![Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED as u8]
+        );
+    }
+
+    #[test]
+    fn test_process_unknown_command() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        // This command does not exist.
+        let reset_reponse =
+            ctap_state.process_command(&[0xDF], DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        let expected_response = vec![Ctap2StatusCode::CTAP1_ERR_INVALID_COMMAND as u8];
+        assert_eq!(reset_reponse, expected_response);
+    }
+
+    #[test]
+    fn test_encrypt_decrypt_credential() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let private_key = crypto::ecdsa::SecKey::gensk(&mut rng);
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        // Usually, the relying party ID or its hash is provided by the client.
+        // We are not testing the correctness of our SHA256 here, only if it is checked.
+        let rp_id_hash = [0x55; 32];
+        let encrypted_id = ctap_state
+            .encrypt_key_handle(private_key.clone(), &rp_id_hash)
+            .unwrap();
+        let decrypted_source = ctap_state
+            .decrypt_credential_source(encrypted_id, &rp_id_hash)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(private_key, decrypted_source.private_key);
+    }
+
+    #[test]
+    fn test_encrypt_decrypt_bad_hmac() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let private_key = crypto::ecdsa::SecKey::gensk(&mut rng);
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        // Same as above.
+        let rp_id_hash = [0x55; 32];
+        let encrypted_id = ctap_state
+            .encrypt_key_handle(private_key, &rp_id_hash)
+            .unwrap();
+        for i in 0..encrypted_id.len() {
+            let mut modified_id = encrypted_id.clone();
+            modified_id[i] ^= 0x01;
+            assert!(ctap_state
+                .decrypt_credential_source(modified_id, &rp_id_hash)
+                .unwrap()
+                .is_none());
+        }
+    }
+
+    #[test]
+    fn test_derived_key_handle() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let rp_id_hash = [0x55; 32];
+        let (private_key, key_handle) =
+            ctap_state.generate_derived_key_handle(&rp_id_hash).unwrap();
+        assert_eq!(key_handle.len(), CREDENTIAL_ID_SIZE);
+        // The same key handle always regenerates the same private key.
+        for _ in 0..2 {
+            let decrypted_source = ctap_state
+                .decrypt_credential_source(key_handle.clone(), &rp_id_hash)
+                .unwrap()
+                .unwrap();
+            assert_eq!(decrypted_source.private_key, private_key);
+            assert_eq!(decrypted_source.credential_id, key_handle);
+        }
+
+        // Another nonce results in another key.
+        let (other_private_key, _) = ctap_state.generate_derived_key_handle(&rp_id_hash).unwrap();
+        assert_ne!(other_private_key, private_key);
+
+        // The key handle is bound to its relying party.
+        assert!(ctap_state
+            .decrypt_credential_source(key_handle, &[0x66; 32])
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_derived_key_handle_tampered() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let rp_id_hash = [0x55; 32];
+        let (_, key_handle) = ctap_state.generate_derived_key_handle(&rp_id_hash).unwrap();
+        for i in 0..key_handle.len() {
+            let mut modified_handle = key_handle.clone();
+            modified_handle[i] ^= 0x01;
+            assert!(ctap_state
+                .decrypt_credential_source(modified_handle, &rp_id_hash)
+                .unwrap()
+                .is_none());
+        }
+    }
+
+    fn get_assertion_with_key_handle<R, CheckUserPresence>(
+        ctap_state: &mut CtapState<R, CheckUserPresence>,
+        rp_id: &str,
+        key_handle: Vec<u8>,
+    ) -> Result<ResponseData, Ctap2StatusCode>
+    where
+        R: Rng256,
+        CheckUserPresence: Fn(ChannelID) -> Result<(), Ctap2StatusCode>,
+    {
+        let get_assertion_params = AuthenticatorGetAssertionParameters {
+            rp_id: String::from(rp_id),
+            client_data_hash: vec![0xCD],
+            allow_list: Some(vec![PublicKeyCredentialDescriptor {
+                key_type: PublicKeyCredentialType::PublicKey,
+                key_id: key_handle,
+                transports: None,
+            }]),
+            extensions: None,
+            options: GetAssertionOptions {
+                up: false,
+                uv: false,
+            },
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: None,
+        };
+        ctap_state.process_get_assertion(get_assertion_params, DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE)
+    }
+
+    #[test]
+    fn test_process_get_assertion_key_handle_checks() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let private_key = crypto::ecdsa::SecKey::gensk(&mut rng);
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let rp_id_hash = Sha256::hash(b"example.com");
+        let encrypted_key_handle = ctap_state
+            .encrypt_key_handle(private_key, &rp_id_hash)
+            .unwrap();
+        let (_, derived_key_handle) = ctap_state.generate_derived_key_handle(&rp_id_hash).unwrap();
+        for key_handle in vec![encrypted_key_handle, derived_key_handle] {
+            assert!(get_assertion_with_key_handle(
+                &mut ctap_state,
+                "example.com",
+                key_handle.clone()
+            )
+            .is_ok());
+            assert_eq!(
+                get_assertion_with_key_handle(&mut ctap_state, "other.com", key_handle.clone()),
+                Err(Ctap2StatusCode::CTAP2_ERR_NO_CREDENTIALS)
+            );
+            let mut modified_handle = key_handle;
+            modified_handle[20] ^= 0x80;
+            assert_eq!(
+                get_assertion_with_key_handle(&mut ctap_state, "example.com", modified_handle),
+                Err(Ctap2StatusCode::CTAP2_ERR_NO_CREDENTIALS)
+            );
+        }
+    }
+
+    #[test]
+    fn test_signature_counter() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let mut last_counter = ctap_state
+            .persistent_store
+            .global_signature_counter()
+            .unwrap();
+        assert!(last_counter > 0);
+        for _ in 0..100 {
+            assert!(ctap_state.increment_global_signature_counter().is_ok());
+            let next_counter = ctap_state
+                .persistent_store
+                .global_signature_counter()
+                .unwrap();
+            assert!(next_counter > last_counter);
+            last_counter = next_counter;
+        }
+    }
+
+    #[test]
+    fn test_signature_counter_exhausted() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        ctap_state
+            .persistent_store
+            .incr_global_signature_counter(u32::MAX - INITIAL_SIGNATURE_COUNTER)
+            .unwrap();
+        assert_eq!(
+            ctap_state.increment_global_signature_counter(),
+            Err(Ctap2StatusCode::CTAP2_ERR_LIMIT_EXCEEDED)
+        );
+        assert_eq!(
+            ctap_state
+                .persistent_store
+                .global_signature_counter()
+                .unwrap(),
+            u32::MAX
+        );
+    }
+
+    #[test]
+    fn test_credential_signature_counter() {
+        let mut rng = ThreadRng256 {};
+        let private_key = crypto::ecdsa::SecKey::gensk(&mut rng);
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let credential1 = PublicKeyCredentialSource {
+            key_type: PublicKeyCredentialType::PublicKey,
+            credential_id: vec![0x01; 32],
+            private_key,
+            rp_id: String::from("example.com"),
+            user_handle: vec![0x01],
+            user_display_name: None,
+            cred_protect_policy: None,
+            creation_order: 0,
+            user_name: None,
+            user_icon: None,
+            cred_blob: None,
+            signature_counter: Some(0),
+            large_blob_key: None,
+        };
+        let credential2 = PublicKeyCredentialSource {
+            credential_id: vec![0x02; 32],
+            user_handle: vec![0x02],
+            creation_order: 1,
+            ..credential1.clone()
+        };
+        for credential in &[credential1, credential2] {
+            assert!(ctap_state
+                .persistent_store
+                .store_credential(credential.clone())
+                .is_ok());
+        }
+
+        for (credential_id, expected_counter) in &[
+            (vec![0x01; 32], 1),
+            (vec![0x01; 32], 2),
+            (vec![0x02; 32], 1),
+            (vec![0x01; 32], 3),
+        ] {
+            let credential = ctap_state
+                .persistent_store
+                .find_credential("example.com", credential_id, false)
+                .unwrap()
+                .unwrap();
+            assert_eq!(
+                ctap_state.increment_credential_signature_counter(&credential),
+                Ok(*expected_counter)
+            );
+        }
+
+        // Non-resident credentials don't support a signature counter.
+        let credential = PublicKeyCredentialSource {
+            signature_counter: None,
+            ..ctap_state
+                .persistent_store
+                .find_credential("example.com", &[0x02; 32], false)
+                .unwrap()
+                .unwrap()
+        };
+        assert_eq!(
+            ctap_state.increment_credential_signature_counter(&credential),
+            Ok(0)
+        );
+        let credential = ctap_state
+            .persistent_store
+            .find_credential("example.com", &[0x02; 32], false)
+            .unwrap()
+            .unwrap();
+        assert_eq!(credential.signature_counter, Some(1));
+    }
+
+    #[test]
+    fn test_process_get_assertion_signature_counter_mode() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let make_credential_params = create_minimal_make_credential_parameters();
+        assert!(ctap_state
+            .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
+            .is_ok());
+
+        for expected_credential_counter in 1..3 {
+            let get_assertion_params = AuthenticatorGetAssertionParameters {
+                rp_id: String::from("example.com"),
+                client_data_hash: vec![0xCD],
+                allow_list: None,
+                extensions: None,
+                options: GetAssertionOptions {
+                    up: false,
+                    uv: false,
+                },
+                pin_uv_auth_param: None,
+                pin_uv_auth_protocol: None,
+            };
+            let get_assertion_response = ctap_state.process_get_assertion(
+                get_assertion_params,
+                DUMMY_CHANNEL_ID,
+                DUMMY_CLOCK_VALUE,
+            );
+            let signature_counter = if USE_PER_CREDENTIAL_SIGNATURE_COUNTER {
+                expected_credential_counter
+            } else {
+                ctap_state
+                    .persistent_store
+                    .global_signature_counter()
+                    .unwrap()
+            };
+            check_assertion_response(get_assertion_response, vec![0x1D], signature_counter, None);
+        }
+    }
+
+    #[test]
+    fn test_vendor_configure() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        // Nothing should be configured at the beginning. The CRP syscalls are stubbed in tests, so
+        // the protection level reads back as unknown.
+        let response = ctap_state.process_vendor_configure(
+            AuthenticatorVendorConfigureParameters {
+                lockdown: None,
+                attestation_material: None,
+            },
+            DUMMY_CHANNEL_ID,
+        );
+        assert_eq!(
+            response,
+            Ok(ResponseData::AuthenticatorVendor(
+                AuthenticatorVendorResponse {
+                    cert_programmed: false,
+                    pkey_programmed: false,
+                    protection_level: Some(crp::ProtectionLevel::Unknown),
+                }
+            ))
+        );
+
+        // Inject dummy values
+        let dummy_key = [0x41u8; key_material::ATTESTATION_PRIVATE_KEY_LENGTH];
+        let dummy_cert = [0xddu8; 20];
+        let response = ctap_state.process_vendor_configure(
+            AuthenticatorVendorConfigureParameters {
+                lockdown: None,
+                attestation_material: Some(AuthenticatorAttestationMaterial {
+                    certificate: dummy_cert.to_vec(),
+                    private_key: dummy_key,
+                    rp_id: None,
+                    aaguid: None,
+                }),
+            },
+            DUMMY_CHANNEL_ID,
+        );
+        assert_eq!(
+            response,
+            Ok(ResponseData::AuthenticatorVendor(
+                AuthenticatorVendorResponse {
+                    cert_programmed: true,
+                    pkey_programmed: true,
+                    protection_level: Some(crp::ProtectionLevel::Unknown),
+                }
+            ))
+        );
+        assert_eq!(
+            ctap_state
+                .persistent_store
+                .attestation_certificate()
+                .unwrap()
+                .unwrap(),
+            dummy_cert
+        );
+        assert_eq!(
+            ctap_state
+                .persistent_store
+                .attestation_private_key()
+                .unwrap()
+                .unwrap(),
+            dummy_key
+        );
+
+        // Try to inject other dummy values and check that initial values are retained.
+        let other_dummy_key = [0x44u8; key_material::ATTESTATION_PRIVATE_KEY_LENGTH];
+        let response = ctap_state.process_vendor_configure(
+            AuthenticatorVendorConfigureParameters {
+                lockdown: None,
+                attestation_material: Some(AuthenticatorAttestationMaterial {
+                    certificate: dummy_cert.to_vec(),
+                    private_key: other_dummy_key,
+                    rp_id: None,
+                    aaguid: None,
+                }),
+            },
+            DUMMY_CHANNEL_ID,
+        );
+        assert_eq!(
+            response,
+            Ok(ResponseData::AuthenticatorVendor(
+                AuthenticatorVendorResponse {
+                    cert_programmed: true,
+                    pkey_programmed: true,
+                    protection_level: Some(crp::ProtectionLevel::Unknown),
+                }
+            ))
+        );
+        assert_eq!(
+            ctap_state
+                .persistent_store
+                .attestation_certificate()
+                .unwrap()
+                .unwrap(),
+            dummy_cert
+        );
+        assert_eq!(
+            ctap_state
+                .persistent_store
+                .attestation_private_key()
+                .unwrap()
+                .unwrap(),
+            dummy_key
+        );
+
+        // Now try to lock the device
+        let response = ctap_state.process_vendor_configure(
+            AuthenticatorVendorConfigureParameters {
+                lockdown: Some(crp::ProtectionLevel::FullyLocked),
+                attestation_material: None,
+            },
+            DUMMY_CHANNEL_ID,
+        );
+        assert_eq!(
+            response,
+            Ok(ResponseData::AuthenticatorVendor(
+                AuthenticatorVendorResponse {
+                    cert_programmed: true,
+                    pkey_programmed: true,
+                    protection_level: Some(crp::ProtectionLevel::Unknown),
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_vendor_configure_lockdown_level() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        // Intermediate levels don't require the attestation material.
+        for level in &[
+            crp::ProtectionLevel::NoProtection,
+            crp::ProtectionLevel::JtagDisabled,
+        ] {
+            let response = ctap_state.process_vendor_configure(
+                AuthenticatorVendorConfigureParameters {
+                    lockdown: Some(*level),
+                    attestation_material: None,
+                },
+                DUMMY_CHANNEL_ID,
+            );
+            assert_eq!(
+                response,
+                Ok(ResponseData::AuthenticatorVendor(
+                    AuthenticatorVendorResponse {
+                        cert_programmed: false,
+                        pkey_programmed: false,
+                        protection_level: Some(crp::ProtectionLevel::Unknown),
+                    }
+                ))
+            );
+        }
+
+        // A full lockdown still does, if U2F/CTAP1 is enabled.
+        let response = ctap_state.process_vendor_configure(
+            AuthenticatorVendorConfigureParameters {
+                lockdown: Some(crp::ProtectionLevel::FullyLocked),
+                attestation_material: None,
+            },
+            DUMMY_CHANNEL_ID,
+        );
+        if cfg!(feature = "with_ctap1") || USE_BATCH_ATTESTATION {
+            assert_eq!(
+                response,
+                Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)
+            );
+        } else {
+            assert!(response.is_ok());
+        }
+    }
+
+    #[test]
+    fn test_vendor_configure_locks_attestation() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        // Reading doesn't lock.
+        let response = ctap_state.process_vendor_configure(
+            AuthenticatorVendorConfigureParameters {
+                lockdown: None,
+                attestation_material: None,
+            },
+            DUMMY_CHANNEL_ID,
+        );
+        assert!(response.is_ok());
+        assert!(!ctap_state.persistent_store.attestation_locked().unwrap());
+
+        let dummy_key = [0x41u8; key_material::ATTESTATION_PRIVATE_KEY_LENGTH];
+        let dummy_cert = [0xddu8; 20];
+        let response = ctap_state.process_vendor_configure(
+            AuthenticatorVendorConfigureParameters {
+                lockdown: None,
+                attestation_material: Some(AuthenticatorAttestationMaterial {
+                    certificate: dummy_cert.to_vec(),
+                    private_key: dummy_key,
+                    rp_id: None,
+                    aaguid: None,
+                }),
+            },
+            DUMMY_CHANNEL_ID,
+        );
+        assert!(response.is_ok());
+        assert!(ctap_state.persistent_store.attestation_locked().unwrap());
+
+        // The material survives a factory reset.
+        assert!(ctap_state
+            .process_vendor_factory_reset(
+                AuthenticatorVendorFactoryResetParameters::default(),
+                DUMMY_CHANNEL_ID,
+                DUMMY_CLOCK_VALUE
+            )
+            .is_ok());
+        assert!(ctap_state.persistent_store.attestation_locked().unwrap());
+        assert_eq!(
+            ctap_state
+                .persistent_store
+                .attestation_private_key()
+                .unwrap(),
+            Some(dummy_key)
+        );
+    }
+
+    #[test]
+    fn test_vendor_configure_rp_attestation() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let dummy_key = [0x41u8; key_material::ATTESTATION_PRIVATE_KEY_LENGTH];
+        let dummy_cert = [0xddu8; 20];
+        let response = ctap_state.process_vendor_configure(
+            AuthenticatorVendorConfigureParameters {
+                lockdown: None,
+                attestation_material: Some(AuthenticatorAttestationMaterial {
+                    certificate: dummy_cert.to_vec(),
+                    private_key: dummy_key,
+                    rp_id: Some(String::from("example.com")),
+                    aaguid: None,
+                }),
+            },
+            DUMMY_CHANNEL_ID,
+        );
+        // The batch attestation is still missing.
+        assert_eq!(
+            response,
+            Ok(ResponseData::AuthenticatorVendor(
+                AuthenticatorVendorResponse {
+                    cert_programmed: false,
+                    pkey_programmed: false,
+                    protection_level: Some(crp::ProtectionLevel::Unknown),
+                }
+            ))
+        );
+
+        let rp_id_hash = Sha256::hash(b"example.com");
+        let material = ctap_state
+            .persistent_store
+            .find_rp_attestation(&rp_id_hash)
+            .unwrap()
+            .unwrap();
+        assert_eq!(material.private_key, dummy_key);
+        assert_eq!(material.certificate, dummy_cert);
+        assert!(ctap_state
+            .persistent_store
+            .attestation_material()
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_vendor_configure_aaguid() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        assert_eq!(
+            &ctap_state.persistent_store.aaguid().unwrap(),
+            key_material::AAGUID
+        );
+
+        let dummy_key = [0x41u8; key_material::ATTESTATION_PRIVATE_KEY_LENGTH];
+        let dummy_cert = [0xddu8; 20];
+        let batch_aaguid = [0xA5u8; key_material::AAGUID_LENGTH];
+        let response = ctap_state.process_vendor_configure(
+            AuthenticatorVendorConfigureParameters {
+                lockdown: None,
+                attestation_material: Some(AuthenticatorAttestationMaterial {
+                    certificate: dummy_cert.to_vec(),
+                    private_key: dummy_key,
+                    rp_id: None,
+                    aaguid: Some(batch_aaguid),
+                }),
+            },
+            DUMMY_CHANNEL_ID,
+        );
+        assert!(response.is_ok());
+
+        let make_credential_params = create_minimal_make_credential_parameters();
+        match ctap_state.process_make_credential(make_credential_params, DUMMY_CHANNEL_ID) {
+            Ok(ResponseData::AuthenticatorMakeCredential(make_credential_response)) => {
+                // The AAGUID starts the attested credential data.
+                let auth_data = make_credential_response.auth_data;
+                assert_eq!(
+                    &auth_data[37..37 + key_material::AAGUID_LENGTH],
+                    &batch_aaguid
+                );
+            }
+            _ => panic!("Invalid response type"),
+        }
+
+        // Once programmed, the AAGUID is retained like the rest of the batch material.
+        let response = ctap_state.process_vendor_configure(
+            AuthenticatorVendorConfigureParameters {
+                lockdown: None,
+                attestation_material: Some(AuthenticatorAttestationMaterial {
+                    certificate: dummy_cert.to_vec(),
+                    private_key: dummy_key,
+                    rp_id: None,
+                    aaguid: Some([0x5A; key_material::AAGUID_LENGTH]),
+                }),
+            },
+            DUMMY_CHANNEL_ID,
+        );
+        assert!(response.is_ok());
+        assert_eq!(ctap_state.persistent_store.aaguid().unwrap(), batch_aaguid);
+    }
+
+    #[test]
+    fn test_pin_retries_state() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let initial_retries = ctap_state.pin_retries().unwrap();
+        assert!(initial_retries > 0);
+        assert!(!ctap_state.is_pin_auth_blocked());
+        ctap_state.persistent_store.decr_pin_retries().unwrap();
+        assert_eq!(ctap_state.pin_retries(), Ok(initial_retries - 1));
+        assert!(!ctap_state.is_pin_auth_blocked());
+    }
+
+    #[test]
+    fn test_compact_store_if_needed() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        // Nothing to do on a fresh store.
+        assert!(ctap_state.compact_store_if_needed().is_ok());
+        assert!(!ctap_state.persistent_store.needs_compaction().unwrap());
+
+        // Overwriting the signature counter leaves deleted entries behind until compaction.
+        for _ in 0..10000 {
+            if ctap_state.persistent_store.needs_compaction().unwrap() {
+                break;
+            }
+            ctap_state
+                .persistent_store
+                .incr_global_signature_counter(1)
+                .unwrap();
+        }
+        assert!(ctap_state.persistent_store.needs_compaction().unwrap());
+        let counter = ctap_state
+            .persistent_store
+            .global_signature_counter()
+            .unwrap();
+
+        assert!(ctap_state.compact_store_if_needed().is_ok());
+        assert!(!ctap_state.persistent_store.needs_compaction().unwrap());
+        assert_eq!(
+            ctap_state
+                .persistent_store
+                .global_signature_counter()
+                .unwrap(),
+            counter
+        );
+    }
+
+    #[test]
+    fn test_vendor_rp_id_policy() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let response = ctap_state.process_vendor_rp_id_policy(
+            AuthenticatorVendorRpIdPolicyParameters {
+                allow: false,
+                rp_ids: vec![String::from("example.com")],
+                pin_uv_auth_protocol: None,
+                pin_uv_auth_param: None,
+            },
+            DUMMY_CHANNEL_ID,
+        );
+        assert_eq!(response, Ok(ResponseData::AuthenticatorVendorRpIdPolicy));
+        let make_credential_params = create_minimal_make_credential_parameters();
+        assert_eq!(
+            ctap_state.process_make_credential(make_credential_params, DUMMY_CHANNEL_ID),
+            Err(Ctap2StatusCode::CTAP2_ERR_OPERATION_DENIED)
+        );
+
+        // Other relying parties are still allowed.
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.rp.rp_id = String::from("other.com");
+        assert!(ctap_state
+            .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
+            .is_ok());
+
+        // An empty deny list allows all relying parties again.
+        let response = ctap_state.process_vendor_rp_id_policy(
+            AuthenticatorVendorRpIdPolicyParameters {
+                allow: false,
+                rp_ids: vec![],
+                pin_uv_auth_protocol: None,
+                pin_uv_auth_param: None,
+            },
+            DUMMY_CHANNEL_ID,
+        );
+        assert_eq!(response, Ok(ResponseData::AuthenticatorVendorRpIdPolicy));
+        assert_eq!(ctap_state.persistent_store.rp_id_policy(), Ok(None));
+        let make_credential_params = create_minimal_make_credential_parameters();
+        assert!(ctap_state
+            .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_process_make_credential_pregenerated_key() {
         let mut rng = ThreadRng256 {};
         let user_immediately_present = |_| Ok(());
         let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
 
-        // This command does not exist.
-        let reset_reponse =
-            ctap_state.process_command(&[0xDF], DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
-        let expected_response = vec![Ctap2StatusCode::CTAP1_ERR_INVALID_COMMAND as u8];
-        assert_eq!(reset_reponse, expected_response);
+        ctap_state.pregenerate_credential_key();
+        let pregenerated_key = **ctap_state.pregenerated_key.as_ref().unwrap();
+
+        let make_credential_params = create_minimal_make_credential_parameters();
+        let credential_id =
+            match ctap_state.process_make_credential(make_credential_params, DUMMY_CHANNEL_ID) {
+                Ok(ResponseData::AuthenticatorMakeCredential(make_credential_response)) => {
+                    // The credential ID follows the AAGUID and its 2 byte length.
+                    let auth_data = make_credential_response.auth_data;
+                    let offset = 37 + key_material::AAGUID_LENGTH;
+                    let length = BigEndian::read_u16(&auth_data[offset..offset + 2]) as usize;
+                    auth_data[offset + 2..offset + 2 + length].to_vec()
+                }
+                _ => panic!("Invalid response type"),
+            };
+        assert!(ctap_state.pregenerated_key.is_none());
+        let credential = ctap_state
+            .persistent_store
+            .find_credential("example.com", &credential_id, false)
+            .unwrap()
+            .unwrap();
+        let mut private_key = [0u8; 32];
+        credential.private_key.to_bytes(&mut private_key);
+        assert_eq!(private_key, pregenerated_key);
     }
 
     #[test]
-    fn test_encrypt_decrypt_credential() {
+    fn test_process_reset_clears_pregenerated_key() {
         let mut rng = ThreadRng256 {};
         let user_immediately_present = |_| Ok(());
-        let private_key = crypto::ecdsa::SecKey::gensk(&mut rng);
         let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
 
-        // Usually, the relying party ID or its hash is provided by the client.
-        // We are not testing the correctness of our SHA256 here, only if it is checked.
-        let rp_id_hash = [0x55; 32];
-        let encrypted_id = ctap_state
-            .encrypt_key_handle(private_key.clone(), &rp_id_hash)
-            .unwrap();
-        let decrypted_source = ctap_state
-            .decrypt_credential_source(encrypted_id, &rp_id_hash)
-            .unwrap()
-            .unwrap();
+        ctap_state.pregenerate_credential_key();
+        assert!(ctap_state.pregenerated_key.is_some());
+        assert!(ctap_state
+            .process_reset(DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE)
+            .is_ok());
+        assert!(ctap_state.pregenerated_key.is_none());
 
-        assert_eq!(private_key, decrypted_source.private_key);
+        ctap_state.pregenerate_credential_key();
+        assert!(ctap_state.pregenerated_key.is_some());
+        assert!(ctap_state
+            .process_vendor_factory_reset(
+                AuthenticatorVendorFactoryResetParameters::default(),
+                DUMMY_CHANNEL_ID,
+                DUMMY_CLOCK_VALUE
+            )
+            .is_ok());
+        assert!(ctap_state.pregenerated_key.is_none());
     }
 
     #[test]
-    fn test_encrypt_decrypt_bad_hmac() {
+    fn test_process_make_credential_throttled() {
         let mut rng = ThreadRng256 {};
         let user_immediately_present = |_| Ok(());
-        let private_key = crypto::ecdsa::SecKey::gensk(&mut rng);
         let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        ctap_state.set_make_credential_min_interval(Some(Duration::from_ms(1000)));
 
-        // Same as above.
-        let rp_id_hash = [0x55; 32];
-        let encrypted_id = ctap_state
-            .encrypt_key_handle(private_key, &rp_id_hash)
-            .unwrap();
-        for i in 0..encrypted_id.len() {
-            let mut modified_id = encrypted_id.clone();
-            modified_id[i] ^= 0x01;
-            assert!(ctap_state
-                .decrypt_credential_source(modified_id, &rp_id_hash)
-                .unwrap()
-                .is_none());
-        }
+        let make_credential_params = create_minimal_make_credential_parameters();
+        assert!(ctap_state
+            .process_throttled_make_credential(
+                make_credential_params,
+                DUMMY_CHANNEL_ID,
+                DUMMY_CLOCK_VALUE
+            )
+            .is_ok());
+
+        // A rapid second creation is denied.
+        let now = DUMMY_CLOCK_VALUE.wrapping_add(Duration::from_ms(999));
+        let make_credential_params = create_minimal_make_credential_parameters();
+        assert_eq!(
+            ctap_state.process_throttled_make_credential(
+                make_credential_params,
+                DUMMY_CHANNEL_ID,
+                now
+            ),
+            Err(Ctap2StatusCode::CTAP2_ERR_OPERATION_DENIED)
+        );
+
+        // After the interval, creating is allowed again.
+        let now = DUMMY_CLOCK_VALUE.wrapping_add(Duration::from_ms(1000));
+        let make_credential_params = create_minimal_make_credential_parameters();
+        assert!(ctap_state
+            .process_throttled_make_credential(make_credential_params, DUMMY_CHANNEL_ID, now)
+            .is_ok());
     }
 
     #[test]
-    fn test_signature_counter() {
+    fn test_process_make_credential_throttled_failure() {
         let mut rng = ThreadRng256 {};
         let user_immediately_present = |_| Ok(());
         let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        ctap_state.set_make_credential_min_interval(Some(Duration::from_ms(1000)));
 
-        let mut last_counter = ctap_state
-            .persistent_store
-            .global_signature_counter()
-            .unwrap();
-        assert!(last_counter > 0);
-        for _ in 0..100 {
-            assert!(ctap_state.increment_global_signature_counter().is_ok());
-            let next_counter = ctap_state
-                .persistent_store
-                .global_signature_counter()
-                .unwrap();
-            assert!(next_counter > last_counter);
-            last_counter = next_counter;
-        }
+        // Failed creations don't start the cooldown.
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.pub_key_cred_params = vec![];
+        assert_eq!(
+            ctap_state.process_throttled_make_credential(
+                make_credential_params,
+                DUMMY_CHANNEL_ID,
+                DUMMY_CLOCK_VALUE
+            ),
+            Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_ALGORITHM)
+        );
+        let make_credential_params = create_minimal_make_credential_parameters();
+        assert!(ctap_state
+            .process_throttled_make_credential(
+                make_credential_params,
+                DUMMY_CHANNEL_ID,
+                DUMMY_CLOCK_VALUE
+            )
+            .is_ok());
+
+        // Without an interval, creations are not throttled.
+        ctap_state.set_make_credential_min_interval(None);
+        let make_credential_params = create_minimal_make_credential_parameters();
+        assert!(ctap_state
+            .process_throttled_make_credential(
+                make_credential_params,
+                DUMMY_CHANNEL_ID,
+                DUMMY_CLOCK_VALUE
+            )
+            .is_ok());
     }
 
+    #[cfg(feature = "with_ctap2_1")]
     #[test]
-    fn test_vendor_configure() {
+    fn test_vendor_reset_boot_count() {
         let mut rng = ThreadRng256 {};
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let pin_uv_auth_token = [0x88; 32];
+        let pin_protocol_v1 = PinProtocolV1::new_test(key_agreement_key, pin_uv_auth_token);
         let user_immediately_present = |_| Ok(());
         let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        ctap_state.persistent_store = ctap_state.persistent_store.reboot(ctap_state.rng);
+        assert_eq!(ctap_state.persistent_store.boot_count().unwrap(), 2);
+
+        let mut message = vec![0xFF; 32];
+        message.push(0x45);
+        let pin_uv_auth_param = hmac_256::<Sha256>(&pin_uv_auth_token, &message)[..16].to_vec();
+        let params = |pin_uv_auth_param| AuthenticatorVendorResetBootCountParameters {
+            pin_uv_auth_protocol: Some(1),
+            pin_uv_auth_param,
+        };
 
-        // Nothing should be configured at the beginning
-        let response = ctap_state.process_vendor_configure(
-            AuthenticatorVendorConfigureParameters {
-                lockdown: false,
-                attestation_material: None,
-            },
-            DUMMY_CHANNEL_ID,
-        );
         assert_eq!(
-            response,
-            Ok(ResponseData::AuthenticatorVendor(
-                AuthenticatorVendorResponse {
-                    cert_programmed: false,
-                    pkey_programmed: false,
-                }
-            ))
+            ctap_state.process_vendor_reset_boot_count(params(Some(pin_uv_auth_param.clone()))),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_NOT_SET)
         );
-
-        // Inject dummy values
-        let dummy_key = [0x41u8; key_material::ATTESTATION_PRIVATE_KEY_LENGTH];
-        let dummy_cert = [0xddu8; 20];
-        let response = ctap_state.process_vendor_configure(
-            AuthenticatorVendorConfigureParameters {
-                lockdown: false,
-                attestation_material: Some(AuthenticatorAttestationMaterial {
-                    certificate: dummy_cert.to_vec(),
-                    private_key: dummy_key,
-                }),
-            },
-            DUMMY_CHANNEL_ID,
+        ctap_state.pin_protocol_v1 = pin_protocol_v1;
+        ctap_state
+            .persistent_store
+            .set_pin_hash(&[0u8; 16])
+            .unwrap();
+        assert_eq!(
+            ctap_state.process_vendor_reset_boot_count(params(None)),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_REQUIRED)
         );
         assert_eq!(
-            response,
-            Ok(ResponseData::AuthenticatorVendor(
-                AuthenticatorVendorResponse {
-                    cert_programmed: true,
-                    pkey_programmed: true,
-                }
-            ))
+            ctap_state.process_vendor_reset_boot_count(params(Some(vec![0x00; 16]))),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
         );
+        assert_eq!(ctap_state.persistent_store.boot_count().unwrap(), 2);
+
         assert_eq!(
-            ctap_state
-                .persistent_store
-                .attestation_certificate()
-                .unwrap()
-                .unwrap(),
-            dummy_cert
+            ctap_state.process_vendor_reset_boot_count(params(Some(pin_uv_auth_param))),
+            Ok(ResponseData::AuthenticatorVendorResetBootCount)
         );
+        assert_eq!(ctap_state.persistent_store.boot_count().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_vendor_store_health() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.user.user_name = Some(String::from("alice"));
+        assert!(ctap_state
+            .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
+            .is_ok());
+
+        let response = ctap_state.process_vendor_store_health();
+        match response.unwrap() {
+            ResponseData::AuthenticatorVendorStoreHealth(store_health_response) => {
+                assert_eq!(
+                    store_health_response.firmware_version,
+                    env!("CARGO_PKG_VERSION")
+                );
+                assert_eq!(store_health_response.boot_count, 1);
+                assert_eq!(store_health_response.num_credentials, 1);
+                assert!(store_health_response.free_space_percent <= 100);
+                assert!(store_health_response.lifetime_used_permille <= 1000);
+            }
+            _ => panic!("Invalid response type"),
+        };
+
+        // The serialized report must not leak any part of the credential.
+        let report = ctap_state.process_command(&[0x43], DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        assert_eq!(report[0], 0x00);
+        let credential = ctap_state
+            .persistent_store
+            .filter_credential("example.com", false)
+            .unwrap()
+            .pop()
+            .unwrap();
+        let mut private_key = [0u8; 32];
+        credential.private_key.to_bytes(&mut private_key);
+        let contains = |needle: &[u8]| report.windows(needle.len()).any(|w| w == needle);
+        assert!(!contains(&credential.credential_id));
+        assert!(!contains(&private_key));
+        assert!(!contains(&credential.user_handle));
+        assert!(!contains(b"example.com"));
+        assert!(!contains(b"alice"));
+    }
+
+    #[test]
+    fn test_vendor_rp_id_policy_unauthorized() {
+        let mut rng = ThreadRng256 {};
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let pin_uv_auth_token = [0x88; 32];
+        let pin_protocol_v1 = PinProtocolV1::new_test(key_agreement_key, pin_uv_auth_token);
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        let params = |pin_uv_auth_param| AuthenticatorVendorRpIdPolicyParameters {
+            allow: false,
+            rp_ids: vec![String::from("example.com")],
+            pin_uv_auth_protocol: Some(1),
+            pin_uv_auth_param,
+        };
+
+        // Without a PIN, the policy can't be changed after provisioning.
+        ctap_state.persistent_store.lock_attestation().unwrap();
         assert_eq!(
-            ctap_state
-                .persistent_store
-                .attestation_private_key()
-                .unwrap()
-                .unwrap(),
-            dummy_key
+            ctap_state.process_vendor_rp_id_policy(params(None), DUMMY_CHANNEL_ID),
+            Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)
         );
 
-        // Try to inject other dummy values and check that initial values are retained.
-        let other_dummy_key = [0x44u8; key_material::ATTESTATION_PRIVATE_KEY_LENGTH];
-        let response = ctap_state.process_vendor_configure(
-            AuthenticatorVendorConfigureParameters {
-                lockdown: false,
-                attestation_material: Some(AuthenticatorAttestationMaterial {
-                    certificate: dummy_cert.to_vec(),
-                    private_key: other_dummy_key,
-                }),
-            },
-            DUMMY_CHANNEL_ID,
+        ctap_state.pin_protocol_v1 = pin_protocol_v1;
+        ctap_state
+            .persistent_store
+            .set_pin_hash(&[0u8; 16])
+            .unwrap();
+        assert_eq!(
+            ctap_state.process_vendor_rp_id_policy(params(None), DUMMY_CHANNEL_ID),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_REQUIRED)
         );
         assert_eq!(
-            response,
-            Ok(ResponseData::AuthenticatorVendor(
-                AuthenticatorVendorResponse {
-                    cert_programmed: true,
-                    pkey_programmed: true,
-                }
-            ))
+            ctap_state.process_vendor_rp_id_policy(params(Some(vec![0x00; 16])), DUMMY_CHANNEL_ID),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
         );
+        assert_eq!(ctap_state.persistent_store.rp_id_policy(), Ok(None));
+
+        let mut message = vec![0xFF; 32];
+        message.extend(&[0x42, 0x00]);
+        message.extend(&Sha256::hash(b"example.com"));
+        let pin_uv_auth_param = hmac_256::<Sha256>(&pin_uv_auth_token, &message)[..16].to_vec();
         assert_eq!(
             ctap_state
-                .persistent_store
-                .attestation_certificate()
-                .unwrap()
-                .unwrap(),
-            dummy_cert
+                .process_vendor_rp_id_policy(params(Some(pin_uv_auth_param)), DUMMY_CHANNEL_ID),
+            Ok(ResponseData::AuthenticatorVendorRpIdPolicy)
         );
         assert_eq!(
-            ctap_state
-                .persistent_store
-                .attestation_private_key()
-                .unwrap()
-                .unwrap(),
-            dummy_key
+            ctap_state.persistent_store.rp_id_policy(),
+            Ok(Some(RpIdPolicy::Deny(vec![Sha256::hash(b"example.com")])))
         );
+    }
 
-        // Now try to lock the device
-        let response = ctap_state.process_vendor_configure(
-            AuthenticatorVendorConfigureParameters {
-                lockdown: true,
-                attestation_material: None,
+    #[test]
+    fn test_vendor_rp_id_policy_allow_list() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let response = ctap_state.process_vendor_rp_id_policy(
+            AuthenticatorVendorRpIdPolicyParameters {
+                allow: true,
+                rp_ids: vec![String::from("example.com")],
+                pin_uv_auth_protocol: None,
+                pin_uv_auth_param: None,
             },
             DUMMY_CHANNEL_ID,
         );
+        assert_eq!(response, Ok(ResponseData::AuthenticatorVendorRpIdPolicy));
+        let make_credential_params = create_minimal_make_credential_parameters();
+        assert!(ctap_state
+            .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
+            .is_ok());
+
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.rp.rp_id = String::from("other.com");
         assert_eq!(
-            response,
-            Ok(ResponseData::AuthenticatorVendor(
-                AuthenticatorVendorResponse {
-                    cert_programmed: true,
-                    pkey_programmed: true,
-                }
-            ))
+            ctap_state.process_make_credential(make_credential_params, DUMMY_CHANNEL_ID),
+            Err(Ctap2StatusCode::CTAP2_ERR_OPERATION_DENIED)
         );
     }
 }
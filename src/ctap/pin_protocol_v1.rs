@@ -19,14 +19,19 @@ use super::status_code::Ctap2StatusCode;
 use super::storage::PersistentStore;
 #[cfg(feature = "with_ctap2_1")]
 use alloc::string::String;
+#[cfg(feature = "with_ctap2_1")]
 use alloc::vec;
 use alloc::vec::Vec;
 use arrayref::array_ref;
+#[cfg(feature = "with_ctap2_1")]
+use cbor::cbor_array_vec;
 use core::convert::TryInto;
 use crypto::cbc::{cbc_decrypt, cbc_encrypt};
-use crypto::hmac::{hmac_256, verify_hmac_256_first_128bits};
+use crypto::hkdf::hkdf_empty_salt_256;
+use crypto::hmac::{hmac_256, verify_hmac_256, verify_hmac_256_first_128bits};
 use crypto::rng256::Rng256;
 use crypto::sha256::Sha256;
+use crypto::util::{zeroize, Zeroizing};
 use crypto::Hash256;
 #[cfg(all(test, feature = "with_ctap2_1"))]
 use enum_iterator::IntoEnumIterator;
@@ -34,99 +39,200 @@ use subtle::ConstantTimeEq;
 
 // Those constants have to be multiples of 16, the AES block size.
 pub const PIN_AUTH_LENGTH: usize = 16;
+const PIN_AUTH_LENGTH_V2: usize = 32;
 const PIN_PADDED_LENGTH: usize = 64;
 const PIN_TOKEN_LENGTH: usize = 32;
 
-/// Checks the given pin_auth against the truncated output of HMAC-SHA256.
-/// Returns LEFT(HMAC(hmac_key, hmac_contents), 16) == pin_auth).
-fn verify_pin_auth(hmac_key: &[u8], hmac_contents: &[u8], pin_auth: &[u8]) -> bool {
-    if pin_auth.len() != PIN_AUTH_LENGTH {
-        return false;
-    }
-    verify_hmac_256_first_128bits::<Sha256>(
-        hmac_key,
-        hmac_contents,
-        array_ref![pin_auth, 0, PIN_AUTH_LENGTH],
-    )
+/// PIN protocol with a hashed shared secret, zero IVs and truncated HMACs.
+pub const PIN_PROTOCOL_V1: u64 = 1;
+/// PIN protocol with HKDF derived keys, random IVs and untruncated HMACs.
+pub const PIN_PROTOCOL_V2: u64 = 2;
+/// The supported PIN protocols, in order of preference.
+pub const PIN_PROTOCOL_VERSIONS: [u64; 2] = [PIN_PROTOCOL_V2, PIN_PROTOCOL_V1];
+
+/// Returns the PIN protocol of a request with a pinUvAuthParam, if it is supported.
+pub fn check_pin_uv_auth_protocol(
+    pin_uv_auth_protocol: Option<u64>,
+) -> Result<u64, Ctap2StatusCode> {
+    match pin_uv_auth_protocol {
+        Some(version) if PIN_PROTOCOL_VERSIONS.contains(&version) => Ok(version),
+        Some(_) => Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID),
+        None => Err(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER),
+    }
+}
+
+/// Checks the given pin_auth against the output of HMAC-SHA256.
+/// PIN protocol 1 returns LEFT(HMAC(hmac_key, hmac_contents), 16) == pin_auth), while PIN
+/// protocol 2 compares the whole HMAC.
+fn verify_pin_auth(
+    hmac_key: &[u8],
+    hmac_contents: &[u8],
+    pin_auth: &[u8],
+    pin_protocol: u64,
+) -> bool {
+    match pin_protocol {
+        PIN_PROTOCOL_V1 if pin_auth.len() == PIN_AUTH_LENGTH => {
+            verify_hmac_256_first_128bits::<Sha256>(
+                hmac_key,
+                hmac_contents,
+                array_ref![pin_auth, 0, PIN_AUTH_LENGTH],
+            )
+        }
+        PIN_PROTOCOL_V2 if pin_auth.len() == PIN_AUTH_LENGTH_V2 => verify_hmac_256::<Sha256>(
+            hmac_key,
+            hmac_contents,
+            array_ref![pin_auth, 0, PIN_AUTH_LENGTH_V2],
+        ),
+        _ => false,
+    }
+}
+
+/// Encrypts with AES-256-CBC. The plaintext length must be a multiple of 16.
+pub fn aes256_cbc_encrypt(key: &[u8; 32], iv: [u8; 16], plaintext: &[u8]) -> Vec<u8> {
+    let aes_enc_key = crypto::aes256::EncryptionKey::new(key);
+    let mut blocks = plaintext
+        .chunks_exact(16)
+        .map(|block| *array_ref![block, 0, 16])
+        .collect::<Vec<[u8; 16]>>();
+    cbc_encrypt(&aes_enc_key, iv, &mut blocks);
+    blocks.iter().flatten().cloned().collect()
+}
+
+/// Decrypts with AES-256-CBC. Returns None if the ciphertext length is not a multiple of 16.
+pub fn aes256_cbc_decrypt(key: &[u8; 32], iv: [u8; 16], ciphertext: &[u8]) -> Option<Vec<u8>> {
+    if ciphertext.len() % 16 != 0 {
+        return None;
+    }
+    let aes_enc_key = crypto::aes256::EncryptionKey::new(key);
+    let aes_dec_key = crypto::aes256::DecryptionKey::new(&aes_enc_key);
+    let mut blocks = ciphertext
+        .chunks_exact(16)
+        .map(|block| *array_ref![block, 0, 16])
+        .collect::<Vec<[u8; 16]>>();
+    cbc_decrypt(&aes_dec_key, iv, &mut blocks);
+    Some(blocks.iter().flatten().cloned().collect())
+}
+
+/// Keys shared with the platform, derived from the key agreement as the PIN protocol specifies.
+enum SharedSecret {
+    // The SHA-256 hash of the shared point, used for both HMAC and AES.
+    V1([u8; 32]),
+    // Separate keys derived with HKDF from the shared point.
+    V2 {
+        hmac_key: [u8; 32],
+        aes_key: [u8; 32],
+    },
+}
+
+impl SharedSecret {
+    fn new(
+        key_agreement_key: &crypto::ecdh::SecKey,
+        platform_key: &crypto::ecdh::PubKey,
+        pin_protocol: u64,
+    ) -> Result<SharedSecret, Ctap2StatusCode> {
+        match pin_protocol {
+            PIN_PROTOCOL_V1 => Ok(SharedSecret::V1(
+                key_agreement_key.exchange_x_sha256(platform_key),
+            )),
+            PIN_PROTOCOL_V2 => {
+                let z = key_agreement_key.exchange_x(platform_key);
+                Ok(SharedSecret::V2 {
+                    hmac_key: hkdf_empty_salt_256::<Sha256>(&z, b"CTAP2 HMAC key"),
+                    aes_key: hkdf_empty_salt_256::<Sha256>(&z, b"CTAP2 AES key"),
+                })
+            }
+            _ => Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER),
+        }
+    }
+
+    /// Checks the authentication of a message received from the platform.
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        match self {
+            SharedSecret::V1(key) => verify_pin_auth(key, message, signature, PIN_PROTOCOL_V1),
+            SharedSecret::V2 { hmac_key, .. } => {
+                verify_pin_auth(hmac_key, message, signature, PIN_PROTOCOL_V2)
+            }
+        }
+    }
+
+    /// Encrypts a message for the platform. The plaintext length must be a multiple of 16.
+    ///
+    /// PIN protocol 1 uses a zero IV, PIN protocol 2 prepends a random IV to the ciphertext.
+    fn encrypt(&self, rng: &mut impl Rng256, plaintext: &[u8]) -> Vec<u8> {
+        match self {
+            SharedSecret::V1(key) => aes256_cbc_encrypt(key, [0; 16], plaintext),
+            SharedSecret::V2 { aes_key, .. } => {
+                let random_bytes = rng.gen_uniform_u8x32();
+                let iv = *array_ref![random_bytes, 0, 16];
+                let mut ciphertext = iv.to_vec();
+                ciphertext.extend(aes256_cbc_encrypt(aes_key, iv, plaintext));
+                ciphertext
+            }
+        }
+    }
+
+    /// Decrypts a message from the platform, or returns None if its length is invalid.
+    fn decrypt(&self, ciphertext: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            SharedSecret::V1(key) => aes256_cbc_decrypt(key, [0; 16], ciphertext),
+            SharedSecret::V2 { aes_key, .. } => {
+                if ciphertext.len() < 16 {
+                    return None;
+                }
+                aes256_cbc_decrypt(aes_key, *array_ref![ciphertext, 0, 16], &ciphertext[16..])
+            }
+        }
+    }
+}
+
+impl Drop for SharedSecret {
+    fn drop(&mut self) {
+        match self {
+            SharedSecret::V1(key) => zeroize(key),
+            SharedSecret::V2 { hmac_key, aes_key } => {
+                zeroize(hmac_key);
+                zeroize(aes_key);
+            }
+        }
+    }
 }
 
 /// Encrypts the HMAC-secret outputs. To compute them, we first have to
 /// decrypt the HMAC secret salt(s) that were encrypted with the shared secret.
 /// The credRandom is used as a secret to HMAC those salts.
 fn encrypt_hmac_secret_output(
-    shared_secret: &[u8; 32],
+    rng: &mut impl Rng256,
+    shared_secret: &SharedSecret,
     salt_enc: &[u8],
     cred_random: &[u8; 32],
 ) -> Result<Vec<u8>, Ctap2StatusCode> {
-    if salt_enc.len() != 32 && salt_enc.len() != 64 {
+    let salts = Zeroizing(
+        shared_secret
+            .decrypt(salt_enc)
+            .ok_or(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_EXTENSION)?,
+    );
+    if salts.len() != 32 && salts.len() != 64 {
         return Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_EXTENSION);
     }
-    let aes_enc_key = crypto::aes256::EncryptionKey::new(shared_secret);
-    let aes_dec_key = crypto::aes256::DecryptionKey::new(&aes_enc_key);
-    // The specification specifically asks for a zero IV.
-    let iv = [0u8; 16];
-
-    // With the if clause restriction above, block_len can only be 2 or 4.
-    let block_len = salt_enc.len() / 16;
-    let mut blocks = vec![[0u8; 16]; block_len];
-    for i in 0..block_len {
-        blocks[i].copy_from_slice(&salt_enc[16 * i..16 * (i + 1)]);
-    }
-    cbc_decrypt(&aes_dec_key, iv, &mut blocks[..block_len]);
-
-    let mut decrypted_salt1 = [0u8; 32];
-    decrypted_salt1[..16].copy_from_slice(&blocks[0]);
-    decrypted_salt1[16..].copy_from_slice(&blocks[1]);
-    let output1 = hmac_256::<Sha256>(&cred_random[..], &decrypted_salt1[..]);
-    for i in 0..2 {
-        blocks[i].copy_from_slice(&output1[16 * i..16 * (i + 1)]);
-    }
-
-    if block_len == 4 {
-        let mut decrypted_salt2 = [0u8; 32];
-        decrypted_salt2[..16].copy_from_slice(&blocks[2]);
-        decrypted_salt2[16..].copy_from_slice(&blocks[3]);
-        let output2 = hmac_256::<Sha256>(&cred_random[..], &decrypted_salt2[..]);
-        for i in 0..2 {
-            blocks[i + 2].copy_from_slice(&output2[16 * i..16 * (i + 1)]);
-        }
-    }
-
-    cbc_encrypt(&aes_enc_key, iv, &mut blocks[..block_len]);
-    let mut encrypted_output = Vec::with_capacity(salt_enc.len());
-    for b in &blocks[..block_len] {
-        encrypted_output.extend(b);
+    // The capacity fits both outputs, so that extending doesn't leave a copy behind.
+    let mut output = Zeroizing(Vec::with_capacity(64));
+    output.extend(&hmac_256::<Sha256>(&cred_random[..], &salts[..32]));
+    if salts.len() == 64 {
+        output.extend(&hmac_256::<Sha256>(&cred_random[..], &salts[32..]));
     }
-    Ok(encrypted_output)
+    Ok(shared_secret.encrypt(rng, &output))
 }
 
 /// Decrypts the new_pin_enc and outputs the found PIN.
-fn decrypt_pin(
-    aes_dec_key: &crypto::aes256::DecryptionKey,
-    new_pin_enc: Vec<u8>,
-) -> Option<Vec<u8>> {
-    if new_pin_enc.len() != PIN_PADDED_LENGTH {
+fn decrypt_pin(shared_secret: &SharedSecret, new_pin_enc: Vec<u8>) -> Option<Vec<u8>> {
+    let padded_pin = Zeroizing(shared_secret.decrypt(&new_pin_enc)?);
+    if padded_pin.len() != PIN_PADDED_LENGTH {
         return None;
     }
-    let iv = [0u8; 16];
-    // Assuming PIN_PADDED_LENGTH % block_size == 0 here.
-    const BLOCK_COUNT: usize = PIN_PADDED_LENGTH / 16;
-    let mut blocks = [[0u8; 16]; BLOCK_COUNT];
-    for i in 0..BLOCK_COUNT {
-        blocks[i].copy_from_slice(&new_pin_enc[i * 16..(i + 1) * 16]);
-    }
-    cbc_decrypt(aes_dec_key, iv, &mut blocks);
     // In CTAP 2.1, the specification changed. The new wording might lead to
     // different behavior when there are non-zero bytes after zero bytes.
     // This implementation consistently ignores those degenerate cases.
-    Some(
-        blocks
-            .iter()
-            .flatten()
-            .cloned()
-            .take_while(|&c| c != 0)
-            .collect::<Vec<u8>>(),
-    )
+    Some(padded_pin.iter().cloned().take_while(|&c| c != 0).collect())
 }
 
 /// Stores the encrypted new PIN in the persistent storage, if it satisfies the
@@ -135,11 +241,13 @@ fn decrypt_pin(
 /// is hashed, truncated to 16 bytes and persistently stored.
 fn check_and_store_new_pin(
     persistent_store: &mut PersistentStore,
-    aes_dec_key: &crypto::aes256::DecryptionKey,
+    shared_secret: &SharedSecret,
     new_pin_enc: Vec<u8>,
 ) -> Result<(), Ctap2StatusCode> {
-    let pin = decrypt_pin(aes_dec_key, new_pin_enc)
-        .ok_or(Ctap2StatusCode::CTAP2_ERR_PIN_POLICY_VIOLATION)?;
+    let pin = Zeroizing(
+        decrypt_pin(shared_secret, new_pin_enc)
+            .ok_or(Ctap2StatusCode::CTAP2_ERR_PIN_POLICY_VIOLATION)?,
+    );
 
     #[cfg(feature = "with_ctap2_1")]
     let min_pin_length = persistent_store.min_pin_length()? as usize;
@@ -165,10 +273,26 @@ pub enum PinPermission {
     GetAssertion = 0x02,
     CredentialManagement = 0x04,
     BioEnrollment = 0x08,
-    PlatformConfiguration = 0x10,
+    LargeBlobWrite = 0x10,
     AuthenticatorConfiguration = 0x20,
 }
 
+/// Checks the permissions requested for a pinUvAuthToken.
+#[cfg(feature = "with_ctap2_1")]
+fn check_permissions(
+    permissions: u8,
+    permissions_rp_id: &Option<String>,
+) -> Result<(), Ctap2StatusCode> {
+    if permissions == 0 {
+        return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+    }
+    // This check is not mentioned protocol steps, but mentioned in a side note.
+    if permissions & 0x03 != 0 && permissions_rp_id.is_none() {
+        return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+    }
+    Ok(())
+}
+
 pub struct PinProtocolV1 {
     key_agreement_key: crypto::ecdh::SecKey,
     pin_uv_auth_token: [u8; PIN_TOKEN_LENGTH],
@@ -177,6 +301,9 @@ pub struct PinProtocolV1 {
     permissions: u8,
     #[cfg(feature = "with_ctap2_1")]
     permissions_rp_id: Option<String>,
+    // Built-in user verification, like a fingerprint sensor. Returns whether the user is verified.
+    #[cfg(feature = "with_ctap2_1")]
+    built_in_uv: Option<fn() -> bool>,
 }
 
 impl PinProtocolV1 {
@@ -191,9 +318,20 @@ impl PinProtocolV1 {
             permissions: 0,
             #[cfg(feature = "with_ctap2_1")]
             permissions_rp_id: None,
+            // None of the supported boards has a user verification sensor.
+            #[cfg(feature = "with_ctap2_1")]
+            built_in_uv: None,
         }
     }
 
+    /// Returns whether PIN verification is blocked until the next power cycle.
+    ///
+    /// This happens after 3 consecutive PIN mismatches. Contrary to the PIN retries, this state is
+    /// not persistent, so that a reboot unblocks it.
+    pub fn is_pin_auth_blocked(&self) -> bool {
+        self.consecutive_pin_mismatches >= 3
+    }
+
     /// Decrypts the encrypted pin_hash and compares it to the stored pin_hash.
     /// Resets or decreases the PIN retries, depending on success or failure.
     /// Also, in case of failure, the key agreement key is randomly reset.
@@ -201,31 +339,29 @@ impl PinProtocolV1 {
         &mut self,
         rng: &mut impl Rng256,
         persistent_store: &mut PersistentStore,
-        aes_dec_key: &crypto::aes256::DecryptionKey,
+        shared_secret: &SharedSecret,
         pin_hash_enc: Vec<u8>,
     ) -> Result<(), Ctap2StatusCode> {
         match persistent_store.pin_hash()? {
             Some(pin_hash) => {
-                if self.consecutive_pin_mismatches >= 3 {
+                if self.is_pin_auth_blocked() {
                     return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_BLOCKED);
                 }
                 persistent_store.decr_pin_retries()?;
-                if pin_hash_enc.len() != PIN_AUTH_LENGTH {
-                    return Err(Ctap2StatusCode::CTAP2_ERR_PIN_INVALID);
-                }
-
-                let iv = [0u8; 16];
-                let mut blocks = [[0u8; 16]; 1];
-                blocks[0].copy_from_slice(&pin_hash_enc);
-                cbc_decrypt(aes_dec_key, iv, &mut blocks);
-
-                if !bool::from(pin_hash.ct_eq(&blocks[0])) {
+                let pin_hash_dec = Zeroizing(
+                    shared_secret
+                        .decrypt(&pin_hash_enc)
+                        .filter(|pin_hash_dec| pin_hash_dec.len() == PIN_AUTH_LENGTH)
+                        .ok_or(Ctap2StatusCode::CTAP2_ERR_PIN_INVALID)?,
+                );
+
+                if !bool::from(pin_hash[..].ct_eq(&pin_hash_dec[..])) {
                     self.key_agreement_key = crypto::ecdh::SecKey::gensk(rng);
                     if persistent_store.pin_retries()? == 0 {
                         return Err(Ctap2StatusCode::CTAP2_ERR_PIN_BLOCKED);
                     }
                     self.consecutive_pin_mismatches += 1;
-                    if self.consecutive_pin_mismatches >= 3 {
+                    if self.is_pin_auth_blocked() {
                         return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_BLOCKED);
                     }
                     return Err(Ctap2StatusCode::CTAP2_ERR_PIN_INVALID);
@@ -240,23 +376,29 @@ impl PinProtocolV1 {
     }
 
     /// Uses the self-owned and passed halves of the key agreement to generate the
-    /// shared secret for checking pin_auth and generating a decryption key.
+    /// shared secret of the given PIN protocol.
+    fn exchange_shared_secret(
+        &self,
+        key_agreement: CoseKey,
+        pin_protocol: u64,
+    ) -> Result<SharedSecret, Ctap2StatusCode> {
+        let pk: crypto::ecdh::PubKey = CoseKey::try_into(key_agreement)?;
+        SharedSecret::new(&self.key_agreement_key, &pk, pin_protocol)
+    }
+
+    /// Generates the shared secret like exchange_shared_secret, and uses it to check pin_auth.
     fn exchange_decryption_key(
         &self,
         key_agreement: CoseKey,
+        pin_protocol: u64,
         pin_auth: &[u8],
         authenticated_message: &[u8],
-    ) -> Result<crypto::aes256::DecryptionKey, Ctap2StatusCode> {
-        let pk: crypto::ecdh::PubKey = CoseKey::try_into(key_agreement)?;
-        let shared_secret = self.key_agreement_key.exchange_x_sha256(&pk);
-
-        if !verify_pin_auth(&shared_secret, authenticated_message, pin_auth) {
+    ) -> Result<SharedSecret, Ctap2StatusCode> {
+        let shared_secret = self.exchange_shared_secret(key_agreement, pin_protocol)?;
+        if !shared_secret.verify(authenticated_message, pin_auth) {
             return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID);
         }
-
-        let aes_enc_key = crypto::aes256::EncryptionKey::new(&shared_secret);
-        let aes_dec_key = crypto::aes256::DecryptionKey::new(&aes_enc_key);
-        Ok(aes_dec_key)
+        Ok(shared_secret)
     }
 
     fn process_get_pin_retries(
@@ -282,6 +424,7 @@ impl PinProtocolV1 {
     fn process_set_pin(
         &mut self,
         persistent_store: &mut PersistentStore,
+        pin_protocol: u64,
         key_agreement: CoseKey,
         pin_auth: Vec<u8>,
         new_pin_enc: Vec<u8>,
@@ -289,17 +432,19 @@ impl PinProtocolV1 {
         if persistent_store.pin_hash()?.is_some() {
             return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID);
         }
-        let pin_decryption_key =
-            self.exchange_decryption_key(key_agreement, &pin_auth, &new_pin_enc)?;
-        check_and_store_new_pin(persistent_store, &pin_decryption_key, new_pin_enc)?;
+        let shared_secret =
+            self.exchange_decryption_key(key_agreement, pin_protocol, &pin_auth, &new_pin_enc)?;
+        check_and_store_new_pin(persistent_store, &shared_secret, new_pin_enc)?;
         persistent_store.reset_pin_retries()?;
         Ok(())
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn process_change_pin(
         &mut self,
         rng: &mut impl Rng256,
         persistent_store: &mut PersistentStore,
+        pin_protocol: u64,
         key_agreement: CoseKey,
         pin_auth: Vec<u8>,
         new_pin_enc: Vec<u8>,
@@ -310,11 +455,11 @@ impl PinProtocolV1 {
         }
         let mut auth_param_data = new_pin_enc.clone();
         auth_param_data.extend(&pin_hash_enc);
-        let pin_decryption_key =
-            self.exchange_decryption_key(key_agreement, &pin_auth, &auth_param_data)?;
-        self.verify_pin_hash_enc(rng, persistent_store, &pin_decryption_key, pin_hash_enc)?;
+        let shared_secret =
+            self.exchange_decryption_key(key_agreement, pin_protocol, &pin_auth, &auth_param_data)?;
+        self.verify_pin_hash_enc(rng, persistent_store, &shared_secret, pin_hash_enc)?;
 
-        check_and_store_new_pin(persistent_store, &pin_decryption_key, new_pin_enc)?;
+        check_and_store_new_pin(persistent_store, &shared_secret, new_pin_enc)?;
         self.pin_uv_auth_token = rng.gen_uniform_u8x32();
         Ok(())
     }
@@ -323,27 +468,24 @@ impl PinProtocolV1 {
         &mut self,
         rng: &mut impl Rng256,
         persistent_store: &mut PersistentStore,
+        pin_protocol: u64,
         key_agreement: CoseKey,
         pin_hash_enc: Vec<u8>,
     ) -> Result<AuthenticatorClientPinResponse, Ctap2StatusCode> {
         if persistent_store.pin_retries()? == 0 {
             return Err(Ctap2StatusCode::CTAP2_ERR_PIN_BLOCKED);
         }
-        let pk: crypto::ecdh::PubKey = CoseKey::try_into(key_agreement)?;
-        let shared_secret = self.key_agreement_key.exchange_x_sha256(&pk);
-
-        let token_encryption_key = crypto::aes256::EncryptionKey::new(&shared_secret);
-        let pin_decryption_key = crypto::aes256::DecryptionKey::new(&token_encryption_key);
-        self.verify_pin_hash_enc(rng, persistent_store, &pin_decryption_key, pin_hash_enc)?;
+        let shared_secret = self.exchange_shared_secret(key_agreement, pin_protocol)?;
+        self.verify_pin_hash_enc(rng, persistent_store, &shared_secret, pin_hash_enc)?;
+        #[cfg(feature = "with_ctap2_1")]
+        {
+            if persistent_store.has_force_pin_change()? {
+                return Err(Ctap2StatusCode::CTAP2_ERR_PIN_POLICY_VIOLATION);
+            }
+        }
 
         // Assuming PIN_TOKEN_LENGTH % block_size == 0 here.
-        let iv = [0u8; 16];
-        let mut blocks = [[0u8; 16]; PIN_TOKEN_LENGTH / 16];
-        for (i, item) in blocks.iter_mut().take(PIN_TOKEN_LENGTH / 16).enumerate() {
-            item.copy_from_slice(&self.pin_uv_auth_token[i * 16..(i + 1) * 16]);
-        }
-        cbc_encrypt(&token_encryption_key, iv, &mut blocks);
-        let pin_token: Vec<u8> = blocks.iter().flatten().cloned().collect();
+        let pin_token = shared_secret.encrypt(rng, &self.pin_uv_auth_token);
 
         #[cfg(feature = "with_ctap2_1")]
         {
@@ -360,22 +502,32 @@ impl PinProtocolV1 {
 
     #[cfg(feature = "with_ctap2_1")]
     fn process_get_pin_uv_auth_token_using_uv_with_permissions(
-        &self,
-        // If you want to support local user verification, implement this function.
-        // Lacking a fingerprint reader, this subcommand is currently unsupported.
-        _key_agreement: CoseKey,
-        _permissions: u8,
-        _permissions_rp_id: Option<String>,
+        &mut self,
+        rng: &mut impl Rng256,
+        pin_protocol: u64,
+        key_agreement: CoseKey,
+        permissions: u8,
+        permissions_rp_id: Option<String>,
     ) -> Result<AuthenticatorClientPinResponse, Ctap2StatusCode> {
-        // User verifications is only supported through PIN currently.
-        #[cfg(not(feature = "with_ctap2_1"))]
-        {
-            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_COMMAND)
-        }
-        #[cfg(feature = "with_ctap2_1")]
-        {
-            Err(Ctap2StatusCode::CTAP2_ERR_INVALID_SUBCOMMAND)
+        // Without a sensor, user verification is only supported through PIN.
+        let built_in_uv = self
+            .built_in_uv
+            .ok_or(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)?;
+        check_permissions(permissions, &permissions_rp_id)?;
+        let shared_secret = self.exchange_shared_secret(key_agreement, pin_protocol)?;
+        if !built_in_uv() {
+            return Err(Ctap2StatusCode::CTAP2_ERR_UV_INVALID);
         }
+
+        let pin_token = shared_secret.encrypt(rng, &self.pin_uv_auth_token);
+        self.permissions = permissions;
+        self.permissions_rp_id = permissions_rp_id;
+
+        Ok(AuthenticatorClientPinResponse {
+            key_agreement: None,
+            pin_token: Some(pin_token),
+            retries: None,
+        })
     }
 
     #[cfg(feature = "with_ctap2_1")]
@@ -395,17 +547,15 @@ impl PinProtocolV1 {
     fn process_set_min_pin_length(
         &mut self,
         persistent_store: &mut PersistentStore,
+        pin_protocol: u64,
         min_pin_length: u8,
         min_pin_length_rp_ids: Option<Vec<String>>,
         pin_auth: Option<Vec<u8>>,
     ) -> Result<(), Ctap2StatusCode> {
-        if min_pin_length_rp_ids.is_some() {
-            return Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_EXTENSION);
-        }
         if persistent_store.pin_hash()?.is_some() {
             match pin_auth {
                 Some(pin_auth) => {
-                    if self.consecutive_pin_mismatches >= 3 {
+                    if self.is_pin_auth_blocked() {
                         return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_BLOCKED);
                     }
                     // TODO(kaczmarczyck) Values are taken from the (not yet public) new revision
@@ -416,12 +566,17 @@ impl PinProtocolV1 {
                     let mut message = vec![0xFF; 32];
                     message.extend(&[0x06, 0x08]);
                     message.extend(&[min_pin_length as u8, 0x00, 0x00, 0x00]);
-                    // TODO(kaczmarczyck) commented code is useful for the extension
-                    // https://github.com/google/OpenSK/issues/129
-                    // if !cbor::write(cbor_array_vec!(min_pin_length_rp_ids), &mut message) {
-                    //     return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_RESPONSE_CANNOT_WRITE_CBOR);
-                    // }
-                    if !verify_pin_auth(&self.pin_uv_auth_token, &message, &pin_auth) {
+                    if let Some(min_pin_length_rp_ids) = &min_pin_length_rp_ids {
+                        if !cbor::write(
+                            cbor_array_vec!(min_pin_length_rp_ids.clone()),
+                            &mut message,
+                        ) {
+                            return Err(
+                                Ctap2StatusCode::CTAP2_ERR_VENDOR_RESPONSE_CANNOT_WRITE_CBOR,
+                            );
+                        }
+                    }
+                    if !self.verify_pin_auth_token(&message, &pin_auth, pin_protocol) {
                         return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID);
                     }
                 }
@@ -432,34 +587,33 @@ impl PinProtocolV1 {
             return Err(Ctap2StatusCode::CTAP2_ERR_PIN_POLICY_VIOLATION);
         }
         persistent_store.set_min_pin_length(min_pin_length)?;
-        // TODO(kaczmarczyck) commented code is useful for the extension
-        // https://github.com/google/OpenSK/issues/129
-        // if let Some(min_pin_length_rp_ids) = min_pin_length_rp_ids {
-        //     persistent_store.set_min_pin_length_rp_ids(min_pin_length_rp_ids)?;
-        // }
+        if let Some(min_pin_length_rp_ids) = min_pin_length_rp_ids {
+            persistent_store.set_min_pin_length_rp_ids(min_pin_length_rp_ids)?;
+        }
         Ok(())
     }
 
     #[cfg(feature = "with_ctap2_1")]
+    #[allow(clippy::too_many_arguments)]
     fn process_get_pin_uv_auth_token_using_pin_with_permissions(
         &mut self,
         rng: &mut impl Rng256,
         persistent_store: &mut PersistentStore,
+        pin_protocol: u64,
         key_agreement: CoseKey,
         pin_hash_enc: Vec<u8>,
         permissions: u8,
         permissions_rp_id: Option<String>,
     ) -> Result<AuthenticatorClientPinResponse, Ctap2StatusCode> {
-        if permissions == 0 {
-            return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
-        }
-        // This check is not mentioned protocol steps, but mentioned in a side note.
-        if permissions & 0x03 != 0 && permissions_rp_id.is_none() {
-            return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
-        }
+        check_permissions(permissions, &permissions_rp_id)?;
 
-        let response =
-            self.process_get_pin_token(rng, persistent_store, key_agreement, pin_hash_enc)?;
+        let response = self.process_get_pin_token(
+            rng,
+            persistent_store,
+            pin_protocol,
+            key_agreement,
+            pin_hash_enc,
+        )?;
 
         self.permissions = permissions;
         self.permissions_rp_id = permissions_rp_id;
@@ -490,7 +644,7 @@ impl PinProtocolV1 {
             permissions_rp_id,
         } = client_pin_params;
 
-        if pin_protocol != 1 {
+        if !PIN_PROTOCOL_VERSIONS.contains(&pin_protocol) {
             #[cfg(not(feature = "with_ctap2_1"))]
             return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID);
             #[cfg(feature = "with_ctap2_1")]
@@ -505,6 +659,7 @@ impl PinProtocolV1 {
             ClientPinSubCommand::SetPin => {
                 self.process_set_pin(
                     persistent_store,
+                    pin_protocol,
                     key_agreement.ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?,
                     pin_auth.ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?,
                     new_pin_enc.ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?,
@@ -515,6 +670,7 @@ impl PinProtocolV1 {
                 self.process_change_pin(
                     rng,
                     persistent_store,
+                    pin_protocol,
                     key_agreement.ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?,
                     pin_auth.ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?,
                     new_pin_enc.ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?,
@@ -525,12 +681,15 @@ impl PinProtocolV1 {
             ClientPinSubCommand::GetPinToken => Some(self.process_get_pin_token(
                 rng,
                 persistent_store,
+                pin_protocol,
                 key_agreement.ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?,
                 pin_hash_enc.ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?,
             )?),
             #[cfg(feature = "with_ctap2_1")]
             ClientPinSubCommand::GetPinUvAuthTokenUsingUvWithPermissions => Some(
                 self.process_get_pin_uv_auth_token_using_uv_with_permissions(
+                    rng,
+                    pin_protocol,
                     key_agreement.ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?,
                     permissions.ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?,
                     permissions_rp_id,
@@ -542,6 +701,7 @@ impl PinProtocolV1 {
             ClientPinSubCommand::SetMinPinLength => {
                 self.process_set_min_pin_length(
                     persistent_store,
+                    pin_protocol,
                     min_pin_length.ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?,
                     min_pin_length_rp_ids,
                     pin_auth,
@@ -553,6 +713,7 @@ impl PinProtocolV1 {
                 self.process_get_pin_uv_auth_token_using_pin_with_permissions(
                     rng,
                     persistent_store,
+                    pin_protocol,
                     key_agreement.ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?,
                     pin_hash_enc.ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?,
                     permissions.ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?,
@@ -563,8 +724,18 @@ impl PinProtocolV1 {
         Ok(ResponseData::AuthenticatorClientPin(response))
     }
 
-    pub fn verify_pin_auth_token(&self, hmac_contents: &[u8], pin_auth: &[u8]) -> bool {
-        verify_pin_auth(&self.pin_uv_auth_token, &hmac_contents, &pin_auth)
+    pub fn verify_pin_auth_token(
+        &self,
+        hmac_contents: &[u8],
+        pin_auth: &[u8],
+        pin_protocol: u64,
+    ) -> bool {
+        verify_pin_auth(
+            &self.pin_uv_auth_token,
+            &hmac_contents,
+            &pin_auth,
+            pin_protocol,
+        )
     }
 
     pub fn reset(&mut self, rng: &mut impl Rng256) {
@@ -580,6 +751,7 @@ impl PinProtocolV1 {
 
     pub fn process_hmac_secret(
         &self,
+        rng: &mut impl Rng256,
         hmac_secret_input: GetAssertionHmacSecretInput,
         cred_random: &[u8; 32],
     ) -> Result<Vec<u8>, Ctap2StatusCode> {
@@ -587,15 +759,15 @@ impl PinProtocolV1 {
             key_agreement,
             salt_enc,
             salt_auth,
+            pin_uv_auth_protocol,
         } = hmac_secret_input;
-        let pk: crypto::ecdh::PubKey = CoseKey::try_into(key_agreement)?;
-        let shared_secret = self.key_agreement_key.exchange_x_sha256(&pk);
-        // HMAC-secret does the same 16 byte truncated check.
-        if !verify_pin_auth(&shared_secret, &salt_enc, &salt_auth) {
+        let shared_secret = self.exchange_shared_secret(key_agreement, pin_uv_auth_protocol)?;
+        // HMAC-secret does the same check as the PIN protocol.
+        if !shared_secret.verify(&salt_enc, &salt_auth) {
             // Hard to tell what the correct error code here is.
             return Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_EXTENSION);
         }
-        encrypt_hmac_secret_output(&shared_secret, &salt_enc[..], cred_random)
+        encrypt_hmac_secret_output(rng, &shared_secret, &salt_enc[..], cred_random)
     }
 
     #[cfg(feature = "with_ctap2_1")]
@@ -620,6 +792,29 @@ impl PinProtocolV1 {
         Ok(())
     }
 
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn has_no_or_rp_id_permission(&self, rp_id: &str) -> Result<(), Ctap2StatusCode> {
+        match &self.permissions_rp_id {
+            Some(permissions_rp_id) if rp_id != permissions_rp_id => {
+                Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn has_no_rp_id_permission(&self) -> Result<(), Ctap2StatusCode> {
+        if self.permissions_rp_id.is_some() {
+            return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID);
+        }
+        Ok(())
+    }
+
+    #[cfg(all(test, feature = "with_ctap2_1"))]
+    pub fn set_permissions(&mut self, permissions: u8) {
+        self.permissions = permissions;
+    }
+
     #[cfg(test)]
     pub fn new_test(
         key_agreement_key: crypto::ecdh::SecKey,
@@ -633,6 +828,8 @@ impl PinProtocolV1 {
             permissions: 0xFF,
             #[cfg(feature = "with_ctap2_1")]
             permissions_rp_id: None,
+            #[cfg(feature = "with_ctap2_1")]
+            built_in_uv: None,
         }
     }
 }
@@ -680,6 +877,14 @@ mod test {
         blocks.iter().flatten().cloned().collect::<Vec<u8>>()
     }
 
+    // Computes the authentication the platform sends with a message.
+    fn authenticate(shared_secret: &SharedSecret, message: &[u8]) -> Vec<u8> {
+        match shared_secret {
+            SharedSecret::V1(key) => hmac_256::<Sha256>(key, message)[..PIN_AUTH_LENGTH].to_vec(),
+            SharedSecret::V2 { hmac_key, .. } => hmac_256::<Sha256>(hmac_key, message).to_vec(),
+        }
+    }
+
     // Fails on PINs bigger than 64 bytes.
     fn encrypt_pin(shared_secret: &[u8; 32], pin: Vec<u8>) -> Vec<u8> {
         assert!(pin.len() <= 64);
@@ -701,6 +906,31 @@ mod test {
         encrypt_message(shared_secret, &pin_hash[..16])
     }
 
+    #[test]
+    fn test_shared_secret_v2() {
+        let mut rng = ThreadRng256 {};
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let pk = key_agreement_key.genpk();
+        let shared_secret = SharedSecret::new(&key_agreement_key, &pk, PIN_PROTOCOL_V2).unwrap();
+
+        let plaintext = [0x55; 32];
+        let ciphertext = shared_secret.encrypt(&mut rng, &plaintext);
+        assert_eq!(ciphertext.len(), 16 + plaintext.len());
+        // The IV is random.
+        assert_ne!(shared_secret.encrypt(&mut rng, &plaintext), ciphertext);
+        assert_eq!(shared_secret.decrypt(&ciphertext), Some(plaintext.to_vec()));
+        assert_eq!(shared_secret.decrypt(&ciphertext[..15]), None);
+        assert_eq!(shared_secret.decrypt(&ciphertext[..40]), None);
+
+        let signature = authenticate(&shared_secret, &plaintext);
+        assert_eq!(signature.len(), 32);
+        assert!(shared_secret.verify(&plaintext, &signature));
+        assert!(!shared_secret.verify(&plaintext, &signature[..PIN_AUTH_LENGTH]));
+        assert!(!shared_secret.verify(&ciphertext, &signature));
+
+        assert!(SharedSecret::new(&key_agreement_key, &pk, 3).is_err());
+    }
+
     #[test]
     fn test_verify_pin_hash_enc() {
         let mut rng = ThreadRng256 {};
@@ -712,8 +942,7 @@ mod test {
         ];
         persistent_store.set_pin_hash(&pin_hash).unwrap();
         let shared_secret = [0x88; 32];
-        let aes_enc_key = crypto::aes256::EncryptionKey::new(&shared_secret);
-        let aes_dec_key = crypto::aes256::DecryptionKey::new(&aes_enc_key);
+        let shared_secret_v1 = SharedSecret::V1(shared_secret);
 
         let mut pin_protocol_v1 = PinProtocolV1::new(&mut rng);
         let pin_hash_enc = vec![
@@ -724,7 +953,7 @@ mod test {
             pin_protocol_v1.verify_pin_hash_enc(
                 &mut rng,
                 &mut persistent_store,
-                &aes_dec_key,
+                &shared_secret_v1,
                 pin_hash_enc
             ),
             Ok(())
@@ -735,7 +964,7 @@ mod test {
             pin_protocol_v1.verify_pin_hash_enc(
                 &mut rng,
                 &mut persistent_store,
-                &aes_dec_key,
+                &shared_secret_v1,
                 pin_hash_enc
             ),
             Err(Ctap2StatusCode::CTAP2_ERR_PIN_INVALID)
@@ -750,7 +979,7 @@ mod test {
             pin_protocol_v1.verify_pin_hash_enc(
                 &mut rng,
                 &mut persistent_store,
-                &aes_dec_key,
+                &shared_secret_v1,
                 pin_hash_enc
             ),
             Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_BLOCKED)
@@ -762,7 +991,7 @@ mod test {
             pin_protocol_v1.verify_pin_hash_enc(
                 &mut rng,
                 &mut persistent_store,
-                &aes_dec_key,
+                &shared_secret_v1,
                 pin_hash_enc
             ),
             Err(Ctap2StatusCode::CTAP2_ERR_PIN_INVALID)
@@ -773,13 +1002,78 @@ mod test {
             pin_protocol_v1.verify_pin_hash_enc(
                 &mut rng,
                 &mut persistent_store,
-                &aes_dec_key,
+                &shared_secret_v1,
                 pin_hash_enc
             ),
             Err(Ctap2StatusCode::CTAP2_ERR_PIN_INVALID)
         );
     }
 
+    #[test]
+    fn test_pin_lockout_across_reboots() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        // The PIN is "1234".
+        let pin_hash = [
+            0x01, 0xD9, 0x88, 0x40, 0x50, 0xBB, 0xD0, 0x7A, 0x23, 0x1A, 0xEB, 0x69, 0xD8, 0x36,
+            0xC4, 0x12,
+        ];
+        persistent_store.set_pin_hash(&pin_hash).unwrap();
+        let shared_secret = [0x88; 32];
+        let shared_secret_v1 = SharedSecret::V1(shared_secret);
+        let initial_retries = persistent_store.pin_retries().unwrap();
+
+        let mut pin_protocol_v1 = PinProtocolV1::new(&mut rng);
+        let bad_pin_hash_enc = vec![0xEE; 16];
+        for _ in 0..2 {
+            assert_eq!(
+                pin_protocol_v1.verify_pin_hash_enc(
+                    &mut rng,
+                    &mut persistent_store,
+                    &shared_secret_v1,
+                    bad_pin_hash_enc.clone()
+                ),
+                Err(Ctap2StatusCode::CTAP2_ERR_PIN_INVALID)
+            );
+            assert!(!pin_protocol_v1.is_pin_auth_blocked());
+        }
+        assert_eq!(
+            pin_protocol_v1.verify_pin_hash_enc(
+                &mut rng,
+                &mut persistent_store,
+                &shared_secret_v1,
+                bad_pin_hash_enc.clone()
+            ),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_BLOCKED)
+        );
+        assert!(pin_protocol_v1.is_pin_auth_blocked());
+
+        // A reboot unblocks PIN verification, but the retries are persistent.
+        let mut pin_protocol_v1 = PinProtocolV1::new(&mut rng);
+        assert!(!pin_protocol_v1.is_pin_auth_blocked());
+        assert_eq!(persistent_store.pin_retries().unwrap(), initial_retries - 3);
+
+        // Exhaust the retries, rebooting whenever PIN verification is blocked.
+        let mut result = Ok(());
+        while persistent_store.pin_retries().unwrap() > 0 {
+            if pin_protocol_v1.is_pin_auth_blocked() {
+                pin_protocol_v1 = PinProtocolV1::new(&mut rng);
+            }
+            result = pin_protocol_v1.verify_pin_hash_enc(
+                &mut rng,
+                &mut persistent_store,
+                &shared_secret_v1,
+                bad_pin_hash_enc.clone(),
+            );
+        }
+        assert_eq!(result, Err(Ctap2StatusCode::CTAP2_ERR_PIN_BLOCKED));
+
+        // The blocked PIN survives a reboot.
+        let pin_protocol_v1 = PinProtocolV1::new(&mut rng);
+        assert!(!pin_protocol_v1.is_pin_auth_blocked());
+        assert_eq!(persistent_store.pin_retries().unwrap(), 0);
+    }
+
     #[test]
     fn test_process_get_pin_retries() {
         let mut rng = ThreadRng256 {};
@@ -825,12 +1119,51 @@ mod test {
         assert_eq!(
             pin_protocol_v1.process_set_pin(
                 &mut persistent_store,
+                PIN_PROTOCOL_V1,
+                key_agreement,
+                pin_auth,
+                new_pin_enc
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_process_set_pin_v2() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut pin_protocol_v1 = PinProtocolV1::new(&mut rng);
+        let pk = pin_protocol_v1.key_agreement_key.genpk();
+        let shared_secret =
+            SharedSecret::new(&pin_protocol_v1.key_agreement_key, &pk, PIN_PROTOCOL_V2).unwrap();
+        let key_agreement = CoseKey::from(pk);
+        let mut padded_pin = [0u8; 64];
+        padded_pin[..4].copy_from_slice(b"1234");
+        let new_pin_enc = shared_secret.encrypt(&mut rng, &padded_pin);
+        let pin_auth = authenticate(&shared_secret, &new_pin_enc);
+        assert_eq!(
+            pin_protocol_v1.process_set_pin(
+                &mut persistent_store,
+                PIN_PROTOCOL_V1,
+                key_agreement.clone(),
+                pin_auth.clone(),
+                new_pin_enc.clone()
+            ),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+        );
+        assert_eq!(
+            pin_protocol_v1.process_set_pin(
+                &mut persistent_store,
+                PIN_PROTOCOL_V2,
                 key_agreement,
                 pin_auth,
                 new_pin_enc
             ),
             Ok(())
         );
+        let mut pin_hash = [0u8; 16];
+        pin_hash.copy_from_slice(&Sha256::hash(b"1234")[..16]);
+        assert_eq!(persistent_store.pin_hash().unwrap(), Some(pin_hash));
     }
 
     #[test]
@@ -851,6 +1184,7 @@ mod test {
             pin_protocol_v1.process_change_pin(
                 &mut rng,
                 &mut persistent_store,
+                PIN_PROTOCOL_V1,
                 key_agreement.clone(),
                 pin_auth.clone(),
                 new_pin_enc.clone(),
@@ -864,6 +1198,7 @@ mod test {
             pin_protocol_v1.process_change_pin(
                 &mut rng,
                 &mut persistent_store,
+                PIN_PROTOCOL_V1,
                 key_agreement.clone(),
                 pin_auth.clone(),
                 new_pin_enc.clone(),
@@ -879,6 +1214,7 @@ mod test {
             pin_protocol_v1.process_change_pin(
                 &mut rng,
                 &mut persistent_store,
+                PIN_PROTOCOL_V1,
                 key_agreement,
                 pin_auth,
                 new_pin_enc,
@@ -902,6 +1238,7 @@ mod test {
             .process_get_pin_token(
                 &mut rng,
                 &mut persistent_store,
+                PIN_PROTOCOL_V1,
                 key_agreement.clone(),
                 pin_hash_enc
             )
@@ -912,6 +1249,50 @@ mod test {
             pin_protocol_v1.process_get_pin_token(
                 &mut rng,
                 &mut persistent_store,
+                PIN_PROTOCOL_V1,
+                key_agreement,
+                pin_hash_enc
+            ),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_INVALID)
+        );
+    }
+
+    #[test]
+    fn test_process_get_pin_token_v2() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        set_standard_pin(&mut persistent_store);
+        let mut pin_protocol_v1 = PinProtocolV1::new(&mut rng);
+        let pk = pin_protocol_v1.key_agreement_key.genpk();
+        let shared_secret =
+            SharedSecret::new(&pin_protocol_v1.key_agreement_key, &pk, PIN_PROTOCOL_V2).unwrap();
+        let key_agreement = CoseKey::from(pk);
+        let mut pin = [0u8; 64];
+        pin[..4].copy_from_slice(b"1234");
+        let pin_hash_enc = shared_secret.encrypt(&mut rng, &Sha256::hash(&pin)[..16]);
+        let response = pin_protocol_v1
+            .process_get_pin_token(
+                &mut rng,
+                &mut persistent_store,
+                PIN_PROTOCOL_V2,
+                key_agreement.clone(),
+                pin_hash_enc,
+            )
+            .unwrap();
+        let pin_token_enc = response.pin_token.unwrap();
+        assert_eq!(pin_token_enc.len(), 16 + PIN_TOKEN_LENGTH);
+        assert_eq!(
+            shared_secret.decrypt(&pin_token_enc),
+            Some(pin_protocol_v1.pin_uv_auth_token.to_vec())
+        );
+
+        // The PIN hash encrypted for protocol 1 lacks the IV.
+        let pin_hash_enc = encrypt_standard_pin_hash(&[0x88; 32]);
+        assert_eq!(
+            pin_protocol_v1.process_get_pin_token(
+                &mut rng,
+                &mut persistent_store,
+                PIN_PROTOCOL_V2,
                 key_agreement,
                 pin_hash_enc
             ),
@@ -919,6 +1300,30 @@ mod test {
         );
     }
 
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_process_get_pin_token_force_pin_change() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        set_standard_pin(&mut persistent_store);
+        persistent_store.force_pin_change().unwrap();
+        let mut pin_protocol_v1 = PinProtocolV1::new(&mut rng);
+        let pk = pin_protocol_v1.key_agreement_key.genpk();
+        let shared_secret = pin_protocol_v1.key_agreement_key.exchange_x_sha256(&pk);
+        let key_agreement = CoseKey::from(pk);
+        let pin_hash_enc = encrypt_standard_pin_hash(&shared_secret);
+        assert_eq!(
+            pin_protocol_v1.process_get_pin_token(
+                &mut rng,
+                &mut persistent_store,
+                PIN_PROTOCOL_V1,
+                key_agreement,
+                pin_hash_enc
+            ),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_POLICY_VIOLATION)
+        );
+    }
+
     #[cfg(feature = "with_ctap2_1")]
     #[test]
     fn test_process_get_pin_uv_auth_token_using_pin_with_permissions() {
@@ -934,6 +1339,7 @@ mod test {
             .process_get_pin_uv_auth_token_using_pin_with_permissions(
                 &mut rng,
                 &mut persistent_store,
+                PIN_PROTOCOL_V1,
                 key_agreement.clone(),
                 pin_hash_enc.clone(),
                 0x03,
@@ -950,6 +1356,7 @@ mod test {
             pin_protocol_v1.process_get_pin_uv_auth_token_using_pin_with_permissions(
                 &mut rng,
                 &mut persistent_store,
+                PIN_PROTOCOL_V1,
                 key_agreement.clone(),
                 pin_hash_enc.clone(),
                 0x00,
@@ -962,6 +1369,7 @@ mod test {
             pin_protocol_v1.process_get_pin_uv_auth_token_using_pin_with_permissions(
                 &mut rng,
                 &mut persistent_store,
+                PIN_PROTOCOL_V1,
                 key_agreement.clone(),
                 pin_hash_enc.clone(),
                 0x03,
@@ -975,6 +1383,7 @@ mod test {
             pin_protocol_v1.process_get_pin_uv_auth_token_using_pin_with_permissions(
                 &mut rng,
                 &mut persistent_store,
+                PIN_PROTOCOL_V1,
                 key_agreement,
                 pin_hash_enc,
                 0x03,
@@ -984,6 +1393,96 @@ mod test {
         );
     }
 
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_process_get_pin_uv_auth_token_using_uv_with_permissions_no_uv() {
+        let mut rng = ThreadRng256 {};
+        let mut pin_protocol_v1 = PinProtocolV1::new(&mut rng);
+        let key_agreement = CoseKey::from(pin_protocol_v1.key_agreement_key.genpk());
+        assert_eq!(
+            pin_protocol_v1.process_get_pin_uv_auth_token_using_uv_with_permissions(
+                &mut rng,
+                PIN_PROTOCOL_V2,
+                key_agreement,
+                0x02,
+                Some(String::from("example.com")),
+            ),
+            Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)
+        );
+        assert_eq!(pin_protocol_v1.permissions, 0);
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_process_get_pin_uv_auth_token_using_uv_with_permissions() {
+        let mut rng = ThreadRng256 {};
+        let mut pin_protocol_v1 = PinProtocolV1::new(&mut rng);
+        let pk = pin_protocol_v1.key_agreement_key.genpk();
+        let shared_secret =
+            SharedSecret::new(&pin_protocol_v1.key_agreement_key, &pk, PIN_PROTOCOL_V2).unwrap();
+        let key_agreement = CoseKey::from(pk);
+
+        pin_protocol_v1.built_in_uv = Some(|| false);
+        assert_eq!(
+            pin_protocol_v1.process_get_pin_uv_auth_token_using_uv_with_permissions(
+                &mut rng,
+                PIN_PROTOCOL_V2,
+                key_agreement.clone(),
+                0x02,
+                Some(String::from("example.com")),
+            ),
+            Err(Ctap2StatusCode::CTAP2_ERR_UV_INVALID)
+        );
+        assert_eq!(pin_protocol_v1.permissions, 0);
+
+        pin_protocol_v1.built_in_uv = Some(|| true);
+        assert_eq!(
+            pin_protocol_v1.process_get_pin_uv_auth_token_using_uv_with_permissions(
+                &mut rng,
+                PIN_PROTOCOL_V2,
+                key_agreement.clone(),
+                0x02,
+                None,
+            ),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+        let response = pin_protocol_v1
+            .process_get_pin_uv_auth_token_using_uv_with_permissions(
+                &mut rng,
+                PIN_PROTOCOL_V2,
+                key_agreement,
+                0x02,
+                Some(String::from("example.com")),
+            )
+            .unwrap();
+        assert_eq!(
+            shared_secret.decrypt(&response.pin_token.unwrap()),
+            Some(pin_protocol_v1.pin_uv_auth_token.to_vec())
+        );
+
+        // The token is scoped to getAssertion on example.com.
+        assert_eq!(
+            pin_protocol_v1.has_permission(PinPermission::GetAssertion),
+            Ok(())
+        );
+        assert_eq!(
+            pin_protocol_v1.has_permission(PinPermission::MakeCredential),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+        );
+        assert_eq!(
+            pin_protocol_v1.has_permission(PinPermission::CredentialManagement),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+        );
+        assert_eq!(
+            pin_protocol_v1.has_permission_for_rp_id("example.com"),
+            Ok(())
+        );
+        assert_eq!(
+            pin_protocol_v1.has_permission_for_rp_id("counter-example.com"),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+        );
+    }
+
     #[cfg(feature = "with_ctap2_1")]
     #[test]
     fn test_process_set_min_pin_length() {
@@ -996,10 +1495,9 @@ mod test {
             0x94, 0x86, 0xEF, 0x4C, 0xB3, 0x84, 0x2C, 0x85, 0x72, 0x02, 0xBF, 0xE4, 0x36, 0x22,
             0xFE, 0xC9,
         ];
-        // TODO(kaczmarczyck) implement test for the min PIN length extension
-        // https://github.com/google/OpenSK/issues/129
         let response = pin_protocol_v1.process_set_min_pin_length(
             &mut persistent_store,
+            PIN_PROTOCOL_V1,
             min_pin_length,
             None,
             Some(pin_auth.clone()),
@@ -1008,6 +1506,7 @@ mod test {
         assert_eq!(persistent_store.min_pin_length().unwrap(), min_pin_length);
         let response = pin_protocol_v1.process_set_min_pin_length(
             &mut persistent_store,
+            PIN_PROTOCOL_V1,
             7,
             None,
             Some(pin_auth),
@@ -1017,6 +1516,30 @@ mod test {
             Err(Ctap2StatusCode::CTAP2_ERR_PIN_POLICY_VIOLATION)
         );
         assert_eq!(persistent_store.min_pin_length().unwrap(), min_pin_length);
+
+        // The RP IDs are part of the authenticated message.
+        let min_pin_length_rp_ids = vec![String::from("example.com")];
+        let mut message = vec![0xFF; 32];
+        message.extend(&[0x06, 0x08, min_pin_length, 0x00, 0x00, 0x00]);
+        assert!(cbor::write(
+            cbor_array_vec!(min_pin_length_rp_ids.clone()),
+            &mut message
+        ));
+        let pin_auth = hmac_256::<Sha256>(&pin_protocol_v1.pin_uv_auth_token, &message)
+            [..PIN_AUTH_LENGTH]
+            .to_vec();
+        let response = pin_protocol_v1.process_set_min_pin_length(
+            &mut persistent_store,
+            PIN_PROTOCOL_V1,
+            min_pin_length,
+            Some(min_pin_length_rp_ids.clone()),
+            Some(pin_auth),
+        );
+        assert_eq!(response, Ok(()));
+        assert_eq!(
+            persistent_store.min_pin_length_rp_ids().unwrap(),
+            min_pin_length_rp_ids
+        );
     }
 
     #[test]
@@ -1045,7 +1568,7 @@ mod test {
             .is_ok());
 
         let client_pin_params = AuthenticatorClientPinParameters {
-            pin_protocol: 2,
+            pin_protocol: 3,
             sub_command: ClientPinSubCommand::GetPinRetries,
             key_agreement: None,
             pin_auth: None,
@@ -1073,8 +1596,7 @@ mod test {
     #[test]
     fn test_decrypt_pin() {
         let shared_secret = [0x88; 32];
-        let aes_enc_key = crypto::aes256::EncryptionKey::new(&shared_secret);
-        let aes_dec_key = crypto::aes256::DecryptionKey::new(&aes_enc_key);
+        let shared_secret_v1 = SharedSecret::V1(shared_secret);
 
         // "1234"
         let new_pin_enc = vec![
@@ -1085,7 +1607,7 @@ mod test {
             0x18, 0x35, 0x06, 0x66, 0x97, 0x84, 0x68, 0xC2,
         ];
         assert_eq!(
-            decrypt_pin(&aes_dec_key, new_pin_enc),
+            decrypt_pin(&shared_secret_v1, new_pin_enc),
             Some(b"1234".to_vec()),
         );
 
@@ -1098,17 +1620,17 @@ mod test {
             0x7C, 0xC7, 0x2D, 0x43, 0x74, 0x4C, 0x1D, 0x7E,
         ];
         assert_eq!(
-            decrypt_pin(&aes_dec_key, new_pin_enc),
+            decrypt_pin(&shared_secret_v1, new_pin_enc),
             Some(b"123".to_vec()),
         );
 
         // Encrypted PIN is too short.
         let new_pin_enc = vec![0x44; 63];
-        assert_eq!(decrypt_pin(&aes_dec_key, new_pin_enc), None,);
+        assert_eq!(decrypt_pin(&shared_secret_v1, new_pin_enc), None,);
 
         // Encrypted PIN is too long.
         let new_pin_enc = vec![0x44; 65];
-        assert_eq!(decrypt_pin(&aes_dec_key, new_pin_enc), None,);
+        assert_eq!(decrypt_pin(&shared_secret_v1, new_pin_enc), None,);
     }
 
     #[test]
@@ -1116,8 +1638,7 @@ mod test {
         let mut rng = ThreadRng256 {};
         let mut persistent_store = PersistentStore::new(&mut rng);
         let shared_secret = [0x88; 32];
-        let aes_enc_key = crypto::aes256::EncryptionKey::new(&shared_secret);
-        let aes_dec_key = crypto::aes256::DecryptionKey::new(&aes_enc_key);
+        let shared_secret_v1 = SharedSecret::V1(shared_secret);
 
         let test_cases = vec![
             // Accept PIN "1234".
@@ -1142,7 +1663,7 @@ mod test {
             let old_pin_hash = persistent_store.pin_hash().unwrap();
             let new_pin_enc = encrypt_pin(&shared_secret, pin);
             assert_eq!(
-                check_and_store_new_pin(&mut persistent_store, &aes_dec_key, new_pin_enc),
+                check_and_store_new_pin(&mut persistent_store, &shared_secret_v1, new_pin_enc),
                 result
             );
             if result.is_ok() {
@@ -1153,6 +1674,30 @@ mod test {
         }
     }
 
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_check_and_store_new_pin_min_pin_length() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        persistent_store.set_min_pin_length(6).unwrap();
+        let shared_secret = [0x88; 32];
+        let shared_secret_v1 = SharedSecret::V1(shared_secret);
+
+        let new_pin_enc = encrypt_pin(&shared_secret, b"12345".to_vec());
+        assert_eq!(
+            check_and_store_new_pin(&mut persistent_store, &shared_secret_v1, new_pin_enc),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_POLICY_VIOLATION)
+        );
+        assert!(persistent_store.pin_hash().unwrap().is_none());
+
+        let new_pin_enc = encrypt_pin(&shared_secret, b"123456".to_vec());
+        assert_eq!(
+            check_and_store_new_pin(&mut persistent_store, &shared_secret_v1, new_pin_enc),
+            Ok(())
+        );
+        assert!(persistent_store.pin_hash().unwrap().is_some());
+    }
+
     #[test]
     fn test_verify_pin_auth() {
         let hmac_key = [0x88; 16];
@@ -1160,27 +1705,60 @@ mod test {
             0x88, 0x09, 0x41, 0x13, 0xF7, 0x97, 0x32, 0x0B, 0x3E, 0xD9, 0xBC, 0x76, 0x4F, 0x18,
             0x56, 0x5D,
         ];
-        assert!(verify_pin_auth(&hmac_key, &[], &pin_auth));
-        assert!(!verify_pin_auth(&hmac_key, &[0x00], &pin_auth));
+        assert!(verify_pin_auth(&hmac_key, &[], &pin_auth, PIN_PROTOCOL_V1));
+        assert!(!verify_pin_auth(
+            &hmac_key,
+            &[0x00],
+            &pin_auth,
+            PIN_PROTOCOL_V1
+        ));
+        assert!(!verify_pin_auth(&hmac_key, &[], &pin_auth, PIN_PROTOCOL_V2));
+
+        let pin_auth_v2 = hmac_256::<Sha256>(&hmac_key, &[]);
+        assert_eq!(pin_auth_v2[..16], pin_auth);
+        assert!(verify_pin_auth(
+            &hmac_key,
+            &[],
+            &pin_auth_v2,
+            PIN_PROTOCOL_V2
+        ));
+        assert!(!verify_pin_auth(
+            &hmac_key,
+            &[0x00],
+            &pin_auth_v2,
+            PIN_PROTOCOL_V2
+        ));
+        assert!(!verify_pin_auth(
+            &hmac_key,
+            &[],
+            &pin_auth_v2,
+            PIN_PROTOCOL_V1
+        ));
+        assert!(!verify_pin_auth(&hmac_key, &[], &pin_auth_v2, 3));
     }
 
     #[test]
     fn test_encrypt_hmac_secret_output() {
+        let mut rng = ThreadRng256 {};
         let shared_secret = [0x55; 32];
+        let shared_secret_v1 = SharedSecret::V1(shared_secret);
         let salt_enc = [0x5E; 32];
         let cred_random = [0xC9; 32];
-        let output = encrypt_hmac_secret_output(&shared_secret, &salt_enc, &cred_random);
+        let output =
+            encrypt_hmac_secret_output(&mut rng, &shared_secret_v1, &salt_enc, &cred_random);
         assert_eq!(output.unwrap().len(), 32);
 
         let salt_enc = [0x5E; 48];
-        let output = encrypt_hmac_secret_output(&shared_secret, &salt_enc, &cred_random);
+        let output =
+            encrypt_hmac_secret_output(&mut rng, &shared_secret_v1, &salt_enc, &cred_random);
         assert_eq!(
             output,
             Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_EXTENSION)
         );
 
         let salt_enc = [0x5E; 64];
-        let output = encrypt_hmac_secret_output(&shared_secret, &salt_enc, &cred_random);
+        let output =
+            encrypt_hmac_secret_output(&mut rng, &shared_secret_v1, &salt_enc, &cred_random);
         assert_eq!(output.unwrap().len(), 64);
 
         let mut salt_enc = [0x00; 32];
@@ -1194,13 +1772,17 @@ mod test {
 
         let salt_enc1 = encrypt_message(&shared_secret, &salt1);
         salt_enc.copy_from_slice(salt_enc1.as_slice());
-        let output = encrypt_hmac_secret_output(&shared_secret, &salt_enc, &cred_random).unwrap();
+        let output =
+            encrypt_hmac_secret_output(&mut rng, &shared_secret_v1, &salt_enc, &cred_random)
+                .unwrap();
         let output_dec = decrypt_message(&shared_secret, &output);
         assert_eq!(&output_dec, &expected_output1);
 
         let salt_enc2 = &encrypt_message(&shared_secret, &salt2);
         salt_enc.copy_from_slice(salt_enc2.as_slice());
-        let output = encrypt_hmac_secret_output(&shared_secret, &salt_enc, &cred_random).unwrap();
+        let output =
+            encrypt_hmac_secret_output(&mut rng, &shared_secret_v1, &salt_enc, &cred_random)
+                .unwrap();
         let output_dec = decrypt_message(&shared_secret, &output);
         assert_eq!(&output_dec, &expected_output2);
 
@@ -1210,7 +1792,9 @@ mod test {
         salt12[32..].copy_from_slice(&salt2);
         let salt_enc12 = encrypt_message(&shared_secret, &salt12);
         salt_enc.copy_from_slice(salt_enc12.as_slice());
-        let output = encrypt_hmac_secret_output(&shared_secret, &salt_enc, &cred_random).unwrap();
+        let output =
+            encrypt_hmac_secret_output(&mut rng, &shared_secret_v1, &salt_enc, &cred_random)
+                .unwrap();
         let output_dec = decrypt_message(&shared_secret, &output);
         assert_eq!(&output_dec[..32], &expected_output1);
         assert_eq!(&output_dec[32..], &expected_output2);
@@ -1220,7 +1804,9 @@ mod test {
         salt02[32..].copy_from_slice(&salt2);
         let salt_enc02 = encrypt_message(&shared_secret, &salt02);
         salt_enc.copy_from_slice(salt_enc02.as_slice());
-        let output = encrypt_hmac_secret_output(&shared_secret, &salt_enc, &cred_random).unwrap();
+        let output =
+            encrypt_hmac_secret_output(&mut rng, &shared_secret_v1, &salt_enc, &cred_random)
+                .unwrap();
         let output_dec = decrypt_message(&shared_secret, &output);
         assert_eq!(&output_dec[32..], &expected_output2);
 
@@ -1229,11 +1815,53 @@ mod test {
         salt10[..32].copy_from_slice(&salt1);
         let salt_enc10 = encrypt_message(&shared_secret, &salt10);
         salt_enc.copy_from_slice(salt_enc10.as_slice());
-        let output = encrypt_hmac_secret_output(&shared_secret, &salt_enc, &cred_random).unwrap();
+        let output =
+            encrypt_hmac_secret_output(&mut rng, &shared_secret_v1, &salt_enc, &cred_random)
+                .unwrap();
         let output_dec = decrypt_message(&shared_secret, &output);
         assert_eq!(&output_dec[..32], &expected_output1);
     }
 
+    #[test]
+    fn test_encrypt_hmac_secret_output_v2() {
+        let mut rng = ThreadRng256 {};
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let pk = key_agreement_key.genpk();
+        let shared_secret = SharedSecret::new(&key_agreement_key, &pk, PIN_PROTOCOL_V2).unwrap();
+        let cred_random = [0xC9; 32];
+        let salt1 = [0x01; 32];
+        let salt2 = [0x02; 32];
+        let expected_output1 = hmac_256::<Sha256>(&cred_random, &salt1);
+        let expected_output2 = hmac_256::<Sha256>(&cred_random, &salt2);
+
+        let salt_enc = shared_secret.encrypt(&mut rng, &salt1);
+        let output =
+            encrypt_hmac_secret_output(&mut rng, &shared_secret, &salt_enc, &cred_random).unwrap();
+        assert_eq!(output.len(), 48);
+        assert_eq!(
+            shared_secret.decrypt(&output),
+            Some(expected_output1.to_vec())
+        );
+
+        let mut salt12 = salt1.to_vec();
+        salt12.extend(&salt2);
+        let salt_enc = shared_secret.encrypt(&mut rng, &salt12);
+        let output =
+            encrypt_hmac_secret_output(&mut rng, &shared_secret, &salt_enc, &cred_random).unwrap();
+        assert_eq!(output.len(), 80);
+        let output_dec = shared_secret.decrypt(&output).unwrap();
+        assert_eq!(&output_dec[..32], &expected_output1);
+        assert_eq!(&output_dec[32..], &expected_output2);
+
+        // Without its IV, the encrypted salt is too short.
+        let output =
+            encrypt_hmac_secret_output(&mut rng, &shared_secret, &salt_enc[16..], &cred_random);
+        assert_eq!(
+            output,
+            Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_EXTENSION)
+        );
+    }
+
     #[cfg(feature = "with_ctap2_1")]
     #[test]
     fn test_has_permission() {
@@ -1274,4 +1902,38 @@ mod test {
             Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
         );
     }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_has_no_rp_id_permission() {
+        let mut rng = ThreadRng256 {};
+        let mut pin_protocol_v1 = PinProtocolV1::new(&mut rng);
+        assert_eq!(pin_protocol_v1.has_no_rp_id_permission(), Ok(()));
+        pin_protocol_v1.permissions_rp_id = Some(String::from("example.com"));
+        assert_eq!(
+            pin_protocol_v1.has_no_rp_id_permission(),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+        );
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_has_no_or_rp_id_permission() {
+        let mut rng = ThreadRng256 {};
+        let mut pin_protocol_v1 = PinProtocolV1::new(&mut rng);
+        assert_eq!(
+            pin_protocol_v1.has_no_or_rp_id_permission("example.com"),
+            Ok(())
+        );
+        assert_eq!(pin_protocol_v1.permissions_rp_id, None);
+        pin_protocol_v1.permissions_rp_id = Some(String::from("example.com"));
+        assert_eq!(
+            pin_protocol_v1.has_no_or_rp_id_permission("example.com"),
+            Ok(())
+        );
+        assert_eq!(
+            pin_protocol_v1.has_no_or_rp_id_permission("counter-example.com"),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+        );
+    }
 }
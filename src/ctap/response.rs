@@ -22,6 +22,7 @@ use alloc::collections::BTreeMap;
 use alloc::string::String;
 use alloc::vec::Vec;
 use cbor::{cbor_array_vec, cbor_bool, cbor_map_btree, cbor_map_options, cbor_text};
+use libtock_drivers::crp::ProtectionLevel;
 
 #[cfg_attr(test, derive(PartialEq))]
 #[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
@@ -33,8 +34,21 @@ pub enum ResponseData {
     AuthenticatorClientPin(Option<AuthenticatorClientPinResponse>),
     AuthenticatorReset,
     #[cfg(feature = "with_ctap2_1")]
+    AuthenticatorCredentialManagement(Option<AuthenticatorCredentialManagementResponse>),
+    #[cfg(feature = "with_ctap2_1")]
     AuthenticatorSelection,
+    #[cfg(feature = "with_ctap2_1")]
+    AuthenticatorLargeBlobs(Option<AuthenticatorLargeBlobsResponse>),
+    #[cfg(feature = "with_ctap2_1")]
+    AuthenticatorConfig,
     AuthenticatorVendor(AuthenticatorVendorResponse),
+    AuthenticatorVendorFactoryReset,
+    AuthenticatorVendorRpIdPolicy,
+    AuthenticatorVendorStoreHealth(AuthenticatorVendorStoreHealthResponse),
+    #[cfg(feature = "with_ctap2_1")]
+    AuthenticatorVendorCredentialBackup(AuthenticatorVendorCredentialBackupResponse),
+    #[cfg(feature = "with_ctap2_1")]
+    AuthenticatorVendorResetBootCount,
 }
 
 impl From<ResponseData> for Option<cbor::Value> {
@@ -48,8 +62,21 @@ impl From<ResponseData> for Option<cbor::Value> {
             ResponseData::AuthenticatorClientPin(None) => None,
             ResponseData::AuthenticatorReset => None,
             #[cfg(feature = "with_ctap2_1")]
+            ResponseData::AuthenticatorCredentialManagement(data) => data.map(|d| d.into()),
+            #[cfg(feature = "with_ctap2_1")]
             ResponseData::AuthenticatorSelection => None,
+            #[cfg(feature = "with_ctap2_1")]
+            ResponseData::AuthenticatorLargeBlobs(data) => data.map(|d| d.into()),
+            #[cfg(feature = "with_ctap2_1")]
+            ResponseData::AuthenticatorConfig => None,
             ResponseData::AuthenticatorVendor(data) => Some(data.into()),
+            ResponseData::AuthenticatorVendorFactoryReset => None,
+            ResponseData::AuthenticatorVendorRpIdPolicy => None,
+            ResponseData::AuthenticatorVendorStoreHealth(data) => Some(data.into()),
+            #[cfg(feature = "with_ctap2_1")]
+            ResponseData::AuthenticatorVendorCredentialBackup(data) => Some(data.into()),
+            #[cfg(feature = "with_ctap2_1")]
+            ResponseData::AuthenticatorVendorResetBootCount => None,
         }
     }
 }
@@ -60,6 +87,8 @@ pub struct AuthenticatorMakeCredentialResponse {
     pub fmt: String,
     pub auth_data: Vec<u8>,
     pub att_stmt: PackedAttestationStatement,
+    pub ep_att: Option<bool>,
+    pub large_blob_key: Option<Vec<u8>>,
 }
 
 impl From<AuthenticatorMakeCredentialResponse> for cbor::Value {
@@ -68,12 +97,16 @@ impl From<AuthenticatorMakeCredentialResponse> for cbor::Value {
             fmt,
             auth_data,
             att_stmt,
+            ep_att,
+            large_blob_key,
         } = make_credential_response;
 
         cbor_map_options! {
             1 => fmt,
             2 => auth_data,
             3 => att_stmt,
+            4 => ep_att,
+            5 => large_blob_key,
         }
     }
 }
@@ -86,6 +119,7 @@ pub struct AuthenticatorGetAssertionResponse {
     pub signature: Vec<u8>,
     pub user: Option<PublicKeyCredentialUserEntity>,
     pub number_of_credentials: Option<u64>,
+    pub large_blob_key: Option<Vec<u8>>,
 }
 
 impl From<AuthenticatorGetAssertionResponse> for cbor::Value {
@@ -96,6 +130,7 @@ impl From<AuthenticatorGetAssertionResponse> for cbor::Value {
             signature,
             user,
             number_of_credentials,
+            large_blob_key,
         } = get_assertion_response;
 
         cbor_map_options! {
@@ -104,6 +139,7 @@ impl From<AuthenticatorGetAssertionResponse> for cbor::Value {
             3 => signature,
             4 => user,
             5 => number_of_credentials,
+            7 => large_blob_key,
         }
     }
 }
@@ -126,11 +162,17 @@ pub struct AuthenticatorGetInfoResponse {
     pub transports: Option<Vec<AuthenticatorTransport>>,
     #[cfg(feature = "with_ctap2_1")]
     pub algorithms: Option<Vec<PublicKeyCredentialParameter>>,
+    #[cfg(feature = "with_ctap2_1")]
+    pub max_serialized_large_blob_array: Option<u64>,
     pub default_cred_protect: Option<CredentialProtectionPolicy>,
     #[cfg(feature = "with_ctap2_1")]
     pub min_pin_length: u8,
     #[cfg(feature = "with_ctap2_1")]
     pub firmware_version: Option<u64>,
+    #[cfg(feature = "with_ctap2_1")]
+    pub max_cred_blob_length: Option<u64>,
+    #[cfg(feature = "with_ctap2_1")]
+    pub remaining_discoverable_credentials: Option<u64>,
 }
 
 impl From<AuthenticatorGetInfoResponse> for cbor::Value {
@@ -147,9 +189,12 @@ impl From<AuthenticatorGetInfoResponse> for cbor::Value {
             max_credential_id_length,
             transports,
             algorithms,
+            max_serialized_large_blob_array,
             default_cred_protect,
             min_pin_length,
             firmware_version,
+            max_cred_blob_length,
+            remaining_discoverable_credentials,
         } = get_info_response;
 
         let options_cbor: Option<cbor::Value> = options.map(|options| {
@@ -171,9 +216,12 @@ impl From<AuthenticatorGetInfoResponse> for cbor::Value {
             0x08 => max_credential_id_length,
             0x09 => transports.map(|vec| cbor_array_vec!(vec)),
             0x0A => algorithms.map(|vec| cbor_array_vec!(vec)),
+            0x0B => max_serialized_large_blob_array,
             0x0C => default_cred_protect.map(|p| p as u64),
             0x0D => min_pin_length as u64,
             0x0E => firmware_version,
+            0x0F => max_cred_blob_length,
+            0x14 => remaining_discoverable_credentials,
         }
     }
 
@@ -233,11 +281,54 @@ impl From<AuthenticatorClientPinResponse> for cbor::Value {
     }
 }
 
+#[cfg(feature = "with_ctap2_1")]
+#[derive(Default)]
+#[cfg_attr(test, derive(PartialEq))]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
+pub struct AuthenticatorCredentialManagementResponse {
+    pub existing_resident_credentials_count: Option<u64>,
+    pub max_possible_remaining_resident_credentials_count: Option<u64>,
+}
+
+#[cfg(feature = "with_ctap2_1")]
+impl From<AuthenticatorCredentialManagementResponse> for cbor::Value {
+    fn from(cred_management_response: AuthenticatorCredentialManagementResponse) -> Self {
+        let AuthenticatorCredentialManagementResponse {
+            existing_resident_credentials_count,
+            max_possible_remaining_resident_credentials_count,
+        } = cred_management_response;
+
+        cbor_map_options! {
+            0x01 => existing_resident_credentials_count,
+            0x02 => max_possible_remaining_resident_credentials_count,
+        }
+    }
+}
+
+#[cfg(feature = "with_ctap2_1")]
+#[cfg_attr(test, derive(PartialEq))]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
+pub struct AuthenticatorLargeBlobsResponse {
+    pub config: Vec<u8>,
+}
+
+#[cfg(feature = "with_ctap2_1")]
+impl From<AuthenticatorLargeBlobsResponse> for cbor::Value {
+    fn from(large_blobs_response: AuthenticatorLargeBlobsResponse) -> Self {
+        let AuthenticatorLargeBlobsResponse { config } = large_blobs_response;
+
+        cbor_map_options! {
+            0x01 => config,
+        }
+    }
+}
+
 #[cfg_attr(test, derive(PartialEq))]
 #[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
 pub struct AuthenticatorVendorResponse {
     pub cert_programmed: bool,
     pub pkey_programmed: bool,
+    pub protection_level: Option<ProtectionLevel>,
 }
 
 impl From<AuthenticatorVendorResponse> for cbor::Value {
@@ -245,11 +336,69 @@ impl From<AuthenticatorVendorResponse> for cbor::Value {
         let AuthenticatorVendorResponse {
             cert_programmed,
             pkey_programmed,
+            protection_level,
         } = vendor_response;
 
         cbor_map_options! {
             1 => cert_programmed,
             2 => pkey_programmed,
+            3 => protection_level.map(|level| level as u64),
+        }
+    }
+}
+
+/// Anonymized store statistics, that never contain credential content.
+#[cfg_attr(test, derive(PartialEq))]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
+pub struct AuthenticatorVendorStoreHealthResponse {
+    pub firmware_version: String,
+    // There is no real time clock, so boots are counted instead of timestamped.
+    pub boot_count: u64,
+    pub num_credentials: u64,
+    pub free_space_percent: u64,
+    // Estimated share of the flash erase cycles already used.
+    pub lifetime_used_permille: u64,
+}
+
+impl From<AuthenticatorVendorStoreHealthResponse> for cbor::Value {
+    fn from(store_health_response: AuthenticatorVendorStoreHealthResponse) -> Self {
+        let AuthenticatorVendorStoreHealthResponse {
+            firmware_version,
+            boot_count,
+            num_credentials,
+            free_space_percent,
+            lifetime_used_permille,
+        } = store_health_response;
+
+        cbor_map_options! {
+            1 => firmware_version,
+            2 => boot_count,
+            3 => num_credentials,
+            4 => free_space_percent,
+            5 => lifetime_used_permille,
+        }
+    }
+}
+
+#[cfg(feature = "with_ctap2_1")]
+#[cfg_attr(test, derive(PartialEq))]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
+pub struct AuthenticatorVendorCredentialBackupResponse {
+    pub bundle: Option<Vec<u8>>,
+    pub num_imported: Option<u64>,
+}
+
+#[cfg(feature = "with_ctap2_1")]
+impl From<AuthenticatorVendorCredentialBackupResponse> for cbor::Value {
+    fn from(backup_response: AuthenticatorVendorCredentialBackupResponse) -> Self {
+        let AuthenticatorVendorCredentialBackupResponse {
+            bundle,
+            num_imported,
+        } = backup_response;
+
+        cbor_map_options! {
+            1 => bundle,
+            2 => num_imported,
         }
     }
 }
@@ -282,6 +431,8 @@ mod test {
             fmt: "packed".to_string(),
             auth_data: vec![0xAD],
             att_stmt,
+            ep_att: Some(true),
+            large_blob_key: Some(vec![0x1B]),
         };
         let response_cbor: Option<cbor::Value> =
             ResponseData::AuthenticatorMakeCredential(make_credential_response).into();
@@ -289,6 +440,8 @@ mod test {
             1 => "packed",
             2 => vec![0xAD],
             3 => cbor_packed_attestation_statement,
+            4 => true,
+            5 => vec![0x1B],
         };
         assert_eq!(response_cbor, Some(expected_cbor));
     }
@@ -301,6 +454,7 @@ mod test {
             signature: vec![0x51],
             user: None,
             number_of_credentials: None,
+            large_blob_key: None,
         };
         let response_cbor: Option<cbor::Value> =
             ResponseData::AuthenticatorGetAssertion(get_assertion_response).into();
@@ -329,11 +483,17 @@ mod test {
             transports: None,
             #[cfg(feature = "with_ctap2_1")]
             algorithms: None,
+            #[cfg(feature = "with_ctap2_1")]
+            max_serialized_large_blob_array: None,
             default_cred_protect: None,
             #[cfg(feature = "with_ctap2_1")]
             min_pin_length: 4,
             #[cfg(feature = "with_ctap2_1")]
             firmware_version: None,
+            #[cfg(feature = "with_ctap2_1")]
+            max_cred_blob_length: None,
+            #[cfg(feature = "with_ctap2_1")]
+            remaining_discoverable_credentials: None,
         };
         let response_cbor: Option<cbor::Value> =
             ResponseData::AuthenticatorGetInfo(get_info_response).into();
@@ -367,9 +527,12 @@ mod test {
             max_credential_id_length: Some(256),
             transports: Some(vec![AuthenticatorTransport::Usb]),
             algorithms: Some(vec![ES256_CRED_PARAM]),
+            max_serialized_large_blob_array: Some(1024),
             default_cred_protect: Some(CredentialProtectionPolicy::UserVerificationRequired),
             min_pin_length: 4,
             firmware_version: Some(0),
+            max_cred_blob_length: Some(32),
+            remaining_discoverable_credentials: Some(150),
         };
         let response_cbor: Option<cbor::Value> =
             ResponseData::AuthenticatorGetInfo(get_info_response).into();
@@ -384,9 +547,12 @@ mod test {
             0x08 => 256,
             0x09 => cbor_array_vec![vec!["usb"]],
             0x0A => cbor_array_vec![vec![ES256_CRED_PARAM]],
+            0x0B => 1024,
             0x0C => CredentialProtectionPolicy::UserVerificationRequired as u64,
             0x0D => 4,
             0x0E => 0,
+            0x0F => 32,
+            0x14 => 150,
         };
         assert_eq!(response_cbor, Some(expected_cbor));
     }
@@ -418,6 +584,25 @@ mod test {
         assert_eq!(response_cbor, None);
     }
 
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_credential_management_into_cbor() {
+        let cred_management_response = AuthenticatorCredentialManagementResponse {
+            existing_resident_credentials_count: Some(2),
+            max_possible_remaining_resident_credentials_count: Some(148),
+        };
+        let response_cbor: Option<cbor::Value> =
+            ResponseData::AuthenticatorCredentialManagement(Some(cred_management_response)).into();
+        let expected_cbor = cbor_map_options! {
+            0x01 => 2,
+            0x02 => 148,
+        };
+        assert_eq!(response_cbor, Some(expected_cbor));
+        let response_cbor: Option<cbor::Value> =
+            ResponseData::AuthenticatorCredentialManagement(None).into();
+        assert_eq!(response_cbor, None);
+    }
+
     #[cfg(feature = "with_ctap2_1")]
     #[test]
     fn test_selection_into_cbor() {
@@ -425,12 +610,36 @@ mod test {
         assert_eq!(response_cbor, None);
     }
 
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_large_blobs_into_cbor() {
+        let large_blobs_response = AuthenticatorLargeBlobsResponse {
+            config: vec![0xC0; 16],
+        };
+        let response_cbor: Option<cbor::Value> =
+            ResponseData::AuthenticatorLargeBlobs(Some(large_blobs_response)).into();
+        let expected_cbor = cbor_map_options! {
+            0x01 => vec![0xC0; 16],
+        };
+        assert_eq!(response_cbor, Some(expected_cbor));
+        let response_cbor: Option<cbor::Value> = ResponseData::AuthenticatorLargeBlobs(None).into();
+        assert_eq!(response_cbor, None);
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_config_into_cbor() {
+        let response_cbor: Option<cbor::Value> = ResponseData::AuthenticatorConfig.into();
+        assert_eq!(response_cbor, None);
+    }
+
     #[test]
     fn test_vendor_response_into_cbor() {
         let response_cbor: Option<cbor::Value> =
             ResponseData::AuthenticatorVendor(AuthenticatorVendorResponse {
                 cert_programmed: true,
                 pkey_programmed: false,
+                protection_level: None,
             })
             .into();
         assert_eq!(
@@ -444,6 +653,7 @@ mod test {
             ResponseData::AuthenticatorVendor(AuthenticatorVendorResponse {
                 cert_programmed: false,
                 pkey_programmed: true,
+                protection_level: Some(ProtectionLevel::FullyLocked),
             })
             .into();
         assert_eq!(
@@ -451,6 +661,61 @@ mod test {
             Some(cbor_map_options! {
                 1 => false,
                 2 => true,
+                3 => 0xFF,
+            })
+        );
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_vendor_credential_backup_into_cbor() {
+        let response_cbor: Option<cbor::Value> = ResponseData::AuthenticatorVendorCredentialBackup(
+            AuthenticatorVendorCredentialBackupResponse {
+                bundle: Some(vec![0xB0, 0x01]),
+                num_imported: None,
+            },
+        )
+        .into();
+        assert_eq!(
+            response_cbor,
+            Some(cbor_map_options! {
+                1 => vec![0xB0, 0x01],
+            })
+        );
+        let response_cbor: Option<cbor::Value> = ResponseData::AuthenticatorVendorCredentialBackup(
+            AuthenticatorVendorCredentialBackupResponse {
+                bundle: None,
+                num_imported: Some(3),
+            },
+        )
+        .into();
+        assert_eq!(
+            response_cbor,
+            Some(cbor_map_options! {
+                2 => 3,
+            })
+        );
+    }
+
+    #[test]
+    fn test_vendor_store_health_into_cbor() {
+        let response_cbor: Option<cbor::Value> =
+            ResponseData::AuthenticatorVendorStoreHealth(AuthenticatorVendorStoreHealthResponse {
+                firmware_version: String::from("1.0.0"),
+                boot_count: 3,
+                num_credentials: 2,
+                free_space_percent: 95,
+                lifetime_used_permille: 12,
+            })
+            .into();
+        assert_eq!(
+            response_cbor,
+            Some(cbor_map_options! {
+                1 => "1.0.0",
+                2 => 3,
+                3 => 2,
+                4 => 95,
+                5 => 12,
             })
         );
     }
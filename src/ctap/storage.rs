@@ -31,6 +31,7 @@ use arrayref::array_ref;
 use cbor::cbor_array_vec;
 use core::convert::TryInto;
 use crypto::rng256::Rng256;
+use persistent_store::StoreUpdate;
 
 // Those constants may be modified before compilation to tune the behavior of the key.
 //
@@ -52,17 +53,40 @@ use crypto::rng256::Rng256;
 // With P=20 and K=150, we have I=2M which is enough for 500 increments per day for 10 years.
 const NUM_PAGES: usize = 20;
 const MAX_SUPPORTED_RESIDENTIAL_KEYS: usize = 150;
+// The maximum size of a residential key in bytes (S above).
+#[cfg(feature = "with_ctap2_1")]
+const MAX_CREDENTIAL_SIZE: usize = 500;
 
 const MAX_PIN_RETRIES: u8 = 8;
+/// Length of the CRC32 appended to attestation entries.
+const CHECKSUM_LENGTH: usize = 4;
+// Entries that a factory reset keeps once the attestation is locked.
+const LOCKED_ATTESTATION_KEYS: &[usize] = &[
+    key::AAGUID,
+    key::ATTESTATION_PRIVATE_KEY,
+    key::ATTESTATION_CERTIFICATE,
+    key::ATTESTATION_LOCKED,
+];
 #[cfg(feature = "with_ctap2_1")]
 const DEFAULT_MIN_PIN_LENGTH: u8 = 4;
-// TODO(kaczmarczyck) use this for the minPinLength extension
-// https://github.com/google/OpenSK/issues/129
+// RP IDs that are always allowed to read the minimum PIN length with the minPinLength extension.
 #[cfg(feature = "with_ctap2_1")]
-const _DEFAULT_MIN_PIN_LENGTH_RP_IDS: Vec<String> = Vec::new();
+const DEFAULT_MIN_PIN_LENGTH_RP_IDS: Vec<String> = Vec::new();
 // TODO(kaczmarczyck) Check whether this constant is necessary, or replace it accordingly.
 #[cfg(feature = "with_ctap2_1")]
-const _MAX_RP_IDS_LENGTH: usize = 8;
+const MAX_RP_IDS_LENGTH: usize = 8;
+// Maximum number of RP IDs in the RP ID policy.
+pub const MAX_RP_ID_POLICY_LENGTH: usize = 16;
+// Maximum size of the serialized large blob array, including its checksum. CTAP 2.1 requires at
+// least 1024 bytes. The array must fit in the LARGE_BLOB_SHARDS entries of the store.
+#[cfg(feature = "with_ctap2_1")]
+pub const MAX_LARGE_BLOB_ARRAY_SIZE: usize = 2048;
+// The initial large blob array: the empty CBOR array followed by the first 16 bytes of its SHA256.
+#[cfg(feature = "with_ctap2_1")]
+const EMPTY_LARGE_BLOB_ARRAY: [u8; 17] = [
+    0x80, 0x76, 0xBE, 0x8B, 0x52, 0x8D, 0x00, 0x75, 0xF7, 0xAA, 0xE9, 0x8D, 0x6F, 0xA5, 0x7A, 0x6D,
+    0x3C,
+];
 
 /// Wrapper for master keys.
 pub struct MasterKeys {
@@ -73,6 +97,33 @@ pub struct MasterKeys {
     pub hmac: [u8; 32],
 }
 
+/// Wrapper for attestation material.
+pub struct AttestationMaterial {
+    /// Attestation private key.
+    pub private_key: [u8; key_material::ATTESTATION_PRIVATE_KEY_LENGTH],
+
+    /// Attestation certificate.
+    pub certificate: Vec<u8>,
+}
+
+/// Relying parties allowed to create credentials, identified by the SHA256 hash of their RP ID.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RpIdPolicy {
+    /// Only the listed relying parties are allowed.
+    Allow(Vec<[u8; 32]>),
+    /// All relying parties except the listed ones are allowed.
+    Deny(Vec<[u8; 32]>),
+}
+
+impl RpIdPolicy {
+    pub fn is_allowed(&self, rp_id_hash: &[u8; 32]) -> bool {
+        match self {
+            RpIdPolicy::Allow(rp_id_hashes) => rp_id_hashes.contains(rp_id_hash),
+            RpIdPolicy::Deny(rp_id_hashes) => !rp_id_hashes.contains(rp_id_hash),
+        }
+    }
+}
+
 /// CTAP persistent storage.
 pub struct PersistentStore {
     store: persistent_store::Store<Storage>,
@@ -84,13 +135,29 @@ impl PersistentStore {
     /// # Safety
     ///
     /// This should be at most one instance of persistent store per program lifetime.
+    #[cfg(test)]
     pub fn new(rng: &mut impl Rng256) -> PersistentStore {
-        let storage = new_storage(NUM_PAGES);
+        PersistentStore::try_new(rng).unwrap()
+    }
+
+    /// Gives access to the persistent store, or returns why the storage can't be used.
+    ///
+    /// # Safety
+    ///
+    /// This should be at most one instance of persistent store per program lifetime.
+    pub fn try_new(rng: &mut impl Rng256) -> Result<PersistentStore, Ctap2StatusCode> {
+        let storage = new_storage(NUM_PAGES).map_err(persistent_store::StoreError::from)?;
+        PersistentStore::open(storage, rng)
+    }
+
+    /// Opens the store on a given storage.
+    fn open(storage: Storage, rng: &mut impl Rng256) -> Result<PersistentStore, Ctap2StatusCode> {
         let mut store = PersistentStore {
-            store: persistent_store::Store::new(storage).ok().unwrap(),
+            store: persistent_store::Store::new(storage).map_err(|(error, _)| error)?,
         };
-        store.init(rng).unwrap();
-        store
+        store.init(rng)?;
+        store.incr_boot_count()?;
+        Ok(store)
     }
 
     /// Initializes the store by creating missing objects.
@@ -118,6 +185,22 @@ impl PersistentStore {
         if self.store.find_handle(key::AAGUID)?.is_none() {
             self.set_aaguid(key_material::AAGUID)?;
         }
+
+        // Migrate the attestation entries written without checksum.
+        for &(legacy_key, key) in &[
+            (key::_ATTESTATION_PRIVATE_KEY, key::ATTESTATION_PRIVATE_KEY),
+            (key::_ATTESTATION_CERTIFICATE, key::ATTESTATION_CERTIFICATE),
+        ] {
+            if let Some(value) = self.store.find(legacy_key)? {
+                self.store.transaction(&[
+                    StoreUpdate::Insert {
+                        key,
+                        value: append_checksum(&value),
+                    },
+                    StoreUpdate::Remove { key: legacy_key },
+                ])?;
+            }
+        }
         Ok(())
     }
 
@@ -156,11 +239,23 @@ impl PersistentStore {
         &mut self,
         new_credential: PublicKeyCredentialSource,
     ) -> Result<(), Ctap2StatusCode> {
-        // Holds the key of the existing credential if this is an update.
-        let mut old_key = None;
+        self.store_credentials(vec![new_credential])
+    }
+
+    /// Stores or updates credentials in a single transaction.
+    ///
+    /// Either all credentials are written or none, even if the authenticator loses power in the
+    /// middle. Each credential replaces an existing one with the same RP id and user handle. At
+    /// most `max_updates_per_transaction` credentials can be written at once.
+    pub fn store_credentials(
+        &mut self,
+        new_credentials: Vec<PublicKeyCredentialSource>,
+    ) -> Result<(), Ctap2StatusCode> {
         let min_key = key::CREDENTIALS.start;
         // Holds whether a key is used (indices are shifted by min_key).
         let mut keys = vec![false; MAX_SUPPORTED_RESIDENTIAL_KEYS];
+        // Holds the key, RP id and user handle of the existing credentials.
+        let mut existing = Vec::new();
         let mut iter_result = Ok(());
         let iter = self.iter_credentials(&mut iter_result)?;
         for (key, credential) in iter {
@@ -171,36 +266,59 @@ impl PersistentStore {
                 return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR);
             }
             keys[key - min_key] = true;
-            if credential.rp_id == new_credential.rp_id
-                && credential.user_handle == new_credential.user_handle
-            {
-                if old_key.is_some() {
-                    return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR);
-                }
-                old_key = Some(key);
-            }
+            existing.push((key, credential.rp_id, credential.user_handle));
         }
         iter_result?;
-        if old_key.is_none()
-            && keys.iter().filter(|&&x| x).count() >= MAX_SUPPORTED_RESIDENTIAL_KEYS
-        {
-            return Err(Ctap2StatusCode::CTAP2_ERR_KEY_STORE_FULL);
+        // Holds the key, RP id and user handle of the credentials written by this transaction.
+        let mut written: Vec<(usize, String, Vec<u8>)> = Vec::new();
+        let mut updates = Vec::new();
+        for new_credential in new_credentials {
+            let is_same = |entry: &&(usize, String, Vec<u8>)| {
+                entry.1 == new_credential.rp_id && entry.2 == new_credential.user_handle
+            };
+            if let Some(index) = written.iter().position(|entry| is_same(&entry)) {
+                // A credential earlier in the same transaction is replaced.
+                let value = serialize_credential(new_credential)?;
+                updates[index] = StoreUpdate::Insert {
+                    key: written[index].0,
+                    value,
+                };
+                continue;
+            }
+            let mut old_keys = existing.iter().filter(is_same).map(|&(key, _, _)| key);
+            let old_key = old_keys.next();
+            if old_keys.next().is_some() {
+                return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR);
+            }
+            let key = match old_key {
+                // This is a new credential being added, we need to allocate a free key. We choose
+                // the first available key.
+                None => key::CREDENTIALS
+                    .take(MAX_SUPPORTED_RESIDENTIAL_KEYS)
+                    .find(|key| !keys[key - min_key])
+                    .ok_or(Ctap2StatusCode::CTAP2_ERR_KEY_STORE_FULL)?,
+                // This is an existing credential being updated, we reuse its key.
+                Some(x) => x,
+            };
+            keys[key - min_key] = true;
+            written.push((
+                key,
+                new_credential.rp_id.clone(),
+                new_credential.user_handle.clone(),
+            ));
+            let value = serialize_credential(new_credential)?;
+            updates.push(StoreUpdate::Insert { key, value });
         }
-        let key = match old_key {
-            // This is a new credential being added, we need to allocate a free key. We choose the
-            // first available key.
-            None => key::CREDENTIALS
-                .take(MAX_SUPPORTED_RESIDENTIAL_KEYS)
-                .find(|key| !keys[key - min_key])
-                .ok_or(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)?,
-            // This is an existing credential being updated, we reuse its key.
-            Some(x) => x,
-        };
-        let value = serialize_credential(new_credential)?;
-        self.store.insert(key, &value)?;
+        self.store.transaction(&updates)?;
         Ok(())
     }
 
+    /// Returns the maximum number of credentials written by one call to `store_credentials`.
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn max_updates_per_transaction(&self) -> usize {
+        self.store.max_updates()
+    }
+
     /// Returns the list of matching credentials.
     ///
     /// Does not return credentials that are not discoverable if `check_cred_protect` is set.
@@ -225,8 +343,51 @@ impl PersistentStore {
         Ok(result)
     }
 
+    /// Returns all credentials.
+    #[cfg(any(feature = "debug_ctap", feature = "with_ctap2_1"))]
+    pub fn credentials(&self) -> Result<Vec<PublicKeyCredentialSource>, Ctap2StatusCode> {
+        let mut iter_result = Ok(());
+        let iter = self.iter_credentials(&mut iter_result)?;
+        let result = iter.map(|(_, credential)| credential).collect();
+        iter_result?;
+        Ok(result)
+    }
+
+    /// Returns the credential with this id.
+    ///
+    /// Returns `CTAP2_ERR_NO_CREDENTIALS` if no credential has this id.
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn get_credential(
+        &self,
+        credential_id: &[u8],
+    ) -> Result<PublicKeyCredentialSource, Ctap2StatusCode> {
+        Ok(self.find_credential_item(credential_id)?.1)
+    }
+
+    /// Deletes a credential.
+    ///
+    /// Returns `CTAP2_ERR_NO_CREDENTIALS` if no credential has this id. The removed entry is
+    /// reclaimed by the next compaction.
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn delete_credential(&mut self, credential_id: &[u8]) -> Result<(), Ctap2StatusCode> {
+        let (key, _) = self.find_credential_item(credential_id)?;
+        Ok(self.store.remove(key)?)
+    }
+
+    // Returns the key and value of the credential with this id.
+    #[cfg(feature = "with_ctap2_1")]
+    fn find_credential_item(
+        &self,
+        credential_id: &[u8],
+    ) -> Result<(usize, PublicKeyCredentialSource), Ctap2StatusCode> {
+        let mut iter_result = Ok(());
+        let mut iter = self.iter_credentials(&mut iter_result)?;
+        let result = iter.find(|(_, credential)| credential.credential_id == credential_id);
+        iter_result?;
+        result.ok_or(Ctap2StatusCode::CTAP2_ERR_NO_CREDENTIALS)
+    }
+
     /// Returns the number of credentials.
-    #[cfg(test)]
     pub fn count_credentials(&self) -> Result<usize, Ctap2StatusCode> {
         let mut iter_result = Ok(());
         let iter = self.iter_credentials(&mut iter_result)?;
@@ -235,6 +396,27 @@ impl PersistentStore {
         Ok(result)
     }
 
+    /// Returns the number of credentials that can still be stored.
+    ///
+    /// Besides the maximum number of credentials, the free space of the store is also taken into
+    /// account, assuming that new credentials have the maximum size.
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn remaining_credentials(&self) -> Result<usize, Ctap2StatusCode> {
+        let remaining_slots =
+            MAX_SUPPORTED_RESIDENTIAL_KEYS.saturating_sub(self.count_credentials()?);
+        // The capacity is measured in words of 4 bytes, and each entry has a header word.
+        let credential_words = (MAX_CREDENTIAL_SIZE + 3) / 4 + 1;
+        let remaining_space = self.store.capacity()?.remaining() / credential_words;
+        Ok(core::cmp::min(remaining_slots, remaining_space))
+    }
+
+    /// Simulates a reboot by reopening the store from its storage.
+    #[cfg(test)]
+    pub fn reboot(self, rng: &mut impl Rng256) -> PersistentStore {
+        let storage = self.store.extract_storage();
+        PersistentStore::open(storage, rng).unwrap()
+    }
+
     /// Iterates through the credentials.
     ///
     /// If an error is encountered during iteration, it is written to `result`.
@@ -254,6 +436,44 @@ impl PersistentStore {
         Ok(max.unwrap_or(0).wrapping_add(1))
     }
 
+    /// Returns how many times the store was opened, which is the number of boots.
+    pub fn boot_count(&self) -> Result<u32, Ctap2StatusCode> {
+        match self.store.find(key::BOOT_COUNT)? {
+            None => Ok(0),
+            Some(value) if value.len() == 4 => Ok(u32::from_ne_bytes(*array_ref!(&value, 0, 4))),
+            Some(_) => Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR),
+        }
+    }
+
+    /// Increments the boot count.
+    fn incr_boot_count(&mut self) -> Result<(), Ctap2StatusCode> {
+        let new_value = self.boot_count()?.saturating_add(1);
+        Ok(self
+            .store
+            .insert(key::BOOT_COUNT, &new_value.to_ne_bytes())?)
+    }
+
+    /// Resets the boot count to zero.
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn reset_boot_count(&mut self) -> Result<(), Ctap2StatusCode> {
+        Ok(self.store.remove(key::BOOT_COUNT)?)
+    }
+
+    /// Returns the percentage of the store capacity that is still free.
+    pub fn free_space_percent(&self) -> Result<usize, Ctap2StatusCode> {
+        let capacity = self.store.capacity()?;
+        Ok(capacity.remaining() * 100 / capacity.total())
+    }
+
+    /// Returns the estimated share of the flash erase cycles already used, in permille.
+    ///
+    /// The store writes its pages in a circular way, so the consumed lifetime grows linearly with
+    /// the number of page erases.
+    pub fn lifetime_used_permille(&self) -> Result<usize, Ctap2StatusCode> {
+        let lifetime = self.store.lifetime()?;
+        Ok(lifetime.used() * 1000 / lifetime.total())
+    }
+
     /// Returns the global signature counter.
     pub fn global_signature_counter(&self) -> Result<u32, Ctap2StatusCode> {
         match self.store.find(key::GLOBAL_SIGNATURE_COUNTER)? {
@@ -264,10 +484,15 @@ impl PersistentStore {
     }
 
     /// Increments the global signature counter.
+    ///
+    /// The counter never wraps around. Once an increment would overflow, the counter is left
+    /// unchanged and `CTAP2_ERR_LIMIT_EXCEEDED` is returned, so no further signatures are made.
     pub fn incr_global_signature_counter(&mut self, increment: u32) -> Result<(), Ctap2StatusCode> {
         let old_value = self.global_signature_counter()?;
-        // In hopes that servers handle the wrapping gracefully.
-        let new_value = old_value.wrapping_add(increment);
+        // Relying parties treat a decreasing counter as a cloned authenticator.
+        let new_value = old_value
+            .checked_add(increment)
+            .ok_or(Ctap2StatusCode::CTAP2_ERR_LIMIT_EXCEEDED)?;
         self.store
             .insert(key::GLOBAL_SIGNATURE_COUNTER, &new_value.to_ne_bytes())?;
         Ok(())
@@ -315,12 +540,27 @@ impl PersistentStore {
 
     /// Sets the PIN hash.
     ///
-    /// If it was already defined, it is updated.
+    /// If it was already defined, it is updated. Setting a PIN fulfills a pending forced PIN change.
     pub fn set_pin_hash(
         &mut self,
         pin_hash: &[u8; PIN_AUTH_LENGTH],
     ) -> Result<(), Ctap2StatusCode> {
-        Ok(self.store.insert(key::PIN_HASH, pin_hash)?)
+        #[cfg(not(feature = "with_ctap2_1"))]
+        let updates = [StoreUpdate::Insert {
+            key: key::PIN_HASH,
+            value: pin_hash.to_vec(),
+        }];
+        #[cfg(feature = "with_ctap2_1")]
+        let updates = [
+            StoreUpdate::Insert {
+                key: key::PIN_HASH,
+                value: pin_hash.to_vec(),
+            },
+            StoreUpdate::Remove {
+                key: key::FORCE_PIN_CHANGE,
+            },
+        ];
+        Ok(self.store.transaction(&updates)?)
     }
 
     /// Returns the number of remaining PIN retries.
@@ -347,6 +587,49 @@ impl PersistentStore {
         Ok(self.store.remove(key::PIN_RETRIES)?)
     }
 
+    /// Returns the serialized large blob array.
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn large_blob_array(&self) -> Result<Vec<u8>, Ctap2StatusCode> {
+        let mut large_blob_array = Vec::new();
+        for key in key::LARGE_BLOB_SHARDS {
+            match self.store.find(key)? {
+                None => break,
+                Some(shard) => large_blob_array.extend(shard),
+            }
+        }
+        if large_blob_array.is_empty() {
+            return Ok(EMPTY_LARGE_BLOB_ARRAY.to_vec());
+        }
+        Ok(large_blob_array)
+    }
+
+    /// Replaces the serialized large blob array.
+    ///
+    /// The array is written atomically: either all shards are updated or none.
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn commit_large_blob_array(
+        &mut self,
+        large_blob_array: &[u8],
+    ) -> Result<(), Ctap2StatusCode> {
+        if large_blob_array.len() > MAX_LARGE_BLOB_ARRAY_SIZE {
+            return Err(Ctap2StatusCode::CTAP2_ERR_LARGE_BLOB_STORAGE_FULL);
+        }
+        let mut shards = large_blob_array.chunks(self.store.max_value_length());
+        let updates = key::LARGE_BLOB_SHARDS
+            .map(|key| match shards.next() {
+                Some(value) => StoreUpdate::Insert {
+                    key,
+                    value: value.to_vec(),
+                },
+                None => StoreUpdate::Remove { key },
+            })
+            .collect::<Vec<_>>();
+        if shards.next().is_some() {
+            return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR);
+        }
+        Ok(self.store.transaction(&updates)?)
+    }
+
     /// Returns the minimum PIN length.
     #[cfg(feature = "with_ctap2_1")]
     pub fn min_pin_length(&self) -> Result<u8, Ctap2StatusCode> {
@@ -363,15 +646,67 @@ impl PersistentStore {
         Ok(self.store.insert(key::MIN_PIN_LENGTH, &[min_pin_length])?)
     }
 
+    /// Returns whether the PIN must be changed before its next use.
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn has_force_pin_change(&self) -> Result<bool, Ctap2StatusCode> {
+        match self.store.find(key::FORCE_PIN_CHANGE)? {
+            None => Ok(false),
+            Some(value) if value.is_empty() => Ok(true),
+            _ => Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR),
+        }
+    }
+
+    /// Requires the PIN to be changed before its next use.
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn force_pin_change(&mut self) -> Result<(), Ctap2StatusCode> {
+        Ok(self.store.insert(key::FORCE_PIN_CHANGE, &[])?)
+    }
+
+    /// Returns whether user verification is required for every operation.
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn has_always_uv(&self) -> Result<bool, Ctap2StatusCode> {
+        match self.store.find(key::ALWAYS_UV)? {
+            None => Ok(false),
+            Some(value) if value.is_empty() => Ok(true),
+            _ => Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR),
+        }
+    }
+
+    /// Enables or disables the requirement of user verification for every operation.
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn toggle_always_uv(&mut self) -> Result<(), Ctap2StatusCode> {
+        if self.has_always_uv()? {
+            Ok(self.store.remove(key::ALWAYS_UV)?)
+        } else {
+            Ok(self.store.insert(key::ALWAYS_UV, &[])?)
+        }
+    }
+
+    /// Returns whether enterprise attestation is enabled.
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn has_enterprise_attestation(&self) -> Result<bool, Ctap2StatusCode> {
+        match self.store.find(key::ENTERPRISE_ATTESTATION)? {
+            None => Ok(false),
+            Some(value) if value.is_empty() => Ok(true),
+            _ => Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR),
+        }
+    }
+
+    /// Enables enterprise attestation until the next reset.
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn enable_enterprise_attestation(&mut self) -> Result<(), Ctap2StatusCode> {
+        Ok(self.store.insert(key::ENTERPRISE_ATTESTATION, &[])?)
+    }
+
     /// Returns the list of RP IDs that are used to check if reading the minimum PIN length is
     /// allowed.
     #[cfg(feature = "with_ctap2_1")]
-    pub fn _min_pin_length_rp_ids(&self) -> Result<Vec<String>, Ctap2StatusCode> {
+    pub fn min_pin_length_rp_ids(&self) -> Result<Vec<String>, Ctap2StatusCode> {
         let rp_ids = self
             .store
-            .find(key::_MIN_PIN_LENGTH_RP_IDS)?
-            .map_or(Some(_DEFAULT_MIN_PIN_LENGTH_RP_IDS), |value| {
-                _deserialize_min_pin_length_rp_ids(&value)
+            .find(key::MIN_PIN_LENGTH_RP_IDS)?
+            .map_or(Some(DEFAULT_MIN_PIN_LENGTH_RP_IDS), |value| {
+                deserialize_min_pin_length_rp_ids(&value)
             });
         debug_assert!(rp_ids.is_some());
         Ok(rp_ids.unwrap_or(vec![]))
@@ -379,22 +714,22 @@ impl PersistentStore {
 
     /// Sets the list of RP IDs that are used to check if reading the minimum PIN length is allowed.
     #[cfg(feature = "with_ctap2_1")]
-    pub fn _set_min_pin_length_rp_ids(
+    pub fn set_min_pin_length_rp_ids(
         &mut self,
         min_pin_length_rp_ids: Vec<String>,
     ) -> Result<(), Ctap2StatusCode> {
         let mut min_pin_length_rp_ids = min_pin_length_rp_ids;
-        for rp_id in _DEFAULT_MIN_PIN_LENGTH_RP_IDS {
+        for rp_id in DEFAULT_MIN_PIN_LENGTH_RP_IDS {
             if !min_pin_length_rp_ids.contains(&rp_id) {
                 min_pin_length_rp_ids.push(rp_id);
             }
         }
-        if min_pin_length_rp_ids.len() > _MAX_RP_IDS_LENGTH {
+        if min_pin_length_rp_ids.len() > MAX_RP_IDS_LENGTH {
             return Err(Ctap2StatusCode::CTAP2_ERR_KEY_STORE_FULL);
         }
         Ok(self.store.insert(
-            key::_MIN_PIN_LENGTH_RP_IDS,
-            &_serialize_min_pin_length_rp_ids(min_pin_length_rp_ids)?,
+            key::MIN_PIN_LENGTH_RP_IDS,
+            &serialize_min_pin_length_rp_ids(min_pin_length_rp_ids)?,
         )?)
     }
 
@@ -402,7 +737,7 @@ impl PersistentStore {
     pub fn attestation_private_key(
         &self,
     ) -> Result<Option<[u8; key_material::ATTESTATION_PRIVATE_KEY_LENGTH]>, Ctap2StatusCode> {
-        match self.store.find(key::ATTESTATION_PRIVATE_KEY)? {
+        match self.find_with_checksum(key::ATTESTATION_PRIVATE_KEY)? {
             None => Ok(None),
             Some(key) if key.len() == key_material::ATTESTATION_PRIVATE_KEY_LENGTH => {
                 Ok(Some(*array_ref![
@@ -417,39 +752,166 @@ impl PersistentStore {
 
     /// Sets the attestation private key.
     ///
-    /// If it is already defined, it is overwritten.
+    /// It can only be written once, and not after the attestation is locked.
     pub fn set_attestation_private_key(
         &mut self,
         attestation_private_key: &[u8; key_material::ATTESTATION_PRIVATE_KEY_LENGTH],
     ) -> Result<(), Ctap2StatusCode> {
-        match self.store.find(key::ATTESTATION_PRIVATE_KEY)? {
-            None => Ok(self
-                .store
-                .insert(key::ATTESTATION_PRIVATE_KEY, attestation_private_key)?),
+        self.check_attestation_unlocked()?;
+        match self.store.find_handle(key::ATTESTATION_PRIVATE_KEY)? {
+            None => Ok(self.store.insert(
+                key::ATTESTATION_PRIVATE_KEY,
+                &append_checksum(attestation_private_key),
+            )?),
             Some(_) => Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR),
         }
     }
 
     /// Returns the attestation certificate if defined.
     pub fn attestation_certificate(&self) -> Result<Option<Vec<u8>>, Ctap2StatusCode> {
-        Ok(self.store.find(key::ATTESTATION_CERTIFICATE)?)
+        self.find_with_checksum(key::ATTESTATION_CERTIFICATE)
     }
 
     /// Sets the attestation certificate.
     ///
-    /// If it is already defined, it is overwritten.
+    /// It can only be written once, and not after the attestation is locked.
     pub fn set_attestation_certificate(
         &mut self,
         attestation_certificate: &[u8],
     ) -> Result<(), Ctap2StatusCode> {
-        match self.store.find(key::ATTESTATION_CERTIFICATE)? {
-            None => Ok(self
-                .store
-                .insert(key::ATTESTATION_CERTIFICATE, attestation_certificate)?),
+        self.check_attestation_unlocked()?;
+        match self.store.find_handle(key::ATTESTATION_CERTIFICATE)? {
+            None => Ok(self.store.insert(
+                key::ATTESTATION_CERTIFICATE,
+                &append_checksum(attestation_certificate),
+            )?),
             Some(_) => Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR),
         }
     }
 
+    /// Returns the batch attestation material, if both its private key and certificate are defined.
+    ///
+    /// Material specific to a relying party identifies the device, so it is only returned by
+    /// `find_rp_attestation` for enterprise attestation.
+    pub fn attestation_material(&self) -> Result<Option<AttestationMaterial>, Ctap2StatusCode> {
+        match (
+            self.attestation_private_key()?,
+            self.attestation_certificate()?,
+        ) {
+            (Some(private_key), Some(certificate)) => Ok(Some(AttestationMaterial {
+                private_key,
+                certificate,
+            })),
+            _ => Ok(None),
+        }
+    }
+
+    /// Sets the attestation material specific to a relying party.
+    ///
+    /// Material that is already defined for this relying party can't be overwritten.
+    pub fn set_rp_attestation(
+        &mut self,
+        rp_id_hash: &[u8; 32],
+        material: &AttestationMaterial,
+    ) -> Result<(), Ctap2StatusCode> {
+        if self.find_rp_attestation(rp_id_hash)?.is_some() {
+            return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR);
+        }
+        let mut free_key = None;
+        for key in key::RP_ATTESTATIONS {
+            if self.store.find(key)?.is_none() {
+                free_key = Some(key);
+                break;
+            }
+        }
+        let key = free_key.ok_or(Ctap2StatusCode::CTAP2_ERR_KEY_STORE_FULL)?;
+        let mut value = rp_id_hash.to_vec();
+        value.extend_from_slice(&material.private_key);
+        value.extend_from_slice(&material.certificate);
+        Ok(self.store.insert(key, &append_checksum(&value))?)
+    }
+
+    /// Returns the attestation material specific to a relying party, if any.
+    pub fn find_rp_attestation(
+        &self,
+        rp_id_hash: &[u8; 32],
+    ) -> Result<Option<AttestationMaterial>, Ctap2StatusCode> {
+        const HEADER_LENGTH: usize = 32 + key_material::ATTESTATION_PRIVATE_KEY_LENGTH;
+        for key in key::RP_ATTESTATIONS {
+            let value = match self.find_with_checksum(key)? {
+                None => continue,
+                Some(value) if value.len() > HEADER_LENGTH => value,
+                Some(_) => return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR),
+            };
+            if &value[..32] == rp_id_hash {
+                return Ok(Some(AttestationMaterial {
+                    private_key: *array_ref![
+                        value,
+                        32,
+                        key_material::ATTESTATION_PRIVATE_KEY_LENGTH
+                    ],
+                    certificate: value[HEADER_LENGTH..].to_vec(),
+                }));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the value of an entry written with `append_checksum`, without its checksum.
+    ///
+    /// Returns an error if the checksum doesn't match.
+    fn find_with_checksum(&self, key: usize) -> Result<Option<Vec<u8>>, Ctap2StatusCode> {
+        let mut value = match self.store.find(key)? {
+            None => return Ok(None),
+            Some(value) if value.len() >= CHECKSUM_LENGTH => value,
+            Some(_) => return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR),
+        };
+        let data_length = value.len() - CHECKSUM_LENGTH;
+        let checksum = u32::from_le_bytes(*array_ref![value, data_length, CHECKSUM_LENGTH]);
+        if crc32(&value[..data_length]) != checksum {
+            return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR);
+        }
+        value.truncate(data_length);
+        Ok(Some(value))
+    }
+
+    /// Returns the RP ID policy if defined.
+    pub fn rp_id_policy(&self) -> Result<Option<RpIdPolicy>, Ctap2StatusCode> {
+        let value = match self.store.find(key::RP_ID_POLICY)? {
+            None => return Ok(None),
+            Some(value) if !value.is_empty() && (value.len() - 1) % 32 == 0 => value,
+            Some(_) => return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR),
+        };
+        let rp_id_hashes = value[1..]
+            .chunks(32)
+            .map(|chunk| *array_ref![chunk, 0, 32])
+            .collect();
+        match value[0] {
+            0 => Ok(Some(RpIdPolicy::Deny(rp_id_hashes))),
+            1 => Ok(Some(RpIdPolicy::Allow(rp_id_hashes))),
+            _ => Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR),
+        }
+    }
+
+    /// Sets the RP ID policy, or removes it if `None`.
+    ///
+    /// If it is already defined, it is overwritten.
+    pub fn set_rp_id_policy(&mut self, policy: Option<RpIdPolicy>) -> Result<(), Ctap2StatusCode> {
+        let (mode, rp_id_hashes) = match policy {
+            None => return Ok(self.store.remove(key::RP_ID_POLICY)?),
+            Some(RpIdPolicy::Deny(rp_id_hashes)) => (0, rp_id_hashes),
+            Some(RpIdPolicy::Allow(rp_id_hashes)) => (1, rp_id_hashes),
+        };
+        if rp_id_hashes.len() > MAX_RP_ID_POLICY_LENGTH {
+            return Err(Ctap2StatusCode::CTAP2_ERR_KEY_STORE_FULL);
+        }
+        let mut value = vec![mode];
+        for rp_id_hash in rp_id_hashes {
+            value.extend_from_slice(&rp_id_hash);
+        }
+        Ok(self.store.insert(key::RP_ID_POLICY, &value)?)
+    }
+
     /// Returns the AAGUID.
     pub fn aaguid(&self) -> Result<[u8; key_material::AAGUID_LENGTH], Ctap2StatusCode> {
         let aaguid = self
@@ -464,14 +926,50 @@ impl PersistentStore {
 
     /// Sets the AAGUID.
     ///
-    /// If it is already defined, it is overwritten.
+    /// If it is already defined, it is overwritten, unless the attestation is locked.
     pub fn set_aaguid(
         &mut self,
         aaguid: &[u8; key_material::AAGUID_LENGTH],
     ) -> Result<(), Ctap2StatusCode> {
+        self.check_attestation_unlocked()?;
         Ok(self.store.insert(key::AAGUID, aaguid)?)
     }
 
+    /// Returns whether the batch attestation material is locked.
+    pub fn attestation_locked(&self) -> Result<bool, Ctap2StatusCode> {
+        Ok(self.store.find_handle(key::ATTESTATION_LOCKED)?.is_some())
+    }
+
+    /// Locks the batch attestation material, which then becomes read-only.
+    ///
+    /// There is no way to unlock it.
+    pub fn lock_attestation(&mut self) -> Result<(), Ctap2StatusCode> {
+        if self.attestation_locked()? {
+            return Ok(());
+        }
+        Ok(self.store.insert(key::ATTESTATION_LOCKED, &[])?)
+    }
+
+    fn check_attestation_unlocked(&self) -> Result<(), Ctap2StatusCode> {
+        if self.attestation_locked()? {
+            return Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED);
+        }
+        Ok(())
+    }
+
+    /// Returns whether the next write to the store may compact it.
+    pub fn needs_compaction(&self) -> Result<bool, Ctap2StatusCode> {
+        Ok(self.store.needs_compaction()?)
+    }
+
+    /// Compacts the store ahead of time.
+    ///
+    /// Writes compact the store when needed, which makes them slow. This permits to move that
+    /// latency to a better moment, e.g. while the device is idle.
+    pub fn compact(&mut self) -> Result<(), Ctap2StatusCode> {
+        Ok(self.store.consolidate()?)
+    }
+
     /// Resets the store as for a CTAP reset.
     ///
     /// In particular persistent entries are not reset.
@@ -480,6 +978,48 @@ impl PersistentStore {
         self.init(rng)?;
         Ok(())
     }
+
+    /// Resets the store to its factory state.
+    ///
+    /// Contrary to a CTAP reset, persistent entries like the attestation material are removed too.
+    /// Once the attestation is locked, the lock and the material it protects are kept.
+    pub fn factory_reset(&mut self, rng: &mut impl Rng256) -> Result<(), Ctap2StatusCode> {
+        if self.attestation_locked()? {
+            self.store.clear(key::NUM_PERSISTENT_KEYS)?;
+            for key in 0..key::NUM_PERSISTENT_KEYS {
+                if !LOCKED_ATTESTATION_KEYS.contains(&key) {
+                    self.store.remove(key)?;
+                }
+            }
+        } else {
+            self.store.clear(0)?;
+        }
+        self.init(rng)?;
+        Ok(())
+    }
+}
+
+/// Computes the CRC32 (as used by IEEE 802.3) of some data.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+/// Returns the data followed by its checksum.
+///
+/// Storing both in the same entry makes sure they are written atomically.
+fn append_checksum(data: &[u8]) -> Vec<u8> {
+    let mut value = Vec::with_capacity(data.len() + CHECKSUM_LENGTH);
+    value.extend_from_slice(data);
+    value.extend_from_slice(&crc32(data).to_le_bytes());
+    value
 }
 
 impl From<persistent_store::StoreError> for Ctap2StatusCode {
@@ -583,7 +1123,7 @@ fn serialize_credential(credential: PublicKeyCredentialSource) -> Result<Vec<u8>
 
 /// Deserializes a list of RP IDs from storage representation.
 #[cfg(feature = "with_ctap2_1")]
-fn _deserialize_min_pin_length_rp_ids(data: &[u8]) -> Option<Vec<String>> {
+fn deserialize_min_pin_length_rp_ids(data: &[u8]) -> Option<Vec<String>> {
     let cbor = cbor::read(data).ok()?;
     extract_array(cbor)
         .ok()?
@@ -595,7 +1135,7 @@ fn _deserialize_min_pin_length_rp_ids(data: &[u8]) -> Option<Vec<String>> {
 
 /// Serializes a list of RP IDs to storage representation.
 #[cfg(feature = "with_ctap2_1")]
-fn _serialize_min_pin_length_rp_ids(rp_ids: Vec<String>) -> Result<Vec<u8>, Ctap2StatusCode> {
+fn serialize_min_pin_length_rp_ids(rp_ids: Vec<String>) -> Result<Vec<u8>, Ctap2StatusCode> {
     let mut data = Vec::new();
     if cbor::write(cbor_array_vec!(rp_ids), &mut data) {
         Ok(data)
@@ -627,6 +1167,9 @@ mod test {
             creation_order: 0,
             user_name: None,
             user_icon: None,
+            cred_blob: None,
+            signature_counter: None,
+            large_blob_key: None,
         }
     }
 
@@ -640,6 +1183,77 @@ mod test {
         assert!(persistent_store.count_credentials().unwrap() > 0);
     }
 
+    #[test]
+    fn test_store_credentials() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let credential_source = create_credential_source(&mut rng, "example.com", vec![0x00]);
+        assert!(persistent_store.store_credential(credential_source).is_ok());
+        let credentials = vec![
+            create_credential_source(&mut rng, "example.com", vec![0x00]),
+            create_credential_source(&mut rng, "example.com", vec![0x01]),
+            create_credential_source(&mut rng, "example.com", vec![0x01]),
+        ];
+        let credential_id = credentials[2].credential_id.clone();
+        assert!(persistent_store.store_credentials(credentials).is_ok());
+        // The first credential replaced the stored one, and the last one replaced the second.
+        assert_eq!(persistent_store.count_credentials().unwrap(), 2);
+        assert!(persistent_store
+            .find_credential("example.com", &credential_id, false)
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn test_store_credentials_interrupted() {
+        let mut rng = ThreadRng256 {};
+        let mut delay = 0;
+        loop {
+            let mut persistent_store = PersistentStore::new(&mut rng);
+            let credentials = (0..3)
+                .map(|i| create_credential_source(&mut rng, "example.com", vec![i]))
+                .collect();
+            persistent_store.store.storage_mut().arm_interruption(delay);
+            if persistent_store.store_credentials(credentials).is_ok() {
+                persistent_store.store.storage_mut().disarm_interruption();
+                assert_eq!(persistent_store.count_credentials().unwrap(), 3);
+                break;
+            }
+            // The power was lost in the middle of the transaction.
+            persistent_store.store.storage_mut().reset_interruption();
+            let persistent_store = persistent_store.reboot(&mut rng);
+            let count = persistent_store.count_credentials().unwrap();
+            assert!(count == 0 || count == 3);
+            delay += 1;
+        }
+    }
+
+    #[test]
+    fn test_open_unsupported_storage() {
+        let mut rng = ThreadRng256 {};
+        // The store needs more pages than that.
+        let storage = new_storage(1).unwrap();
+        assert_eq!(
+            PersistentStore::open(storage, &mut rng).err(),
+            Some(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)
+        );
+    }
+
+    #[test]
+    fn test_open_corrupted_storage() {
+        use persistent_store::Storage as _;
+
+        let mut rng = ThreadRng256 {};
+        let mut storage = new_storage(NUM_PAGES).unwrap();
+        // Writes a page header that no store could have written.
+        let index = persistent_store::StorageIndex { page: 0, byte: 0 };
+        storage.write_slice(index, &[0x00; 8]).unwrap();
+        assert_eq!(
+            PersistentStore::open(storage, &mut rng).err(),
+            Some(Ctap2StatusCode::CTAP2_ERR_VENDOR_HARDWARE_FAILURE)
+        );
+    }
+
     #[test]
     fn test_credential_order() {
         let mut rng = ThreadRng256 {};
@@ -655,6 +1269,29 @@ mod test {
         assert!(persistent_store.new_creation_order().unwrap() > current_latest_creation);
     }
 
+    #[test]
+    fn test_credential_order_across_reboot() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut old_credential = create_credential_source(&mut rng, "example.com", vec![0x01]);
+        old_credential.creation_order = persistent_store.new_creation_order().unwrap();
+        assert!(persistent_store.store_credential(old_credential).is_ok());
+
+        let mut persistent_store = persistent_store.reboot(&mut rng);
+        let mut new_credential = create_credential_source(&mut rng, "example.com", vec![0x02]);
+        new_credential.creation_order = persistent_store.new_creation_order().unwrap();
+        assert!(persistent_store.store_credential(new_credential).is_ok());
+
+        let mut credentials = persistent_store
+            .filter_credential("example.com", false)
+            .unwrap();
+        credentials.sort_unstable_by_key(|c| c.creation_order);
+        assert_eq!(credentials.len(), 2);
+        assert_eq!(credentials[0].user_handle, vec![0x01]);
+        assert_eq!(credentials[1].user_handle, vec![0x02]);
+        assert!(credentials[0].creation_order < credentials[1].creation_order);
+    }
+
     #[test]
     #[allow(clippy::assertions_on_constants)]
     fn test_fill_store() {
@@ -785,6 +1422,9 @@ mod test {
             creation_order: 0,
             user_name: None,
             user_icon: None,
+            cred_blob: None,
+            signature_counter: None,
+            large_blob_key: None,
         };
         assert!(persistent_store.store_credential(credential).is_ok());
 
@@ -828,6 +1468,9 @@ mod test {
             creation_order: 0,
             user_name: None,
             user_icon: None,
+            cred_blob: None,
+            signature_counter: None,
+            large_blob_key: None,
         };
         assert_eq!(found_credential, Some(expected_credential));
     }
@@ -849,6 +1492,9 @@ mod test {
             creation_order: 0,
             user_name: None,
             user_icon: None,
+            cred_blob: None,
+            signature_counter: None,
+            large_blob_key: None,
         };
         assert!(persistent_store.store_credential(credential).is_ok());
 
@@ -858,6 +1504,50 @@ mod test {
         assert_eq!(no_credential, None);
     }
 
+    #[test]
+    fn test_cred_protect_visibility() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let policies = [
+            CredentialProtectionPolicy::UserVerificationOptional,
+            CredentialProtectionPolicy::UserVerificationOptionalWithCredentialIdList,
+            CredentialProtectionPolicy::UserVerificationRequired,
+        ];
+        let mut credential_ids = vec![];
+        for (i, policy) in policies.iter().enumerate() {
+            let mut credential = create_credential_source(&mut rng, "example.com", vec![i as u8]);
+            credential.cred_protect_policy = Some(*policy);
+            credential_ids.push(credential.credential_id.clone());
+            assert!(persistent_store.store_credential(credential).is_ok());
+        }
+
+        // Without user verification, only the first level is discoverable.
+        let discoverable = persistent_store
+            .filter_credential("example.com", true)
+            .unwrap();
+        assert_eq!(discoverable.len(), 1);
+        assert_eq!(discoverable[0].user_handle, vec![0x00]);
+        assert_eq!(
+            persistent_store
+                .filter_credential("example.com", false)
+                .unwrap()
+                .len(),
+            3
+        );
+
+        // With an allow list, only the last level requires user verification.
+        for (i, credential_id) in credential_ids.iter().enumerate() {
+            let found_without_uv = persistent_store
+                .find_credential("example.com", credential_id, true)
+                .unwrap();
+            assert_eq!(found_without_uv.is_some(), i < 2);
+            let found_with_uv = persistent_store
+                .find_credential("example.com", credential_id, false)
+                .unwrap();
+            assert_eq!(found_with_uv.unwrap().user_handle, vec![i as u8]);
+        }
+    }
+
     #[test]
     fn test_master_keys() {
         let mut rng = ThreadRng256 {};
@@ -988,9 +1678,266 @@ mod test {
         assert_eq!(&persistent_store.aaguid().unwrap(), key_material::AAGUID);
     }
 
-    #[cfg(feature = "with_ctap2_1")]
     #[test]
-    fn test_min_pin_length() {
+    fn test_lock_attestation() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        assert!(!persistent_store.attestation_locked().unwrap());
+
+        // The first writes succeed.
+        let dummy_key = [0x41u8; key_material::ATTESTATION_PRIVATE_KEY_LENGTH];
+        let dummy_cert = [0xddu8; 20];
+        let dummy_aaguid = [0xA5u8; key_material::AAGUID_LENGTH];
+        persistent_store
+            .set_attestation_private_key(&dummy_key)
+            .unwrap();
+        persistent_store
+            .set_attestation_certificate(&dummy_cert)
+            .unwrap();
+        persistent_store.set_aaguid(&dummy_aaguid).unwrap();
+        persistent_store.lock_attestation().unwrap();
+        assert!(persistent_store.attestation_locked().unwrap());
+        // Locking twice is fine.
+        persistent_store.lock_attestation().unwrap();
+
+        // Later writes are rejected.
+        assert_eq!(
+            persistent_store.set_aaguid(key_material::AAGUID),
+            Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)
+        );
+
+        // The lock and the material survive a reset.
+        persistent_store.reset(&mut rng).unwrap();
+        assert!(persistent_store.attestation_locked().unwrap());
+        assert_eq!(
+            persistent_store.set_attestation_private_key(&dummy_key),
+            Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)
+        );
+        assert_eq!(
+            persistent_store.set_attestation_certificate(&dummy_cert),
+            Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)
+        );
+        assert_eq!(
+            persistent_store.attestation_private_key().unwrap(),
+            Some(dummy_key)
+        );
+        assert_eq!(
+            persistent_store.attestation_certificate().unwrap(),
+            Some(dummy_cert.to_vec())
+        );
+        assert_eq!(persistent_store.aaguid().unwrap(), dummy_aaguid);
+    }
+
+    #[test]
+    fn test_rp_attestation() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let rp_id_hash = [0x55; 32];
+        let other_rp_id_hash = [0x66; 32];
+
+        // Without any attestation material, there is nothing to return.
+        assert!(persistent_store.attestation_material().unwrap().is_none());
+        assert!(persistent_store
+            .find_rp_attestation(&rp_id_hash)
+            .unwrap()
+            .is_none());
+
+        let batch_key = [0x41; key_material::ATTESTATION_PRIVATE_KEY_LENGTH];
+        let batch_cert = vec![0xdd; 20];
+        persistent_store
+            .set_attestation_private_key(&batch_key)
+            .unwrap();
+        persistent_store
+            .set_attestation_certificate(&batch_cert)
+            .unwrap();
+        let material = persistent_store.attestation_material().unwrap().unwrap();
+        assert_eq!(material.private_key, batch_key);
+        assert_eq!(material.certificate, batch_cert);
+
+        // Specific material is only found for its relying party, and doesn't replace the batch.
+        let rp_material = AttestationMaterial {
+            private_key: [0x42; key_material::ATTESTATION_PRIVATE_KEY_LENGTH],
+            certificate: vec![0xee; 30],
+        };
+        persistent_store
+            .set_rp_attestation(&rp_id_hash, &rp_material)
+            .unwrap();
+        let material = persistent_store
+            .find_rp_attestation(&rp_id_hash)
+            .unwrap()
+            .unwrap();
+        assert_eq!(material.private_key, rp_material.private_key);
+        assert_eq!(material.certificate, rp_material.certificate);
+        assert!(persistent_store
+            .find_rp_attestation(&other_rp_id_hash)
+            .unwrap()
+            .is_none());
+        let material = persistent_store.attestation_material().unwrap().unwrap();
+        assert_eq!(material.private_key, batch_key);
+
+        // Specific material can't be overwritten and survives a reset.
+        assert_eq!(
+            persistent_store.set_rp_attestation(&rp_id_hash, &rp_material),
+            Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)
+        );
+        persistent_store.reset(&mut rng).unwrap();
+        let material = persistent_store
+            .find_rp_attestation(&rp_id_hash)
+            .unwrap()
+            .unwrap();
+        assert_eq!(material.certificate, rp_material.certificate);
+    }
+
+    #[test]
+    fn test_rp_id_policy() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        assert_eq!(persistent_store.rp_id_policy(), Ok(None));
+
+        let policy = RpIdPolicy::Allow(vec![[0x55; 32], [0x66; 32]]);
+        persistent_store
+            .set_rp_id_policy(Some(policy.clone()))
+            .unwrap();
+        assert_eq!(persistent_store.rp_id_policy(), Ok(Some(policy)));
+        let policy = RpIdPolicy::Deny(vec![]);
+        persistent_store
+            .set_rp_id_policy(Some(policy.clone()))
+            .unwrap();
+        assert_eq!(persistent_store.rp_id_policy(), Ok(Some(policy.clone())));
+
+        // The policy persists a reset, but not a factory reset.
+        persistent_store.reset(&mut rng).unwrap();
+        assert_eq!(persistent_store.rp_id_policy(), Ok(Some(policy)));
+        persistent_store.factory_reset(&mut rng).unwrap();
+        assert_eq!(persistent_store.rp_id_policy(), Ok(None));
+
+        let policy = RpIdPolicy::Deny(vec![[0x55; 32]; MAX_RP_ID_POLICY_LENGTH + 1]);
+        assert_eq!(
+            persistent_store.set_rp_id_policy(Some(policy)),
+            Err(Ctap2StatusCode::CTAP2_ERR_KEY_STORE_FULL)
+        );
+        persistent_store.set_rp_id_policy(None).unwrap();
+        assert_eq!(persistent_store.rp_id_policy(), Ok(None));
+    }
+
+    #[test]
+    fn test_rp_id_policy_is_allowed() {
+        let allow = RpIdPolicy::Allow(vec![[0x55; 32]]);
+        assert!(allow.is_allowed(&[0x55; 32]));
+        assert!(!allow.is_allowed(&[0x66; 32]));
+        let deny = RpIdPolicy::Deny(vec![[0x55; 32]]);
+        assert!(!deny.is_allowed(&[0x55; 32]));
+        assert!(deny.is_allowed(&[0x66; 32]));
+    }
+
+    #[test]
+    fn test_fill_rp_attestations() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let material = AttestationMaterial {
+            private_key: [0x42; key_material::ATTESTATION_PRIVATE_KEY_LENGTH],
+            certificate: vec![0xee; 30],
+        };
+
+        let num_rp_attestations = key::RP_ATTESTATIONS.end - key::RP_ATTESTATIONS.start;
+        for i in 0..num_rp_attestations {
+            persistent_store
+                .set_rp_attestation(&[i as u8; 32], &material)
+                .unwrap();
+        }
+        assert_eq!(
+            persistent_store.set_rp_attestation(&[0xFF; 32], &material),
+            Err(Ctap2StatusCode::CTAP2_ERR_KEY_STORE_FULL)
+        );
+    }
+
+    #[test]
+    fn test_attestation_checksum() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let rp_id_hash = [0x55; 32];
+        let material = AttestationMaterial {
+            private_key: [0x42; key_material::ATTESTATION_PRIVATE_KEY_LENGTH],
+            certificate: vec![0xee; 30],
+        };
+        persistent_store
+            .set_attestation_private_key(&material.private_key)
+            .unwrap();
+        persistent_store
+            .set_attestation_certificate(&material.certificate)
+            .unwrap();
+        persistent_store
+            .set_rp_attestation(&rp_id_hash, &material)
+            .unwrap();
+
+        // Flip a bit in each entry.
+        for key in &[
+            key::ATTESTATION_PRIVATE_KEY,
+            key::ATTESTATION_CERTIFICATE,
+            key::RP_ATTESTATIONS.start,
+        ] {
+            let mut value = persistent_store.store.find(*key).unwrap().unwrap();
+            value[1] ^= 0x01;
+            persistent_store.store.insert(*key, &value).unwrap();
+        }
+
+        assert_eq!(
+            persistent_store.attestation_private_key(),
+            Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)
+        );
+        assert_eq!(
+            persistent_store.attestation_certificate(),
+            Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)
+        );
+        assert!(persistent_store.attestation_material().is_err());
+        assert!(persistent_store.find_rp_attestation(&rp_id_hash).is_err());
+    }
+
+    #[test]
+    fn test_attestation_migration() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let private_key = [0x42; key_material::ATTESTATION_PRIVATE_KEY_LENGTH];
+        let certificate = vec![0xee; 30];
+        persistent_store
+            .store
+            .insert(key::_ATTESTATION_PRIVATE_KEY, &private_key)
+            .unwrap();
+        persistent_store
+            .store
+            .insert(key::_ATTESTATION_CERTIFICATE, &certificate)
+            .unwrap();
+
+        persistent_store.init(&mut rng).unwrap();
+        assert!(persistent_store
+            .store
+            .find_handle(key::_ATTESTATION_PRIVATE_KEY)
+            .unwrap()
+            .is_none());
+        assert!(persistent_store
+            .store
+            .find_handle(key::_ATTESTATION_CERTIFICATE)
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            persistent_store.attestation_private_key().unwrap(),
+            Some(private_key)
+        );
+        assert_eq!(
+            persistent_store.attestation_certificate().unwrap(),
+            Some(certificate)
+        );
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_min_pin_length() {
         let mut rng = ThreadRng256 {};
         let mut persistent_store = PersistentStore::new(&mut rng);
 
@@ -1011,6 +1958,64 @@ mod test {
         );
     }
 
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_always_uv() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+
+        // Always UV is initially disabled.
+        assert!(!persistent_store.has_always_uv().unwrap());
+
+        // Toggling flips the value and a reset disables it again.
+        persistent_store.toggle_always_uv().unwrap();
+        assert!(persistent_store.has_always_uv().unwrap());
+        persistent_store.toggle_always_uv().unwrap();
+        assert!(!persistent_store.has_always_uv().unwrap());
+        persistent_store.toggle_always_uv().unwrap();
+        persistent_store.reset(&mut rng).unwrap();
+        assert!(!persistent_store.has_always_uv().unwrap());
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_enterprise_attestation() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+
+        assert!(!persistent_store.has_enterprise_attestation().unwrap());
+        persistent_store.enable_enterprise_attestation().unwrap();
+        assert!(persistent_store.has_enterprise_attestation().unwrap());
+        // Enabling twice is fine, and a reset disables it again.
+        persistent_store.enable_enterprise_attestation().unwrap();
+        assert!(persistent_store.has_enterprise_attestation().unwrap());
+        persistent_store.reset(&mut rng).unwrap();
+        assert!(!persistent_store.has_enterprise_attestation().unwrap());
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_force_pin_change() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+
+        // No PIN change is initially required.
+        assert!(!persistent_store.has_force_pin_change().unwrap());
+
+        // Forcing a change is cleared by setting a new PIN.
+        persistent_store.force_pin_change().unwrap();
+        assert!(persistent_store.has_force_pin_change().unwrap());
+        persistent_store
+            .set_pin_hash(&[0x88; PIN_AUTH_LENGTH])
+            .unwrap();
+        assert!(!persistent_store.has_force_pin_change().unwrap());
+
+        // Resetting the storage clears the flag.
+        persistent_store.force_pin_change().unwrap();
+        persistent_store.reset(&mut rng).unwrap();
+        assert!(!persistent_store.has_force_pin_change().unwrap());
+    }
+
     #[cfg(feature = "with_ctap2_1")]
     #[test]
     fn test_min_pin_length_rp_ids() {
@@ -1019,22 +2024,22 @@ mod test {
 
         // The minimum PIN length RP IDs are initially at the default.
         assert_eq!(
-            persistent_store._min_pin_length_rp_ids().unwrap(),
-            _DEFAULT_MIN_PIN_LENGTH_RP_IDS
+            persistent_store.min_pin_length_rp_ids().unwrap(),
+            DEFAULT_MIN_PIN_LENGTH_RP_IDS
         );
 
         // Changes by the setter are reflected by the getter.
         let mut rp_ids = vec![String::from("example.com")];
         assert_eq!(
-            persistent_store._set_min_pin_length_rp_ids(rp_ids.clone()),
+            persistent_store.set_min_pin_length_rp_ids(rp_ids.clone()),
             Ok(())
         );
-        for rp_id in _DEFAULT_MIN_PIN_LENGTH_RP_IDS {
+        for rp_id in DEFAULT_MIN_PIN_LENGTH_RP_IDS {
             if !rp_ids.contains(&rp_id) {
                 rp_ids.push(rp_id);
             }
         }
-        assert_eq!(persistent_store._min_pin_length_rp_ids().unwrap(), rp_ids);
+        assert_eq!(persistent_store.min_pin_length_rp_ids().unwrap(), rp_ids);
     }
 
     #[test]
@@ -1059,6 +2064,239 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_boot_count() {
+        let mut rng = ThreadRng256 {};
+        let persistent_store = PersistentStore::new(&mut rng);
+        assert_eq!(persistent_store.boot_count().unwrap(), 1);
+
+        let mut persistent_store = persistent_store.reboot(&mut rng);
+        assert_eq!(persistent_store.boot_count().unwrap(), 2);
+
+        // The boot count survives a CTAP reset, but not a factory reset.
+        persistent_store.reset(&mut rng).unwrap();
+        assert_eq!(persistent_store.boot_count().unwrap(), 2);
+        persistent_store.factory_reset(&mut rng).unwrap();
+        assert_eq!(persistent_store.boot_count().unwrap(), 0);
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_reset_boot_count() {
+        let mut rng = ThreadRng256 {};
+        let persistent_store = PersistentStore::new(&mut rng);
+        let mut persistent_store = persistent_store.reboot(&mut rng);
+        assert_eq!(persistent_store.boot_count().unwrap(), 2);
+
+        persistent_store.reset_boot_count().unwrap();
+        assert_eq!(persistent_store.boot_count().unwrap(), 0);
+        let persistent_store = persistent_store.reboot(&mut rng);
+        assert_eq!(persistent_store.boot_count().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_store_health() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let free_space = persistent_store.free_space_percent().unwrap();
+        let lifetime_used = persistent_store.lifetime_used_permille().unwrap();
+        assert!(free_space <= 100);
+        assert!(lifetime_used <= 1000);
+
+        for i in 0..20 {
+            let credential_source = create_credential_source(&mut rng, "example.com", vec![i]);
+            assert!(persistent_store.store_credential(credential_source).is_ok());
+        }
+        assert!(persistent_store.free_space_percent().unwrap() < free_space);
+        assert!(persistent_store.lifetime_used_permille().unwrap() >= lifetime_used);
+    }
+
+    #[test]
+    fn test_global_signature_counter_exhausted() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+
+        assert!(persistent_store
+            .incr_global_signature_counter(u32::MAX - INITIAL_SIGNATURE_COUNTER - 1)
+            .is_ok());
+        assert_eq!(
+            persistent_store.global_signature_counter().unwrap(),
+            u32::MAX - 1
+        );
+        assert_eq!(
+            persistent_store.incr_global_signature_counter(2),
+            Err(Ctap2StatusCode::CTAP2_ERR_LIMIT_EXCEEDED)
+        );
+        assert_eq!(
+            persistent_store.global_signature_counter().unwrap(),
+            u32::MAX - 1
+        );
+        assert!(persistent_store.incr_global_signature_counter(1).is_ok());
+        assert_eq!(
+            persistent_store.global_signature_counter().unwrap(),
+            u32::MAX
+        );
+        assert_eq!(
+            persistent_store.incr_global_signature_counter(1),
+            Err(Ctap2StatusCode::CTAP2_ERR_LIMIT_EXCEEDED)
+        );
+        assert_eq!(
+            persistent_store.global_signature_counter().unwrap(),
+            u32::MAX
+        );
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_commit_get_large_blob_array() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+
+        assert_eq!(
+            persistent_store.large_blob_array().unwrap(),
+            EMPTY_LARGE_BLOB_ARRAY.to_vec()
+        );
+
+        // A large array spans several shards.
+        let large_blob_array = (0..MAX_LARGE_BLOB_ARRAY_SIZE)
+            .map(|i| i as u8)
+            .collect::<Vec<u8>>();
+        assert!(persistent_store
+            .commit_large_blob_array(&large_blob_array)
+            .is_ok());
+        assert_eq!(
+            persistent_store.large_blob_array().unwrap(),
+            large_blob_array
+        );
+
+        // A smaller array replaces all shards of the previous one.
+        let large_blob_array = vec![0x01, 0x02, 0x03];
+        assert!(persistent_store
+            .commit_large_blob_array(&large_blob_array)
+            .is_ok());
+        assert_eq!(
+            persistent_store.large_blob_array().unwrap(),
+            large_blob_array
+        );
+
+        let large_blob_array = vec![0xC0; MAX_LARGE_BLOB_ARRAY_SIZE + 1];
+        assert_eq!(
+            persistent_store.commit_large_blob_array(&large_blob_array),
+            Err(Ctap2StatusCode::CTAP2_ERR_LARGE_BLOB_STORAGE_FULL)
+        );
+        assert_eq!(
+            persistent_store.large_blob_array().unwrap(),
+            vec![0x01, 0x02, 0x03]
+        );
+
+        // The array is reset with the store.
+        assert!(persistent_store.reset(&mut rng).is_ok());
+        assert_eq!(
+            persistent_store.large_blob_array().unwrap(),
+            EMPTY_LARGE_BLOB_ARRAY.to_vec()
+        );
+    }
+
+    #[test]
+    fn test_compact() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        assert!(!persistent_store.needs_compaction().unwrap());
+
+        // Overwriting the same credential leaves deleted entries behind until compaction.
+        for _ in 0..1000 {
+            if persistent_store.needs_compaction().unwrap() {
+                break;
+            }
+            let credential_source = create_credential_source(&mut rng, "example.com", vec![0x00]);
+            assert!(persistent_store.store_credential(credential_source).is_ok());
+        }
+        assert!(persistent_store.needs_compaction().unwrap());
+        assert_eq!(persistent_store.count_credentials().unwrap(), 1);
+
+        assert!(persistent_store.compact().is_ok());
+        assert!(!persistent_store.needs_compaction().unwrap());
+        assert_eq!(persistent_store.count_credentials().unwrap(), 1);
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_delete_credential() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut credential_ids = vec![];
+        for user_handle in 0..3 {
+            let credential_source =
+                create_credential_source(&mut rng, "example.com", vec![user_handle]);
+            credential_ids.push(credential_source.credential_id.clone());
+            assert!(persistent_store.store_credential(credential_source).is_ok());
+        }
+        assert_eq!(persistent_store.count_credentials().unwrap(), 3);
+        assert_eq!(
+            persistent_store.remaining_credentials().unwrap(),
+            MAX_SUPPORTED_RESIDENTIAL_KEYS - 3
+        );
+        let used_capacity = persistent_store.store.capacity().unwrap().used();
+
+        assert!(persistent_store
+            .delete_credential(&credential_ids[1])
+            .is_ok());
+        assert_eq!(persistent_store.count_credentials().unwrap(), 2);
+        assert_eq!(
+            persistent_store.remaining_credentials().unwrap(),
+            MAX_SUPPORTED_RESIDENTIAL_KEYS - 2
+        );
+        assert_eq!(
+            persistent_store.get_credential(&credential_ids[1]),
+            Err(Ctap2StatusCode::CTAP2_ERR_NO_CREDENTIALS)
+        );
+        assert_eq!(
+            persistent_store
+                .get_credential(&credential_ids[0])
+                .unwrap()
+                .user_handle,
+            vec![0x00]
+        );
+        // The deleted entry no longer counts as used, so compaction can reclaim it.
+        assert!(persistent_store.store.capacity().unwrap().used() < used_capacity);
+
+        assert_eq!(
+            persistent_store.delete_credential(&credential_ids[1]),
+            Err(Ctap2StatusCode::CTAP2_ERR_NO_CREDENTIALS)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "with_ctap2_1")]
+    fn test_remaining_credentials() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        assert_eq!(
+            persistent_store.remaining_credentials().unwrap(),
+            MAX_SUPPORTED_RESIDENTIAL_KEYS
+        );
+
+        let credential_source = create_credential_source(&mut rng, "example.com", vec![]);
+        let credential_id = credential_source.credential_id.clone();
+        assert!(persistent_store.store_credential(credential_source).is_ok());
+        assert_eq!(
+            persistent_store.remaining_credentials().unwrap(),
+            MAX_SUPPORTED_RESIDENTIAL_KEYS - 1
+        );
+
+        assert!(persistent_store.delete_credential(&credential_id).is_ok());
+        assert_eq!(
+            persistent_store.remaining_credentials().unwrap(),
+            MAX_SUPPORTED_RESIDENTIAL_KEYS
+        );
+
+        // Other objects in the store take space away from credentials.
+        persistent_store
+            .commit_large_blob_array(&[0x55; MAX_LARGE_BLOB_ARRAY_SIZE])
+            .unwrap();
+        assert!(persistent_store.remaining_credentials().unwrap() < MAX_SUPPORTED_RESIDENTIAL_KEYS);
+    }
+
     #[test]
     fn test_serialize_deserialize_credential() {
         let mut rng = ThreadRng256 {};
@@ -1074,6 +2312,9 @@ mod test {
             creation_order: 0,
             user_name: None,
             user_icon: None,
+            cred_blob: None,
+            signature_counter: None,
+            large_blob_key: None,
         };
         let serialized = serialize_credential(credential.clone()).unwrap();
         let reconstructed = deserialize_credential(&serialized).unwrap();
@@ -1084,8 +2325,85 @@ mod test {
     #[test]
     fn test_serialize_deserialize_min_pin_length_rp_ids() {
         let rp_ids = vec![String::from("example.com")];
-        let serialized = _serialize_min_pin_length_rp_ids(rp_ids.clone()).unwrap();
-        let reconstructed = _deserialize_min_pin_length_rp_ids(&serialized).unwrap();
+        let serialized = serialize_min_pin_length_rp_ids(rp_ids.clone()).unwrap();
+        let reconstructed = deserialize_min_pin_length_rp_ids(&serialized).unwrap();
         assert_eq!(rp_ids, reconstructed);
     }
+
+    #[test]
+    fn test_factory_reset() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let credential_source = create_credential_source(&mut rng, "example.com", vec![]);
+        assert!(persistent_store.store_credential(credential_source).is_ok());
+        persistent_store
+            .set_pin_hash(&[0x88; PIN_AUTH_LENGTH])
+            .unwrap();
+        persistent_store
+            .set_attestation_private_key(&[0x41; key_material::ATTESTATION_PRIVATE_KEY_LENGTH])
+            .unwrap();
+        persistent_store
+            .set_attestation_certificate(&[0xDD; 20])
+            .unwrap();
+        let master_keys = persistent_store.master_keys().unwrap();
+
+        persistent_store.factory_reset(&mut rng).unwrap();
+        assert_eq!(persistent_store.count_credentials().unwrap(), 0);
+        assert!(persistent_store.pin_hash().unwrap().is_none());
+        assert!(persistent_store
+            .attestation_private_key()
+            .unwrap()
+            .is_none());
+        assert!(persistent_store
+            .attestation_certificate()
+            .unwrap()
+            .is_none());
+        assert_eq!(persistent_store.aaguid().unwrap(), *key_material::AAGUID);
+        assert_ne!(
+            persistent_store.master_keys().unwrap().encryption,
+            master_keys.encryption
+        );
+    }
+
+    #[test]
+    fn test_factory_reset_locked_attestation() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let credential_source = create_credential_source(&mut rng, "example.com", vec![]);
+        assert!(persistent_store.store_credential(credential_source).is_ok());
+        persistent_store
+            .set_pin_hash(&[0x88; PIN_AUTH_LENGTH])
+            .unwrap();
+        let dummy_key = [0x41u8; key_material::ATTESTATION_PRIVATE_KEY_LENGTH];
+        let dummy_cert = [0xddu8; 20];
+        let dummy_aaguid = [0xA5u8; key_material::AAGUID_LENGTH];
+        persistent_store
+            .set_attestation_private_key(&dummy_key)
+            .unwrap();
+        persistent_store
+            .set_attestation_certificate(&dummy_cert)
+            .unwrap();
+        persistent_store.set_aaguid(&dummy_aaguid).unwrap();
+        persistent_store.lock_attestation().unwrap();
+        let master_keys = persistent_store.master_keys().unwrap();
+
+        // Everything is erased, except the locked attestation.
+        persistent_store.factory_reset(&mut rng).unwrap();
+        assert_eq!(persistent_store.count_credentials().unwrap(), 0);
+        assert!(persistent_store.pin_hash().unwrap().is_none());
+        assert_ne!(
+            persistent_store.master_keys().unwrap().encryption,
+            master_keys.encryption
+        );
+        assert!(persistent_store.attestation_locked().unwrap());
+        assert_eq!(
+            persistent_store.attestation_private_key().unwrap(),
+            Some(dummy_key)
+        );
+        assert_eq!(
+            persistent_store.attestation_certificate().unwrap(),
+            Some(dummy_cert.to_vec())
+        );
+        assert_eq!(persistent_store.aaguid().unwrap(), dummy_aaguid);
+    }
 }
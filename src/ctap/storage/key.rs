@@ -61,15 +61,46 @@ make_partition! {
 
     // WARNING: Keys should not be deleted but prefixed with `_` to avoid accidentally reusing them.
 
-    /// The attestation private key.
-    ATTESTATION_PRIVATE_KEY = 1;
+    /// The attestation private key without checksum.
+    ///
+    /// This entry is migrated to `ATTESTATION_PRIVATE_KEY` at startup.
+    _ATTESTATION_PRIVATE_KEY = 1;
 
-    /// The attestation certificate.
-    ATTESTATION_CERTIFICATE = 2;
+    /// The attestation certificate without checksum.
+    ///
+    /// This entry is migrated to `ATTESTATION_CERTIFICATE` at startup.
+    _ATTESTATION_CERTIFICATE = 2;
 
     /// The aaguid.
     AAGUID = 3;
 
+    /// Attestation material specific to some relying parties.
+    ///
+    /// Each entry holds the SHA256 hash of the RP ID, the attestation private key and the
+    /// attestation certificate, in that order, followed by their checksum.
+    RP_ATTESTATIONS = 4..12;
+
+    /// The attestation private key, followed by its checksum.
+    ATTESTATION_PRIVATE_KEY = 12;
+
+    /// The attestation certificate, followed by its checksum.
+    ATTESTATION_CERTIFICATE = 13;
+
+    /// The relying parties allowed to create credentials.
+    ///
+    /// The first byte is 1 for an allow list and 0 for a deny list. It is followed by the SHA256
+    /// hashes of the listed RP IDs. If the entry is absent, all relying parties are allowed.
+    RP_ID_POLICY = 14;
+
+    /// The number of times the store was opened since the last factory reset.
+    BOOT_COUNT = 15;
+
+    /// Whether the batch attestation material is locked.
+    ///
+    /// If the entry is absent, the material is not locked. Once present, the attestation private
+    /// key, certificate and AAGUID can't be written anymore, not even after a factory reset.
+    ATTESTATION_LOCKED = 16;
+
     // This is the persistent key limit:
     // - When adding a (persistent) key above this message, make sure its value is smaller than
     //   NUM_PERSISTENT_KEYS.
@@ -88,12 +119,37 @@ make_partition! {
     /// board may configure `MAX_SUPPORTED_RESIDENTIAL_KEYS` depending on the storage size.
     CREDENTIALS = 1700..2000;
 
+    /// The serialized large blob array, split into consecutive shards.
+    ///
+    /// Only a prefix of those keys is used, depending on the size of the array. If all entries are
+    /// absent, the array is the empty CBOR array followed by its checksum.
+    #[cfg(feature = "with_ctap2_1")]
+    LARGE_BLOB_SHARDS = 2000..2004;
+
+    /// Whether enterprise attestation is enabled.
+    ///
+    /// If the entry is absent, enterprise attestation is disabled. Only a reset disables it again.
+    #[cfg(feature = "with_ctap2_1")]
+    ENTERPRISE_ATTESTATION = 2038;
+
+    /// Whether the PIN must be changed before it can be used again.
+    ///
+    /// If the entry is absent, the PIN can be used as is. The entry is removed when a new PIN is set.
+    #[cfg(feature = "with_ctap2_1")]
+    FORCE_PIN_CHANGE = 2039;
+
+    /// Whether user verification is required for every operation.
+    ///
+    /// If the entry is absent, user verification is only required as requested by the platform.
+    #[cfg(feature = "with_ctap2_1")]
+    ALWAYS_UV = 2040;
+
     /// The secret of the CredRandom feature.
     CRED_RANDOM_SECRET = 2041;
 
     /// List of RP IDs allowed to read the minimum PIN length.
     #[cfg(feature = "with_ctap2_1")]
-    _MIN_PIN_LENGTH_RP_IDS = 2042;
+    MIN_PIN_LENGTH_RP_IDS = 2042;
 
     /// The minimum PIN length.
     ///
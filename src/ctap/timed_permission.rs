@@ -12,6 +12,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+#[cfg(feature = "debug_ctap")]
+use crate::debug_log::{log, LogLevel};
 use libtock_drivers::timer::{ClockValue, Duration};
 
 #[derive(Clone, Copy, Debug)]
@@ -25,34 +27,72 @@ impl TimedPermission {
         TimedPermission::Waiting
     }
 
+    // Durations that don't fit the alarm range are clamped. Otherwise, the timeout would wrap
+    // around the 24-bit alarm counter and the permission might be expired immediately.
     pub fn granted(now: ClockValue, grant_duration: Duration<isize>) -> TimedPermission {
+        let max_duration = now.max_duration();
+        let grant_duration = if grant_duration > max_duration {
+            #[cfg(feature = "debug_ctap")]
+            log(
+                LogLevel::Warn,
+                format_args!(
+                    "Clamping permission duration of {} ms to {} ms",
+                    grant_duration.ms(),
+                    max_duration.ms()
+                ),
+            );
+            max_duration
+        } else {
+            grant_duration
+        };
         TimedPermission::Granted(now.wrapping_add(grant_duration))
     }
 
     // Checks if the timeout is not reached, false for differing ClockValue frequencies.
     pub fn is_granted(&self, now: ClockValue) -> bool {
         if let TimedPermission::Granted(timeout) = self {
-            if let Some(remaining_duration) = timeout.wrapping_sub(now) {
-                return remaining_duration > Duration::from_ms(0);
-            }
+            return is_before(now, *timeout);
         }
         false
     }
 
+    // Returns the time left until the timeout, rounded down to milliseconds. Zero if the
+    // permission is not granted at time "now".
+    pub fn remaining(&self, now: ClockValue) -> Duration<isize> {
+        if let TimedPermission::Granted(timeout) = self {
+            if let Some(ticks) = timeout.wrapping_sub_ticks(now) {
+                if ticks > 0 {
+                    let remaining = ClockValue::new(ticks, now.clock_frequency().hz());
+                    return Duration::from_ms(remaining.ms());
+                }
+            }
+        }
+        Duration::from_ms(0)
+    }
+
     // Consumes the state and returns the current new permission state at time "now".
     // Returns a new state for differing ClockValue frequencies.
     pub fn check_expiration(self, now: ClockValue) -> TimedPermission {
         if let TimedPermission::Granted(timeout) = self {
-            if let Some(remaining_duration) = timeout.wrapping_sub(now) {
-                if remaining_duration > Duration::from_ms(0) {
-                    return TimedPermission::Granted(timeout);
-                }
+            if is_before(now, timeout) {
+                return TimedPermission::Granted(timeout);
             }
         }
         TimedPermission::Waiting
     }
 }
 
+// Checks if "now" is strictly before "timeout", i.e. a timeout is reached at its exact tick.
+// Ticks are compared directly, because converting to milliseconds would round away the last
+// sub-millisecond remainder. They are compared on the 24 bits of the alarm counter, because "now"
+// wraps around at 2^24 ticks while "timeout" was computed without wrapping.
+// False for differing ClockValue frequencies.
+fn is_before(now: ClockValue, timeout: ClockValue) -> bool {
+    timeout
+        .wrapping_sub_ticks(now)
+        .map_or(false, |ticks| ticks > 0)
+}
+
 #[cfg(feature = "with_ctap1")]
 #[derive(Debug)]
 pub struct U2fUserPresenceState {
@@ -114,10 +154,126 @@ impl U2fUserPresenceState {
     }
 }
 
-#[cfg(feature = "with_ctap1")]
 #[cfg(test)]
 mod test {
     use super::*;
+
+    const CLOCK_FREQUENCY_HZ: usize = 32768;
+    const ZERO: ClockValue = ClockValue::new(0, CLOCK_FREQUENCY_HZ);
+    // The timeout of a permission granted at this instant wraps around.
+    const BEFORE_WRAP: ClockValue = ClockValue::new(isize::MAX - 10, CLOCK_FREQUENCY_HZ);
+    // Same for the 24-bit alarm counter, from which clock values are read.
+    const BEFORE_COUNTER_WRAP: ClockValue = ClockValue::new(0xFF_FFF0, CLOCK_FREQUENCY_HZ);
+    const GRANT_DURATION: Duration<isize> = Duration::from_ms(1000);
+
+    fn offset_ticks(clock_value: ClockValue, ticks: isize) -> ClockValue {
+        ClockValue::new(
+            clock_value.num_ticks().wrapping_add(ticks),
+            CLOCK_FREQUENCY_HZ,
+        )
+    }
+
+    fn timeout_boundary(start_time: ClockValue) {
+        let permission = TimedPermission::granted(start_time, GRANT_DURATION);
+        let timeout = start_time.wrapping_add(GRANT_DURATION);
+        assert!(permission.is_granted(offset_ticks(timeout, -1)));
+        // The timeout excludes equality, so it should be over at this instant.
+        assert!(!permission.is_granted(timeout));
+        assert!(!permission.is_granted(offset_ticks(timeout, 1)));
+
+        assert!(permission
+            .check_expiration(offset_ticks(timeout, -1))
+            .is_granted(offset_ticks(timeout, -1)));
+        assert!(!permission.check_expiration(timeout).is_granted(start_time));
+    }
+
+    // Clock values read after the 24-bit counter wrapped around.
+    fn read_counter(clock_value: ClockValue) -> ClockValue {
+        ClockValue::new(clock_value.num_ticks() & 0xFF_FFFF, CLOCK_FREQUENCY_HZ)
+    }
+
+    #[test]
+    fn test_timeout_boundary() {
+        timeout_boundary(ZERO);
+        timeout_boundary(BEFORE_WRAP);
+        timeout_boundary(BEFORE_COUNTER_WRAP);
+    }
+
+    #[test]
+    fn test_timeout_boundary_counter_wrap() {
+        let permission = TimedPermission::granted(BEFORE_COUNTER_WRAP, GRANT_DURATION);
+        let timeout = BEFORE_COUNTER_WRAP.wrapping_add(GRANT_DURATION);
+        // The timeout is past the counter range, but the clock reads it after wrapping around.
+        assert!(timeout.num_ticks() > 0xFF_FFFF);
+        assert!(permission.is_granted(read_counter(offset_ticks(timeout, -1))));
+        assert!(!permission.is_granted(read_counter(timeout)));
+        assert!(!permission.is_granted(read_counter(offset_ticks(timeout, 1))));
+        assert_eq!(
+            permission.remaining(read_counter(
+                BEFORE_COUNTER_WRAP.wrapping_add(Duration::from_ms(100))
+            )),
+            Duration::from_ms(900)
+        );
+        assert!(permission
+            .check_expiration(read_counter(offset_ticks(timeout, -1)))
+            .is_granted(read_counter(offset_ticks(timeout, -1))));
+        assert!(!permission
+            .check_expiration(read_counter(timeout))
+            .is_granted(BEFORE_COUNTER_WRAP));
+    }
+
+    #[test]
+    fn test_long_duration_is_clamped() {
+        let long_duration = Duration::from_ms(isize::MAX);
+        for start_time in &[ZERO, BEFORE_WRAP] {
+            let permission = TimedPermission::granted(*start_time, long_duration);
+            assert!(permission.is_granted(*start_time));
+            assert!(permission.is_granted(start_time.wrapping_add(GRANT_DURATION)));
+            // At 32768 Hz, the alarm range is just short of 256 seconds.
+            let max_duration = Duration::from_ms(255_999);
+            assert_eq!(start_time.max_duration(), max_duration);
+            assert!(permission.is_granted(offset_ticks(start_time.wrapping_add(max_duration), -1)));
+            assert!(!permission.is_granted(start_time.wrapping_add(Duration::from_ms(256_000))));
+        }
+    }
+
+    #[test]
+    fn test_remaining() {
+        for start_time in &[ZERO, BEFORE_WRAP] {
+            let permission = TimedPermission::granted(*start_time, GRANT_DURATION);
+            assert_eq!(permission.remaining(*start_time), GRANT_DURATION);
+            // The remaining time decreases as the clock advances.
+            let mut last_remaining = GRANT_DURATION;
+            for elapsed_ms in (100..=1000).step_by(100) {
+                let now = start_time.wrapping_add(Duration::from_ms(elapsed_ms));
+                let remaining = permission.remaining(now);
+                assert!(remaining < last_remaining);
+                assert_eq!(remaining.ms(), 1000 - elapsed_ms);
+                last_remaining = remaining;
+            }
+            let timeout = start_time.wrapping_add(GRANT_DURATION);
+            assert_eq!(permission.remaining(offset_ticks(timeout, 1)).ms(), 0);
+        }
+        assert_eq!(TimedPermission::waiting().remaining(ZERO).ms(), 0);
+    }
+
+    #[test]
+    fn test_differing_frequencies() {
+        let permission = TimedPermission::granted(ZERO, GRANT_DURATION);
+        assert!(!permission.is_granted(ClockValue::new(0, 2 * CLOCK_FREQUENCY_HZ)));
+        assert_eq!(
+            permission
+                .remaining(ClockValue::new(0, 2 * CLOCK_FREQUENCY_HZ))
+                .ms(),
+            0
+        );
+    }
+}
+
+#[cfg(feature = "with_ctap1")]
+#[cfg(test)]
+mod u2f_test {
+    use super::*;
     use core::isize;
 
     const CLOCK_FREQUENCY_HZ: usize = 32768;
@@ -0,0 +1,186 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#[cfg(any(test, feature = "debug_hid"))]
+use alloc::collections::VecDeque;
+#[cfg(any(test, feature = "debug_hid"))]
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(feature = "debug_ctap")]
+use libtock_drivers::console::Console;
+
+/// Severity of a debug message, from most to least severe.
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Trace,
+}
+
+// Least severe level printed with the debug_ctap feature. Lower it to Info or Warn to silence the
+// per-packet and per-command trace messages.
+pub const LOG_LEVEL: LogLevel = LogLevel::Trace;
+
+impl LogLevel {
+    fn prefix(self) -> &'static str {
+        match self {
+            LogLevel::Error => "ERROR",
+            LogLevel::Warn => "WARN",
+            LogLevel::Info => "INFO",
+            LogLevel::Trace => "TRACE",
+        }
+    }
+}
+
+/// Writes a message with its level prefix, unless it is less severe than the threshold.
+pub fn write_log(
+    writer: &mut impl fmt::Write,
+    threshold: LogLevel,
+    level: LogLevel,
+    args: fmt::Arguments,
+) -> fmt::Result {
+    if level > threshold {
+        return Ok(());
+    }
+    writeln!(writer, "[{}] {}", level.prefix(), args)
+}
+
+/// Prints a message to the console, unless it is less severe than `LOG_LEVEL`.
+///
+/// With the debug_hid feature, the message is also kept for the HID debug log command.
+#[cfg(feature = "debug_ctap")]
+pub fn log(level: LogLevel, args: fmt::Arguments) {
+    write_log(&mut Console::new(), LOG_LEVEL, level, args).unwrap();
+    #[cfg(feature = "debug_hid")]
+    write_log(log_buffer(), LOG_LEVEL, level, args).unwrap();
+}
+
+// Number of bytes of log output kept for the HID debug log command.
+#[cfg(any(test, feature = "debug_hid"))]
+const LOG_BUFFER_CAPACITY: usize = 2048;
+
+/// Bounded buffer of log output that drops the oldest bytes when full.
+#[cfg(any(test, feature = "debug_hid"))]
+pub struct LogBuffer {
+    bytes: VecDeque<u8>,
+}
+
+#[cfg(any(test, feature = "debug_hid"))]
+impl LogBuffer {
+    pub const fn new() -> LogBuffer {
+        LogBuffer {
+            bytes: VecDeque::new(),
+        }
+    }
+
+    /// Removes and returns at most `max_len` of the oldest bytes.
+    pub fn drain(&mut self, max_len: usize) -> Vec<u8> {
+        let len = core::cmp::min(max_len, self.bytes.len());
+        self.bytes.drain(..len).collect()
+    }
+}
+
+#[cfg(any(test, feature = "debug_hid"))]
+impl fmt::Write for LogBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            if self.bytes.len() == LOG_BUFFER_CAPACITY {
+                self.bytes.pop_front();
+            }
+            self.bytes.push_back(byte);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "debug_hid")]
+static mut LOG_BUFFER: LogBuffer = LogBuffer::new();
+
+/// Returns the log output buffered for the HID debug log command.
+#[cfg(feature = "debug_hid")]
+pub fn log_buffer() -> &'static mut LogBuffer {
+    // The application is single-threaded and callers don't keep the reference.
+    unsafe { &mut *core::ptr::addr_of_mut!(LOG_BUFFER) }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::string::String;
+    use core::fmt::Write;
+
+    #[test]
+    fn test_write_log_prefix() {
+        let mut output = String::new();
+        write_log(
+            &mut output,
+            LogLevel::Trace,
+            LogLevel::Warn,
+            format_args!("Value {}", 42),
+        )
+        .unwrap();
+        assert_eq!(output, "[WARN] Value 42\n");
+    }
+
+    #[test]
+    fn test_write_log_threshold() {
+        let mut output = String::new();
+        for &level in &[
+            LogLevel::Error,
+            LogLevel::Warn,
+            LogLevel::Info,
+            LogLevel::Trace,
+        ] {
+            write_log(&mut output, LogLevel::Warn, level, format_args!("message")).unwrap();
+        }
+        assert_eq!(output, "[ERROR] message\n[WARN] message\n");
+    }
+
+    #[test]
+    fn test_log_buffer_drain() {
+        let mut buffer = LogBuffer::new();
+        write_log(
+            &mut buffer,
+            LogLevel::Trace,
+            LogLevel::Info,
+            format_args!("first"),
+        )
+        .unwrap();
+        write_log(
+            &mut buffer,
+            LogLevel::Trace,
+            LogLevel::Info,
+            format_args!("second"),
+        )
+        .unwrap();
+        assert_eq!(buffer.drain(10), b"[INFO] fir".to_vec());
+        assert_eq!(buffer.drain(100), b"st\n[INFO] second\n".to_vec());
+        assert!(buffer.drain(100).is_empty());
+    }
+
+    #[test]
+    fn test_log_buffer_drops_oldest() {
+        let mut buffer = LogBuffer::new();
+        for i in 0..LOG_BUFFER_CAPACITY {
+            write!(buffer, "{}", i % 10).unwrap();
+        }
+        write!(buffer, "new").unwrap();
+        let output = buffer.drain(LOG_BUFFER_CAPACITY + 1);
+        assert_eq!(output.len(), LOG_BUFFER_CAPACITY);
+        assert_eq!(&output[..3], b"345");
+        assert_eq!(&output[LOG_BUFFER_CAPACITY - 3..], b"new");
+    }
+}
@@ -0,0 +1,92 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use libtock_drivers::led::Led;
+use libtock_drivers::result::FlexUnwrap;
+
+/// Brightness from which LEDs without dimming support are switched on.
+pub const BINARY_BRIGHTNESS_THRESHOLD: u8 = 0x80;
+
+/// LED used for user presence feedback.
+///
+/// Boards with PWM-dimmable LEDs override `set_brightness`. All other LEDs are simply switched on
+/// or off.
+pub trait DimmableLed {
+    fn set_on(&mut self, on: bool);
+
+    /// Sets the brightness, from 0 (off) to 255 (fully on).
+    fn set_brightness(&mut self, brightness: u8) {
+        self.set_on(brightness >= BINARY_BRIGHTNESS_THRESHOLD);
+    }
+}
+
+// The Tock LED driver only supports GPIO LEDs.
+impl DimmableLed for Led {
+    fn set_on(&mut self, on: bool) {
+        self.set_state(on).flex_unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct GpioLed {
+        on: bool,
+    }
+
+    impl DimmableLed for GpioLed {
+        fn set_on(&mut self, on: bool) {
+            self.on = on;
+        }
+    }
+
+    struct PwmLed {
+        brightness: u8,
+    }
+
+    impl DimmableLed for PwmLed {
+        fn set_on(&mut self, on: bool) {
+            self.set_brightness(if on { 0xFF } else { 0x00 });
+        }
+
+        fn set_brightness(&mut self, brightness: u8) {
+            self.brightness = brightness;
+        }
+    }
+
+    #[test]
+    fn test_binary_brightness() {
+        let mut led = GpioLed { on: false };
+        led.set_brightness(0xFF);
+        assert!(led.on);
+        led.set_brightness(0x00);
+        assert!(!led.on);
+        led.set_brightness(BINARY_BRIGHTNESS_THRESHOLD);
+        assert!(led.on);
+        led.set_brightness(BINARY_BRIGHTNESS_THRESHOLD - 1);
+        assert!(!led.on);
+    }
+
+    #[test]
+    fn test_pwm_brightness() {
+        let mut led = PwmLed { brightness: 0x00 };
+        led.set_brightness(0x40);
+        assert_eq!(led.brightness, 0x40);
+        led.set_on(true);
+        assert_eq!(led.brightness, 0xFF);
+        led.set_on(false);
+        assert_eq!(led.brightness, 0x00);
+    }
+}
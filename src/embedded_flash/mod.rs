@@ -23,8 +23,8 @@ pub use self::syscall::SyscallStorage;
 mod prod {
     pub type Storage = super::SyscallStorage;
 
-    pub fn new_storage(num_pages: usize) -> Storage {
-        Storage::new(num_pages).unwrap()
+    pub fn new_storage(num_pages: usize) -> persistent_store::StorageResult<Storage> {
+        Storage::new(num_pages)
     }
 }
 #[cfg(not(feature = "std"))]
@@ -35,7 +35,7 @@ pub use self::prod::{new_storage, Storage};
 mod test {
     pub type Storage = persistent_store::BufferStorage;
 
-    pub fn new_storage(num_pages: usize) -> Storage {
+    pub fn new_storage(num_pages: usize) -> persistent_store::StorageResult<Storage> {
         const PAGE_SIZE: usize = 0x1000;
         let store = vec![0xff; num_pages * PAGE_SIZE].into_boxed_slice();
         let options = persistent_store::BufferOptions {
@@ -45,7 +45,7 @@ mod test {
             max_page_erases: 10000,
             strict_mode: true,
         };
-        Storage::new(store, options)
+        Ok(Storage::new(store, options))
     }
 }
 #[cfg(feature = "std")]
@@ -0,0 +1,96 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use libtock_drivers::timer::{ClockValue, Duration};
+
+/// Slow LED toggle showing that an idle authenticator is powered.
+///
+/// The main loop only drives it while no other LED pattern is shown. Call `reset` whenever the
+/// LEDs are used for something else, so that the heartbeat restarts switched off.
+pub struct Heartbeat {
+    interval: Duration<isize>,
+    last_toggle: ClockValue,
+    is_on: bool,
+}
+
+impl Heartbeat {
+    pub fn new(interval: Duration<isize>, now: ClockValue) -> Heartbeat {
+        Heartbeat {
+            interval,
+            last_toggle: now,
+            is_on: false,
+        }
+    }
+
+    /// Toggles the state if the interval elapsed, and returns whether the LED should be lit.
+    pub fn update(&mut self, now: ClockValue) -> bool {
+        let is_due = match now.wrapping_sub(self.last_toggle) {
+            Some(elapsed) => elapsed.ms() >= self.interval.ms(),
+            // This branch means the clock frequency changed. This should never happen.
+            None => true,
+        };
+        if is_due {
+            self.is_on = !self.is_on;
+            self.last_toggle = now;
+        }
+        self.is_on
+    }
+
+    pub fn reset(&mut self, now: ClockValue) {
+        self.is_on = false;
+        self.last_toggle = now;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::DeterministicClock;
+
+    const TICK_DURATION: Duration<isize> = Duration::from_ms(100);
+    const HEARTBEAT_INTERVAL: Duration<isize> = Duration::from_ms(500);
+
+    #[test]
+    fn test_heartbeat_toggles_at_interval() {
+        let mut clock = DeterministicClock::new(TICK_DURATION);
+        let mut heartbeat = Heartbeat::new(HEARTBEAT_INTERVAL, clock.get_current_clock());
+        for expected_on in &[true, false, true] {
+            for _ in 0..4 {
+                clock.tick();
+                assert_eq!(heartbeat.update(clock.get_current_clock()), !expected_on);
+            }
+            clock.tick();
+            assert_eq!(heartbeat.update(clock.get_current_clock()), *expected_on);
+        }
+    }
+
+    #[test]
+    fn test_heartbeat_reset() {
+        let mut clock = DeterministicClock::new(TICK_DURATION);
+        let mut heartbeat = Heartbeat::new(HEARTBEAT_INTERVAL, clock.get_current_clock());
+        for _ in 0..5 {
+            clock.tick();
+        }
+        assert!(heartbeat.update(clock.get_current_clock()));
+        // For example, user presence blinking took over the LEDs.
+        clock.tick();
+        heartbeat.reset(clock.get_current_clock());
+        for _ in 0..4 {
+            clock.tick();
+            assert!(!heartbeat.update(clock.get_current_clock()));
+        }
+        clock.tick();
+        assert!(heartbeat.update(clock.get_current_clock()));
+    }
+}
@@ -0,0 +1,175 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/// Physical LEDs used to show one kind of feedback.
+pub enum LedSelection {
+    All,
+    /// LED indices, in the order the patterns go through them.
+    Only(&'static [usize]),
+}
+
+impl LedSelection {
+    /// Returns whether LED `l` of a board with `count` LEDs is lit.
+    ///
+    /// The `pattern` receives the position of the LED in the selection and the selection size.
+    /// LEDs outside the selection are off.
+    pub fn is_on(&self, l: usize, count: usize, pattern: impl Fn(usize, usize) -> bool) -> bool {
+        match self {
+            LedSelection::All => pattern(l, count),
+            LedSelection::Only(indices) => match indices.iter().position(|&i| i == l) {
+                Some(position) => pattern(position, indices.len()),
+                None => false,
+            },
+        }
+    }
+
+    pub fn contains(&self, l: usize) -> bool {
+        self.is_on(l, l + 1, |_, _| true)
+    }
+}
+
+/// Maps each kind of LED feedback to the LEDs showing it.
+pub struct LedRoles {
+    /// Blinks while waiting for user presence.
+    pub presence: LedSelection,
+    /// Winks after a WINK command.
+    pub wink: LedSelection,
+    /// Flashes the blink code when the authenticator halts at boot.
+    pub error: LedSelection,
+    /// Toggles while idle, if the heartbeat is enabled.
+    pub heartbeat: LedSelection,
+}
+
+/// Pattern of the LEDs that blink while waiting for user presence.
+pub enum BlinkStyle {
+    /// Lights the LEDs whose index differs from the seed by an odd number of bits.
+    Parity,
+    /// Circles through the LEDs, like the wink pattern.
+    Snake,
+    /// Lights all LEDs.
+    AllOn,
+}
+
+impl BlinkStyle {
+    /// Returns whether the LED at `position` of a selection of `size` LEDs is lit.
+    pub fn is_on(&self, pattern_seed: usize, position: usize, size: usize) -> bool {
+        match self {
+            BlinkStyle::Parity => (pattern_seed ^ position).count_ones() & 1 != 0,
+            BlinkStyle::Snake => is_snake_led_on(pattern_seed, size, position),
+            BlinkStyle::AllOn => true,
+        }
+    }
+}
+
+/// Returns whether LED `l` out of `count` is lit in the "snake" pattern circling through the LEDs.
+pub fn is_snake_led_on(pattern_seed: usize, count: usize, l: usize) -> bool {
+    // This generates a "snake" pattern circling through the LEDs.
+    // Fox example with 4 LEDs the sequence of lit LEDs will be the following.
+    // 0 1 2 3
+    // * *
+    // * * *
+    //   * *
+    //   * * *
+    //     * *
+    // *   * *
+    // *     *
+    // * *   *
+    // * *
+    let a = (pattern_seed / 2) % count;
+    let b = ((pattern_seed + 1) / 2) % count;
+    let c = ((pattern_seed + 3) / 2) % count;
+
+    // On nRF52840-DK, logically swap LEDs 3 and 4 so that the order of LEDs form a circle.
+    let k = match l {
+        2 => 3,
+        3 => 2,
+        _ => l,
+    };
+    k == a || k == b || k == c
+}
+
+/// All feedback uses all LEDs, except the heartbeat that only uses the first one.
+pub const DEFAULT_LED_ROLES: LedRoles = LedRoles {
+    presence: LedSelection::All,
+    wink: LedSelection::All,
+    error: LedSelection::All,
+    heartbeat: LedSelection::Only(&[0]),
+};
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const NUM_LEDS: usize = 4;
+
+    fn lit_leds(selection: &LedSelection) -> Vec<usize> {
+        (0..NUM_LEDS)
+            .filter(|&l| selection.is_on(l, NUM_LEDS, |_, _| true))
+            .collect()
+    }
+
+    #[test]
+    fn test_default_roles() {
+        assert_eq!(lit_leds(&DEFAULT_LED_ROLES.presence), vec![0, 1, 2, 3]);
+        assert_eq!(lit_leds(&DEFAULT_LED_ROLES.wink), vec![0, 1, 2, 3]);
+        assert_eq!(lit_leds(&DEFAULT_LED_ROLES.error), vec![0, 1, 2, 3]);
+        assert_eq!(lit_leds(&DEFAULT_LED_ROLES.heartbeat), vec![0]);
+    }
+
+    #[test]
+    fn test_distinct_roles() {
+        let roles = LedRoles {
+            presence: LedSelection::Only(&[1]),
+            wink: LedSelection::Only(&[2]),
+            error: LedSelection::Only(&[0]),
+            heartbeat: LedSelection::Only(&[3]),
+        };
+        assert_eq!(lit_leds(&roles.presence), vec![1]);
+        assert_eq!(lit_leds(&roles.wink), vec![2]);
+        assert_eq!(lit_leds(&roles.error), vec![0]);
+        assert_eq!(lit_leds(&roles.heartbeat), vec![3]);
+        assert!(roles.error.contains(0));
+        assert!(!roles.error.contains(1));
+    }
+
+    fn blinking_leds(style: BlinkStyle, pattern_seed: usize) -> Vec<usize> {
+        (0..NUM_LEDS)
+            .filter(|&l| style.is_on(pattern_seed, l, NUM_LEDS))
+            .collect()
+    }
+
+    #[test]
+    fn test_blink_styles() {
+        assert_eq!(blinking_leds(BlinkStyle::Parity, 0), vec![1, 2]);
+        assert_eq!(blinking_leds(BlinkStyle::Parity, 1), vec![0, 3]);
+        assert_eq!(blinking_leds(BlinkStyle::Snake, 0), vec![0, 1]);
+        assert_eq!(blinking_leds(BlinkStyle::Snake, 1), vec![0, 1, 3]);
+        assert_eq!(blinking_leds(BlinkStyle::AllOn, 0), vec![0, 1, 2, 3]);
+        assert_eq!(blinking_leds(BlinkStyle::AllOn, 1), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_pattern_positions() {
+        // Patterns see the selected LEDs as consecutive, in the configured order.
+        let selection = LedSelection::Only(&[3, 1]);
+        let first_only = |position, size| {
+            assert_eq!(size, 2);
+            position == 0
+        };
+        let lit = (0..NUM_LEDS)
+            .filter(|&l| selection.is_on(l, NUM_LEDS, first_only))
+            .collect::<Vec<_>>();
+        assert_eq!(lit, vec![3]);
+    }
+}
@@ -16,8 +16,16 @@
 
 extern crate alloc;
 
+pub mod capabilities;
+pub mod clock;
 pub mod ctap;
+pub mod debug_log;
+pub mod dimmable_led;
 pub mod embedded_flash;
+pub mod heartbeat;
+pub mod led_roles;
+pub mod low_power;
+pub mod watchdog;
 
 #[macro_use]
 extern crate arrayref;
@@ -0,0 +1,104 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use libtock_drivers::timer::{ClockValue, Duration};
+
+/// Low power mode of an authenticator that stayed idle for a while.
+///
+/// While the mode is active, the main loop switches off the LEDs and polls for packets less
+/// often. The caller exits the mode on any activity, including partially received messages, so
+/// that no transaction waits on the slower polling.
+pub struct LowPower {
+    idle_timeout: Duration<isize>,
+    last_activity: ClockValue,
+    is_low_power: bool,
+}
+
+impl LowPower {
+    pub fn new(idle_timeout: Duration<isize>, now: ClockValue) -> LowPower {
+        LowPower {
+            idle_timeout,
+            last_activity: now,
+            is_low_power: false,
+        }
+    }
+
+    /// Enters low power mode if the idle timeout elapsed, and returns whether the mode is active.
+    pub fn update(&mut self, now: ClockValue) -> bool {
+        let is_idle = match now.wrapping_sub(self.last_activity) {
+            Some(elapsed) => elapsed.ms() >= self.idle_timeout.ms(),
+            // This branch means the clock frequency changed. This should never happen.
+            None => true,
+        };
+        if is_idle {
+            self.enter_low_power();
+        }
+        self.is_low_power
+    }
+
+    pub fn enter_low_power(&mut self) {
+        self.is_low_power = true;
+    }
+
+    /// Leaves low power mode and restarts the idle timeout.
+    pub fn exit_low_power(&mut self, now: ClockValue) {
+        self.is_low_power = false;
+        self.last_activity = now;
+    }
+
+    pub fn is_low_power(&self) -> bool {
+        self.is_low_power
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::DeterministicClock;
+
+    const TICK_DURATION: Duration<isize> = Duration::from_ms(100);
+    const IDLE_TIMEOUT: Duration<isize> = Duration::from_ms(500);
+
+    #[test]
+    fn test_low_power_after_idle_timeout() {
+        let mut clock = DeterministicClock::new(TICK_DURATION);
+        let mut low_power = LowPower::new(IDLE_TIMEOUT, clock.get_current_clock());
+        for _ in 0..4 {
+            clock.tick();
+            assert!(!low_power.update(clock.get_current_clock()));
+        }
+        clock.tick();
+        assert!(low_power.update(clock.get_current_clock()));
+        assert!(low_power.is_low_power());
+    }
+
+    #[test]
+    fn test_low_power_exit_on_activity() {
+        let mut clock = DeterministicClock::new(TICK_DURATION);
+        let mut low_power = LowPower::new(IDLE_TIMEOUT, clock.get_current_clock());
+        low_power.enter_low_power();
+        assert!(low_power.is_low_power());
+
+        // For example, a packet was received.
+        clock.tick();
+        low_power.exit_low_power(clock.get_current_clock());
+        assert!(!low_power.is_low_power());
+        for _ in 0..4 {
+            clock.tick();
+            assert!(!low_power.update(clock.get_current_clock()));
+        }
+        clock.tick();
+        assert!(low_power.update(clock.get_current_clock()));
+    }
+}
@@ -22,87 +22,248 @@ extern crate lang_items;
 extern crate arrayref;
 extern crate byteorder;
 
+#[cfg(feature = "debug_ctap")]
+mod capabilities;
+#[cfg(any(test, feature = "deterministic_clock"))]
+mod clock;
 mod ctap;
+#[cfg(feature = "debug_ctap")]
+mod debug_log;
+mod dimmable_led;
 pub mod embedded_flash;
-
+mod heartbeat;
+pub mod led_roles;
+mod low_power;
+mod watchdog;
+
+use alloc::boxed::Box;
+#[cfg(feature = "deterministic_clock")]
+use clock::DeterministicClock;
 use core::cell::Cell;
-#[cfg(feature = "debug_ctap")]
-use core::fmt::Write;
+#[cfg(feature = "rng_health_test")]
+use crypto::rng256::HealthTestedRng256;
 use crypto::rng256::TockRng256;
-use ctap::hid::{ChannelID, CtapHid, KeepaliveStatus, ProcessedPacket};
+use ctap::hid::send::send_all;
+#[cfg(not(feature = "deterministic_clock"))]
+use ctap::hid::KeepalivePacer;
+use ctap::hid::{
+    ButtonChord, ButtonHold, ChannelID, CtapHid, DoubleTap, KeepaliveStatus, PresenceBackoff,
+};
 use ctap::status_code::Ctap2StatusCode;
 use ctap::CtapState;
+#[cfg(feature = "debug_ctap")]
+use debug_log::{log, LogLevel};
+use dimmable_led::DimmableLed;
+use heartbeat::Heartbeat;
+use led_roles::{is_snake_led_on, BlinkStyle, LedRoles, DEFAULT_LED_ROLES};
 use libtock_core::result::{CommandError, EALREADY};
 use libtock_drivers::buttons;
 use libtock_drivers::buttons::ButtonState;
-#[cfg(feature = "debug_ctap")]
-use libtock_drivers::console::Console;
 use libtock_drivers::led;
 use libtock_drivers::result::{FlexUnwrap, TockError};
 use libtock_drivers::timer;
+use libtock_drivers::timer::ClockValue;
 use libtock_drivers::timer::Duration;
 #[cfg(feature = "debug_ctap")]
 use libtock_drivers::timer::Timer;
 #[cfg(feature = "debug_ctap")]
 use libtock_drivers::timer::Timestamp;
 use libtock_drivers::usb_ctap_hid;
+use low_power::LowPower;
+use watchdog::Watchdog;
 
 const KEEPALIVE_DELAY_MS: isize = 100;
 const KEEPALIVE_DELAY: Duration<isize> = Duration::from_ms(KEEPALIVE_DELAY_MS);
 const SEND_TIMEOUT: Duration<isize> = Duration::from_ms(1000);
+// Time the deterministic clock advances for each received packet.
+#[cfg(feature = "deterministic_clock")]
+const DETERMINISTIC_TICK_DURATION: Duration<isize> = KEEPALIVE_DELAY;
+
+// LED pattern shown by blink_leds while waiting for user presence. Pick the style that is most
+// legible for the LED layout of your board.
+const BLINK_STYLE: BlinkStyle = BlinkStyle::Parity;
+
+// How long the LEDs wink after a WINK command, and how long each step of the wink pattern lasts.
+// LEDs are updated at most every KEEPALIVE_DELAY, so shorter steps are not visible.
+const WINK_DURATION: Duration<isize> = Duration::from_ms(5000);
+const WINK_STEP_DELAY_MS: isize = KEEPALIVE_DELAY_MS;
+
+// Brightness of lit LEDs, from 0 to 255. Only boards with dimmable LEDs show values other than
+// fully on and off.
+const LED_BRIGHTNESS: u8 = 0xFF;
+
+// Index of a button that cancels user presence checks instead of confirming them. All other
+// buttons confirm user presence. Set it if your board has a dedicated cancel button.
+const CANCEL_BUTTON: Option<usize> = None;
+
+// Which LEDs show user presence, winking, boot errors and the heartbeat. On boards with several
+// LEDs, give each role its own LEDs with LedSelection::Only.
+const LED_ROLES: LedRoles = DEFAULT_LED_ROLES;
+
+// If set, the heartbeat LEDs toggle at this interval while the authenticator is idle, to show that
+// it is powered. Winking and user presence blinking take precedence over the heartbeat.
+const HEARTBEAT_INTERVAL: Option<Duration<isize>> = None;
+
+// If set, the authenticator enters a low power mode after being idle for this long. It then
+// switches off the LEDs and only polls for packets every LOW_POWER_POLL_INTERVAL, until the next
+// packet arrives.
+const LOW_POWER_IDLE_TIMEOUT: Option<Duration<isize>> = None;
+const LOW_POWER_POLL_INTERVAL: Duration<isize> = Duration::from_ms(1000);
+
+// If set, this function is called at every keep-alive iteration, including while waiting for user
+// presence. Set it if your board runs a hardware watchdog that the application must feed.
+const WATCHDOG_FEED: Option<fn()> = None;
+
+// If set, the private key of the next credential is generated while no packet is received, which
+// makes makeCredential faster. The key is kept in RAM until it is used or the device resets.
+const PREGENERATE_CREDENTIAL_KEY: bool = false;
+
+// If set, the store is compacted while no packet is received, instead of during the next write that
+// needs it. Compaction erases a flash page, which would otherwise delay that command.
+const COMPACT_STORE_WHEN_IDLE: bool = false;
 
 fn main() {
     // Setup the timer with a dummy callback (we only care about reading the current time, but the
     // API forces us to set an alarm callback too).
+    #[cfg(any(not(feature = "deterministic_clock"), feature = "debug_ctap"))]
     let mut with_callback = timer::with_callback(|_, _| {});
+    #[cfg(any(not(feature = "deterministic_clock"), feature = "debug_ctap"))]
     let timer = with_callback.init().flex_unwrap();
 
     // Setup USB driver.
     if !usb_ctap_hid::setup() {
         panic!("Cannot setup USB driver");
     }
-
+    #[cfg(feature = "debug_ctap")]
+    log(
+        LogLevel::Info,
+        format_args!("{:?}", capabilities::Capabilities::detect()),
+    );
+
+    // With the deterministic clock, CTAP timeouts only depend on the sequence of received packets.
+    #[cfg(feature = "deterministic_clock")]
+    let mut clock = DeterministicClock::new(DETERMINISTIC_TICK_DURATION);
+    #[cfg(feature = "deterministic_clock")]
+    let boot_time = clock.get_current_clock();
+    #[cfg(not(feature = "deterministic_clock"))]
     let boot_time = timer.get_current_clock().flex_unwrap();
+    // With the health test, a stuck hardware RNG panics instead of producing weak key material.
+    #[cfg(feature = "rng_health_test")]
+    let mut rng = HealthTestedRng256::new(TockRng256 {});
+    #[cfg(not(feature = "rng_health_test"))]
     let mut rng = TockRng256 {};
-    let mut ctap_state = CtapState::new(&mut rng, check_user_presence, boot_time);
+    // Paces the processing keep-alives of long CBOR commands. It restarts with every packet.
+    #[cfg(not(feature = "deterministic_clock"))]
+    let keepalive_pacer = Cell::new(KeepalivePacer::new(boot_time, KEEPALIVE_DELAY));
+    // Delays user presence checks after timeouts, if enabled.
+    let presence_backoff = Cell::new(PresenceBackoff::new());
+    let watchdog = Watchdog::new(WATCHDOG_FEED.map(|feed| Box::new(feed) as Box<dyn Fn()>));
+    let mut ctap_state = match CtapState::try_new(
+        &mut rng,
+        |cid| check_user_presence(cid, &presence_backoff, &watchdog),
+        boot_time,
+    ) {
+        Ok(ctap_state) => ctap_state,
+        Err(_) => halt_on_storage_error(),
+    };
+    #[cfg(feature = "debug_ctap")]
+    log(
+        LogLevel::Info,
+        format_args!("PIN retries: {:?}", ctap_state.pin_retries()),
+    );
+    // Development keys print their resident credentials, to help building host UIs.
+    #[cfg(feature = "debug_ctap")]
+    match ctap_state.list_credentials_unsafe() {
+        Ok(credentials) => {
+            for credential in credentials {
+                log(LogLevel::Trace, format_args!("{:?}", credential));
+            }
+        }
+        Err(e) => log(
+            LogLevel::Warn,
+            format_args!("Cannot list credentials: {:?}", e),
+        ),
+    }
+    #[cfg(not(feature = "deterministic_clock"))]
+    ctap_state.set_processing_keepalive(Box::new(|cid| {
+        watchdog.feed();
+        let mut pacer = keepalive_pacer.get();
+        let result = if pacer.is_due(timer.get_current_clock().flex_unwrap()) {
+            // A cancel received in the meantime aborts the command, if it is still possible.
+            send_keepalive(cid, KeepaliveStatus::Processing, KEEPALIVE_DELAY)
+        } else {
+            Ok(())
+        };
+        keepalive_pacer.set(pacer);
+        result
+    }));
+    ctap_state.set_transport_max_msg_size(CtapHid::MAX_PAYLOAD_LENGTH);
     let mut ctap_hid = CtapHid::new();
+    ctap_hid.set_wink_duration(WINK_DURATION);
 
     let mut led_counter = 0;
     let mut last_led_increment = boot_time;
+    let mut heartbeat = HEARTBEAT_INTERVAL.map(|interval| Heartbeat::new(interval, boot_time));
+    let mut low_power = LOW_POWER_IDLE_TIMEOUT.map(|timeout| LowPower::new(timeout, boot_time));
 
     // Main loop. If CTAP1 is used, we register button presses for U2F while receiving and waiting.
     // The way TockOS and apps currently interact, callbacks need a yield syscall to execute,
     // making consistent blinking patterns and sending keepalives harder.
     loop {
+        watchdog.feed();
         // Create the button callback, used for CTAP1.
         #[cfg(feature = "with_ctap1")]
         let button_touched = Cell::new(false);
         #[cfg(feature = "with_ctap1")]
-        let mut buttons_callback = buttons::with_callback(|_button_num, state| {
+        let mut buttons_callback = buttons::with_callback(|button_num, state| {
             match state {
-                ButtonState::Pressed => button_touched.set(true),
-                ButtonState::Released => (),
+                ButtonState::Pressed if Some(button_num) != CANCEL_BUTTON => {
+                    button_touched.set(true)
+                }
+                ButtonState::Pressed | ButtonState::Released => (),
             };
         });
         #[cfg(feature = "with_ctap1")]
         let mut buttons = buttons_callback.init().flex_unwrap();
         #[cfg(feature = "with_ctap1")]
-        // At the moment, all buttons are accepted. You can customize your setup here.
+        // All buttons except CANCEL_BUTTON confirm user presence.
         for mut button in &mut buttons {
             button.enable().flex_unwrap();
         }
 
         let mut pkt_request = [0; 64];
-        let has_packet = match usb_ctap_hid::recv_with_timeout(&mut pkt_request, KEEPALIVE_DELAY) {
+        let is_low_power = low_power.as_ref().map_or(false, LowPower::is_low_power);
+        #[cfg(feature = "deterministic_clock")]
+        let wink_remaining = ctap_hid.wink_remaining(clock.get_current_clock());
+        #[cfg(not(feature = "deterministic_clock"))]
+        let wink_remaining = ctap_hid.wink_remaining(timer.get_current_clock().flex_unwrap());
+        let recv_timeout = if is_low_power {
+            LOW_POWER_POLL_INTERVAL
+        } else if wink_remaining.ms() > 0 && wink_remaining < KEEPALIVE_DELAY {
+            // Wakes up when the wink ends, so that the LEDs don't wink past WINK_DURATION.
+            wink_remaining
+        } else {
+            KEEPALIVE_DELAY
+        };
+        let has_packet = match usb_ctap_hid::recv_with_timeout(&mut pkt_request, recv_timeout) {
             Some(usb_ctap_hid::SendOrRecvStatus::Received) => {
                 #[cfg(feature = "debug_ctap")]
-                print_packet_notice("Received packet", &timer);
+                print_packet_notice(LogLevel::Trace, "Received packet", &timer);
                 true
             }
             Some(_) => panic!("Error receiving packet"),
             None => false,
         };
 
+        #[cfg(feature = "deterministic_clock")]
+        {
+            if has_packet {
+                clock.tick();
+            }
+        }
+        #[cfg(feature = "deterministic_clock")]
+        let now = clock.get_current_clock();
+        #[cfg(not(feature = "deterministic_clock"))]
         let now = timer.get_current_clock().flex_unwrap();
         #[cfg(feature = "with_ctap1")]
         {
@@ -124,33 +285,53 @@ fn main() {
         ctap_state.update_command_permission(now);
         ctap_hid.wink_permission = ctap_hid.wink_permission.check_expiration(now);
 
-        if has_packet {
-            let reply = ctap_hid.process_hid_packet(&pkt_request, now, &mut ctap_state);
-            // This block handles sending packets.
-            for mut pkt_reply in reply {
-                let status = usb_ctap_hid::send_or_recv_with_timeout(&mut pkt_reply, SEND_TIMEOUT);
-                match status {
-                    None => {
-                        #[cfg(feature = "debug_ctap")]
-                        print_packet_notice("Sending packet timed out", &timer);
-                        // TODO: reset the ctap_hid state.
-                        // Since sending the packet timed out, we cancel this reply.
-                        break;
-                    }
-                    Some(usb_ctap_hid::SendOrRecvStatus::Error) => panic!("Error sending packet"),
-                    Some(usb_ctap_hid::SendOrRecvStatus::Sent) => {
-                        #[cfg(feature = "debug_ctap")]
-                        print_packet_notice("Sent packet", &timer);
-                    }
-                    Some(usb_ctap_hid::SendOrRecvStatus::Received) => {
-                        #[cfg(feature = "debug_ctap")]
-                        print_packet_notice("Received an UNEXPECTED packet", &timer);
-                        // TODO: handle this unexpected packet.
-                    }
+        let mut reply = if has_packet {
+            #[cfg(not(feature = "deterministic_clock"))]
+            keepalive_pacer.set(KeepalivePacer::new(now, KEEPALIVE_DELAY));
+            ctap_hid.process_hid_packet(&pkt_request, now, &mut ctap_state)
+        } else {
+            if PREGENERATE_CREDENTIAL_KEY {
+                ctap_state.pregenerate_credential_key();
+            }
+            if COMPACT_STORE_WHEN_IDLE && !ctap_hid.has_pending_message() {
+                // On failure, the next write compacts the store anyway.
+                ctap_state.compact_store_if_needed().ok();
+            }
+            // Without a new packet, a partially received message may have timed out.
+            ctap_hid.check_timeout(now)
+        };
+        // This block handles sending packets.
+        let mut pkt_reply = [0; 64];
+        let status = send_all(&mut reply, &mut pkt_reply, |pkt| {
+            let status = usb_ctap_hid::send_or_recv_with_timeout(pkt, SEND_TIMEOUT);
+            #[cfg(feature = "debug_ctap")]
+            {
+                if let Some(usb_ctap_hid::SendOrRecvStatus::Sent) = status {
+                    print_packet_notice(LogLevel::Trace, "Sent packet", &timer);
                 }
             }
+            status
+        });
+        match status {
+            None => {
+                #[cfg(feature = "debug_ctap")]
+                print_packet_notice(LogLevel::Warn, "Sending packet timed out", &timer);
+                #[cfg(all(feature = "debug_ctap", feature = "hid_stats"))]
+                log(LogLevel::Info, format_args!("{:?}", usb_ctap_hid::stats()));
+                // TODO: reset the ctap_hid state.
+                // Since sending the packet timed out, we cancel this reply.
+            }
+            Some(usb_ctap_hid::SendOrRecvStatus::Error) => panic!("Error sending packet"),
+            Some(usb_ctap_hid::SendOrRecvStatus::Sent) => (),
+            Some(usb_ctap_hid::SendOrRecvStatus::Received) => {
+                #[cfg(feature = "debug_ctap")]
+                print_packet_notice(LogLevel::Warn, "Received an UNEXPECTED packet", &timer);
+                // TODO: handle this unexpected packet.
+                // The rest of the reply is useless without the packet that wasn't sent.
+            }
         }
 
+        #[cfg(not(feature = "deterministic_clock"))]
         let now = timer.get_current_clock().flex_unwrap();
         if let Some(wait_duration) = now.wrapping_sub(last_led_increment) {
             if wait_duration > KEEPALIVE_DELAY {
@@ -165,187 +346,244 @@ fn main() {
             last_led_increment = now;
         }
 
-        if ctap_hid.wink_permission.is_granted(now) {
-            wink_leds(led_counter);
+        // Pending transactions and LED feedback keep the authenticator out of low power mode.
+        #[cfg(feature = "with_ctap1")]
+        let is_up_needed = ctap_state.u2f_up_state.is_up_needed(now);
+        #[cfg(not(feature = "with_ctap1"))]
+        let is_up_needed = false;
+        let is_active = has_packet
+            || ctap_hid.has_pending_message()
+            || ctap_hid.should_wink(now)
+            || is_up_needed;
+        let is_low_power = match &mut low_power {
+            Some(low_power) if is_active => {
+                low_power.exit_low_power(now);
+                false
+            }
+            Some(low_power) => low_power.update(now),
+            None => false,
+        };
+
+        if is_low_power {
+            switch_off_leds();
+            reset_heartbeat(&mut heartbeat, now);
+        } else if ctap_hid.should_wink(now) {
+            wink_leds(led_counter * KEEPALIVE_DELAY_MS as usize / WINK_STEP_DELAY_MS as usize);
+            reset_heartbeat(&mut heartbeat, now);
         } else {
             #[cfg(not(feature = "with_ctap1"))]
-            switch_off_leds();
+            show_idle_leds(&mut heartbeat, now);
             #[cfg(feature = "with_ctap1")]
             {
                 if ctap_state.u2f_up_state.is_up_needed(now) {
                     // Flash the LEDs with an almost regular pattern. The inaccuracy comes from
                     // delay caused by processing and sending of packets.
                     blink_leds(led_counter);
+                    reset_heartbeat(&mut heartbeat, now);
                 } else {
-                    switch_off_leds();
+                    show_idle_leds(&mut heartbeat, now);
                 }
             }
         }
     }
 }
 
+// Without storage, the authenticator can't work. Instead of panicking in the middle of the store
+// initialization, we halt showing the storage blink code of the panic handler.
+#[cfg(not(feature = "std"))]
+fn halt_on_storage_error() -> ! {
+    lang_items::flash_error_code_on(lang_items::blink_code::ErrorCategory::Storage, |l| {
+        LED_ROLES.error.contains(l)
+    })
+}
+
+#[cfg(feature = "std")]
+fn halt_on_storage_error() -> ! {
+    panic!("Cannot open the persistent store");
+}
+
 #[cfg(feature = "debug_ctap")]
-fn print_packet_notice(notice_text: &str, timer: &Timer) {
+fn print_packet_notice(level: LogLevel, notice_text: &str, timer: &Timer) {
     let now = timer.get_current_clock().flex_unwrap();
     let now_us = (Timestamp::<f64>::from_clock_value(now).ms() * 1000.0) as u64;
-    writeln!(
-        Console::new(),
-        "{} at {}.{:06} s",
-        notice_text,
-        now_us / 1_000_000,
-        now_us % 1_000_000
-    )
-    .unwrap();
+    log(
+        level,
+        format_args!(
+            "{} at {}.{:06} s",
+            notice_text,
+            now_us / 1_000_000,
+            now_us % 1_000_000
+        ),
+    );
 }
 
-// Returns whether the keepalive was sent, or false if cancelled.
-fn send_keepalive_up_needed(
+// Returns whether the keepalive was sent, or an error if cancelled.
+fn send_keepalive(
     cid: ChannelID,
+    keepalive_status: KeepaliveStatus,
     timeout: Duration<isize>,
 ) -> Result<(), Ctap2StatusCode> {
-    let keepalive_msg = CtapHid::keepalive(cid, KeepaliveStatus::UpNeeded);
-    for mut pkt in keepalive_msg {
-        let status = usb_ctap_hid::send_or_recv_with_timeout(&mut pkt, timeout);
-        match status {
-            None => {
-                #[cfg(feature = "debug_ctap")]
-                writeln!(Console::new(), "Sending a KEEPALIVE packet timed out").unwrap();
-                // TODO: abort user presence test?
-            }
-            Some(usb_ctap_hid::SendOrRecvStatus::Error) => panic!("Error sending KEEPALIVE packet"),
-            Some(usb_ctap_hid::SendOrRecvStatus::Sent) => {
-                #[cfg(feature = "debug_ctap")]
-                writeln!(Console::new(), "Sent KEEPALIVE packet").unwrap();
-            }
-            Some(usb_ctap_hid::SendOrRecvStatus::Received) => {
-                // We only parse one packet, because we only care about CANCEL.
-                let (received_cid, processed_packet) = CtapHid::process_single_packet(&pkt);
-                if received_cid != &cid {
-                    #[cfg(feature = "debug_ctap")]
-                    writeln!(
-                        Console::new(),
-                        "Received a packet on channel ID {:?} while sending a KEEPALIVE packet",
-                        received_cid,
-                    )
-                    .unwrap();
-                    return Ok(());
-                }
-                match processed_packet {
-                    ProcessedPacket::InitPacket { cmd, .. } => {
-                        if cmd == CtapHid::COMMAND_CANCEL {
-                            // We ignore the payload, we can't answer with an error code anyway.
-                            #[cfg(feature = "debug_ctap")]
-                            writeln!(Console::new(), "User presence check cancelled").unwrap();
-                            return Err(Ctap2StatusCode::CTAP2_ERR_KEEPALIVE_CANCEL);
-                        } else {
-                            #[cfg(feature = "debug_ctap")]
-                            writeln!(
-                                Console::new(),
-                                "Discarded packet with command {} received while sending a KEEPALIVE packet",
-                                cmd,
-                            )
-                            .unwrap();
-                        }
-                    }
-                    ProcessedPacket::ContinuationPacket { .. } => {
-                        #[cfg(feature = "debug_ctap")]
-                        writeln!(
-                            Console::new(),
-                            "Discarded continuation packet received while sending a KEEPALIVE packet",
-                        )
-                        .unwrap();
-                    }
-                }
-            }
-        }
-    }
-    Ok(())
+    CtapHid::send_keepalive(cid, keepalive_status, |pkt| {
+        usb_ctap_hid::send_or_recv_with_timeout(pkt, timeout)
+    })
+}
+
+// Boards without LEDs have no LED driver. All LED feedback is then skipped, but user presence
+// still works with the buttons.
+fn led_count() -> usize {
+    led::count().unwrap_or(0)
 }
 
 fn blink_leds(pattern_seed: usize) {
-    for l in 0..led::count().flex_unwrap() {
-        if (pattern_seed ^ l).count_ones() & 1 != 0 {
-            led::get(l).flex_unwrap().on().flex_unwrap();
-        } else {
-            led::get(l).flex_unwrap().off().flex_unwrap();
-        }
+    let count = led_count();
+    for l in 0..count {
+        let is_on = LED_ROLES
+            .presence
+            .is_on(l, count, |k, n| BLINK_STYLE.is_on(pattern_seed, k, n));
+        set_led(l, is_on);
     }
 }
 
 fn wink_leds(pattern_seed: usize) {
-    // This generates a "snake" pattern circling through the LEDs.
-    // Fox example with 4 LEDs the sequence of lit LEDs will be the following.
-    // 0 1 2 3
-    // * *
-    // * * *
-    //   * *
-    //   * * *
-    //     * *
-    // *   * *
-    // *     *
-    // * *   *
-    // * *
-    let count = led::count().flex_unwrap();
-    let a = (pattern_seed / 2) % count;
-    let b = ((pattern_seed + 1) / 2) % count;
-    let c = ((pattern_seed + 3) / 2) % count;
-
+    let count = led_count();
     for l in 0..count {
-        // On nRF52840-DK, logically swap LEDs 3 and 4 so that the order of LEDs form a circle.
-        let k = match l {
-            2 => 3,
-            3 => 2,
-            _ => l,
-        };
-        if k == a || k == b || k == c {
-            led::get(l).flex_unwrap().on().flex_unwrap();
-        } else {
-            led::get(l).flex_unwrap().off().flex_unwrap();
+        let is_on = LED_ROLES
+            .wink
+            .is_on(l, count, |k, n| is_snake_led_on(pattern_seed, n, k));
+        set_led(l, is_on);
+    }
+}
+
+fn set_led(l: usize, is_on: bool) {
+    let brightness = if is_on { LED_BRIGHTNESS } else { 0 };
+    led::get(l).flex_unwrap().set_brightness(brightness);
+}
+
+// Switches off all LEDs, except the heartbeat if it is enabled and due.
+fn show_idle_leds(heartbeat: &mut Option<Heartbeat>, now: ClockValue) {
+    match heartbeat {
+        Some(heartbeat) => {
+            let is_heartbeat_on = heartbeat.update(now);
+            for l in 0..led_count() {
+                set_led(l, is_heartbeat_on && LED_ROLES.heartbeat.contains(l));
+            }
         }
+        None => switch_off_leds(),
+    }
+}
+
+fn reset_heartbeat(heartbeat: &mut Option<Heartbeat>, now: ClockValue) {
+    if let Some(heartbeat) = heartbeat {
+        heartbeat.reset(now);
     }
 }
 
 fn switch_off_leds() {
-    for l in 0..led::count().flex_unwrap() {
+    for l in 0..led_count() {
         led::get(l).flex_unwrap().off().flex_unwrap();
     }
 }
 
-fn check_user_presence(cid: ChannelID) -> Result<(), Ctap2StatusCode> {
-    // The timeout is N times the keepalive delay.
-    const TIMEOUT_ITERATIONS: usize = ctap::TOUCH_TIMEOUT_MS as usize / KEEPALIVE_DELAY_MS as usize;
-
+fn check_user_presence(
+    cid: ChannelID,
+    presence_backoff: &Cell<PresenceBackoff>,
+    watchdog: &Watchdog,
+) -> Result<(), Ctap2StatusCode> {
     // First, send a keep-alive packet to notify that the keep-alive status has changed.
-    send_keepalive_up_needed(cid, KEEPALIVE_DELAY)?;
+    send_keepalive(cid, KeepaliveStatus::UpNeeded, KEEPALIVE_DELAY)?;
 
-    // Listen to the button presses.
+    // Listen to the button presses and releases. Touches and releases are those of the whole
+    // chord of buttons required for user presence.
     let button_touched = Cell::new(false);
-    let mut buttons_callback = buttons::with_callback(|_button_num, state| {
+    let button_released = Cell::new(false);
+    let button_chord = Cell::new(ButtonChord::new(
+        ctap::USER_PRESENCE_BUTTONS,
+        ctap::USER_PRESENCE_CONCURRENT_BUTTONS,
+    ));
+    let button_canceled = Cell::new(false);
+    let press_button = |button_num| {
+        let mut chord = button_chord.get();
+        if chord.press(button_num) {
+            button_touched.set(true);
+        }
+        button_chord.set(chord);
+    };
+    let mut buttons_callback = buttons::with_callback(|button_num, state| {
         match state {
-            ButtonState::Pressed => button_touched.set(true),
-            ButtonState::Released => (),
+            ButtonState::Pressed if Some(button_num) == CANCEL_BUTTON => button_canceled.set(true),
+            ButtonState::Pressed => press_button(button_num),
+            ButtonState::Released if Some(button_num) == CANCEL_BUTTON => (),
+            ButtonState::Released => {
+                let mut chord = button_chord.get();
+                if chord.release(button_num) {
+                    button_released.set(true);
+                }
+                button_chord.set(chord);
+            }
         };
     });
     let mut buttons = buttons_callback.init().flex_unwrap();
-    // At the moment, all buttons are accepted. You can customize your setup here.
-    for mut button in &mut buttons {
-        button.enable().flex_unwrap();
+    // All buttons are enabled, CANCEL_BUTTON is told apart in the callback.
+    for (button_num, mut button) in (&mut buttons).into_iter().enumerate() {
+        let button = button.enable().flex_unwrap();
+        // A button held before the check started doesn't trigger the callback. It counts as a
+        // press at the start of the check, so that the first iteration doesn't wait.
+        if Some(button_num) != CANCEL_BUTTON {
+            if let ButtonState::Pressed = button.read().flex_unwrap() {
+                press_button(button_num);
+            }
+        }
     }
 
+    // Setup a keep-alive callback. The timer is only initialized once and reused for every
+    // iteration, so that the clock frequency is not queried again for each alarm.
+    let keepalive_expired = Cell::new(false);
+    let mut keepalive_callback = timer::with_callback(|_, _| {
+        keepalive_expired.set(true);
+    });
+    let mut keepalive = keepalive_callback.init().flex_unwrap();
+
     let mut keepalive_response = Ok(());
-    for i in 0..TIMEOUT_ITERATIONS {
-        blink_leds(i);
+    let mut button_hold = ButtonHold::new(Duration::from_ms(ctap::USER_PRESENCE_HOLD_MS));
+    let mut double_tap = ctap::DOUBLE_TAP_CANCEL_WINDOW_MS
+        .map(|window_ms| DoubleTap::new(Duration::from_ms(window_ms)));
+    // Whether a press or hold confirmed, possibly still waiting for the double tap window to close.
+    let mut pending_confirmation = false;
+    let mut user_confirmed = false;
+    // Time elapsed since the beginning of the check, accumulated after each iteration.
+    let mut elapsed_ms = 0;
+    let mut last_clock = keepalive.get_current_clock().flex_unwrap();
+    let cooldown_ms = if ctap::USE_USER_PRESENCE_BACKOFF {
+        presence_backoff.get().cooldown(last_clock).ms()
+    } else {
+        0
+    };
+    while elapsed_ms < cooldown_ms + ctap::TOUCH_TIMEOUT_MS {
+        watchdog.feed();
+        // The blink pattern and the keep-alive cadence are derived from the elapsed time, so that
+        // the time spent sending packets doesn't make them drift.
+        let in_cooldown = elapsed_ms < cooldown_ms;
+        if in_cooldown {
+            switch_off_leds();
+        } else {
+            blink_leds(((elapsed_ms - cooldown_ms) / KEEPALIVE_DELAY_MS) as usize);
+        }
+        let mut keepalive_delay_ms = KEEPALIVE_DELAY_MS - elapsed_ms % KEEPALIVE_DELAY_MS;
+        // Rounding the clock to milliseconds may lag behind the alarm. We don't want to send two
+        // keep-alive packets in a row because of that.
+        if keepalive_delay_ms < KEEPALIVE_DELAY_MS / 2 {
+            keepalive_delay_ms += KEEPALIVE_DELAY_MS;
+        }
 
-        // Setup a keep-alive callback.
-        let keepalive_expired = Cell::new(false);
-        let mut keepalive_callback = timer::with_callback(|_, _| {
-            keepalive_expired.set(true);
-        });
-        let mut keepalive = keepalive_callback.init().flex_unwrap();
-        let keepalive_alarm = keepalive.set_alarm(KEEPALIVE_DELAY).flex_unwrap();
+        keepalive_expired.set(false);
+        let keepalive_alarm = keepalive
+            .set_alarm_us(keepalive_delay_ms as u32 * 1000)
+            .flex_unwrap();
 
         // Wait for a button touch or an alarm.
-        libtock_drivers::util::yieldk_for(|| button_touched.get() || keepalive_expired.get());
+        libtock_drivers::util::yieldk_for(|| {
+            button_touched.get() || button_canceled.get() || keepalive_expired.get()
+        });
 
         // Cleanup alarm callback.
         match keepalive.stop_alarm(keepalive_alarm) {
@@ -362,19 +600,60 @@ fn check_user_presence(cid: ChannelID) -> Result<(), Ctap2StatusCode> {
             }
         }
 
-        // TODO: this may take arbitrary time. The keepalive_delay should be adjusted accordingly,
-        // so that LEDs blink with a consistent pattern.
+        let now = keepalive.get_current_clock().flex_unwrap();
+        elapsed_ms += match now.wrapping_sub(last_clock) {
+            Some(duration) if duration.ms() >= 0 => duration.ms(),
+            // The clock wrapped around, we assume that the whole delay elapsed.
+            _ => keepalive_delay_ms,
+        };
+        last_clock = now;
+
+        if in_cooldown {
+            // Touches during the cooldown don't count, but cancelling still works.
+            button_touched.set(false);
+        }
+
+        // Handling the release first makes a release followed by a new press restart the hold. A
+        // short press still confirms if no hold is required.
+        if button_released.get() {
+            button_hold.release();
+        }
+        if button_touched.get() {
+            button_hold.press(now);
+            if let Some(double_tap) = &mut double_tap {
+                if double_tap.tap(now) {
+                    button_canceled.set(true);
+                }
+            }
+        }
+        pending_confirmation |= button_hold.is_confirmed(now);
+        user_confirmed = pending_confirmation
+            && !double_tap
+                .as_ref()
+                .map_or(false, |double_tap| double_tap.is_pending(now));
+        if !button_chord.get().is_held() {
+            button_hold.release();
+        }
+        button_touched.set(false);
+        button_released.set(false);
+
         if keepalive_expired.get() {
             // Do not return immediately, because we must clean up still.
-            keepalive_response = send_keepalive_up_needed(cid, KEEPALIVE_DELAY);
+            keepalive_response = send_keepalive(cid, KeepaliveStatus::UpNeeded, KEEPALIVE_DELAY);
         }
 
-        if button_touched.get() || keepalive_response.is_err() {
+        if user_confirmed || button_canceled.get() || keepalive_response.is_err() {
             break;
         }
     }
 
     switch_off_leds();
+    let mut backoff = presence_backoff.get();
+    backoff.record(
+        last_clock,
+        !user_confirmed && !button_canceled.get() && keepalive_response.is_ok(),
+    );
+    presence_backoff.set(backoff);
 
     // Cleanup button callbacks.
     for mut button in &mut buttons {
@@ -384,7 +663,14 @@ fn check_user_presence(cid: ChannelID) -> Result<(), Ctap2StatusCode> {
     // Returns whether the user was present.
     if keepalive_response.is_err() {
         keepalive_response
-    } else if button_touched.get() {
+    } else if button_canceled.get() {
+        #[cfg(feature = "debug_ctap")]
+        log(
+            LogLevel::Info,
+            format_args!("User presence check cancelled by button"),
+        );
+        Err(Ctap2StatusCode::CTAP2_ERR_KEEPALIVE_CANCEL)
+    } else if user_confirmed {
         Ok(())
     } else {
         Err(Ctap2StatusCode::CTAP2_ERR_USER_ACTION_TIMEOUT)
@@ -0,0 +1,76 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::boxed::Box;
+
+/// Feeds a hardware watchdog during long waits, if the board has one.
+///
+/// The feed function is called once per keep-alive iteration, both while idling and while waiting
+/// for user presence, so the watchdog period must be longer than the keep-alive delay. Without a
+/// feed function, feeding does nothing.
+pub struct Watchdog<'a> {
+    feed: Option<Box<dyn Fn() + 'a>>,
+}
+
+impl<'a> Watchdog<'a> {
+    pub fn new(feed: Option<Box<dyn Fn() + 'a>>) -> Watchdog<'a> {
+        Watchdog { feed }
+    }
+
+    pub fn feed(&self) {
+        if let Some(feed) = &self.feed {
+            feed();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::DeterministicClock;
+    use crate::ctap::TOUCH_TIMEOUT_MS;
+    use core::cell::Cell;
+    use libtock_drivers::timer::Duration;
+
+    const KEEPALIVE_DELAY_MS: isize = 100;
+
+    // Simulates a user presence check that times out, feeding the watchdog at each keep-alive.
+    fn wait_for_user_presence(watchdog: &Watchdog) {
+        let mut clock = DeterministicClock::new(Duration::from_ms(KEEPALIVE_DELAY_MS));
+        let start = clock.get_current_clock();
+        loop {
+            clock.tick();
+            watchdog.feed();
+            let elapsed = clock.get_current_clock().wrapping_sub(start).unwrap();
+            if elapsed.ms() >= TOUCH_TIMEOUT_MS {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn test_watchdog_fed_at_each_keepalive() {
+        let num_feeds = Cell::new(0);
+        let watchdog = Watchdog::new(Some(Box::new(|| num_feeds.set(num_feeds.get() + 1))));
+        wait_for_user_presence(&watchdog);
+        assert_eq!(num_feeds.get(), TOUCH_TIMEOUT_MS / KEEPALIVE_DELAY_MS);
+    }
+
+    #[test]
+    fn test_watchdog_without_feed() {
+        let watchdog = Watchdog::new(None);
+        // Feeding is a no-op and must not panic.
+        wait_for_user_presence(&watchdog);
+    }
+}
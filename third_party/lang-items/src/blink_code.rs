@@ -0,0 +1,99 @@
+// Blink codes shown on the LEDs after a panic, to tell apart the most common failures without a
+// console. A code of N is N short flashes followed by a pause, repeated forever.
+
+// Number of steps of the pause between two repetitions of a code.
+const PAUSE_STEPS: usize = 6;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorCategory {
+    Other = 1,
+    Crypto = 2,
+    Storage = 3,
+    Usb = 4,
+}
+
+impl ErrorCategory {
+    // Guesses the category from the source file of the panic, without allocating.
+    pub fn from_file(file: &str) -> ErrorCategory {
+        if file.contains("crypto") {
+            ErrorCategory::Crypto
+        } else if file.contains("store") || file.contains("storage") || file.contains("flash") {
+            ErrorCategory::Storage
+        } else if file.contains("usb") || file.contains("hid") {
+            ErrorCategory::Usb
+        } else {
+            ErrorCategory::Other
+        }
+    }
+
+    // Returns whether the LEDs are on at the given step of the blink code.
+    pub fn is_on(self, step: usize) -> bool {
+        let flash_steps = 2 * self as usize;
+        let step = step % (flash_steps + PAUSE_STEPS);
+        step < flash_steps && step % 2 == 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn pattern(category: ErrorCategory, num_steps: usize) -> Vec<bool> {
+        (0..num_steps).map(|step| category.is_on(step)).collect()
+    }
+
+    #[test]
+    fn test_category_from_file() {
+        assert_eq!(
+            ErrorCategory::from_file("libraries/crypto/src/ecdsa.rs"),
+            ErrorCategory::Crypto
+        );
+        assert_eq!(
+            ErrorCategory::from_file("libraries/persistent_store/src/store.rs"),
+            ErrorCategory::Storage
+        );
+        assert_eq!(
+            ErrorCategory::from_file("src/embedded_flash/syscall.rs"),
+            ErrorCategory::Storage
+        );
+        assert_eq!(
+            ErrorCategory::from_file("third_party/libtock-drivers/src/usb_ctap_hid.rs"),
+            ErrorCategory::Usb
+        );
+        assert_eq!(
+            ErrorCategory::from_file("src/main.rs"),
+            ErrorCategory::Other
+        );
+    }
+
+    #[test]
+    fn test_blink_code_pattern() {
+        let (x, o) = (true, false);
+        assert_eq!(
+            pattern(ErrorCategory::Other, 16),
+            vec![x, o, o, o, o, o, o, o, x, o, o, o, o, o, o, o]
+        );
+        assert_eq!(
+            pattern(ErrorCategory::Storage, 14),
+            vec![x, o, x, o, x, o, o, o, o, o, o, o, x, o]
+        );
+    }
+
+    #[test]
+    fn test_blink_code_flash_count() {
+        for &category in &[
+            ErrorCategory::Other,
+            ErrorCategory::Crypto,
+            ErrorCategory::Storage,
+            ErrorCategory::Usb,
+        ] {
+            let period = 2 * category as usize + PAUSE_STEPS;
+            let num_flashes = pattern(category, period).iter().filter(|&&on| on).count();
+            assert_eq!(num_flashes, category as usize);
+            assert_eq!(
+                pattern(category, 2 * period)[period..],
+                pattern(category, period)[..]
+            );
+        }
+    }
+}
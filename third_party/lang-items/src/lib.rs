@@ -3,11 +3,16 @@
 
 #[cfg(not(feature = "std"))]
 mod allocator;
+#[cfg(any(not(feature = "std"), test))]
+pub mod blink_code;
 #[cfg(not(feature = "std"))]
 mod panic_handler;
 #[cfg(not(feature = "std"))]
 mod util;
 
+#[cfg(not(feature = "std"))]
+pub use util::{flash_error_code, flash_error_code_on};
+
 #[cfg(feature = "std")]
 #[no_mangle]
 unsafe fn libtock_alloc_init(_app_heap_start: usize, _app_heap_size: usize) {
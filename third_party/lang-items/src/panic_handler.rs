@@ -1,3 +1,4 @@
+use crate::blink_code::ErrorCategory;
 use crate::util;
 #[cfg(feature = "panic_console")]
 use core::fmt::Write;
@@ -22,5 +23,9 @@ fn panic_handler(_info: &PanicInfo) -> ! {
         }
     }
 
-    util::flash_all_leds();
+    let category = match _info.location() {
+        Some(location) => ErrorCategory::from_file(location.file()),
+        None => ErrorCategory::Other,
+    };
+    util::flash_error_code(category);
 }
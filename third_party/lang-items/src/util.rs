@@ -1,3 +1,4 @@
+use crate::blink_code::ErrorCategory;
 use libtock_drivers::led;
 use libtock_drivers::timer::{self, Duration};
 
@@ -11,22 +12,25 @@ pub fn signal_oom() {
     let _ = libtock_core::syscalls::command1_insecure(8, 2, 1);
 }
 
-pub fn flash_all_leds() -> ! {
-    // Flash all LEDs (if available). All errors from syscalls are ignored: we are already inside a
-    // panic handler so there is nothing much to do if simple drivers (timer, LEDs) don't work.
+pub fn flash_error_code(category: ErrorCategory) -> ! {
+    flash_error_code_on(category, |_| true)
+}
+
+pub fn flash_error_code_on(category: ErrorCategory, is_error_led: impl Fn(usize) -> bool) -> ! {
+    // Flash the selected LEDs (if available) with the blink code of the category. All errors from
+    // syscalls are ignored: we are already inside a panic handler so there is nothing much to do
+    // if simple drivers (timer, LEDs) don't work.
+    let mut step = 0usize;
     loop {
         if let Ok(leds) = led::all() {
-            for led in leds {
-                let _ = led.on();
-            }
-        }
-        let _ = timer::sleep(Duration::from_ms(100));
-        if let Ok(leds) = led::all() {
-            for led in leds {
-                let _ = led.off();
+            for (l, led) in leds.enumerate() {
+                if is_error_led(l) {
+                    let _ = led.set_state(category.is_on(step));
+                }
             }
         }
-        let _ = timer::sleep(Duration::from_ms(100));
+        let _ = timer::sleep(Duration::from_ms(200));
+        step = step.wrapping_add(1);
     }
 }
 
@@ -16,6 +16,11 @@ mod subscribe_nr {
     pub const SUBSCRIBE_CALLBACK: usize = 0;
 }
 
+pub fn count() -> TockResult<usize> {
+    let count = syscalls::command(DRIVER_NUMBER, command_nr::COUNT, 0, 0)?;
+    Ok(count)
+}
+
 pub fn with_callback<CB>(callback: CB) -> WithCallback<CB> {
     WithCallback { callback }
 }
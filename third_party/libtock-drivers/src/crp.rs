@@ -1,4 +1,5 @@
-use crate::result::TockResult;
+use crate::result::{TockError, TockResult};
+use libtock_core::result::{CommandError, EALREADY};
 use libtock_core::syscalls;
 
 const DRIVER_NUMBER: usize = 0x00008;
@@ -9,7 +10,7 @@ mod command_nr {
     pub const SET_PROTECTION: usize = 2;
 }
 
-#[derive(PartialOrd, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialOrd, PartialEq)]
 pub enum ProtectionLevel {
     /// Unsupported feature
     Unknown = 0,
@@ -50,3 +51,22 @@ pub fn set_protection(level: ProtectionLevel) -> TockResult<()> {
     syscalls::command(DRIVER_NUMBER, command_nr::SET_PROTECTION, level as usize, 0)?;
     Ok(())
 }
+
+/// Raises the protection level to `level`.
+///
+/// Unlike `set_protection`, this succeeds if the chip is already at the requested level.
+pub fn lock_level(level: ProtectionLevel) -> TockResult<()> {
+    match set_protection(level) {
+        Ok(())
+        | Err(TockError::Command(CommandError {
+            return_code: EALREADY,
+            ..
+        })) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Raises the protection level to the maximum the chip supports.
+pub fn lock() -> TockResult<()> {
+    lock_level(ProtectionLevel::FullyLocked)
+}
@@ -1,6 +1,7 @@
 use crate::result::{FlexUnwrap, OtherError, TockError, TockResult};
 use crate::util;
 use core::cell::Cell;
+use core::convert::TryFrom;
 use core::isize;
 use core::marker::PhantomData;
 use core::ops::{Add, AddAssign, Sub};
@@ -10,6 +11,14 @@ use libtock_core::syscalls;
 
 const DRIVER_NUMBER: usize = 0x00000;
 
+/// The alarm counter of the nRF52840 RTC has 24 bits, so clock values wrap around at 2^24 ticks.
+const ALARM_COUNTER_BITS: usize = 24;
+
+/// Largest number of ticks an alarm can be set ahead of the current clock value.
+///
+/// Past half of the counter range, an alarm instant can't be told apart from one in the past.
+pub const MAX_ALARM_TICKS: usize = (1 << (ALARM_COUNTER_BITS - 1)) - 1;
+
 mod command_nr {
     pub const IS_DRIVER_AVAILABLE: usize = 0;
     pub const GET_CLOCK_FREQUENCY: usize = 1;
@@ -150,7 +159,30 @@ impl<'a> Timer<'a> {
                 }
             }
         };
-        let alarm_instant = now.num_ticks() as usize + ticks;
+        let alarm_instant = match alarm_instant(now, ticks) {
+            Some(alarm_instant) => alarm_instant,
+            None => return Err(OtherError::TimerDriverDurationOutOfRange.into()),
+        };
+
+        let alarm_id = syscalls::command(DRIVER_NUMBER, command_nr::SET_ALARM, alarm_instant, 0)?;
+
+        Ok(Alarm { alarm_id })
+    }
+
+    /// Sets an alarm with microsecond precision.
+    ///
+    /// Unlike `set_alarm`, the number of ticks is computed from the clock frequency directly,
+    /// without rounding the duration to whole milliseconds.
+    pub fn set_alarm_us(&mut self, duration_us: u32) -> TockResult<Alarm> {
+        let now = self.get_current_clock()?;
+        let alarm_instant = match self
+            .clock_frequency
+            .ticks_from_us(duration_us)
+            .and_then(|ticks| alarm_instant(now, ticks))
+        {
+            Some(alarm_instant) => alarm_instant,
+            None => return Err(OtherError::TimerDriverDurationOutOfRange.into()),
+        };
 
         let alarm_id = syscalls::command(DRIVER_NUMBER, command_nr::SET_ALARM, alarm_instant, 0)?;
 
@@ -158,6 +190,15 @@ impl<'a> Timer<'a> {
     }
 }
 
+// Returns the counter value of an alarm that fires the given number of ticks after "now", or None
+// if the alarm counter can't represent that many ticks ahead.
+fn alarm_instant(now: ClockValue, ticks: usize) -> Option<usize> {
+    if ticks > MAX_ALARM_TICKS {
+        return None;
+    }
+    Some((now.num_ticks() as usize).wrapping_add(ticks))
+}
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct ClockFrequency {
     hz: usize,
@@ -167,6 +208,15 @@ impl ClockFrequency {
     pub fn hz(&self) -> usize {
         self.hz
     }
+
+    /// Converts a duration in microseconds to a number of ticks, rounded down.
+    ///
+    /// Returns `None` if the number of ticks doesn't fit in a `usize`.
+    fn ticks_from_us(&self, duration_us: u32) -> Option<usize> {
+        // The frequency may not fit in 32 bits on a 64-bit host, so the product is checked.
+        let ticks = (duration_us as u64).checked_mul(self.hz as u64)? / 1_000_000;
+        usize::try_from(ticks).ok()
+    }
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -187,6 +237,10 @@ impl ClockValue {
         self.num_ticks
     }
 
+    pub fn clock_frequency(&self) -> ClockFrequency {
+        self.clock_frequency
+    }
+
     // Computes (value * factor) / divisor, even when value * factor >= isize::MAX.
     fn scale_int(value: isize, factor: isize, divisor: isize) -> isize {
         // As long as isize is not i64, this should be fine. If not, this is an alternative:
@@ -212,10 +266,20 @@ impl ClockValue {
         }
     }
 
+    // Longest duration that can be added to this clock value, while an alarm can still be set to
+    // the result. Both conversions round down, so the duration is at most MAX_ALARM_TICKS ticks.
+    pub fn max_duration(&self) -> Duration<isize> {
+        Duration::from_ms(ClockValue::scale_int(
+            MAX_ALARM_TICKS as isize,
+            1000,
+            self.clock_frequency.hz() as isize,
+        ))
+    }
+
     pub fn wrapping_sub(self, other: ClockValue) -> Option<Duration<isize>> {
         if self.clock_frequency == other.clock_frequency {
             let clock_duration = ClockValue {
-                num_ticks: self.num_ticks - other.num_ticks,
+                num_ticks: self.num_ticks.wrapping_sub(other.num_ticks),
                 clock_frequency: self.clock_frequency,
             };
             Some(Duration::from_ms(clock_duration.ms()))
@@ -223,6 +287,19 @@ impl ClockValue {
             None
         }
     }
+
+    // Returns the number of ticks from "other" to this clock value, taken on the bits of the alarm
+    // counter, i.e. in [-2^23, 2^23). Clock values read from the driver wrap around at 2^24 ticks,
+    // while those computed with wrapping_add don't, so only these bits can be compared.
+    // None for differing frequencies.
+    pub fn wrapping_sub_ticks(self, other: ClockValue) -> Option<isize> {
+        if self.clock_frequency == other.clock_frequency {
+            let shift = 8 * core::mem::size_of::<isize>() - ALARM_COUNTER_BITS;
+            Some(self.num_ticks.wrapping_sub(other.num_ticks) << shift >> shift)
+        } else {
+            None
+        }
+    }
 }
 
 pub struct Alarm {
@@ -332,3 +409,99 @@ where
         self.ms += duration.ms();
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CLOCK_FREQUENCY: ClockFrequency = ClockFrequency { hz: 32768 };
+
+    #[test]
+    fn test_ticks_from_us_rounding() {
+        // One tick lasts about 30.5 microseconds.
+        assert_eq!(CLOCK_FREQUENCY.ticks_from_us(0), Some(0));
+        assert_eq!(CLOCK_FREQUENCY.ticks_from_us(30), Some(0));
+        assert_eq!(CLOCK_FREQUENCY.ticks_from_us(31), Some(1));
+        assert_eq!(CLOCK_FREQUENCY.ticks_from_us(1_000), Some(32));
+        assert_eq!(CLOCK_FREQUENCY.ticks_from_us(1_500), Some(49));
+        assert_eq!(CLOCK_FREQUENCY.ticks_from_us(1_000_000), Some(32768));
+    }
+
+    #[test]
+    fn test_ticks_from_us_matches_ms() {
+        // Whole milliseconds give the same number of ticks as the millisecond path.
+        for duration_ms in [1, 10, 100, 1000, 30_000].iter() {
+            let clock = ClockValue::new(0, CLOCK_FREQUENCY.hz());
+            let ms_ticks = clock
+                .wrapping_add(Duration::from_ms(*duration_ms))
+                .num_ticks();
+            assert_eq!(
+                CLOCK_FREQUENCY.ticks_from_us(*duration_ms as u32 * 1000),
+                Some(ms_ticks as usize)
+            );
+        }
+    }
+
+    #[test]
+    fn test_alarm_instant() {
+        let now = ClockValue::new(0xFF_FFF0, CLOCK_FREQUENCY.hz());
+        assert_eq!(alarm_instant(now, 0), Some(0xFF_FFF0));
+        assert_eq!(alarm_instant(now, 0x20), Some(0x100_0010));
+        assert_eq!(
+            alarm_instant(now, MAX_ALARM_TICKS),
+            Some(0xFF_FFF0 + MAX_ALARM_TICKS)
+        );
+        assert_eq!(alarm_instant(now, MAX_ALARM_TICKS + 1), None);
+        // The addition wraps instead of overflowing.
+        let last = ClockValue::new(-1, CLOCK_FREQUENCY.hz());
+        assert_eq!(alarm_instant(last, 1), Some(0));
+    }
+
+    #[test]
+    fn test_wrapping_sub_ticks() {
+        let hz = CLOCK_FREQUENCY.hz();
+        let before_wrap = ClockValue::new(0xFF_FFFF, hz);
+        let after_wrap = ClockValue::new(0, hz);
+        // The counter wraps around, but values computed from it don't.
+        let computed = before_wrap.wrapping_add(Duration::from_ms(1000));
+        let read = ClockValue::new(computed.num_ticks() & 0xFF_FFFF, hz);
+        assert_eq!(after_wrap.wrapping_sub_ticks(before_wrap), Some(1));
+        assert_eq!(before_wrap.wrapping_sub_ticks(after_wrap), Some(-1));
+        assert_eq!(computed.wrapping_sub_ticks(read), Some(0));
+        assert_eq!(read.wrapping_sub_ticks(before_wrap), Some(32768));
+        assert_eq!(
+            ClockValue::new(MAX_ALARM_TICKS as isize, hz).wrapping_sub_ticks(after_wrap),
+            Some(MAX_ALARM_TICKS as isize)
+        );
+        assert_eq!(
+            ClockValue::new(MAX_ALARM_TICKS as isize + 1, hz).wrapping_sub_ticks(after_wrap),
+            Some(-(MAX_ALARM_TICKS as isize) - 1)
+        );
+        assert_eq!(
+            after_wrap.wrapping_sub_ticks(ClockValue::new(0, 2 * hz)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_max_duration() {
+        for hz in [1000, CLOCK_FREQUENCY.hz(), 1_000_000].iter() {
+            let clock = ClockValue::new(0, *hz);
+            let max_duration = clock.max_duration();
+            let max_ticks = clock.wrapping_add(max_duration).num_ticks() as usize;
+            assert!(max_ticks <= MAX_ALARM_TICKS);
+            // One more millisecond doesn't fit anymore.
+            let longer_ticks = clock
+                .wrapping_add(Duration::from_ms(max_duration.ms() + 1))
+                .num_ticks() as usize;
+            assert!(longer_ticks > MAX_ALARM_TICKS);
+        }
+    }
+
+    #[test]
+    fn test_ticks_from_us_out_of_range() {
+        let fast_clock = ClockFrequency { hz: usize::MAX };
+        assert_eq!(fast_clock.ticks_from_us(0), Some(0));
+        assert_eq!(fast_clock.ticks_from_us(u32::MAX), None);
+    }
+}
@@ -171,6 +171,84 @@ pub fn send_or_recv(buf: &mut [u8; 64]) -> SendOrRecvStatus {
     status.get().unwrap()
 }
 
+#[cfg(feature = "hid_stats")]
+pub use self::stats::{reset_stats, stats, HidStats};
+
+// Packet counters of the CTAP HID endpoint, to debug flaky USB stacks.
+#[cfg(feature = "hid_stats")]
+mod stats {
+    use super::SendOrRecvStatus;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    static SENT_PACKETS: AtomicUsize = AtomicUsize::new(0);
+    static RECEIVED_PACKETS: AtomicUsize = AtomicUsize::new(0);
+    static TIMEOUTS: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub struct HidStats {
+        pub sent_packets: usize,
+        pub received_packets: usize,
+        pub timeouts: usize,
+    }
+
+    // Returns the counters since boot or since the last call to reset_stats.
+    pub fn stats() -> HidStats {
+        HidStats {
+            sent_packets: SENT_PACKETS.load(Ordering::Relaxed),
+            received_packets: RECEIVED_PACKETS.load(Ordering::Relaxed),
+            timeouts: TIMEOUTS.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn reset_stats() {
+        SENT_PACKETS.store(0, Ordering::Relaxed);
+        RECEIVED_PACKETS.store(0, Ordering::Relaxed);
+        TIMEOUTS.store(0, Ordering::Relaxed);
+    }
+
+    pub(super) fn record(status: Option<SendOrRecvStatus>) {
+        let counter = match status {
+            Some(SendOrRecvStatus::Sent) => &SENT_PACKETS,
+            Some(SendOrRecvStatus::Received) => &RECEIVED_PACKETS,
+            Some(SendOrRecvStatus::Error) => return,
+            None => &TIMEOUTS,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        // The counters are global, so a single test covers them to avoid races between tests.
+        #[test]
+        fn test_record_and_reset() {
+            reset_stats();
+            assert_eq!(stats(), HidStats::default());
+
+            record(Some(SendOrRecvStatus::Sent));
+            record(Some(SendOrRecvStatus::Received));
+            record(Some(SendOrRecvStatus::Received));
+            record(None);
+            record(None);
+            record(None);
+            // Errors are not counted.
+            record(Some(SendOrRecvStatus::Error));
+            assert_eq!(
+                stats(),
+                HidStats {
+                    sent_packets: 1,
+                    received_packets: 2,
+                    timeouts: 3,
+                }
+            );
+
+            reset_stats();
+            assert_eq!(stats(), HidStats::default());
+        }
+    }
+}
+
 // Same as recv, but with a timeout.
 // If the timeout elapses, return None.
 #[allow(clippy::let_and_return)]
@@ -187,6 +265,8 @@ pub fn recv_with_timeout(
     .unwrap();
 
     let result = recv_with_timeout_detail(buf, timeout_delay);
+    #[cfg(feature = "hid_stats")]
+    stats::record(result);
 
     #[cfg(feature = "verbose_usb")]
     {
@@ -215,6 +295,8 @@ pub fn send_or_recv_with_timeout(
     .unwrap();
 
     let result = send_or_recv_with_timeout_detail(buf, timeout_delay);
+    #[cfg(feature = "hid_stats")]
+    stats::record(result);
 
     #[cfg(feature = "verbose_usb")]
     {
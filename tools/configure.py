@@ -37,6 +37,12 @@ from fido2 import hid
 
 OPENSK_VID_PID = (0x1915, 0x521F)
 OPENSK_VENDOR_CONFIGURE = 0x40
+# Firmware protection levels, as defined by the CRP driver.
+PROTECTION_LEVELS = {
+    "no-protection": 0x01,
+    "jtag-disabled": 0x02,
+    "fully-locked": 0xFF,
+}
 
 
 def fatal(msg):
@@ -84,8 +90,12 @@ def main(args):
   # We need either both the certificate and the key or none
   if bool(args.priv_key) ^ bool(args.certificate):
     fatal("Certificate and private key must be set together or both omitted.")
+  if args.aaguid and (not args.priv_key or args.rp_id):
+    fatal("The AAGUID can only be set with the batch certificate and key.")
 
   cbor_data = {1: args.lock}
+  if args.lock:
+    cbor_data[3] = PROTECTION_LEVELS[args.protection_level]
 
   if args.priv_key:
     cbor_data[1] = args.lock
@@ -123,6 +133,10 @@ def main(args):
             priv_key.private_numbers().private_value.to_bytes(
                 length=32, byteorder='big', signed=False)
     }
+    if args.rp_id:
+      cbor_data[2][3] = args.rp_id
+    if args.aaguid:
+      cbor_data[2][4] = args.aaguid.bytes
 
   for authenticator in tqdm(get_opensk_devices(args.batch)):
     # If the device supports it, wink to show which device
@@ -139,8 +153,15 @@ def main(args):
       )
       info(f"Certificate: {'Present' if result[1] else 'Missing'}")
       info(f"Private Key: {'Present' if result[2] else 'Missing'}")
+      protection_level = result.get(3)
+      if protection_level is not None:
+        info(f"Protection level: {protection_level:#04x}")
       if args.lock:
-        info("Device is now locked down!")
+        if (protection_level is not None and
+            protection_level != PROTECTION_LEVELS[args.protection_level]):
+          error("Device was not locked down (unexpected protection level).")
+        else:
+          info("Device is now locked down!")
     except ctap.CtapError as ex:
       if ex.code.value == ctap.CtapError.ERR.INVALID_COMMAND:
         error("Failed to configure OpenSK (unsupported command).")
@@ -184,6 +205,24 @@ if __name__ == "__main__":
       help=("PEM file containing the private key associated "
             "with the certificate."),
   )
+  parser.add_argument(
+      "--rp-id",
+      default=None,
+      metavar="RP_ID",
+      dest="rp_id",
+      help=("Only use the certificate and private key when attesting "
+            "credentials for this relying party. Other relying parties keep "
+            "using the batch attestation."),
+  )
+  parser.add_argument(
+      "--aaguid",
+      default=None,
+      type=uuid.UUID,
+      metavar="AAGUID",
+      dest="aaguid",
+      help=("Replaces the AAGUID of the firmware, for example to identify a "
+            "product model. Requires the certificate and private key."),
+  )
   parser.add_argument(
       "--lock-device",
       default=False,
@@ -193,4 +232,13 @@ if __name__ == "__main__":
             "This command can fail if the certificate or the private key "
             "haven't been both programmed yet."),
   )
+  parser.add_argument(
+      "--protection-level",
+      default="fully-locked",
+      choices=PROTECTION_LEVELS.keys(),
+      dest="protection_level",
+      help=("Protection level to apply when locking the device. Intermediate "
+            "levels are meant for development and don't require the "
+            "certificate and private key to be programmed."),
+  )
   main(parser.parse_args())